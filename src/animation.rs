@@ -0,0 +1,335 @@
+use basic::*;
+use raytrace::Camera;
+
+/// How the values between the keyframes of a track are interpolated.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Interpolation {
+    /// Linearly, or spherically for rotations (see `Quaternion::slerp`).
+    Linear,
+    /// Along a smooth curve through the keyframes (Catmull-Rom splines, or spherical
+    /// quadrangles for rotations), so the motion does not change abruptly at the keyframes,
+    /// even if they are unevenly spaced in time.
+    Cubic,
+}
+
+/// A value of a track at a point in time.
+#[derive(Debug, Copy, Clone)]
+pub struct Keyframe<T> {
+    /// The time, e.g. in seconds.
+    pub time: Float,
+    /// The value at that time.
+    pub value: T,
+}
+
+/// Values that can be interpolated between keyframes.
+pub trait Animatable: Copy {
+    /// Interpolates between `a` (for `t = 0`) and `b` (for `t = 1`).
+    fn linear(a: Self, b: Self, t: Float) -> Self;
+    /// Interpolates between `b` (for `t = 0`) and `c` (for `t = 1`) along a smooth curve
+    /// that also passes through the neighboring keyframes `a` and `d`.
+    fn cubic(a: Self, b: Self, c: Self, d: Self, t: Float) -> Self;
+}
+
+impl Animatable for Float {
+    fn linear(a: Float, b: Float, t: Float) -> Float {
+        a + t * (b - a)
+    }
+
+    fn cubic(a: Float, b: Float, c: Float, d: Float, t: Float) -> Float {
+        catmull_rom(a, b, c, d, t)
+    }
+}
+
+impl Animatable for Vec3 {
+    fn linear(a: Vec3, b: Vec3, t: Float) -> Vec3 {
+        a.lerp(b, t)
+    }
+
+    fn cubic(a: Vec3, b: Vec3, c: Vec3, d: Vec3, t: Float) -> Vec3 {
+        Vec3::new(catmull_rom(a.x(), b.x(), c.x(), d.x(), t),
+                  catmull_rom(a.y(), b.y(), c.y(), d.y(), t),
+                  catmull_rom(a.z(), b.z(), c.z(), d.z(), t))
+    }
+}
+
+impl Animatable for Quaternion {
+    fn linear(a: Quaternion, b: Quaternion, t: Float) -> Quaternion {
+        a.slerp(b, t)
+    }
+
+    /// Spherical quadrangle interpolation (squad) by Shoemake.
+    fn cubic(a: Quaternion, b: Quaternion, c: Quaternion, d: Quaternion, t: Float) -> Quaternion {
+        // q and -q are the same rotation, so take the neighbors closest to each other.
+        let a = closest(a, b);
+        let c = closest(c, b);
+        let d = closest(d, c);
+        let control = |prev: Quaternion, q: Quaternion, next: Quaternion| {
+            let inverse = q.conjugate();
+            let tangent = -0.25 * (log(inverse * next) + log(inverse * prev));
+            q * exp(tangent)
+        };
+        let (sb, sc) = (control(a, b, c), control(b, c, d));
+        b.slerp(c, t).slerp(sb.slerp(sc, t), 2. * t * (1. - t))
+    }
+}
+
+/// The Catmull-Rom spline between `b` and `c`.
+fn catmull_rom(a: Float, b: Float, c: Float, d: Float, t: Float) -> Float {
+    let (t2, t3) = (t * t, t * t * t);
+    let (c2, c3) = (2. * a - 5. * b + 4. * c - d, 3. * (b - c) + d - a);
+    b + 0.5 * ((c - a) * t + c2 * t2 + c3 * t3)
+}
+
+/// Returns `q` or `-q`, whichever is closer to `p`.
+fn closest(q: Quaternion, p: Quaternion) -> Quaternion {
+    if q.dot(p) < 0. {
+        Quaternion::new(-q.w(), -q.xyz().x(), -q.xyz().y(), -q.xyz().z())
+    } else {
+        q
+    }
+}
+
+/// The logarithm of a unit quaternion, i.e. its axis scaled by half its angle.
+fn log(q: Quaternion) -> Vec3 {
+    let v = q.xyz();
+    let s = v.norm();
+    if s < 1e-9 {
+        v
+    } else {
+        s.atan2(q.w()) / s * v
+    }
+}
+
+/// The inverse of `log`.
+fn exp(v: Vec3) -> Quaternion {
+    let angle = v.norm();
+    if angle < 1e-9 {
+        return Quaternion::new(1., v.x(), v.y(), v.z()).normalize();
+    }
+    let v = angle.sin() / angle * v;
+    Quaternion::new(angle.cos(), v.x(), v.y(), v.z())
+}
+
+/// The keyframes of an animated value, sorted by time.
+///
+/// Before the first and after the last keyframe, the value stays constant.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let track = Track::new(Interpolation::Linear).key(1.0, 10.0).key(0.0, 0.0).key(2.0, 0.0);
+/// assert_eq!(track.sample(0.25), Some(2.5));
+/// assert_eq!(track.sample(1.5), Some(5.0));
+/// assert_eq!(track.sample(-1.0), Some(0.0));
+/// // Cubic interpolation passes through the keyframes, too, but overshoots in between.
+/// let track = Track { interpolation: Interpolation::Cubic, ..track };
+/// assert_eq!(track.sample(1.0), Some(10.0));
+/// assert!(track.sample(0.75).unwrap() > 7.5);
+/// assert_eq!(Track::<Float>::new(Interpolation::Linear).sample(0.0), None);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Track<T> {
+    /// The keyframes, sorted by time. See `key` for adding keyframes in any order.
+    pub keyframes: Vec<Keyframe<T>>,
+    /// How the values between the keyframes are interpolated.
+    pub interpolation: Interpolation,
+}
+
+impl<T: Animatable> Track<T> {
+    /// Creates a track without keyframes.
+    pub fn new(interpolation: Interpolation) -> Track<T> {
+        Track {
+            keyframes: vec![],
+            interpolation: interpolation,
+        }
+    }
+
+    /// Adds a keyframe, replacing the one at the same time, if any.
+    pub fn key(mut self, time: Float, value: T) -> Track<T> {
+        let keyframe = Keyframe {
+            time: time,
+            value: value,
+        };
+        match self.keyframes.iter().position(|k| k.time >= time) {
+            Some(i) if self.keyframes[i].time == time => self.keyframes[i] = keyframe,
+            Some(i) => self.keyframes.insert(i, keyframe),
+            None => self.keyframes.push(keyframe),
+        }
+        self
+    }
+
+    /// Returns the value at the given time, or `None` if the track has no keyframes.
+    pub fn sample(&self, time: Float) -> Option<T> {
+        let keys = &self.keyframes;
+        let next = match keys.iter().position(|k| k.time > time) {
+            None => return keys.last().map(|k| k.value),
+            Some(0) => return Some(keys[0].value),
+            Some(next) => next,
+        };
+        let (b, c) = (&keys[next - 1], &keys[next]);
+        let t = (time - b.time) / (c.time - b.time);
+        Some(match self.interpolation {
+            Interpolation::Linear => T::linear(b.value, c.value, t),
+            Interpolation::Cubic => {
+                // The neighbors are moved as if the keyframes were evenly spaced, so the speed
+                // is continuous at the keyframes. The first and last keyframes are their own
+                // missing neighbors.
+                let duration = c.time - b.time;
+                let a = match keys.get(next.wrapping_sub(2)) {
+                    Some(a) => T::linear(c.value, a.value, 2. * duration / (c.time - a.time)),
+                    None => b.value,
+                };
+                let d = match keys.get(next + 1) {
+                    Some(d) => T::linear(b.value, d.value, 2. * duration / (d.time - b.time)),
+                    None => c.value,
+                };
+                T::cubic(a, b.value, c.value, d, t)
+            }
+        })
+    }
+}
+
+/// Animates the position, orientation, and size of an object or a camera by keyframes.
+///
+/// At each point in time, the tracks define a transformation that scales first,
+/// then rotates, and then translates. Tracks without keyframes leave the respective part
+/// unchanged.
+///
+/// To render a sequence of frames, compute the time of each frame, e.g. `frame / 24.0`,
+/// place the objects and the camera for it, and render the scene.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let camera = Camera {
+///     pos: Vec3::new(0.0, 0.0, 10.0),
+///     look_at: Vec3::zero(),
+///     up: Vec3::e2().to(),
+///     horizontal_fov: PI / 2.0,
+///     aspect_ratio: 1.0,
+///     width: 64,
+///     height: 64,
+/// };
+/// // Orbit a quarter turn around the y-axis while moving up.
+/// let mut animation = TransformAnimation::new();
+/// animation.rotation = Track::new(Interpolation::Linear)
+///     .key(0.0, Quaternion::identity())
+///     .key(2.0, Quaternion::from_axis_angle(Vec3::e2().to(), PI / 2.0));
+/// animation.translation = Track::new(Interpolation::Linear)
+///     .key(0.0, Vec3::zero())
+///     .key(2.0, Vec3::new(0.0, 5.0, 0.0));
+/// let end = animation.camera_at(&camera, 2.0);
+/// assert_eq!(end.pos, Vec3::new(10.0, 5.0, 0.0));
+/// assert_eq!(end.look_at, Vec3::new(0.0, 5.0, 0.0));
+/// let middle = animation.camera_at(&camera, 1.0);
+/// assert!(appr(middle.pos.y(), 2.5) && appr((middle.pos - middle.look_at).norm(), 10.0));
+/// assert_eq!(animation.matrix_at(-1.0), Matrix34::identity());
+/// ```
+#[derive(Debug, Clone)]
+pub struct TransformAnimation {
+    /// The translation over time.
+    pub translation: Track<Vec3>,
+    /// The rotation over time, as unit quaternions.
+    pub rotation: Track<Quaternion>,
+    /// The scaling factors along the axes over time.
+    pub scale: Track<Vec3>,
+}
+
+impl Default for TransformAnimation {
+    fn default() -> TransformAnimation {
+        TransformAnimation::new()
+    }
+}
+
+impl TransformAnimation {
+    /// Creates an animation without keyframes, i.e. the identity at all times.
+    pub fn new() -> TransformAnimation {
+        TransformAnimation {
+            translation: Track::new(Interpolation::Linear),
+            rotation: Track::new(Interpolation::Linear),
+            scale: Track::new(Interpolation::Linear),
+        }
+    }
+
+    /// Returns the transformation at the given time.
+    pub fn matrix_at(&self, time: Float) -> Matrix34 {
+        let translation = self.translation.sample(time).unwrap_or(Vec3::zero());
+        let rotation = self.rotation.sample(time).unwrap_or(Quaternion::identity());
+        let scale = self.scale.sample(time).unwrap_or(Vec3::ones());
+        Matrix34::translate(translation) * rotation.normalize().to_matrix() *
+        Matrix34::scale(scale)
+    }
+
+    /// Returns the camera moved by the transformation at the given time.
+    pub fn camera_at(&self, camera: &Camera, time: Float) -> Camera {
+        let m = self.matrix_at(time);
+        Camera {
+            pos: m * camera.pos,
+            look_at: m * camera.look_at,
+            up: m * camera.up - m * Vec3::zero(),
+            ..*camera
+        }
+    }
+}
+
+/// A smooth camera flight through waypoints, e.g. for fly-through animations.
+///
+/// The position and the target of the camera follow Catmull-Rom splines through the positions
+/// and targets of the waypoints, which are reached at their times from 0 to 1.
+/// The other values of the camera stay the same.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let camera = Camera {
+///     pos: Vec3::zero(),
+///     look_at: -Vec3::e3().to(),
+///     up: Vec3::e2().to(),
+///     horizontal_fov: PI / 2.0,
+///     aspect_ratio: 1.0,
+///     width: 64,
+///     height: 64,
+/// };
+/// let path = CameraPath::new(camera)
+///     .waypoint(0.0, Vec3::new(0.0, 1.0, 10.0), Vec3::zero())
+///     .waypoint(0.5, Vec3::new(10.0, 2.0, 0.0), Vec3::zero())
+///     .waypoint(1.0, Vec3::new(0.0, 1.0, -10.0), Vec3::new(0.0, 0.0, -20.0));
+/// assert_eq!(path.camera_at(0.5).pos, Vec3::new(10.0, 2.0, 0.0));
+/// assert_eq!(path.camera_at(1.0).look_at, Vec3::new(0.0, 0.0, -20.0));
+/// // Between the waypoints, the camera flies along a curve instead of a straight line.
+/// assert!(path.camera_at(0.25).pos.x() > 5.0);
+/// assert_eq!(path.camera_at(0.25).horizontal_fov, PI / 2.0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CameraPath {
+    /// The camera whose position and target are moved along the path.
+    pub camera: Camera,
+    /// The positions of the waypoints over time.
+    pub positions: Track<Vec3>,
+    /// The targets of the waypoints over time.
+    pub targets: Track<Vec3>,
+}
+
+impl CameraPath {
+    /// Creates a path without waypoints, which leaves the camera where it is.
+    pub fn new(camera: Camera) -> CameraPath {
+        CameraPath {
+            camera: camera,
+            positions: Track::new(Interpolation::Cubic),
+            targets: Track::new(Interpolation::Cubic),
+        }
+    }
+
+    /// Adds a waypoint where the camera is at `pos` and looks at `look_at` at time `t`.
+    pub fn waypoint(mut self, t: Float, pos: Vec3, look_at: Vec3) -> CameraPath {
+        self.positions = self.positions.key(t, pos);
+        self.targets = self.targets.key(t, look_at);
+        self
+    }
+
+    /// Returns the camera at time `t`, from 0 to 1.
+    pub fn camera_at(&self, t: Float) -> Camera {
+        Camera {
+            pos: self.positions.sample(t).unwrap_or(self.camera.pos),
+            look_at: self.targets.sample(t).unwrap_or(self.camera.look_at),
+            ..self.camera
+        }
+    }
+}
@@ -2,107 +2,253 @@ pub use lin_alg::*;
 pub use color::*;
 pub use material::*;
 use physics::*;
+use std::cmp;
+use texture::{Texture, TextureFootprint};
 
 /// Structure for representing rays
+///
+/// The fields are private, so that the cached `inv_dir` and `sign` always match the direction.
+/// Create a new ray to change the origin or the direction.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Ray {
     /// Where the ray comes from.
-    pub origin: Vec3,
+    origin: Vec3,
     /// The direction the ray is traveling.
-    pub dir: UnitVec3,
+    dir: UnitVec3,
+    /// The coordinate-wise inverse of `dir`, cached for ray-box intersection tests.
+    inv_dir: Vec3,
+    /// For each coordinate, 1 if the direction is negative and 0 otherwise.
+    sign: [usize; 3],
+    /// Intersections closer to the origin than this are ignored.
+    t_min: Float,
 }
 
 impl Ray {
-    /// Creates a `Ray`.
+    /// Creates a `Ray`, which ignores intersections closer than `EPS` to its origin.
     pub fn new(origin: Vec3, dir: UnitVec3) -> Ray {
+        let inv_dir = Vec3::new(1. / dir[0], 1. / dir[1], 1. / dir[2]);
         Ray {
             origin: origin,
             dir: dir,
+            inv_dir: inv_dir,
+            sign: [sign(inv_dir[0]), sign(inv_dir[1]), sign(inv_dir[2])],
+            t_min: EPS,
         }
     }
 
+    /// Sets the distance from the origin below which intersections are ignored,
+    /// e.g. smaller than `EPS` for tiny scenes, see `RayEpsilon`.
+    pub fn with_t_min(self, t_min: Float) -> Ray {
+        Ray { t_min: t_min, ..self }
+    }
+
+    /// Returns where the ray comes from.
+    pub fn origin(&self) -> Vec3 {
+        self.origin
+    }
+
+    /// Returns the direction the ray is traveling.
+    pub fn dir(&self) -> UnitVec3 {
+        self.dir
+    }
+
+    /// Returns the distance from the origin below which intersections are ignored.
+    pub fn t_min(&self) -> Float {
+        self.t_min
+    }
+
     /// Creates a `Ray` and normalizes the given direction.
     pub fn newn(origin: Vec3, dir: Vec3) -> Ray {
         Ray::new(origin, dir.normalize())
     }
+
+    /// Returns the coordinate-wise inverse of the direction.
+    pub fn inv_dir(&self) -> Vec3 {
+        self.inv_dir
+    }
+
+    /// Returns, for each coordinate, 1 if the direction is negative and 0 otherwise.
+    pub fn sign(&self) -> [usize; 3] {
+        self.sign
+    }
+}
+
+/// Returns 1 for negative numbers and 0 otherwise.
+fn sign(f: Float) -> usize {
+    if f < 0. {
+        1
+    } else {
+        0
+    }
 }
 
 /// Given a ray intersection and the direction of the light source, computes the shadow ray.
-pub fn shadow_ray(inter: &Intersection, dir: UnitVec3) -> Ray {
-    let point = inter.point + EPS * inter.normal;
-    Ray::new(point, dir)
+///
+/// The ray starts at the distance `eps` from the surface, e.g. `EPS` (see `RayEpsilon`),
+/// so that rounding errors do not make it hit the surface again, and ignores intersections
+/// closer than `eps`.
+pub fn shadow_ray(inter: &Intersection, dir: UnitVec3, eps: Float) -> Ray {
+    let point = inter.point + eps * inter.normal;
+    Ray::new(point, dir).with_t_min(eps)
 }
 
-/// Given a ray intersection and the direction of a ray, computes its reflection ray.
-pub fn reflect_ray(inter: &Intersection, dir: UnitVec3) -> Ray {
+/// Given a ray intersection and the direction of a ray, computes its reflection ray,
+/// starting at the distance `eps` from the surface like `shadow_ray`.
+pub fn reflect_ray(inter: &Intersection, dir: UnitVec3, eps: Float) -> Ray {
     let reflected_dir = reflect(dir, inter.normal);
-    let point = inter.point + EPS * inter.normal;
-    Ray::new(point, reflected_dir)
+    let point = inter.point + eps * inter.normal;
+    Ray::new(point, reflected_dir).with_t_min(eps)
 }
 
 /// Computes the refracted ray or `None` in case of total internal reflection.
 ///
 /// Inputs are a ray intersection, the direction of the ray,
-/// and the index of refraction (ior of material after intersection / ior before intersection).
-pub fn refract_ray(inter: &Intersection, dir: UnitVec3, ior: f64) -> Option<Ray> {
+/// the index of refraction (ior of material after intersection / ior before intersection),
+/// and the distance `eps` of the origin of the ray from the surface like for `shadow_ray`.
+pub fn refract_ray(inter: &Intersection, dir: UnitVec3, ior: Float, eps: Float) -> Option<Ray> {
     let maybe_refracted_dir = refract(dir, inter.normal, ior);
-    let point = inter.point - EPS * inter.normal;
-    maybe_refracted_dir.map(|dir| Ray::new(point, dir))
+    let point = inter.point - eps * inter.normal;
+    maybe_refracted_dir.map(|dir| Ray::new(point, dir).with_t_min(eps))
 }
 
 /// Contains information about the intersection of a ray and an object.
 pub struct Intersection {
     /// Distance of the hit point from the origin of the ray.
-    pub t: f64,
+    pub t: Float,
     /// The intersection point. It is: (origin of ray) + t * (direction of ray).
     pub point: Vec3,
     /// The normal vector at the intersection point (orthogonal to the surface).
+    /// Open surfaces like planes and meshes turn it towards the ray.
     pub normal: UnitVec3,
+    /// `true` if the ray hit the outside of the surface, i.e. the side its own normal points
+    /// to, and `false` if it comes from inside of a closed object.
+    pub front_face: bool,
     /// The material properties at the intersection point.
     pub material: Material,
+    /// The texture coordinates of the intersection point.
+    pub uv: Vec2,
+    /// The tangent and the bitangent, i.e. the directions in which the texture coordinates
+    /// `u` and `v` increase, orthogonal to the normal, if the surface has them.
+    /// Normal maps are relative to these directions.
+    pub tangents: Option<(UnitVec3, UnitVec3)>,
+    /// The barycentric coordinates of the second and third vertex, if a triangle was hit.
+    pub barycentric: Option<Vec2>,
+    /// The index of the leaf of the innermost bounding volume hierarchy that contains the hit
+    /// object, if it is in one, for debugging hierarchies.
+    pub bvh_leaf: Option<usize>,
 }
 
 impl Intersection {
     /// Constructs an intersection.
     ///
     /// `ray` is the ray that hits the object,
-    /// `t` is the distance of the intersection point from `ray.origin`,
+    /// `t` is the distance of the intersection point from `ray.origin()`,
     /// `normal` is the normal vector at the intersection point,
     /// `material` is the material at the intersection point.
-    pub fn new(ray: &Ray, t: f64, normal: UnitVec3, material: Material) -> Intersection {
+    ///
+    /// The front face is the side that `normal` points to, see `with_front_face` for surfaces
+    /// that turn their normals towards the ray.
+    pub fn new(ray: &Ray, t: Float, normal: UnitVec3, material: Material) -> Intersection {
         Intersection {
             t: t,
             point: ray.origin + t * ray.dir,
             normal: normal,
+            front_face: ray.dir * normal < 0.,
             material: material,
+            uv: Vec2::zero(),
+            tangents: None,
+            barycentric: None,
+            bvh_leaf: None,
+        }
+    }
+
+    /// Sets whether the ray hit the outside of the surface, for surfaces whose normal
+    /// does not tell it.
+    pub fn with_front_face(self, front_face: bool) -> Intersection {
+        Intersection { front_face: front_face, ..self }
+    }
+
+    /// Sets the texture coordinates of the intersection point.
+    pub fn with_uv(self, uv: Vec2) -> Intersection {
+        Intersection { uv: uv, ..self }
+    }
+
+    /// Sets the tangent and the bitangent at the intersection point.
+    pub fn with_tangents(self, tangents: Option<(UnitVec3, UnitVec3)>) -> Intersection {
+        Intersection { tangents: tangents, ..self }
+    }
+
+    /// Sets the barycentric coordinates of the second and third vertex of a hit triangle.
+    pub fn with_barycentric(self, u: Float, v: Float) -> Intersection {
+        Intersection { barycentric: Some(Vec2::new(u, v)), ..self }
+    }
+
+    /// Applies the textures of the material at the intersection point:
+    /// the color and roughness of the material and the normal are changed accordingly.
+    pub fn apply_textures(&mut self) {
+        self.apply_filtered_textures(None);
+    }
+
+    /// Like `apply_textures`, but averages the textures over the footprint of a pixel, if given.
+    pub fn apply_filtered_textures(&mut self, footprint: Option<&TextureFootprint>) {
+        let (uv, point) = (self.uv, self.point);
+        let sample = |texture: &Texture| match footprint {
+            Some(footprint) => texture.sample_filtered(uv, point, footprint),
+            None => texture.sample(uv, point),
+        };
+        let mut mat = self.material;
+        if let Some(texture) = mat.color_texture {
+            mat.color = sample(texture) * mat.color;
+        }
+        if let Some(texture) = mat.roughness_texture {
+            let factor = sample(texture).red();
+            if let DiffuseModel::OrenNayar { ref mut roughness } = mat.diffuse_model {
+                *roughness *= factor;
+            }
+            if let SpecularModel::Ggx { ref mut roughness, .. } = mat.specular_model {
+                *roughness *= factor;
+            }
         }
+        if let Some(texture) = mat.normal_texture {
+            let c = sample(texture);
+            let local = Vec3::new(2. * c.red() - 1., 2. * c.green() - 1., 2. * c.blue() - 1.);
+            let n = match self.tangents {
+                Some((t, b)) => local.x() * t + local.y() * b + local.z() * self.normal,
+                None => OrthonormalBasis::from_normal(self.normal).to_world(local),
+            };
+            if n.norm2() > 0. {
+                self.normal = n.normalize();
+            }
+        }
+        self.material = mat;
     }
 }
 
 /// Represents partial information about an intersection.
 /// The distance from the origin of the ray is directly available but nothing more.
 /// The actual intersection can be computed using `eval` which might be expensive.
-// TODO: Remove the `FnMut` workaround to make boxed `FnOnce`s work,
-// as soon as FnBox (or similar) is in stable Rust.
 pub struct DelayedIntersection<'a> {
-    pub t: f64,
-    f: Box<FnMut() -> Intersection + 'a>,
+    pub t: Float,
+    f: Box<FnOnce() -> Intersection + 'a>,
 }
 
 impl<'a> DelayedIntersection<'a> {
     // Creates a `DelayedIntersection`, given a closure that returns the intersection.
-    pub fn new<T: FnOnce() -> Intersection + 'a>(t: f64, f: T) -> DelayedIntersection<'a> {
-        let mut opt_f = Some(f);
-        let boxed_f: Box<FnMut() -> Intersection> = Box::new(move || (opt_f.take().unwrap())());
-        DelayedIntersection { t: t, f: boxed_f }
+    pub fn new<T: FnOnce() -> Intersection + 'a>(t: Float, f: T) -> DelayedIntersection<'a> {
+        DelayedIntersection {
+            t: t,
+            f: Box::new(f),
+        }
     }
 
     /// Computes the intersection information.
     pub fn eval(self) -> Intersection {
-        unsafe {
-            use std::mem;
-            let this: *mut Box<FnMut() -> Intersection + 'a> = mem::transmute(&self.f);
-            (**this)()
-        }
+        (self.f)()
     }
 }
+
+/// Sorts intersections by their distance from the origin of the ray, nearest first.
+pub fn sort_by_distance(intersections: &mut [DelayedIntersection]) {
+    // A degenerate primitive may report a NaN distance, which must not abort the query.
+    intersections.sort_by(|a, b| a.t.partial_cmp(&b.t).unwrap_or(cmp::Ordering::Equal));
+}
@@ -1,195 +1,297 @@
 extern crate raydiancy;
-extern crate time;
 
+use raydiancy::distributed::*;
 use raydiancy::raytrace::*;
+use std::env;
+use std::io::{stdout, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// The scenes rendered by the benchmark, at their usual settings.
+const BENCH_SCENES: [&'static str; 5] = ["single_sphere", "bunny", "dragon", "spheres", "axes"];
+/// The number of renders per scene in the benchmark, if not given.
+const BENCH_RUNS: usize = 3;
+const BUNNY_OBJ: &'static str = "scenes/bunny.obj";
+const DRAGON_OBJ: &'static str = "scenes/dragon.obj";
 
 macro_rules! render {
     ($scene:ident) => { {
         use std::path::Path;
         use std::fs;
         use std::io::*;
-        use time::*;
+        use std::time::Instant;
 
         let _ = fs::create_dir("output/");
         let name = stringify!($scene);
         println!("Scene: {}", name);
         println!("  Constructing ...");
         let scene = $scene();
-        let start_time = precise_time_s();
+        let report = scene.validate();
+        if !report.is_ok() {
+            println!("  Problems found:");
+            for issue in report.issues.iter() {
+                println!("    {}", issue);
+            }
+        }
+        let start_time = Instant::now();
         print!("  Rendering ... ");
         stdout().flush().unwrap();
-        let rendered = scene.render();
-        let end_time = precise_time_s();
-        println!("({:.2} seconds)", end_time - start_time);
+        let (rendered, diagnostics) = scene.render_with_diagnostics();
+        let elapsed = start_time.elapsed();
+        println!("({}.{:02} seconds)", elapsed.as_secs(), elapsed.subsec_nanos() / 10_000_000);
+        if !diagnostics.is_ok() {
+            println!("  Problems detected: {}", diagnostics);
+        }
         let file = format!("output/{}.png", name);
         println!("  Writing to file {}...", file);
-        write_pixels_to_file(
-            rendered,
-            Path::new(&file),
-        );
+        if let Err(e) = write_pixels_to_file(rendered, Path::new(&file)) {
+            println!("  Failed: {}", e);
+        }
+        let nan_count = take_nan_conversions();
+        if nan_count > 0 {
+            println!("  Warning: {} NaN color channels were written as 0", nan_count);
+        }
     } }
 }
 
+/// Without arguments, renders all scenes.
+///
+/// `main worker SCENE ADDR` renders tiles of the scene for coordinators connecting to ADDR.
+/// `main coordinator SCENE ADDR...` renders the scene on the workers at the given addresses.
+/// `main bench [RUNS]` renders a standard set of scenes several times and reports timings.
+/// `main heatmap SCENE` writes the cost of every pixel of the scene as a false-color image.
+/// The costs are only counted if the feature `stats` is enabled.
 fn main() {
-    render!(single_sphere);
-    render!(bunny);
-    render!(dragon);
-    render!(spheres);
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(|s| &s[..]) {
+        Some("worker") if args.len() == 4 => {
+            let scene = scene_by_name(&args[2]).expect("unknown scene");
+            println!("Worker for scene {} listening on {}", args[2], args[3]);
+            run_worker(&scene, &args[3][..]).unwrap();
+        }
+        Some("coordinator") if args.len() >= 4 => {
+            let scene = scene_by_name(&args[2]).expect("unknown scene");
+            let workers: Vec<&str> = args[3..].iter().map(|s| &s[..]).collect();
+            println!("Rendering scene {} on {} workers", args[2], workers.len());
+            let rendered = render_distributed(&scene, &workers).unwrap();
+            let _ = std::fs::create_dir("output/");
+            let file = format!("output/{}.png", args[2]);
+            println!("  Writing to file {}...", file);
+            write_pixels_to_file(rendered, Path::new(&file)).unwrap();
+        }
+        Some("bench") if args.len() <= 3 => {
+            let runs = args.get(2).map_or(BENCH_RUNS, |s| s.parse().expect("invalid run count"));
+            bench(runs.max(1));
+        }
+        Some("heatmap") if args.len() == 3 => {
+            let scene = scene_by_name(&args[2]).expect("unknown scene");
+            if !cfg!(feature = "stats") {
+                println!("Build with `--features stats` to count the costs of the pixels.");
+            }
+            println!("Rendering scene {} with statistics", args[2]);
+            let (_, stats) = scene.render_with_stats();
+            println!("{}", stats);
+            let _ = std::fs::create_dir("output/");
+            let file = format!("output/{}_cost.png", args[2]);
+            println!("  Writing cost heatmap (maximum {}) to file {}...",
+                     stats.max_pixel_cost(),
+                     file);
+            write_pixels_to_file(stats.cost_heatmap(stats.max_pixel_cost()), Path::new(&file))
+                .unwrap();
+        }
+        _ => {
+            render!(single_sphere);
+            render!(bunny);
+            render!(dragon);
+            render!(spheres);
+            render!(axes);
+        }
+    }
 }
 
-fn single_sphere() -> Scene {
-    Scene {
-        camera: Camera {
-            pos: Vec3::new(10.0, 10.0, 10.0),
-            look_at: Vec3::zero(),
-            up: Vec3::new(0.0, 1.0, 0.0),
-            horizontal_fov: 120_f64.to_radians(),
-            aspect_ratio: 1.0,
-            width: 360,
-            height: 360,
-        },
-        objects: vec![Box::new(Sphere {
-                          center: Vec3::zero(),
-                          radius: 8.0,
-                          material: color_material(Color::new(0.0, 0.0, 1.0)),
-                      })],
-        ambient_color: white(),
-        lights: vec![LightSource {
-                         pos: Vec3::new(0.0, 10.0, 10.0),
-                         col: white(),
-                     }],
+/// Renders the benchmark scenes `runs` times each and prints the minimum and average
+/// rendering times, the rays traced per second, and the shape of the hierarchies of the meshes.
+fn bench(runs: usize) {
+    println!("Benchmark: {} runs per scene", runs);
+    for &name in BENCH_SCENES.iter() {
+        println!("Scene: {}", name);
+        if let Some(path) = mesh_path(name) {
+            let stats = Mesh::from_obj_file(path, color_material(white())).unwrap().stats();
+            println!("  BVH: {} nodes, {} leaves, depth {}, {} references, {} unbounded",
+                     stats.nodes,
+                     stats.leaves,
+                     stats.max_depth,
+                     stats.references,
+                     stats.unbounded);
+        }
+        let scene = scene_by_name(name).unwrap();
+        let mut times = Vec::with_capacity(runs);
+        let mut rays = 0;
+        for run in 0..runs {
+            print!("\r  Rendering ... {}/{}", run + 1, runs);
+            stdout().flush().unwrap();
+            let start_time = Instant::now();
+            let (_, diagnostics) = scene.render_with_diagnostics();
+            times.push(seconds(start_time.elapsed()));
+            rays = diagnostics.ray_count;
+        }
+        let min = times.iter().cloned().fold(f64::INFINITY, f64::min);
+        let avg = times.iter().sum::<f64>() / runs as f64;
+        println!("\r  min {:.3} s, avg {:.3} s, {:.2} Mrays/s ({} rays per render)",
+                 min,
+                 avg,
+                 rays as f64 / avg / 1e6,
+                 rays);
     }
 }
 
+/// Returns the duration in seconds.
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9
+}
+
+/// Returns the OBJ file of the scene with the given name, if it shows a mesh.
+fn mesh_path(name: &str) -> Option<&'static str> {
+    match name {
+        "bunny" => Some(BUNNY_OBJ),
+        "dragon" => Some(DRAGON_OBJ),
+        _ => None,
+    }
+}
+
+/// Returns the scene with the given name.
+fn scene_by_name(name: &str) -> Option<Scene> {
+    match name {
+        "single_sphere" => Some(single_sphere()),
+        "bunny" => Some(bunny()),
+        "dragon" => Some(dragon()),
+        "spheres" => Some(spheres()),
+        "axes" => Some(axes()),
+        _ => None,
+    }
+}
+
+fn single_sphere() -> Scene {
+    Scene::builder()
+        .resolution(360, 360)
+        .look_at(Vec3::new(10.0, 10.0, 10.0), Vec3::zero())
+        .fov_degrees(120.0)
+        .add(Sphere {
+            center: Vec3::zero(),
+            radius: 8.0,
+            material: color_material(Color::new(0.0, 0.0, 1.0)),
+        })
+        .light(PointLight {
+            pos: Vec3::new(0.0, 10.0, 10.0),
+            col: white(),
+        })
+        .build()
+        .unwrap()
+}
+
 fn bunny() -> Scene {
-    let width = 640;
-    let height = 360;
-    let material = color_material(white());
-    let camera = Camera {
-        pos: Vec3::new(0.0, 4.0, 12.0),
-        look_at: 4.0 * Vec3::e2(),
-        up: Vec3::e2().to(),
-        horizontal_fov: 120_f64.to_radians(),
-        aspect_ratio: width as f64 / height as f64,
-        width: width,
-        height: height,
-    };
-    let mesh = Mesh::from_obj_file("scenes/bunny.obj", material);
-    let light = LightSource {
-        pos: Vec3::new(0.0, 10.0, 10.0),
-        col: white(),
-    };
-    return Scene {
-        camera: camera,
-        objects: vec![
-            Box::new(mesh.unwrap()),
-            ],
-        ambient_color: white(),
-        lights: vec![light],
-    };
+    mesh_scene(BUNNY_OBJ)
 }
 
 fn dragon() -> Scene {
-    let width = 640;
-    let height = 360;
-    let material = color_material(white());
-    let camera = Camera {
-        pos: Vec3::new(0.0, 4.0, 12.0),
-        look_at: 4.0 * Vec3::e2(),
-        up: Vec3::e2().to(),
-        horizontal_fov: 120_f64.to_radians(),
-        aspect_ratio: width as f64 / height as f64,
-        width: width,
-        height: height,
-    };
-    let mesh = Mesh::from_obj_file("scenes/dragon.obj", material);
-    let light = LightSource {
-        pos: Vec3::new(0.0, 10.0, 10.0),
-        col: white(),
-    };
-    return Scene {
-        camera: camera,
-        objects: vec![
-            Box::new(mesh.unwrap()),
-            ],
-        ambient_color: white(),
-        lights: vec![light],
-    };
+    mesh_scene(DRAGON_OBJ)
 }
 
-fn spheres() -> Scene {
-    let width = 1280;
-    let height = 720;
-    // Camera:
-    let camera = Camera {
-        pos: Vec3::new(9.0, 4.0, 1.0),
-        look_at: Vec3::new(0.0, 0.0, -3.0),
-        up: Vec3::e2().to(),
-        horizontal_fov: 120_f64.to_radians(),
-        aspect_ratio: width as f64 / height as f64,
-        width: width,
-        height: height,
-    };
+/// A white mesh from the given OBJ file, seen from the front.
+fn mesh_scene(path: &str) -> Scene {
+    Scene::builder()
+        .look_at(Vec3::new(0.0, 4.0, 12.0), 4.0 * Vec3::e2())
+        .fov_degrees(120.0)
+        .add_mesh(path, color_material(white()))
+        .light(PointLight {
+            pos: Vec3::new(0.0, 10.0, 10.0),
+            col: white(),
+        })
+        .build()
+        .unwrap()
+}
 
-    // Objects:
-    let wall1 = Plane {
-        normal: Vec3::e3(),
-        offset: -5.0,
-        material: reflective_material(0.9, black()),
-    };
-    let wall2 = Plane {
-        normal: Vec3::e1(),
-        offset: -10.0,
-        material: reflective_material(0.9, black()),
-    };
-    let floor = Plane {
-        normal: Vec3::e2(),
-        offset: 0.0,
-        material: color_material(white()),
-    };
+fn spheres() -> Scene {
     let big_radius = 3.;
     let small_radius = 1.;
     let num_spheres = 8;
-    let mut objects: Vec<_> = (0..num_spheres)
-        .map(|i| {
-            let angle = (2 * i) as f64 * PI / (num_spheres as f64);
-            Box::new(Sphere {
-                center: big_radius * Vec3::new(angle.sin(), 0.0, angle.cos()) +
-                        small_radius * Vec3::e2(),
-                radius: small_radius,
-                material: color_material(Color::new((angle / 2.).sin(),
-                                                    (angle / 2. + PI / 3.).sin().abs(),
-                                                    (angle / 2. + PI / 1.5).sin().abs())),
-            }) as Box<Surface + Sync>
+    let mut builder = Scene::builder()
+        .resolution(1280, 720)
+        .look_at(Vec3::new(9.0, 4.0, 1.0), Vec3::new(0.0, 0.0, -3.0))
+        .fov_degrees(120.0);
+    for i in 0..num_spheres {
+        let angle = (2 * i) as Float * PI / (num_spheres as Float);
+        builder = builder.add(Sphere {
+            center: big_radius * Vec3::new(angle.sin(), 0.0, angle.cos()) +
+                    small_radius * Vec3::e2(),
+            radius: small_radius,
+            material: color_material(Color::new((angle / 2.).sin(),
+                                                (angle / 2. + PI / 3.).sin().abs(),
+                                                (angle / 2. + PI / 1.5).sin().abs())),
+        });
+    }
+    builder.add(Sphere {
+            center: Vec3::new(0.0, big_radius / 2., 0.0),
+            radius: big_radius / 2.,
+            material: reflective_material(0.9, white()),
         })
-        .collect();
-    objects.push(Box::new(Sphere {
-        center: Vec3::new(0.0, big_radius / 2., 0.0),
-        radius: big_radius / 2.,
-        material: reflective_material(0.9, white()),
-    }));
-    objects.push(Box::new(floor));
-    objects.push(Box::new(wall1));
-    objects.push(Box::new(wall2));
-
-    // Lights:
-    let light = LightSource {
-        pos: Vec3::new(0.0, 10.0, 0.0),
-        col: 0.5 * white(),
-    };
-    let light2 = LightSource {
-        pos: Vec3::new(10.0, 10.0, 10.0),
-        col: 0.5 * white(),
-    };
+        // Floor and walls:
+        .add(Plane {
+            normal: Vec3::e2(),
+            offset: 0.0,
+            material: color_material(white()),
+        })
+        .add(Plane {
+            normal: Vec3::e3(),
+            offset: -5.0,
+            material: reflective_material(0.9, black()),
+        })
+        .add(Plane {
+            normal: Vec3::e1(),
+            offset: -10.0,
+            material: reflective_material(0.9, black()),
+        })
+        .light(PointLight {
+            pos: Vec3::new(0.0, 10.0, 0.0),
+            col: 0.5 * white(),
+        })
+        .light(PointLight {
+            pos: Vec3::new(10.0, 10.0, 10.0),
+            col: 0.5 * white(),
+        })
+        .build()
+        .unwrap()
+}
 
-    // Scene:
-    return Scene {
-        camera: camera,
-        objects: objects,
-        ambient_color: Color::new(1.0, 1.0, 1.0),
-        lights: vec![
-            light,
-            light2,
-            ],
-    };
+/// A debug scene for checking the conventions of the coordinate system.
+///
+/// There is a unit sphere at the origin and smaller spheres on the positive x-axis (red),
+/// y-axis (green) and z-axis (blue). The axis gizmo and the grid are drawn on top.
+fn axes() -> Scene {
+    let mut builder = Scene::builder()
+        .look_at(Vec3::new(6.0, 5.0, 8.0), Vec3::zero())
+        .add(Sphere {
+            center: Vec3::zero(),
+            radius: 1.0,
+            material: color_material(white()),
+        });
+    let colors = [Color::new(1.0, 0.0, 0.0), Color::new(0.0, 1.0, 0.0), Color::new(0.0, 0.0, 1.0)];
+    for i in 0..3 {
+        builder = builder.add(Sphere {
+            center: 3.0 * Vec3::e(i),
+            radius: 0.5,
+            material: color_material(colors[i]),
+        });
+    }
+    builder.light(PointLight {
+            pos: Vec3::new(5.0, 10.0, 5.0),
+            col: white(),
+        })
+        .options(RenderOptions {
+            axis_gizmo: true,
+            grid_spacing: Some(1.0),
+            ..RenderOptions::default()
+        })
+        .build()
+        .unwrap()
 }
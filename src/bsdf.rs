@@ -0,0 +1,123 @@
+use color::*;
+use lin_alg::*;
+use material::*;
+use physics::*;
+use std::fmt;
+
+/// A direction chosen by `Bsdf::sample`.
+#[derive(Debug, Copy, Clone)]
+pub struct BsdfSample {
+    /// The chosen direction from which light arrives.
+    pub dir: UnitVec3,
+    /// The value of `Bsdf::evaluate` for this direction.
+    pub value: Color,
+    /// The probability density of choosing `dir`.
+    pub pdf: Float,
+}
+
+/// Trait for shading models, i.e. how a surface reflects the light arriving at it.
+///
+/// All directions point away from the surface: `view_dir` to the viewer
+/// and `light_dir` to where the light comes from.
+/// The normal is on the side of the viewer.
+/// Mirror reflection and refraction are handled by the renderer
+/// using the `reflectance` and `refractivity` of the `Material`.
+///
+/// `Material` implements this trait with its Phong-like model.
+/// Other models can be used by setting `Material::bsdf`:
+///
+/// ```
+/// use raydiancy::raytrace::*;
+///
+/// /// Reflects more light at grazing angles, like velvet.
+/// struct Velvet;
+///
+/// impl Bsdf for Velvet {
+///     fn evaluate(&self, view_dir: UnitVec3, light_dir: UnitVec3, normal: UnitVec3) -> Color {
+///         let sheen = 1. - (view_dir * normal).max(0.);
+///         ((light_dir * normal).max(0.) * sheen) * Color::new(0.6, 0.1, 0.2)
+///     }
+/// }
+///
+/// static VELVET: Velvet = Velvet;
+/// let material = Material { bsdf: Some(&VELVET), ..color_material(white()) };
+/// assert!(validate_material(&material).is_empty());
+/// let n = Vec3::e3();
+/// let grazing = Vec3::new(1.0, 0.0, 0.1).normalize();
+/// assert!(VELVET.evaluate(grazing, n, n).red() > VELVET.evaluate(n, n, n).red());
+/// ```
+pub trait Bsdf {
+    /// Returns the fraction of the light arriving from `light_dir` that is reflected
+    /// towards `view_dir`, including the cosine factor of the light direction.
+    fn evaluate(&self, view_dir: UnitVec3, light_dir: UnitVec3, normal: UnitVec3) -> Color;
+
+    /// Chooses a direction from which light arrives, e.g. for indirect lighting.
+    /// `u` are two random numbers, uniformly distributed in [0, 1).
+    ///
+    /// The default implementation chooses directions proportional to the cosine factor
+    /// in the hemisphere around the normal.
+    fn sample(&self, view_dir: UnitVec3, normal: UnitVec3, u: Vec2) -> Option<BsdfSample> {
+        let dir = cosine_weighted_direction(normal, u);
+        let pdf = self.pdf(view_dir, dir, normal);
+        if !(pdf > 0.) {
+            return None;
+        }
+        Some(BsdfSample {
+            dir: dir,
+            value: self.evaluate(view_dir, dir, normal),
+            pdf: pdf,
+        })
+    }
+
+    /// Returns the probability density with which `sample` chooses `light_dir`.
+    fn pdf(&self, _view_dir: UnitVec3, light_dir: UnitVec3, normal: UnitVec3) -> Float {
+        (light_dir * normal).max(0.) / PI
+    }
+
+    /// Returns the light emitted by the surface towards `view_dir`.
+    fn emission(&self, _view_dir: UnitVec3, _normal: UnitVec3) -> Color {
+        black()
+    }
+}
+
+impl<'a> fmt::Debug for Bsdf + Sync + 'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Bsdf")
+    }
+}
+
+/// Returns a direction in the hemisphere around the normal,
+/// chosen with a density proportional to the cosine of the angle to the normal.
+/// `u` are two random numbers, uniformly distributed in [0, 1).
+pub fn cosine_weighted_direction(normal: UnitVec3, u: Vec2) -> UnitVec3 {
+    let r = u.x().sqrt();
+    let phi = 2. * PI * u.y();
+    let z = (1. - u.x()).max(0.).sqrt();
+    OrthonormalBasis::from_normal(normal).to_world(Vec3::new(r * phi.cos(), r * phi.sin(), z))
+        .normalize()
+}
+
+impl Bsdf for Material {
+    fn evaluate(&self, view_dir: UnitVec3, light_dir: UnitVec3, normal: UnitVec3) -> Color {
+        let diffuse_factor = match self.diffuse_model {
+            DiffuseModel::Lambert => Float::max(0.0, light_dir * normal),
+            DiffuseModel::OrenNayar { roughness } => {
+                oren_nayar(view_dir, light_dir, normal, roughness)
+            }
+            DiffuseModel::Subsurface { wrap, .. } => wrap_lighting(light_dir, normal, wrap),
+        };
+        let specular = match self.specular_model {
+            SpecularModel::BlinnPhong => {
+                self.specular * compute_specular(view_dir, light_dir, normal, self.shininess)
+            }
+            SpecularModel::Ggx { roughness, f0 } => {
+                ggx_specular(view_dir, light_dir, normal, roughness, f0)
+            }
+        };
+        self.diffuse * diffuse_factor * self.color + specular * white()
+    }
+
+    fn emission(&self, _view_dir: UnitVec3, _normal: UnitVec3) -> Color {
+        self.emission
+    }
+}
@@ -1,47 +1,415 @@
+use error::{Error, Result};
+use lin_alg::Float;
+use std::cell::Cell;
 use std::ops;
 
-/// The gamma value used for gamma correction.
-const GAMMA_VALUE: f64 = 2.2;
+/// The gamma value used for gamma correction by default.
+pub const GAMMA_VALUE: Float = 2.2;
 
-fn is_in_unit_interval(x: f64) -> bool {
+/// The function used for encoding linear color values when writing images.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TransferFunction {
+    /// Stores the linear values unchanged.
+    Linear,
+    /// Simple gamma correction `x^(1/gamma)` with the given gamma value.
+    Gamma(Float),
+    /// The piecewise transfer function of the sRGB standard.
+    Srgb,
+}
+
+impl TransferFunction {
+    /// Decodes an encoded value back to a linear value. Negative values are treated as 0.
+    pub fn decode(&self, x: Float) -> Float {
+        let x = x.max(0.);
+        match *self {
+            TransferFunction::Linear => x,
+            TransferFunction::Gamma(gamma) => x.powf(gamma),
+            TransferFunction::Srgb => {
+                if x <= 0.04045 {
+                    x / 12.92
+                } else {
+                    ((x + 0.055) / 1.055).powf(2.4)
+                }
+            }
+        }
+    }
+
+    /// Encodes a linear value. Negative values are treated as 0.
+    ///
+    /// ```
+    /// use raydiancy::color::TransferFunction;
+    /// assert_eq!(TransferFunction::Linear.encode(0.5), 0.5);
+    /// assert_eq!(TransferFunction::Gamma(2.).encode(0.25), 0.5);
+    /// assert!((TransferFunction::Srgb.encode(0.2140) - 0.5).abs() < 1e-3);
+    /// ```
+    pub fn encode(&self, x: Float) -> Float {
+        // NaN is kept, so that the conversion to 8 bits can count it.
+        let x = if x < 0. { 0. } else { x };
+        match *self {
+            TransferFunction::Linear => x,
+            TransferFunction::Gamma(gamma) => x.powf(1. / gamma),
+            TransferFunction::Srgb => {
+                if x <= 0.0031308 {
+                    12.92 * x
+                } else {
+                    1.055 * x.powf(1. / 2.4) - 0.055
+                }
+            }
+        }
+    }
+}
+
+/// Uses gamma correction with the gamma value 2.2.
+impl Default for TransferFunction {
+    fn default() -> TransferFunction {
+        TransferFunction::Gamma(GAMMA_VALUE)
+    }
+}
+
+/// The noise added to the encoded colors of the pixels before they are quantized to 8 bits.
+/// It breaks up the banding in smooth gradients, e.g. light falling off on a wall.
+///
+/// The noise depends only on the position of the pixel, so writing an image twice gives the
+/// same result. Black and white and colors beyond are not dithered.
+///
+/// ```
+/// use raydiancy::color::*;
+/// let gray = Color::new(0.5, 0.5, 0.5);
+/// let undithered = gray.to_rgb_dithered(TransferFunction::Linear, Dither::None, (0, 0));
+/// assert_eq!(undithered, gray.to_rgb_with(TransferFunction::Linear));
+/// // Averaged over many pixels, the dithered values match the color more closely.
+/// let gray = Color::new_gray(100.3 / 255.);
+/// for &dither in &[Dither::Triangular, Dither::BlueNoise] {
+///     let mut sum = 0;
+///     for y in 0..32 {
+///         for x in 0..32 {
+///             let (r, _, _) = gray.to_rgb_dithered(TransferFunction::Linear, dither, (x, y));
+///             assert!(r >= 99 && r <= 101);
+///             sum += r as u32;
+///         }
+///     }
+///     assert!((sum as f64 / 1024. - 100.3).abs() < 0.1, "{:?}", dither);
+/// }
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Dither {
+    /// No noise, so smooth gradients may show bands.
+    None,
+    /// White noise with a triangular distribution between -1 and 1 steps, which makes the error
+    /// independent of the color.
+    Triangular,
+    /// Like `Triangular`, but with a pattern (the R2 sequence over the pixels) whose noise is
+    /// mostly of high frequency like blue noise, so it is less visible.
+    BlueNoise,
+}
+
+impl Dither {
+    /// Returns the noise for the given channel (0 to 2) of the pixel (x, y),
+    /// in steps of the quantization between -1 and 1.
+    pub fn offset(&self, pixel: (usize, usize), channel: usize) -> Float {
+        let (x, y) = (pixel.0 as u32, pixel.1 as u32);
+        match *self {
+            Dither::None => 0.,
+            Dither::Triangular => {
+                let seed = x.wrapping_mul(0x9e3779b9) ^ y.wrapping_mul(0x85ebca6b) ^
+                           (channel as u32).wrapping_mul(0xc2b2ae35);
+                let (u, v) = (hash(seed), hash(seed ^ 0x68e31da4));
+                (u as Float + v as Float) / 4294967296. - 1.
+            }
+            Dither::BlueNoise => {
+                // The generalized golden ratios in two dimensions,
+                // and the golden ratio to decorrelate the channels.
+                let u = 0.5 + 0.7548776662466927 * x as Float + 0.5698402909980532 * y as Float +
+                        0.6180339887498949 * channel as Float;
+                let u = u.fract();
+                // Changes the uniform distribution to a triangular one.
+                if u < 0.5 {
+                    (2. * u).sqrt() - 1.
+                } else {
+                    1. - (2. - 2. * u).sqrt()
+                }
+            }
+        }
+    }
+}
+
+/// Dithers the encoded 8-bit output by a blue noise pattern.
+impl Default for Dither {
+    fn default() -> Dither {
+        Dither::BlueNoise
+    }
+}
+
+/// Mixes the bits of `x` (a variant of the hash function by Chris Wellons).
+fn hash(x: u32) -> u32 {
+    let mut x = x;
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x7feb352d);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x846ca68b);
+    x ^ (x >> 16)
+}
+
+fn is_in_unit_interval(x: Float) -> bool {
     0. <= x && x <= 1.
 }
 
 /// Represents an RGB color, each channel ranges between 0.0 and 1.0.
-#[derive(Debug, Copy, Clone)]
+///
+/// Note that the arithmetic operations do not check the range,
+/// so intermediate results may leave it or even become non-finite.
+/// Values outside the range are clamped when the color is converted to RGBA.
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct Color {
-    r: f64,
-    g: f64,
-    b: f64,
+    r: Float,
+    g: Float,
+    b: Float,
 }
 
 impl Color {
     /// Creates a new (opaque) Color given the red, green, blue values.
-    pub fn new(r: f64, g: f64, b: f64) -> Color {
-        assert!(is_in_unit_interval(r) && is_in_unit_interval(g) && is_in_unit_interval(b));
+    /// Panics if a value is outside of the range from 0 to 1, see `try_new`.
+    pub fn new(r: Float, g: Float, b: Float) -> Color {
+        match Color::try_new(r, g, b) {
+            Ok(c) => c,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Creates a new (opaque) Color given the red, green, blue values.
+    /// Returns an error if a value is outside of the range from 0 to 1.
+    ///
+    /// ```
+    /// use raydiancy::color::*;
+    /// assert!(Color::try_new(0.2, 0.4, 1.0).is_ok());
+    /// assert!(Color::try_new(0.2, 1.5, 1.0).is_err());
+    /// ```
+    pub fn try_new(r: Float, g: Float, b: Float) -> Result<Color> {
+        for &v in [r, g, b].iter() {
+            if !is_in_unit_interval(v) {
+                return Err(Error::ColorOutOfRange { value: v });
+            }
+        }
+        Ok(Color { r: r, g: g, b: b })
+    }
+
+    /// Creates a new color without checking the range of the channels.
+    /// This is useful for colors brighter than white or for storing other data, like normals.
+    pub fn new_unchecked(r: Float, g: Float, b: Float) -> Color {
         Color { r: r, g: g, b: b }
     }
 
+    /// Creates a color from hue (in degrees), saturation and value (between 0 and 1).
+    /// Saturation and value outside of this range are clamped, and a hue that is not finite
+    /// is treated as 0.
+    ///
+    /// ```
+    /// use raydiancy::color::*;
+    /// use raydiancy::lin_alg::Float;
+    /// let c = Color::from_hsv(120., 1., 0.5);
+    /// assert_eq!((c.red(), c.green(), c.blue()), (0., 0.5, 0.));
+    /// let c = Color::from_hsv(Float::NAN, 2., 1.5);
+    /// assert_eq!((c.red(), c.green(), c.blue()), (1., 0., 0.));
+    /// assert_eq!(Color::from_hsv(Float::INFINITY, -1., 0.5).red(), 0.5);
+    /// ```
+    pub fn from_hsv(hue: Float, saturation: Float, value: Float) -> Color {
+        let hue = if hue.is_finite() { hue } else { 0. };
+        let saturation = saturation.max(0.).min(1.);
+        let value = value.max(0.).min(1.);
+        let h = (hue % 360. + 360.) % 360. / 60.;
+        let chroma = value * saturation;
+        let x = chroma * (1. - (h % 2. - 1.).abs());
+        let (r, g, b) = match h as u32 {
+            0 => (chroma, x, 0.),
+            1 => (x, chroma, 0.),
+            2 => (0., chroma, x),
+            3 => (0., x, chroma),
+            4 => (x, 0., chroma),
+            _ => (chroma, 0., x),
+        };
+        let m = value - chroma;
+        // Rounding may leave a channel just outside of [0, 1].
+        let clamp = |c: Float| c.max(0.).min(1.);
+        Color::new_unchecked(clamp(r + m), clamp(g + m), clamp(b + m))
+    }
+
+    /// Parses a color in the hexadecimal notation "#rrggbb" (the "#" is optional).
+    /// Since such colors are usually given in sRGB, they are converted to linear values.
+    /// Returns `None` if the string is not of this form.
+    ///
+    /// ```
+    /// use raydiancy::color::*;
+    /// let c = Color::from_hex("#ff8000").unwrap();
+    /// assert_eq!(c.red(), 1.);
+    /// assert!((c.green() - 0.2158).abs() < 1e-3);
+    /// assert_eq!(c.blue(), 0.);
+    /// assert!(Color::from_hex("#ff80").is_none());
+    /// ```
+    pub fn from_hex(hex: &str) -> Option<Color> {
+        let hex = if hex.starts_with('#') { &hex[1..] } else { hex };
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let mut channels = [0.; 3];
+        for i in 0..3 {
+            match u8::from_str_radix(&hex[2 * i..2 * i + 2], 16) {
+                Ok(v) => channels[i] = TransferFunction::Srgb.decode(v as Float / 255.),
+                Err(_) => return None,
+            }
+        }
+        Some(Color::new(channels[0], channels[1], channels[2]))
+    }
+
+    /// Approximates the color of a black body of the given temperature in Kelvin,
+    /// e.g. 2700 for warm incandescent light or 6500 for daylight.
+    /// The result is normalized such that the brightest channel is 1.
+    ///
+    /// The approximation by Tanner Helland is accurate enough for temperatures
+    /// between 1000 and 40000 Kelvin.
+    pub fn from_temperature(kelvin: Float) -> Color {
+        let t = kelvin.max(1000.).min(40000.) / 100.;
+        let r = if t <= 66. {
+            255.
+        } else {
+            329.698727446 * (t - 60.).powf(-0.1332047592)
+        };
+        let g = if t <= 66. {
+            99.4708025861 * t.ln() - 161.1195681661
+        } else {
+            288.1221695283 * (t - 60.).powf(-0.0755148492)
+        };
+        let b = if t >= 66. {
+            255.
+        } else if t <= 19. {
+            0.
+        } else {
+            138.5177312231 * (t - 10.).ln() - 305.0447927307
+        };
+        let decode = |x: Float| TransferFunction::Srgb.decode(x.max(0.).min(255.) / 255.);
+        let c = Color::new(decode(r), decode(g), decode(b));
+        (1. / c.max_channel()) * c
+    }
+
+    /// Interpolates linearly between this color (`t = 0`) and `other` (`t = 1`).
+    ///
+    /// ```
+    /// use raydiancy::color::*;
+    /// let c = black().lerp(white(), 0.25);
+    /// assert_eq!(c.green(), 0.25);
+    /// ```
+    pub fn lerp(&self, other: Color, t: Float) -> Color {
+        (1. - t) * *self + t * other
+    }
+
     /// Creates a gray color with the given brightness. 0 -> black, 1 -> white.
-    pub fn new_gray(b: f64) -> Color {
+    pub fn new_gray(b: Float) -> Color {
         Color::new(b, b, b)
     }
 
     /// Returns the red channel of the color.
-    pub fn red(&self) -> f64 {
+    pub fn red(&self) -> Float {
         self.r
     }
 
     /// Returns the green channel of the color.
-    pub fn green(&self) -> f64 {
+    pub fn green(&self) -> Float {
         self.g
     }
 
     /// Returns the blue channel of the color.
-    pub fn blue(&self) -> f64 {
+    pub fn blue(&self) -> Float {
         self.b
     }
 
+    /// Returns `true` if no channel is NaN or infinite.
+    pub fn is_finite(&self) -> bool {
+        self.r.is_finite() && self.g.is_finite() && self.b.is_finite()
+    }
+
+    /// Returns the largest of the three channels.
+    pub fn max_channel(&self) -> Float {
+        self.r.max(self.g).max(self.b)
+    }
+
+    /// Returns the relative luminance (brightness perceived by humans) of the color.
+    pub fn luminance(&self) -> Float {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    /// Estimates the correlated color temperature in Kelvin, i.e. the temperature of the
+    /// black body whose color is closest to this one (McCamy's approximation).
+    /// Returns `None` for black.
+    ///
+    /// The approximation is accurate for colors close to white light between about
+    /// 2000 and 12000 Kelvin.
+    ///
+    /// ```
+    /// use raydiancy::color::*;
+    /// let warm = Color::from_temperature(3000.).correlated_color_temperature().unwrap();
+    /// let cold = Color::from_temperature(8000.).correlated_color_temperature().unwrap();
+    /// assert!((warm - 3000.).abs() < 300. && (cold - 8000.).abs() < 800.);
+    /// assert!(black().correlated_color_temperature().is_none());
+    /// ```
+    pub fn correlated_color_temperature(&self) -> Option<Float> {
+        let x = 0.4124 * self.r + 0.3576 * self.g + 0.1805 * self.b;
+        let y = self.luminance();
+        let z = 0.0193 * self.r + 0.1192 * self.g + 0.9505 * self.b;
+        let sum = x + y + z;
+        if !(sum > 0.) {
+            return None;
+        }
+        let n = (x / sum - 0.3320) / (y / sum - 0.1858);
+        Some(((-449. * n + 3525.) * n - 6823.3) * n + 5520.33)
+    }
+
+    /// Computes the fraction of light transmitted through a distance `d` of a medium
+    /// with the given absorption coefficients (Beer-Lambert law).
+    /// Negative or NaN coefficients are treated as 0, i.e. such a medium absorbs nothing.
+    ///
+    /// ```
+    /// use raydiancy::color::*;
+    /// use raydiancy::lin_alg::Float;
+    /// let absorption = Color::new_unchecked(-1., 1., Float::NAN);
+    /// let t = Color::transmittance(absorption, 2.);
+    /// assert_eq!((t.red(), t.blue()), (1., 1.));
+    /// assert!((t.green() - Float::exp(-2.)).abs() < 1e-6);
+    /// ```
+    pub fn transmittance(absorption: Color, d: Float) -> Color {
+        let channel = |a: Float| (-a.max(0.) * d).exp();
+        Color::new_unchecked(channel(absorption.r), channel(absorption.g), channel(absorption.b))
+    }
+
+    /// Converts the color to RGB, encoding the channels with the given transfer function.
+    pub fn to_rgb_with(&self, transfer: TransferFunction) -> (u8, u8, u8) {
+        (to_u8(transfer.encode(self.r)),
+         to_u8(transfer.encode(self.g)),
+         to_u8(transfer.encode(self.b)))
+    }
+
+    /// Converts the color of the given pixel to RGB like `to_rgb_with`,
+    /// but rounds the encoded channels after adding the noise of the dither.
+    /// Without dither, the result is the same as the one of `to_rgb_with`.
+    pub fn to_rgb_dithered(&self,
+                           transfer: TransferFunction,
+                           dither: Dither,
+                           pixel: (usize, usize))
+                           -> (u8, u8, u8) {
+        if dither == Dither::None {
+            return self.to_rgb_with(transfer);
+        }
+        let channel = |x: Float, i| to_u8_dithered(transfer.encode(x), dither.offset(pixel, i));
+        (channel(self.r, 0), channel(self.g, 1), channel(self.b, 2))
+    }
+
+    /// Converts to a color with the given transparency.
+    /// The color is not multiplied by the opacity, so it is expected to be premultiplied already.
+    pub fn with_transparency(self, transparency: Float) -> AColor {
+        AColor {
+            c: self,
+            a: transparency,
+        }
+    }
+
     /// Convert to an opaque color with alpha channel.
     pub fn with_alpha(self) -> AColor {
         AColor { c: self, a: 0.0 }
@@ -52,15 +420,23 @@ impl ops::Add for Color {
     type Output = Color;
 
     fn add(self, c: Color) -> Color {
-        Color::new(self.r + c.r, self.g + c.g, self.b + c.b)
+        Color {
+            r: self.r + c.r,
+            g: self.g + c.g,
+            b: self.b + c.b,
+        }
     }
 }
 
-impl ops::Mul<Color> for f64 {
+impl ops::Mul<Color> for Float {
     type Output = Color;
 
     fn mul(self, c: Color) -> Color {
-        Color::new(self * c.r, self * c.g, self * c.b)
+        Color {
+            r: self * c.r,
+            g: self * c.g,
+            b: self * c.b,
+        }
     }
 }
 
@@ -68,7 +444,11 @@ impl ops::Mul<Color> for Color {
     type Output = Color;
 
     fn mul(self, c: Color) -> Color {
-        Color::new(self.r * c.r, self.g * c.g, self.b * c.b)
+        Color {
+            r: self.r * c.r,
+            g: self.g * c.g,
+            b: self.b * c.b,
+        }
     }
 }
 
@@ -82,21 +462,63 @@ pub fn white() -> Color {
     Color::new(1., 1., 1.)
 }
 
-/// Represents an RGB color with transparency.
-/// For a background color b, the final color is `c + a * b`.
+/// Maps a value from 0 to 1 to a color for visualizations: 0 to blue, 1 to red, and the values
+/// in between to cyan, green, and yellow. Values outside are clamped.
+///
+/// ```
+/// use raydiancy::color::*;
+/// assert_eq!(false_color(0.0).blue(), 1.0);
+/// assert_eq!(false_color(0.5).green(), 1.0);
+/// assert_eq!(false_color(2.0).red(), 1.0);
+/// ```
+pub fn false_color(value: Float) -> Color {
+    let stops = [(0., 0., 1.), (0., 1., 1.), (0., 1., 0.), (1., 1., 0.), (1., 0., 0.)];
+    let x = value.max(0.).min(1.) * 4.;
+    let i = (x as usize).min(3);
+    let f = x - i as Float;
+    let (a, b) = (stops[i], stops[i + 1]);
+    Color::new(a.0 + f * (b.0 - a.0), a.1 + f * (b.1 - a.1), a.2 + f * (b.2 - a.2))
+}
+
+/// Represents an RGB color with transparency, using premultiplied alpha.
+///
+/// The color `c` is already weighted by the opacity and `a` is the transparency,
+/// so for a background color b, the final color is `c + a * b`.
+/// Adding two colors sums their contributions, e.g. of several rays;
+/// the transparency saturates at 1. `over` stacks one color in front of another.
+///
+/// ```
+/// use raydiancy::color::*;
+/// let half = 0.5 * AColor::new(1., 0., 0.) + 0.5 * AColor::transparent();
+/// assert_eq!(half.transparency(), 0.5);
+/// // The straight (not premultiplied) color is used for output:
+/// assert_eq!(half.to_rgba_with(TransferFunction::Linear), (255, 0, 0, 127));
+/// // Stacking two half transparent layers leaves a quarter of the background visible:
+/// let blue = 0.5 * AColor::new(0., 0., 1.) + 0.5 * AColor::transparent();
+/// let stacked = half.over(blue);
+/// assert_eq!(stacked.transparency(), 0.25);
+/// assert_eq!(stacked.opaque().red(), 0.5);
+/// assert_eq!(stacked.opaque().blue(), 0.25);
+/// // Opaque colors hide everything behind them, transparent ones nothing:
+/// assert_eq!(AColor::new(0., 1., 0.).over(blue).opaque().blue(), 0.);
+/// assert_eq!(AColor::transparent().over(blue).transparency(), 0.5);
+/// // The transparency saturates:
+/// assert_eq!((AColor::transparent() + AColor::transparent()).transparency(), 1.);
+/// assert_eq!(AColor::transparent().to_rgba(), (0, 0, 0, 0));
+/// ```
 #[derive(Debug, Copy, Clone)]
 pub struct AColor {
     c: Color,
-    a: f64,
+    a: Float,
 }
 
 impl AColor {
     /// Creates a new (opaque) AColor given the red, green, blue values.
-    pub fn new(r: f64, g: f64, b: f64) -> AColor {
+    pub fn new(r: Float, g: Float, b: Float) -> AColor {
         AColor::newa(r, g, b, 0.0)
     }
 
-    fn newa(r: f64, g: f64, b: f64, a: f64) -> AColor {
+    fn newa(r: Float, g: Float, b: Float, a: Float) -> AColor {
         assert!(r + a <= 1.0 && g + a <= 1.0 && b + a <= 1.0);
         AColor {
             c: Color::new(r, g, b),
@@ -110,40 +532,130 @@ impl AColor {
     }
 
     /// Returns the transparency of the color. 0 means opaque, 1 means transparent.
-    pub fn transparency(&self) -> f64 {
+    pub fn transparency(&self) -> Float {
         self.a
     }
 
+    /// Returns `true` if neither the color nor the transparency is NaN or infinite.
+    pub fn is_finite(&self) -> bool {
+        self.c.is_finite() && self.a.is_finite()
+    }
+
+    /// Scales the color down such that no channel exceeds `max`, keeping its hue.
+    /// The transparency is unchanged.
+    pub fn clamp_radiance(&self, max: Float) -> AColor {
+        let m = self.c.max_channel();
+        if m <= max {
+            return *self;
+        }
+        AColor {
+            c: (max / m) * self.c,
+            a: self.a,
+        }
+    }
+
+    /// Multiplies the color by `factor`, leaving the transparency unchanged.
+    pub fn scale_radiance(&self, factor: Float) -> AColor {
+        AColor {
+            c: factor * self.c,
+            a: self.a,
+        }
+    }
+
+    /// Multiplies the color channels by those of `filter`, leaving the transparency unchanged.
+    pub fn filter_radiance(&self, filter: Color) -> AColor {
+        AColor {
+            c: filter * self.c,
+            a: self.a,
+        }
+    }
+
+    /// Multiplies the color channels by those of `weight` and the transparency by their
+    /// average, like `Float * AColor` does for a single factor. This is how the result of
+    /// a secondary ray contributes, e.g. of a reflection that tints what it shows.
+    pub fn weighted(&self, weight: Color) -> AColor {
+        AColor {
+            c: weight * self.c,
+            a: (weight.r + weight.g + weight.b) / 3. * self.a,
+        }
+    }
+
+    /// Puts this color in front of `below`, which is seen through the transparent part.
+    pub fn over(&self, below: AColor) -> AColor {
+        AColor {
+            c: self.c + self.a * below.c,
+            a: self.a * below.a,
+        }
+    }
+
+    /// Returns the final color in front of the given (opaque) background.
+    pub fn over_background(&self, background: Color) -> Color {
+        self.c + self.a * background
+    }
+
     /// Creates a AColor that is completely transparent.
     pub fn transparent() -> AColor {
         AColor::newa(0.0, 0.0, 0.0, 1.0)
     }
 
-    /// Converts the color to RGBA.
+    /// Converts the color to RGBA using the default gamma correction.
     pub fn to_rgba(&self) -> (u8, u8, u8, u8) {
-        if self.a == 1. {
-            return (0, 0, 0, 0);
+        self.to_rgba_with(TransferFunction::default())
+    }
+
+    /// Converts the color to RGBA, encoding the color channels with the given transfer function.
+    ///
+    /// The channels are divided by the opacity since RGBA images do not use premultiplied alpha.
+    pub fn to_rgba_with(&self, transfer: TransferFunction) -> (u8, u8, u8, u8) {
+        let opacity = 1. - self.a;
+        if !(opacity > 0.) {
+            return (0, 0, 0, to_u8(opacity));
         }
-        let c = (1. - self.a) * self.c;
-        (to_u8(gamma_correct(c.r)),
-         to_u8(gamma_correct(c.g)),
-         to_u8(gamma_correct(c.b)),
-         0xff - to_u8(self.a))
+        let (r, g, b) = ((1. / opacity) * self.c).to_rgb_with(transfer);
+        (r, g, b, to_u8(opacity))
+    }
+
+    /// Converts the color of the given pixel to RGBA like `to_rgba_with`,
+    /// but dithers the color channels, see `Color::to_rgb_dithered`.
+    pub fn to_rgba_dithered(&self,
+                            transfer: TransferFunction,
+                            dither: Dither,
+                            pixel: (usize, usize))
+                            -> (u8, u8, u8, u8) {
+        let opacity = 1. - self.a;
+        if !(opacity > 0.) {
+            return (0, 0, 0, to_u8(opacity));
+        }
+        let (r, g, b) = ((1. / opacity) * self.c).to_rgb_dithered(transfer, dither, pixel);
+        (r, g, b, to_u8(opacity))
     }
 }
 
+/// Sums the contributions of both colors. The transparency saturates at 1.
 impl ops::Add for AColor {
     type Output = AColor;
 
     fn add(self, c: AColor) -> AColor {
         AColor {
             c: self.c + c.c,
-            a: self.a + c.a,
+            a: (self.a + c.a).min(1.),
         }
     }
 }
 
-impl ops::Mul<AColor> for f64 {
+/// Filters the color channels, leaving the transparency unchanged.
+impl ops::Mul<AColor> for Color {
+    type Output = AColor;
+
+    fn mul(self, c: AColor) -> AColor {
+        AColor {
+            c: self * c.c,
+            a: c.a,
+        }
+    }
+}
+
+impl ops::Mul<AColor> for Float {
     type Output = AColor;
 
     fn mul(self, c: AColor) -> AColor {
@@ -154,13 +666,48 @@ impl ops::Mul<AColor> for f64 {
     }
 }
 
+thread_local! {
+    /// The number of NaN channels converted to 8 bits on this thread.
+    static NAN_CONVERSIONS: Cell<usize> = Cell::new(0);
+}
+
+/// Returns the number of NaN channels that were converted to 8 bits (as 0) on the current
+/// thread since the last call, e.g. to warn that an image contains invalid colors.
+///
+/// ```
+/// use raydiancy::color::*;
+/// use raydiancy::lin_alg::Float;
+/// take_nan_conversions();
+/// let nan = Color::new_unchecked(0.5, 0.5, 1.0).with_transparency(Float::NAN);
+/// assert_eq!(nan.to_rgba_with(TransferFunction::Linear), (0, 0, 0, 0));
+/// assert_eq!(take_nan_conversions(), 1);
+/// let nan = Color::new_unchecked(0.5, Float::NAN, 1.0);
+/// assert_eq!(nan.to_rgb_with(TransferFunction::Linear), (127, 0, 255));
+/// let dithered = nan.to_rgb_dithered(TransferFunction::Srgb, Dither::BlueNoise, (3, 4));
+/// assert_eq!((dithered.1, dithered.2), (0, 255));
+/// assert_eq!(take_nan_conversions(), 2);
+/// assert_eq!(take_nan_conversions(), 0);
+/// ```
+pub fn take_nan_conversions() -> usize {
+    NAN_CONVERSIONS.with(|count| count.replace(0))
+}
+
 /// Converts a floating point value between 0 and 1 to an integer between 0 and 255.
-fn to_u8(x: f64) -> u8 {
-    assert!(!x.is_nan());
-    (x * 255.0) as u8
+/// Values outside this range are clamped. NaN becomes 0 and is counted,
+/// see `take_nan_conversions`.
+fn to_u8(x: Float) -> u8 {
+    if x.is_nan() {
+        NAN_CONVERSIONS.with(|count| count.set(count.get() + 1));
+        return 0;
+    }
+    (x.max(0.).min(1.) * 255.0) as u8
 }
 
-/// Applies standard gamma correction (2.2) to the AColor.
-fn gamma_correct(x: f64) -> f64 {
-    x.powf(1. / GAMMA_VALUE)
+/// Rounds the value in [0, 1] to 8 bits after adding `offset` steps.
+/// The extremes stay exact, and NaN is handled like in `to_u8`.
+fn to_u8_dithered(x: Float, offset: Float) -> u8 {
+    if !(x > 0. && x < 1.) {
+        return to_u8(x);
+    }
+    (x * 255.0 + 0.5 + offset).max(0.).min(255.) as u8
 }
@@ -0,0 +1,98 @@
+use color::*;
+use img_output::*;
+use lin_alg::Float;
+use passes::*;
+
+/// The weights of the B3 spline kernel used by the à-trous wavelet filter.
+const KERNEL: [Float; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+
+/// Options for the denoiser.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DenoiseOptions {
+    /// The number of filter passes. Each pass doubles the size of the filter,
+    /// so 5 passes cover a radius of 62 pixels.
+    pub iterations: usize,
+    /// How strongly differences in color stop the filter.
+    /// Larger values blur more. It is halved with every pass.
+    pub sigma_color: Float,
+    /// How strongly differences in the normals stop the filter (edges of objects).
+    pub sigma_normal: Float,
+    /// How strongly differences in the albedo stop the filter (edges of textures).
+    pub sigma_albedo: Float,
+    /// How strongly relative differences in depth stop the filter (silhouettes).
+    pub sigma_depth: Float,
+}
+
+impl Default for DenoiseOptions {
+    fn default() -> DenoiseOptions {
+        DenoiseOptions {
+            iterations: 5,
+            sigma_color: 1.0,
+            sigma_normal: 0.1,
+            sigma_albedo: 0.1,
+            sigma_depth: 0.1,
+        }
+    }
+}
+
+/// Returns the squared distance of the colors and transparencies.
+fn distance2(a: AColor, b: AColor) -> Float {
+    let (c, d) = (a.opaque(), b.opaque());
+    let dr = c.red() - d.red();
+    let dg = c.green() - d.green();
+    let db = c.blue() - d.blue();
+    let da = a.transparency() - b.transparency();
+    dr * dr + dg * dg + db * db + da * da
+}
+
+/// Removes noise from a rendered image using the edge-avoiding à-trous wavelet filter
+/// (Dammertz et al.).
+///
+/// The filter averages neighboring pixels, but the normal, albedo, and depth passes
+/// of the primary rays keep it from blurring across edges and textures.
+/// Pixels whose neighbors all get a weight of 0, e.g. because a sigma is 0, are kept as they are.
+pub fn denoise(img: &Image, passes: &RenderPasses, options: &DenoiseOptions) -> Image {
+    let (width, height) = (img.width, img.height);
+    let mut current = img.clone();
+    for iteration in 0..options.iterations {
+        let step = 1 << iteration;
+        let sigma_color = options.sigma_color / (1 << iteration) as Float;
+        let mut next = Image::new(width, height);
+        for (x, y, col) in next.iter_mut() {
+            let center = current.get(x, y);
+            let normal = passes.normal.get(x, y);
+            let albedo = passes.albedo.get(x, y);
+            let depth = passes.depth.get(x, y).opaque().red();
+            let mut sum = AColor::new(0., 0., 0.);
+            let mut total_weight = 0.;
+            for (i, &kx) in KERNEL.iter().enumerate() {
+                for (j, &ky) in KERNEL.iter().enumerate() {
+                    let qx = x as isize + (i as isize - 2) * step;
+                    let qy = y as isize + (j as isize - 2) * step;
+                    if qx < 0 || qy < 0 || qx >= width as isize || qy >= height as isize {
+                        continue;
+                    }
+                    let (qx, qy) = (qx as usize, qy as usize);
+                    let q_depth = passes.depth.get(qx, qy).opaque().red();
+                    let depth_difference = (depth - q_depth) / depth.max(q_depth).max(1e-6);
+                    let sample = current.get(qx, qy);
+                    let exponent = distance2(center, sample) / (sigma_color * sigma_color) +
+                                   distance2(normal, passes.normal.get(qx, qy)) /
+                                   (options.sigma_normal * options.sigma_normal) +
+                                   distance2(albedo, passes.albedo.get(qx, qy)) /
+                                   (options.sigma_albedo * options.sigma_albedo) +
+                                   depth_difference * depth_difference /
+                                   (options.sigma_depth * options.sigma_depth);
+                    let weight = kx * ky * (-exponent).exp();
+                    sum = sum + weight * sample;
+                    total_weight += weight;
+                }
+            }
+            // The weights vanish if they underflow, and they are NaN if a sigma is 0.
+            // Then the pixel is kept as it is.
+            *col = if total_weight > 0. { (1. / total_weight) * sum } else { center };
+        }
+        current = next;
+    }
+    current
+}
@@ -0,0 +1,95 @@
+use basic::*;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The width and height of the tiles used for aggregating problems.
+pub const DIAGNOSTICS_TILE_SIZE: usize = 32;
+/// The maximum number of non-finite samples that are recorded in detail.
+/// All of them are counted, though.
+const MAX_RECORDED_SAMPLES: usize = 100;
+
+/// Describes where a non-finite (NaN or infinite) color was produced.
+#[derive(Debug, Clone)]
+pub struct NonFiniteSample {
+    /// The pixel (x, y) that the sample belongs to.
+    pub pixel: (usize, usize),
+    /// The index of the hit object in `Scene::objects`.
+    pub object: usize,
+    /// The material at the hit point.
+    pub material: Material,
+    /// The ray that hit the object.
+    pub ray: Ray,
+    /// The recursion depth of the ray (0 for primary rays).
+    pub depth: usize,
+}
+
+/// Problems detected while rendering a scene.
+///
+/// Non-finite colors are replaced by black in the rendered image.
+#[derive(Debug, Default)]
+pub struct RenderDiagnostics {
+    /// The total number of non-finite samples.
+    pub non_finite_count: usize,
+    /// The first non-finite samples (at most 100 of them).
+    pub non_finite_samples: Vec<NonFiniteSample>,
+    /// The number of non-finite samples per tile.
+    /// A tile (i, j) covers the pixels (x, y) with `x / DIAGNOSTICS_TILE_SIZE == i`
+    /// and `y / DIAGNOSTICS_TILE_SIZE == j`.
+    pub tile_counts: BTreeMap<(usize, usize), usize>,
+    /// The number of rays traced, including shadow rays, e.g. for measuring performance.
+    pub ray_count: usize,
+}
+
+impl RenderDiagnostics {
+    /// Creates an empty report.
+    pub fn new() -> RenderDiagnostics {
+        RenderDiagnostics::default()
+    }
+
+    /// Returns `true` if no problems were detected.
+    pub fn is_ok(&self) -> bool {
+        self.non_finite_count == 0
+    }
+
+    /// Records a non-finite sample.
+    pub fn record_non_finite(&mut self, sample: NonFiniteSample) {
+        self.non_finite_count += 1;
+        let tile = (sample.pixel.0 / DIAGNOSTICS_TILE_SIZE, sample.pixel.1 / DIAGNOSTICS_TILE_SIZE);
+        *self.tile_counts.entry(tile).or_insert(0) += 1;
+        if self.non_finite_samples.len() < MAX_RECORDED_SAMPLES {
+            self.non_finite_samples.push(sample);
+        }
+    }
+}
+
+impl fmt::Display for RenderDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_ok() {
+            return write!(f, "no problems detected");
+        }
+        try!(writeln!(f,
+                      "{} non-finite samples in {} tiles of {}x{} pixels",
+                      self.non_finite_count,
+                      self.tile_counts.len(),
+                      DIAGNOSTICS_TILE_SIZE,
+                      DIAGNOSTICS_TILE_SIZE));
+        for (tile, count) in self.tile_counts.iter() {
+            try!(writeln!(f, "  tile {:?}: {}", tile, count));
+        }
+        let mut counts_per_object = BTreeMap::new();
+        for sample in self.non_finite_samples.iter() {
+            *counts_per_object.entry(sample.object).or_insert(0) += 1;
+        }
+        for (object, count) in counts_per_object.iter() {
+            try!(writeln!(f, "  object #{}: {} of the recorded samples", object, count));
+        }
+        let first = &self.non_finite_samples[0];
+        write!(f,
+               "  first sample: pixel {:?}, object #{}, depth {}, {:?}, {:?}",
+               first.pixel,
+               first.object,
+               first.depth,
+               first.ray,
+               first.material)
+    }
+}
@@ -0,0 +1,364 @@
+//! Distributes the rendering of a scene over several machines.
+//!
+//! Every worker constructs the same scene itself and renders the tiles that the coordinator
+//! requests over TCP. The coordinator assembles the tiles and applies the post-processing.
+//!
+//! All messages are prefixed with their length as a big-endian `u32`.
+//! A request consists of six `u32`s: the width and height of the image including the overscan
+//! (which must match the worker's scene) and the position and dimensions of the tile.
+//! The response repeats the position and dimensions of the tile, followed by red, green, blue,
+//! and transparency of each pixel as little-endian `f32`s, line by line.
+
+extern crate simple_parallel;
+
+use color::*;
+use img_output::*;
+use lin_alg::Float;
+use raytrace::Scene;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+/// The width and height of the tiles that are sent to the workers.
+pub const DISTRIBUTED_TILE_SIZE: usize = 64;
+
+/// The length of a request.
+const REQUEST_LENGTH: usize = 24;
+
+/// The length of the response for the largest tile.
+/// Longer messages are rejected before anything is allocated for them.
+const MAX_RESPONSE_LENGTH: usize = 16 + 16 * DISTRIBUTED_TILE_SIZE * DISTRIBUTED_TILE_SIZE;
+
+/// Reading or writing fails if the other side does not respond for this long.
+/// Rendering a tile can take a while, so this is generous.
+const TIMEOUT_SECS: u64 = 600;
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_message<W: Write>(out: &mut W, payload: &[u8]) -> io::Result<()> {
+    let length = payload.len() as u32;
+    try!(out.write_all(&[(length >> 24) as u8, (length >> 16) as u8, (length >> 8) as u8,
+                         length as u8]));
+    try!(out.write_all(payload));
+    out.flush()
+}
+
+/// Reads a message of at most `max_length` bytes.
+/// Returns `None` if the connection was closed before it.
+fn read_message<R: Read>(input: &mut R, max_length: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut prefix = [0u8; 4];
+    let mut read = 0;
+    while read < 4 {
+        match try!(input.read(&mut prefix[read..])) {
+            0 if read == 0 => return Ok(None),
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete message")),
+            n => read += n,
+        }
+    }
+    let length = prefix.iter().fold(0, |acc, &b| (acc << 8) | b as usize);
+    if length > max_length {
+        return Err(invalid_data("message too long"));
+    }
+    let mut payload = vec![0; length];
+    try!(input.read_exact(&mut payload));
+    Ok(Some(payload))
+}
+
+fn push_u32(bytes: &mut Vec<u8>, v: u32) {
+    bytes.extend_from_slice(&[(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8]);
+}
+
+fn get_u32(bytes: &[u8], i: usize) -> u32 {
+    bytes[4 * i..4 * i + 4].iter().fold(0, |acc, &b| (acc << 8) | b as u32)
+}
+
+fn push_f32(bytes: &mut Vec<u8>, v: Float) {
+    let bits = (v as f32).to_bits();
+    bytes.extend_from_slice(&[bits as u8, (bits >> 8) as u8, (bits >> 16) as u8,
+                              (bits >> 24) as u8]);
+}
+
+fn get_f32(bytes: &[u8], offset: usize) -> Float {
+    let b = &bytes[offset..offset + 4];
+    let bits = b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24;
+    f32::from_bits(bits) as Float
+}
+
+fn set_timeouts(stream: &TcpStream) -> io::Result<()> {
+    let timeout = Some(Duration::from_secs(TIMEOUT_SECS));
+    try!(stream.set_read_timeout(timeout));
+    stream.set_write_timeout(timeout)
+}
+
+/// A rectangle of the image: position of the top-left pixel and dimensions.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct TileRect {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+/// Handles the requests of a coordinator until it closes the connection.
+fn serve_connection(scene: &Scene, stream: TcpStream) -> io::Result<()> {
+    try!(set_timeouts(&stream));
+    let mut input = BufReader::new(try!(stream.try_clone()));
+    let mut output = BufWriter::new(stream);
+    while let Some(request) = try!(read_message(&mut input, REQUEST_LENGTH)) {
+        if request.len() != REQUEST_LENGTH {
+            return Err(invalid_data("invalid request"));
+        }
+        let v: Vec<usize> = (0..6).map(|i| get_u32(&request, i) as usize).collect();
+        let rect = TileRect {
+            x: v[2],
+            y: v[3],
+            width: v[4],
+            height: v[5],
+        };
+        if rect.width > DISTRIBUTED_TILE_SIZE || rect.height > DISTRIBUTED_TILE_SIZE {
+            return Err(invalid_data("the tile is too large"));
+        }
+        let camera = scene.overscan_camera();
+        if v[0] != camera.width || v[1] != camera.height ||
+           rect.x + rect.width > v[0] || rect.y + rect.height > v[1] {
+            return Err(invalid_data("the tile does not fit into the image of the scene"));
+        }
+        let tile = scene.render_tile(rect.x, rect.y, rect.width, rect.height);
+        let mut response = Vec::with_capacity(16 + 16 * rect.width * rect.height);
+        for &v in [rect.x, rect.y, rect.width, rect.height].iter() {
+            push_u32(&mut response, v as u32);
+        }
+        for y in 0..rect.height {
+            for x in 0..rect.width {
+                let col = tile.get(x, y);
+                let c = col.opaque();
+                for &v in [c.red(), c.green(), c.blue(), col.transparency()].iter() {
+                    push_f32(&mut response, v);
+                }
+            }
+        }
+        try!(write_message(&mut output, &response));
+    }
+    Ok(())
+}
+
+/// Runs a worker: listens on the given address and renders the tiles requested by
+/// coordinators, one connection at a time.
+/// Failed connections are dropped, so this only returns if listening fails.
+pub fn run_worker<A: ToSocketAddrs>(scene: &Scene, addr: A) -> io::Result<()> {
+    run_worker_on(scene, try!(TcpListener::bind(addr)))
+}
+
+/// Like `run_worker`, but accepts the connections of a listener that is already bound,
+/// for example to an address with port 0, where the system chooses a free port.
+///
+/// Connections that send invalid requests are dropped, before the worker allocates memory
+/// for messages that are too long:
+///
+/// ```
+/// use raydiancy::distributed::*;
+/// use raydiancy::raytrace::*;
+/// use std::io::{Read, Write};
+/// use std::net::{TcpListener, TcpStream};
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let addr = listener.local_addr().unwrap();
+/// thread::spawn(move || {
+///     let scene = Scene::builder().resolution(8, 8).build().unwrap();
+///     run_worker_on(&scene, listener).unwrap();
+/// });
+/// let mut stream = TcpStream::connect(addr).unwrap();
+/// stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+/// // Announce a request of 16 MiB, which would be a valid length for a response.
+/// stream.write_all(&[0x01, 0x00, 0x00, 0x00]).unwrap();
+/// let mut buffer = [0; 1];
+/// assert_eq!(stream.read(&mut buffer).unwrap(), 0);
+/// ```
+pub fn run_worker_on(scene: &Scene, listener: TcpListener) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let _ = stream.and_then(|stream| serve_connection(scene, stream));
+    }
+    Ok(())
+}
+
+/// The tiles that still have to be rendered and the number of tiles that are being rendered.
+struct TileQueue {
+    state: Mutex<(Vec<TileRect>, usize)>,
+    changed: Condvar,
+}
+
+impl TileQueue {
+    fn new(tiles: Vec<TileRect>) -> TileQueue {
+        TileQueue {
+            state: Mutex::new((tiles, 0)),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Takes the next tile. If the queue is empty, waits until the tiles in flight are done,
+    /// since a failing worker may put its tile back. Returns `None` once all tiles are done.
+    fn take(&self) -> Option<TileRect> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(rect) = state.0.pop() {
+                state.1 += 1;
+                return Some(rect);
+            }
+            if state.1 == 0 {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Marks a taken tile as done, or puts it back into the queue if it failed.
+    fn finish(&self, rect: TileRect, success: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.1 -= 1;
+        if !success {
+            state.0.push(rect);
+        }
+        self.changed.notify_all();
+    }
+
+    /// The number of tiles that were not rendered.
+    fn remaining(self) -> usize {
+        self.state.into_inner().unwrap().0.len()
+    }
+}
+
+/// Sends the tiles from the queue to one worker and puts the results into the image.
+/// If the worker fails, its current tile is put back into the queue.
+fn drive_worker(addr: &str,
+                width: usize,
+                height: usize,
+                queue: &TileQueue,
+                img: &Mutex<Image>)
+                -> io::Result<()> {
+    let stream = try!(TcpStream::connect(addr));
+    try!(set_timeouts(&stream));
+    let mut input = BufReader::new(try!(stream.try_clone()));
+    let mut output = BufWriter::new(stream);
+    while let Some(rect) = queue.take() {
+        let result = render_remotely(&mut input, &mut output, width, height, rect, img);
+        queue.finish(rect, result.is_ok());
+        try!(result);
+    }
+    Ok(())
+}
+
+fn render_remotely<R: Read, W: Write>(input: &mut R,
+                                      output: &mut W,
+                                      width: usize,
+                                      height: usize,
+                                      rect: TileRect,
+                                      img: &Mutex<Image>)
+                                      -> io::Result<()> {
+    let mut request = Vec::with_capacity(REQUEST_LENGTH);
+    for &v in [width, height, rect.x, rect.y, rect.width, rect.height].iter() {
+        push_u32(&mut request, v as u32);
+    }
+    try!(write_message(output, &request));
+    let response = match try!(read_message(input, MAX_RESPONSE_LENGTH)) {
+        Some(response) => response,
+        None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "worker disconnected")),
+    };
+    if response.len() != 16 + 16 * rect.width * rect.height {
+        return Err(invalid_data("invalid response"));
+    }
+    let v: Vec<usize> = (0..4).map(|i| get_u32(&response, i) as usize).collect();
+    if v != [rect.x, rect.y, rect.width, rect.height] {
+        return Err(invalid_data("response for the wrong tile"));
+    }
+    let mut img = img.lock().unwrap();
+    for y in 0..rect.height {
+        for x in 0..rect.width {
+            let offset = 16 + 16 * (y * rect.width + x);
+            let c = Color::new_unchecked(get_f32(&response, offset),
+                                         get_f32(&response, offset + 4),
+                                         get_f32(&response, offset + 8));
+            let transparency = get_f32(&response, offset + 12);
+            img.set(rect.x + x, rect.y + y, c.with_transparency(transparency));
+        }
+    }
+    Ok(())
+}
+
+/// Renders the scene by distributing its tiles to the workers at the given addresses,
+/// which have to render the same scene (see `run_worker`).
+///
+/// Tiles of failed workers are given to the remaining ones.
+/// Fails if no worker is left before all tiles are rendered.
+///
+/// The colors are sent as `f32`s, so the image matches a local rendering up to their precision:
+///
+/// ```
+/// use raydiancy::distributed::*;
+/// use raydiancy::raytrace::*;
+/// use std::net::TcpListener;
+/// use std::thread;
+///
+/// fn scene() -> Scene {
+///     Scene::builder()
+///         .resolution(80, 70)
+///         .add(Sphere { center: Vec3::zero(), radius: 1.0, material: color_material(white()) })
+///         .light(PointLight { pos: Vec3::new(0.0, 0.0, 10.0), col: white() })
+///         .build()
+///         .unwrap()
+/// }
+///
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let addr = listener.local_addr().unwrap().to_string();
+/// thread::spawn(move || run_worker_on(&scene(), listener).unwrap());
+/// let local = scene().render();
+/// let remote = render_distributed(&scene(), &[&addr]).unwrap();
+/// for y in 0..70 {
+///     for x in 0..80 {
+///         let (a, b) = (local.get(x, y), remote.get(x, y));
+///         assert!((a.opaque().luminance() - b.opaque().luminance()).abs() < 1e-4);
+///         assert!((a.transparency() - b.transparency()).abs() < 1e-4);
+///     }
+/// }
+/// ```
+pub fn render_distributed(scene: &Scene, workers: &[&str]) -> io::Result<Image> {
+    let camera = scene.overscan_camera();
+    let (width, height) = (camera.width, camera.height);
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(DISTRIBUTED_TILE_SIZE) {
+        for x in (0..width).step_by(DISTRIBUTED_TILE_SIZE) {
+            tiles.push(TileRect {
+                x: x,
+                y: y,
+                width: DISTRIBUTED_TILE_SIZE.min(width - x),
+                height: DISTRIBUTED_TILE_SIZE.min(height - y),
+            });
+        }
+    }
+    // The tiles are popped from the end, so render them from top to bottom:
+    tiles.reverse();
+    let queue = TileQueue::new(tiles);
+    let img = Mutex::new(Image::new(width, height));
+    let failures = Mutex::new(Vec::new());
+    let mut pool = simple_parallel::pool::Pool::new(workers.len().max(1));
+    pool.for_(workers.iter(), |addr| {
+        if let Err(e) = drive_worker(addr, width, height, &queue, &img) {
+            failures.lock().unwrap().push(format!("{}: {}", addr, e));
+        }
+    });
+    let remaining = queue.remaining();
+    if remaining > 0 {
+        let failures = failures.into_inner().unwrap();
+        let message = format!("{} tiles not rendered, all {} workers failed: {}",
+                              remaining,
+                              failures.len(),
+                              failures.join("; "));
+        return Err(io::Error::new(io::ErrorKind::Other, message));
+    }
+    Ok(scene.finish_image(img.into_inner().unwrap()))
+}
@@ -0,0 +1,73 @@
+use lin_alg::Float;
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+/// The errors returned by the fallible functions of this crate.
+#[derive(Debug)]
+pub enum Error {
+    /// A color channel is outside of the range from 0 to 1, or NaN.
+    ColorOutOfRange {
+        /// The offending value.
+        value: Float,
+    },
+    /// A vector that should have norm 1 has a different norm.
+    NotUnitVector {
+        /// The norm of the vector.
+        norm: Float,
+    },
+    /// A line of a text file, e.g. an OBJ file, is malformed.
+    Parse {
+        /// The number of the line, starting at 1.
+        line: usize,
+        /// What is wrong with the line.
+        message: String,
+    },
+    /// Reading or writing failed.
+    Io(io::Error),
+    /// Encoding an image failed.
+    Image(String),
+}
+
+/// The result type of the fallible functions of this crate.
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::ColorOutOfRange { value } => {
+                write!(f, "color channel {} is outside of the range from 0 to 1", value)
+            }
+            Error::NotUnitVector { norm } => write!(f, "vector has norm {} instead of 1", norm),
+            Error::Parse { line, ref message } => write!(f, "line {}: {}", line, message),
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::Image(ref message) => write!(f, "image error: {}", message),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl From<Error> for io::Error {
+    /// Keeps I/O errors and reports the others as `InvalidData`.
+    fn from(e: Error) -> io::Error {
+        match e {
+            Error::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+        }
+    }
+}
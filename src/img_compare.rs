@@ -0,0 +1,145 @@
+use color::*;
+use img_output::*;
+use lin_alg::Float;
+
+/// The width and height of the windows used for computing the SSIM.
+const SSIM_WINDOW: usize = 8;
+/// The distance between neighboring SSIM windows.
+const SSIM_STRIDE: usize = 4;
+
+/// Returns the channels of the pixel as they are written to 8-bit files, scaled to [0, 1].
+fn display_values(img: &Image, x: usize, y: usize) -> [Float; 4] {
+    let (r, g, b, a) = img.get(x, y).to_rgba();
+    [r as Float / 255., g as Float / 255., b as Float / 255., a as Float / 255.]
+}
+
+/// Returns the brightness of the pixel as it is displayed in front of a black background.
+fn display_luma(img: &Image, x: usize, y: usize) -> Float {
+    let v = display_values(img, x, y);
+    (0.299 * v[0] + 0.587 * v[1] + 0.114 * v[2]) * v[3]
+}
+
+fn assert_same_dimensions(a: &Image, b: &Image) {
+    assert!(a.width == b.width && a.height == b.height,
+            "images of different dimensions cannot be compared");
+}
+
+/// Computes the mean squared error of the RGBA values of two images of the same dimensions.
+///
+/// The images are compared as they are written to 8-bit files, with channels scaled to [0, 1].
+/// Empty images have no differences, so their error is 0.
+pub fn mean_squared_error(a: &Image, b: &Image) -> Float {
+    assert_same_dimensions(a, b);
+    if a.width == 0 || a.height == 0 {
+        return 0.;
+    }
+    let mut sum = 0.;
+    for y in 0..a.height {
+        for x in 0..a.width {
+            let (u, v) = (display_values(a, x, y), display_values(b, x, y));
+            for i in 0..4 {
+                sum += (u[i] - v[i]) * (u[i] - v[i]);
+            }
+        }
+    }
+    sum / (4 * a.width * a.height) as Float
+}
+
+/// Computes the peak signal-to-noise ratio of two images of the same dimensions in decibels.
+///
+/// Higher is better, identical images yield infinity, and so do empty ones.
+///
+/// ```
+/// use raydiancy::color::*;
+/// use raydiancy::img_compare::*;
+/// use raydiancy::img_output::*;
+/// use raydiancy::lin_alg::Float;
+/// let mut a = Image::new(2, 2);
+/// assert_eq!(psnr(&a, &a), Float::INFINITY);
+/// let b = a.clone();
+/// a.set(0, 0, AColor::new(1., 1., 1.));
+/// assert!(psnr(&a, &b) < 10.);
+/// let empty = Image::new(0, 3);
+/// assert_eq!(psnr(&empty, &empty), Float::INFINITY);
+/// assert_eq!(ssim(&empty, &empty), 1.);
+/// ```
+pub fn psnr(a: &Image, b: &Image) -> Float {
+    let mse = mean_squared_error(a, b);
+    if mse == 0. {
+        return Float::INFINITY;
+    }
+    -10. * mse.log10()
+}
+
+/// Computes the mean structural similarity index (SSIM) of two images of the same dimensions.
+///
+/// The brightness of the pixels is compared in windows of 8x8 pixels.
+/// The result is 1 for identical images and smaller the more different they are.
+/// Like identical images, empty images yield 1.
+pub fn ssim(a: &Image, b: &Image) -> Float {
+    assert_same_dimensions(a, b);
+    if a.width == 0 || a.height == 0 {
+        return 1.;
+    }
+    let c1 = 0.01 * 0.01;
+    let c2 = 0.03 * 0.03;
+    let window = |size: usize| {
+        if size <= SSIM_WINDOW {
+            (size, vec![0])
+        } else {
+            let mut starts: Vec<_> = (0..size - SSIM_WINDOW + 1).step_by(SSIM_STRIDE).collect();
+            if starts.last() != Some(&(size - SSIM_WINDOW)) {
+                starts.push(size - SSIM_WINDOW);
+            }
+            (SSIM_WINDOW, starts)
+        }
+    };
+    let (w, xs) = window(a.width);
+    let (h, ys) = window(a.height);
+    let n = (w * h) as Float;
+    let mut sum = 0.;
+    for &y0 in ys.iter() {
+        for &x0 in xs.iter() {
+            let (mut mu_a, mut mu_b) = (0., 0.);
+            for y in y0..y0 + h {
+                for x in x0..x0 + w {
+                    mu_a += display_luma(a, x, y);
+                    mu_b += display_luma(b, x, y);
+                }
+            }
+            mu_a /= n;
+            mu_b /= n;
+            let (mut var_a, mut var_b, mut cov) = (0., 0., 0.);
+            for y in y0..y0 + h {
+                for x in x0..x0 + w {
+                    let (da, db) = (display_luma(a, x, y) - mu_a, display_luma(b, x, y) - mu_b);
+                    var_a += da * da;
+                    var_b += db * db;
+                    cov += da * db;
+                }
+            }
+            var_a /= n;
+            var_b /= n;
+            cov /= n;
+            sum += (2. * mu_a * mu_b + c1) * (2. * cov + c2) /
+                   ((mu_a * mu_a + mu_b * mu_b + c1) * (var_a + var_b + c2));
+        }
+    }
+    sum / (xs.len() * ys.len()) as Float
+}
+
+/// Creates an image visualizing the differences of two images of the same dimensions.
+///
+/// Each pixel contains the absolute differences of the displayed color channels
+/// multiplied by `scale`, so small differences can be made visible.
+/// Identical pixels are black.
+pub fn diff_image(a: &Image, b: &Image, scale: Float) -> Image {
+    assert_same_dimensions(a, b);
+    let mut diff = Image::new(a.width, a.height);
+    for (x, y, col) in diff.iter_mut() {
+        let (u, v) = (display_values(a, x, y), display_values(b, x, y));
+        let d = |i: usize| (scale * (u[i] * u[3] - v[i] * v[3]).abs()).min(1.);
+        *col = AColor::new(d(0), d(1), d(2));
+    }
+    diff
+}
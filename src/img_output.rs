@@ -1,11 +1,35 @@
+#[cfg(feature = "image")]
 extern crate image;
+#[cfg(feature = "png")]
+extern crate png;
 
 use color::*;
+#[cfg(all(feature = "fs", feature = "image"))]
+use error::{Error, Result};
+use lin_alg::Float;
+#[cfg(feature = "fs")]
+use std::fs::File;
+use std::io;
+#[cfg(feature = "fs")]
+use std::io::BufWriter;
+use std::io::Write;
 use std::path::Path;
 use std::slice;
-use self::image::*;
+#[cfg(feature = "png")]
+use std::cell::RefCell;
+#[cfg(feature = "png")]
+use std::mem;
+#[cfg(feature = "png")]
+use std::rc::Rc;
+#[cfg(feature = "image")]
+use self::image::{ExtendedColorType, ImageEncoder};
+#[cfg(all(feature = "fs", feature = "image"))]
+use self::image::{ImageBuffer, ImageError, Rgba, RgbaImage};
+#[cfg(feature = "image")]
+use self::image::codecs::png::PngEncoder;
 
 // Stores an image and its dimensions.
+#[derive(Clone)]
 pub struct Image {
     /// The width of the image in pixels.
     pub width: usize,
@@ -36,15 +60,179 @@ impl Image {
         self
     }
 
+    /// Returns the rectangle of the image with the top-left pixel (x, y)
+    /// and the given dimensions.
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> Image {
+        let mut result = Image::new(width, height);
+        for row in 0..height {
+            let start = (y + row) * self.width + x;
+            result.pixels[row * width..(row + 1) * width]
+                .copy_from_slice(&self.pixels[start..start + width]);
+        }
+        result
+    }
+
+    /// Extends the opaque parts of the image by the given number of pixels:
+    /// Each step sets the completely transparent pixels next to opaque ones to the average
+    /// of these neighbors. Baked textures need this, so that texture filtering at the
+    /// borders of the parts does not blend in the background.
+    ///
+    /// ```
+    /// use raydiancy::color::*;
+    /// use raydiancy::img_output::*;
+    /// let mut img = Image::new(4, 1);
+    /// img.set(0, 0, AColor::new(1.0, 0.0, 0.0));
+    /// let img = img.dilate(2);
+    /// assert_eq!(img.get(2, 0).opaque().red(), 1.0);
+    /// assert_eq!(img.get(3, 0).transparency(), 1.0);
+    /// ```
+    pub fn dilate(&self, pixels: usize) -> Image {
+        let mut img = self.clone();
+        for _ in 0..pixels {
+            let previous = img.clone();
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    if previous.get(x, y).transparency() < 1. {
+                        continue;
+                    }
+                    let mut sum = black();
+                    let mut count = 0;
+                    let neighbors = [(x.wrapping_sub(1), y), (x + 1, y),
+                                     (x, y.wrapping_sub(1)), (x, y + 1)];
+                    for &(nx, ny) in neighbors.iter() {
+                        if nx < self.width && ny < self.height &&
+                           previous.get(nx, ny).transparency() == 0. {
+                            sum = sum + previous.get(nx, ny).opaque();
+                            count += 1;
+                        }
+                    }
+                    if count > 0 {
+                        img.set(x, y, ((1. / count as Float) * sum).with_alpha());
+                    }
+                }
+            }
+        }
+        img
+    }
+
+    /// Multiplies the colors of all pixels by `factor`, leaving their transparency unchanged.
+    pub fn scale_radiance(&mut self, factor: Float) {
+        for col in self.pixels.iter_mut() {
+            *col = col.scale_radiance(factor);
+        }
+    }
+
+    /// Multiplies the colors of all pixels by `filter` channel by channel,
+    /// leaving their transparency unchanged.
+    pub fn filter_radiance(&mut self, filter: Color) {
+        for col in self.pixels.iter_mut() {
+            *col = col.filter_radiance(filter);
+        }
+    }
+
+    /// Returns the log-average (geometric mean) luminance of the image.
+    /// Completely transparent pixels are ignored.
+    /// Returns 0 if there are no such pixels.
+    ///
+    /// ```
+    /// use raydiancy::color::*;
+    /// use raydiancy::img_output::*;
+    /// let mut img = Image::new(2, 1);
+    /// img.set(0, 0, AColor::new(0.1, 0.1, 0.1));
+    /// img.set(1, 0, AColor::new(0.4, 0.4, 0.4));
+    /// assert!((img.log_average_luminance() - 0.2).abs() < 1e-3);
+    /// ```
+    pub fn log_average_luminance(&self) -> Float {
+        // Avoids the singularity of the logarithm for black pixels.
+        let delta = 1e-4;
+        let mut sum = 0.;
+        let mut count = 0;
+        for col in self.pixels.iter() {
+            let opacity = 1. - col.transparency();
+            if opacity > 0. {
+                sum += (delta + col.opaque().luminance() / opacity).ln();
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return 0.;
+        }
+        (sum / count as Float).exp()
+    }
+
+    /// Returns a mutable view of all rows, which can be split into disjoint parts.
+    pub fn rows_mut(&mut self) -> ImageRows {
+        ImageRows {
+            first_row: 0,
+            width: self.width,
+            pixels: &mut self.pixels,
+        }
+    }
+
+    /// Splits the image into two mutable views: the rows above `row` and the remaining ones.
+    /// Since they do not overlap, they can be processed by different threads.
+    pub fn split_at_rows(&mut self, row: usize) -> (ImageRows, ImageRows) {
+        self.rows_mut().split_at_rows(row)
+    }
+
+    /// Splits the image into mutable tiles of (at most) the given dimensions,
+    /// ordered line by line. Tiles at the right and bottom border may be smaller.
+    ///
+    /// ```
+    /// use raydiancy::color::*;
+    /// use raydiancy::img_output::*;
+    /// let mut img = Image::new(5, 3);
+    /// let mut tiles = img.tiles_mut(2, 2);
+    /// assert_eq!(tiles.len(), 6);
+    /// let tile = &mut tiles[5];
+    /// assert_eq!((tile.x, tile.y, tile.width, tile.height), (4, 2, 1, 1));
+    /// for (x, y, col) in tile.iter_mut() {
+    ///     assert_eq!((x, y), (4, 2));
+    ///     *col = AColor::new(1., 0., 0.);
+    /// }
+    /// drop(tiles);
+    /// assert_eq!(img.get(4, 2).opaque().red(), 1.);
+    /// assert!(Image::new(0, 3).tiles_mut(2, 2).is_empty());
+    /// ```
+    pub fn tiles_mut(&mut self, tile_width: usize, tile_height: usize) -> Vec<ImageTile> {
+        assert!(tile_width > 0 && tile_height > 0);
+        let width = self.width;
+        let mut tiles = Vec::new();
+        if width == 0 || self.pixels.is_empty() {
+            return tiles;
+        }
+        for (band_index, band) in self.pixels.chunks_mut(width * tile_height).enumerate() {
+            let first_tile = tiles.len();
+            for (row_index, row) in band.chunks_mut(width).enumerate() {
+                for (column_index, part) in row.chunks_mut(tile_width).enumerate() {
+                    if row_index == 0 {
+                        tiles.push(ImageTile {
+                            x: column_index * tile_width,
+                            y: band_index * tile_height,
+                            width: part.len(),
+                            height: 0,
+                            rows: Vec::new(),
+                        });
+                    }
+                    let tile = &mut tiles[first_tile + column_index];
+                    tile.height += 1;
+                    tile.rows.push(part);
+                }
+            }
+        }
+        tiles
+    }
+
     /// Returns a mutable iterator over the pixels of the image.
     ///
     /// ```
     /// use raydiancy::color::*;
     /// use raydiancy::img_output::*;
+    /// use raydiancy::lin_alg::Float;
     /// use std::path::*;
     /// let mut img = Image::new(255,255);
     /// for (x,y,col) in img.iter_mut() {
-    ///     *col = AColor::new(x as f64/255.0,y as f64/255.0,0.0);
+    ///     *col = AColor::new(x as Float/255.0,y as Float/255.0,0.0);
     /// }
     /// // Now, img transitions is black at the top-left, green at the bottom-left,
     /// // red at the top-right and yellow at the bottom-right.
@@ -58,6 +246,109 @@ impl Image {
             y: 0,
         }
     }
+
+    /// Writes the pixels line by line as 8-bit RGBA values (not premultiplied) into `buffer`,
+    /// encoding the colors with the given transfer function and dithering them with
+    /// `Dither::default()`.
+    ///
+    /// This is the layout of `ImageData` in browsers, for example.
+    /// Panics if the buffer does not have exactly `4 * width * height` bytes.
+    ///
+    /// ```
+    /// use raydiancy::color::*;
+    /// use raydiancy::img_output::*;
+    /// let mut img = Image::new(2, 1);
+    /// img.set(0, 0, AColor::new(1., 0., 0.));
+    /// let mut buffer = [0xaa; 8];
+    /// img.write_rgba(&mut buffer, TransferFunction::Linear);
+    /// assert_eq!(buffer, [255, 0, 0, 255, 0, 0, 0, 0]);
+    /// ```
+    pub fn write_rgba(&self, buffer: &mut [u8], transfer: TransferFunction) {
+        self.write_rgba_dithered(buffer, transfer, Dither::default());
+    }
+
+    /// Writes the pixels into `buffer` like `write_rgba`, but with the given dither.
+    pub fn write_rgba_dithered(&self,
+                               buffer: &mut [u8],
+                               transfer: TransferFunction,
+                               dither: Dither) {
+        assert!(buffer.len() == 4 * self.width * self.height,
+                "the buffer does not match the dimensions of the image");
+        for (i, (bytes, col)) in buffer.chunks_mut(4).zip(self.pixels.iter()).enumerate() {
+            let pixel = (i % self.width, i / self.width);
+            let (r, g, b, a) = col.to_rgba_dithered(transfer, dither, pixel);
+            bytes.copy_from_slice(&[r, g, b, a]);
+        }
+    }
+}
+
+/// A mutable view of consecutive rows of an `Image`.
+pub struct ImageRows<'a> {
+    first_row: usize,
+    width: usize,
+    pixels: &'a mut [AColor],
+}
+
+impl<'a> ImageRows<'a> {
+    /// Returns the index of the first row in the image.
+    pub fn first_row(&self) -> usize {
+        self.first_row
+    }
+
+    /// Returns the number of rows.
+    pub fn height(&self) -> usize {
+        if self.width > 0 { self.pixels.len() / self.width } else { 0 }
+    }
+
+    /// Splits the view into the rows above `row` (given as index in the image)
+    /// and the remaining ones.
+    pub fn split_at_rows(self, row: usize) -> (ImageRows<'a>, ImageRows<'a>) {
+        assert!(self.first_row <= row && row <= self.first_row + self.height());
+        let (upper, lower) = self.pixels.split_at_mut((row - self.first_row) * self.width);
+        (ImageRows {
+            first_row: self.first_row,
+            width: self.width,
+            pixels: upper,
+        },
+         ImageRows {
+            first_row: row,
+            width: self.width,
+            pixels: lower,
+        })
+    }
+
+    /// Returns a mutable iterator over the pixels with their coordinates in the image.
+    pub fn iter_mut(&mut self) -> ImageIterator {
+        ImageIterator {
+            pixels: self.pixels.iter_mut(),
+            width: self.width,
+            x: 0,
+            y: self.first_row,
+        }
+    }
+}
+
+/// A mutable view of a rectangular part of an `Image`.
+pub struct ImageTile<'a> {
+    /// The column of the top-left pixel in the image.
+    pub x: usize,
+    /// The row of the top-left pixel in the image.
+    pub y: usize,
+    /// The width of the tile in pixels.
+    pub width: usize,
+    /// The height of the tile in pixels.
+    pub height: usize,
+    rows: Vec<&'a mut [AColor]>,
+}
+
+impl<'a> ImageTile<'a> {
+    /// Returns a mutable iterator over the pixels with their coordinates in the image.
+    pub fn iter_mut<'b>(&'b mut self) -> Box<Iterator<Item = (usize, usize, &'b mut AColor)> + 'b> {
+        let (x0, y0) = (self.x, self.y);
+        Box::new(self.rows.iter_mut().enumerate().flat_map(move |(j, row)| {
+            row.iter_mut().enumerate().map(move |(i, col)| (x0 + i, y0 + j, col))
+        }))
+    }
 }
 
 /// Iterator over the pixels of an `Image`.
@@ -84,15 +375,526 @@ impl<'a> Iterator for ImageIterator<'a> {
     }
 }
 
-/// Writes a given image to the given file path.
+/// Writes a given image to the given file path, using the default gamma correction.
 ///
 /// The file type is determined by the file extension. Only ".png" was tested.
-pub fn write_pixels_to_file(image: Image, filepath: &Path) {
+#[cfg(all(feature = "fs", feature = "image"))]
+pub fn write_pixels_to_file(image: Image, filepath: &Path) -> Result<()> {
+    write_pixels_to_file_with(image, filepath, TransferFunction::default())
+}
+
+/// Writes a given image to the given file path,
+/// encoding the colors with the given transfer function and dithering them with
+/// `Dither::default()`.
+///
+/// The file type is determined by the file extension. Only ".png" was tested.
+#[cfg(all(feature = "fs", feature = "image"))]
+pub fn write_pixels_to_file_with(image: Image,
+                                 filepath: &Path,
+                                 transfer: TransferFunction)
+                                 -> Result<()> {
     let mut output: RgbaImage = ImageBuffer::new(image.width as u32, image.height as u32);
     for (x, y, pixel) in output.enumerate_pixels_mut() {
         let c = image.get(x as usize, y as usize);
-        let (r, g, b, a) = c.to_rgba();
+        let position = (x as usize, y as usize);
+        let (r, g, b, a) = c.to_rgba_dithered(transfer, Dither::default(), position);
         *pixel = Rgba([r, g, b, a]);
     }
-    output.save(filepath).unwrap()
+    output.save(filepath).map_err(image_error)
+}
+
+/// Reads an image from the given file path,
+/// decoding the colors with the given transfer function, e.g. for textures.
+///
+/// The file type is determined by the contents of the file.
+#[cfg(all(feature = "fs", feature = "image"))]
+pub fn read_pixels_from_file(filepath: &Path, transfer: TransferFunction) -> Result<Image> {
+    let input = try!(image::open(filepath).map_err(image_error)).to_rgba8();
+    let mut img = Image::new(input.width() as usize, input.height() as usize);
+    for (x, y, pixel) in input.enumerate_pixels() {
+        let Rgba([r, g, b, a]) = *pixel;
+        let decode = |v: u8| transfer.decode(v as Float / 255.);
+        let opacity = a as Float / 255.;
+        // The colors of images are premultiplied by the opacity.
+        let c = opacity * Color::new_unchecked(decode(r), decode(g), decode(b));
+        img.set(x as usize, y as usize, c.with_transparency(1. - opacity));
+    }
+    Ok(img)
+}
+
+#[cfg(all(feature = "fs", feature = "image"))]
+fn image_error(e: ImageError) -> Error {
+    match e {
+        ImageError::IoError(e) => Error::Io(e),
+        e => Error::Image(e.to_string()),
+    }
+}
+
+/// Encodes a color in the shared-exponent RGBE format of Radiance.
+/// Negative values are treated as 0.
+fn to_rgbe(c: Color) -> [u8; 4] {
+    let (r, g, b) = (c.red().max(0.), c.green().max(0.), c.blue().max(0.));
+    let v = r.max(g).max(b);
+    if !(v >= 1e-32) {
+        return [0, 0, 0, 0];
+    }
+    // Write v = m * 2^e with 0.5 <= m < 1:
+    let e = v.log2().floor() as i32 + 1;
+    let scale = 256. / (2. as Float).powi(e);
+    [(r * scale).min(255.) as u8,
+     (g * scale).min(255.) as u8,
+     (b * scale).min(255.) as u8,
+     (e + 128).max(0).min(255) as u8]
+}
+
+/// Writes the header of a Radiance HDR file.
+fn write_hdr_header<W: Write>(out: &mut W, width: usize, height: usize) -> io::Result<()> {
+    write!(out, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width)
+}
+
+/// Writes the image in the Radiance HDR format.
+///
+/// In contrast to PNG, the colors are neither clamped nor quantized to 8 bits,
+/// so highlights are preserved for later tone mapping or compositing.
+/// The format has no alpha channel, so transparent parts are written as black.
+pub fn write_hdr<W: Write>(image: &Image, out: &mut W) -> io::Result<()> {
+    try!(write_hdr_header(out, image.width, image.height));
+    for col in image.pixels.iter() {
+        try!(out.write_all(&to_rgbe(col.opaque())));
+    }
+    Ok(())
+}
+
+/// Writes a given image to the given file path in the Radiance HDR format (".hdr").
+#[cfg(feature = "fs")]
+pub fn write_hdr_file(image: &Image, filepath: &Path) -> io::Result<()> {
+    let mut file = BufWriter::new(try!(File::create(filepath)));
+    try!(write_hdr(image, &mut file));
+    file.flush()
+}
+
+/// Writes the header of a binary PPM file.
+fn write_ppm_header<W: Write>(out: &mut W, width: usize, height: usize) -> io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", width, height)
+}
+
+/// Writes the image in the binary PPM format (8 bits per channel),
+/// encoding the colors with the given transfer function and dithering them with
+/// `Dither::default()`.
+/// The format has no alpha channel, so transparent parts are written as black.
+///
+/// ```
+/// use raydiancy::color::*;
+/// use raydiancy::img_output::*;
+/// let mut img = Image::new(2, 1);
+/// img.set(0, 0, AColor::new(1., 0., 0.));
+/// let mut bytes = Vec::new();
+/// write_ppm(&img, &mut bytes, TransferFunction::Linear).unwrap();
+/// assert_eq!(bytes, b"P6\n2 1\n255\n\xff\x00\x00\x00\x00\x00".to_vec());
+/// ```
+pub fn write_ppm<W: Write>(image: &Image,
+                           out: &mut W,
+                           transfer: TransferFunction)
+                           -> io::Result<()> {
+    try!(write_ppm_header(out, image.width, image.height));
+    for (i, col) in image.pixels.iter().enumerate() {
+        let pixel = (i % image.width, i / image.width);
+        let (r, g, b) =
+            col.over_background(black()).to_rgb_dithered(transfer, Dither::default(), pixel);
+        try!(out.write_all(&[r, g, b]));
+    }
+    Ok(())
+}
+
+/// Writes the image in the PFM format (32-bit floating point numbers per channel).
+/// The colors are stored linearly, without clamping.
+/// The format has no alpha channel, so transparent parts are written as black.
+pub fn write_pfm<W: Write>(image: &Image, out: &mut W) -> io::Result<()> {
+    // A negative scale indicates little-endian numbers.
+    try!(write!(out, "PF\n{} {}\n-1.0\n", image.width, image.height));
+    // The rows are stored from bottom to top.
+    for y in (0..image.height).rev() {
+        for x in 0..image.width {
+            let c = image.get(x, y).opaque();
+            for &v in [c.red(), c.green(), c.blue()].iter() {
+                let bits = (v as f32).to_bits();
+                let bytes = [bits as u8, (bits >> 8) as u8, (bits >> 16) as u8, (bits >> 24) as u8];
+                try!(out.write_all(&bytes));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes a given image to the given file path in the binary PPM format (".ppm").
+#[cfg(feature = "fs")]
+pub fn write_ppm_file(image: &Image,
+                      filepath: &Path,
+                      transfer: TransferFunction)
+                      -> io::Result<()> {
+    let mut file = BufWriter::new(try!(File::create(filepath)));
+    try!(write_ppm(image, &mut file, transfer));
+    file.flush()
+}
+
+/// Writes a given image to the given file path in the PFM format (".pfm").
+#[cfg(feature = "fs")]
+pub fn write_pfm_file(image: &Image, filepath: &Path) -> io::Result<()> {
+    let mut file = BufWriter::new(try!(File::create(filepath)));
+    try!(write_pfm(image, &mut file));
+    file.flush()
+}
+
+/// The file formats that images can be written in.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ImageFormat {
+    /// PNG with 8 bits per channel and alpha channel.
+    Png,
+    /// Binary PPM with 8 bits per channel.
+    Ppm,
+    /// PFM with 32-bit floating point numbers per channel.
+    Pfm,
+    /// Radiance HDR with a shared exponent per pixel.
+    Hdr,
+}
+
+impl ImageFormat {
+    /// Determines the format from the extension of the path, ignoring its case.
+    ///
+    /// ```
+    /// use raydiancy::img_output::*;
+    /// use std::path::Path;
+    /// assert_eq!(ImageFormat::from_path(Path::new("out/image.PNG")), Some(ImageFormat::Png));
+    /// assert_eq!(ImageFormat::from_path(Path::new("image.jpg")), None);
+    /// ```
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        let extension = match path.extension().and_then(|e| e.to_str()) {
+            Some(e) => e.to_lowercase(),
+            None => return None,
+        };
+        match &extension[..] {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            "pfm" => Some(ImageFormat::Pfm),
+            "hdr" => Some(ImageFormat::Hdr),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "image")]
+fn write_png<W: Write>(image: &Image, out: &mut W, transfer: TransferFunction) -> io::Result<()> {
+    let mut bytes = vec![0; 4 * image.width * image.height];
+    image.write_rgba(&mut bytes, transfer);
+    PngEncoder::new(out)
+        .write_image(&bytes,
+                     image.width as u32,
+                     image.height as u32,
+                     ExtendedColorType::Rgba8)
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+}
+
+#[cfg(not(feature = "image"))]
+fn write_png<W: Write>(_: &Image, _: &mut W, _: TransferFunction) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::InvalidInput,
+                       "PNG output requires the feature `image`"))
+}
+
+/// Writes the image in the given format to any writer, e.g. a file, stdout or a byte vector.
+///
+/// The transfer function and the dither are used by the 8-bit formats (PNG and PPM) only,
+/// the others store linear values.
+/// PNG is only available with the feature `image`.
+///
+/// ```
+/// use raydiancy::color::*;
+/// use raydiancy::img_output::*;
+/// let img = Image::new(4, 4);
+/// let mut png = Vec::new();
+/// write_image(&img, &mut png, ImageFormat::Png, TransferFunction::default()).unwrap();
+/// assert_eq!(&png[1..4], b"PNG");
+/// ```
+pub fn write_image<W: Write>(image: &Image,
+                             out: &mut W,
+                             format: ImageFormat,
+                             transfer: TransferFunction)
+                             -> io::Result<()> {
+    match format {
+        ImageFormat::Png => write_png(image, out, transfer),
+        ImageFormat::Ppm => write_ppm(image, out, transfer),
+        ImageFormat::Pfm => write_pfm(image, out),
+        ImageFormat::Hdr => write_hdr(image, out),
+    }
+}
+
+/// Writes the image in the given format to the given file path.
+#[cfg(feature = "fs")]
+pub fn write_image_file(image: &Image,
+                        filepath: &Path,
+                        format: ImageFormat,
+                        transfer: TransferFunction)
+                        -> io::Result<()> {
+    let mut file = BufWriter::new(try!(File::create(filepath)));
+    try!(write_image(image, &mut file, format, transfer));
+    file.flush()
+}
+
+/// The suffixes of the file names of the cube map faces, see `CubeMap::write_files`.
+pub const CUBE_MAP_FACE_NAMES: [&'static str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+/// The six square faces of a cube map, seen from its center, e.g. for an environment map.
+///
+/// The faces looking along +x, -x, +z, and -z have +y at the top,
+/// the one looking along +y has +z at the top, and the one looking along -y has -z at the top.
+///
+/// ```
+/// use raydiancy::color::*;
+/// use raydiancy::img_output::*;
+/// let faces = (0..6).map(|_| Image::new(2, 2)).collect();
+/// let cross = CubeMap { faces: faces }.to_cross();
+/// assert_eq!((cross.width, cross.height), (8, 6));
+/// ```
+#[derive(Clone)]
+pub struct CubeMap {
+    /// The faces looking along +x, -x, +y, -y, +z, and -z, in this order.
+    pub faces: Vec<Image>,
+}
+
+impl CubeMap {
+    /// Assembles the faces into a single image in the horizontal cross layout:
+    /// -x, -z, +x, +z from left to right in the middle row, +y above and -y below -z.
+    /// The remaining parts of the image are transparent.
+    pub fn to_cross(&self) -> Image {
+        let size = self.faces[0].width;
+        // The columns and rows of the faces in units of the face size.
+        let cells = [(2, 1), (0, 1), (1, 0), (1, 2), (3, 1), (1, 1)];
+        let mut img = Image::new(4 * size, 3 * size);
+        for (face, &(column, row)) in self.faces.iter().zip(cells.iter()) {
+            for y in 0..size {
+                for x in 0..size {
+                    img.set(column * size + x, row * size + y, face.get(x, y));
+                }
+            }
+        }
+        img
+    }
+
+    /// Writes each face to its own file, named after the given path with the suffix
+    /// from `CUBE_MAP_FACE_NAMES`, e.g. "env_px.png" for the path "env.png".
+    /// The format is determined by the extension of the path.
+    #[cfg(feature = "fs")]
+    pub fn write_files(&self, path: &Path, transfer: TransferFunction) -> io::Result<()> {
+        let format = match ImageFormat::from_path(path) {
+            Some(format) => format,
+            None => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "unknown image format"));
+            }
+        };
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        for (face, name) in self.faces.iter().zip(CUBE_MAP_FACE_NAMES.iter()) {
+            let filename = format!("{}_{}.{}", stem, name, extension);
+            try!(write_image_file(face, &path.with_file_name(filename), format, transfer));
+        }
+        Ok(())
+    }
+}
+
+/// Writes an image in parts of complete rows, from top to bottom,
+/// so that the whole image never has to be kept in memory.
+///
+/// PPM, HDR, and PNG (with the feature `png`) are supported, but not PFM,
+/// which stores the image from bottom to top.
+///
+/// ```
+/// use raydiancy::color::*;
+/// use raydiancy::img_output::*;
+/// let mut bytes = Vec::new();
+/// {
+///     let mut writer =
+///         ScanlineWriter::new(&mut bytes, 2, 3, ImageFormat::Ppm, TransferFunction::default())
+///             .unwrap();
+///     writer.write_rows(&Image::new(2, 2)).unwrap();
+///     writer.write_rows(&Image::new(2, 1)).unwrap();
+///     writer.finish().unwrap();
+/// }
+/// let mut expected = Vec::new();
+/// write_ppm(&Image::new(2, 3), &mut expected, TransferFunction::default()).unwrap();
+/// assert_eq!(bytes, expected);
+///
+/// let mut png = Vec::new();
+/// {
+///     let mut writer =
+///         ScanlineWriter::new(&mut png, 2, 3, ImageFormat::Png, TransferFunction::default())
+///             .unwrap();
+///     writer.write_rows(&Image::new(2, 3)).unwrap();
+///     writer.finish().unwrap();
+/// }
+/// assert_eq!(&png[1..4], b"PNG");
+/// assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+/// ```
+pub struct ScanlineWriter<W: Write> {
+    out: W,
+    format: ImageFormat,
+    transfer: TransferFunction,
+    width: usize,
+    height: usize,
+    rows_written: usize,
+    /// The encoder of the rows if the format is PNG.
+    png: Option<PngStream>,
+}
+
+impl<W: Write> ScanlineWriter<W> {
+    /// Creates a writer for an image of the given dimensions and writes the file header.
+    ///
+    /// Fails with `InvalidInput` if the format cannot be written row by row.
+    pub fn new(mut out: W,
+               width: usize,
+               height: usize,
+               format: ImageFormat,
+               transfer: TransferFunction)
+               -> io::Result<ScanlineWriter<W>> {
+        let mut png = None;
+        match format {
+            ImageFormat::Ppm => try!(write_ppm_header(&mut out, width, height)),
+            ImageFormat::Hdr => try!(write_hdr_header(&mut out, width, height)),
+            ImageFormat::Png => png = Some(try!(PngStream::new(width, height))),
+            ImageFormat::Pfm => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                          "format cannot be written row by row"));
+            }
+        }
+        Ok(ScanlineWriter {
+            out: out,
+            format: format,
+            transfer: transfer,
+            width: width,
+            height: height,
+            rows_written: 0,
+            png: png,
+        })
+    }
+
+    /// Writes the next rows, given as an image of the same width.
+    pub fn write_rows(&mut self, rows: &Image) -> io::Result<()> {
+        if rows.width != self.width || self.rows_written + rows.height > self.height {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                      "rows do not fit into the image"));
+        }
+        let mut bytes = Vec::with_capacity(4 * rows.pixels.len());
+        for (i, col) in rows.pixels.iter().enumerate() {
+            let pixel = (i % self.width, self.rows_written + i / self.width);
+            match self.format {
+                ImageFormat::Hdr => bytes.extend_from_slice(&to_rgbe(col.opaque())),
+                ImageFormat::Png => {
+                    let (r, g, b, a) =
+                        col.to_rgba_dithered(self.transfer, Dither::default(), pixel);
+                    bytes.extend_from_slice(&[r, g, b, a]);
+                }
+                _ => {
+                    let (r, g, b) = col.over_background(black())
+                        .to_rgb_dithered(self.transfer, Dither::default(), pixel);
+                    bytes.extend_from_slice(&[r, g, b]);
+                }
+            }
+        }
+        if let Some(ref mut png) = self.png {
+            bytes = try!(png.encode(&bytes));
+        }
+        try!(self.out.write_all(&bytes));
+        self.rows_written += rows.height;
+        Ok(())
+    }
+
+    /// Checks that all rows have been written, flushes the output and returns it.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.rows_written != self.height {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "image is incomplete"));
+        }
+        if let Some(png) = self.png.take() {
+            try!(self.out.write_all(&try!(png.finish())));
+        }
+        try!(self.out.flush());
+        Ok(self.out)
+    }
+}
+
+/// A buffer for the output of the PNG encoder, which has to own its output.
+#[cfg(feature = "png")]
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+#[cfg(feature = "png")]
+impl SharedBuffer {
+    /// Returns the bytes written so far and empties the buffer.
+    fn take(&self) -> Vec<u8> {
+        mem::replace(&mut *self.0.borrow_mut(), Vec::new())
+    }
+}
+
+#[cfg(feature = "png")]
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Encodes the rows of a PNG file with 8-bit RGBA pixels one after the other.
+#[cfg(feature = "png")]
+struct PngStream {
+    encoder: png::StreamWriter<'static, SharedBuffer>,
+    buffer: SharedBuffer,
+}
+
+#[cfg(feature = "png")]
+impl PngStream {
+    fn new(width: usize, height: usize) -> io::Result<PngStream> {
+        let buffer = SharedBuffer::default();
+        let mut encoder = png::Encoder::new(buffer.clone(), width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let writer = try!(encoder.write_header());
+        Ok(PngStream {
+            encoder: try!(writer.into_stream_writer()),
+            buffer: buffer,
+        })
+    }
+
+    /// Encodes the pixels and returns the bytes of the file that are complete so far,
+    /// starting with the header.
+    fn encode(&mut self, pixels: &[u8]) -> io::Result<Vec<u8>> {
+        try!(self.encoder.write_all(pixels));
+        Ok(self.buffer.take())
+    }
+
+    /// Returns the rest of the file after all pixels have been encoded.
+    fn finish(self) -> io::Result<Vec<u8>> {
+        try!(self.encoder.finish());
+        Ok(self.buffer.take())
+    }
+}
+
+/// Without the feature `png`, no encoder can be created.
+#[cfg(not(feature = "png"))]
+enum PngStream {}
+
+#[cfg(not(feature = "png"))]
+impl PngStream {
+    fn new(_: usize, _: usize) -> io::Result<PngStream> {
+        Err(io::Error::new(io::ErrorKind::InvalidInput,
+                           "writing PNG row by row requires the feature `png`"))
+    }
+
+    fn encode(&mut self, _: &[u8]) -> io::Result<Vec<u8>> {
+        match *self {}
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {}
+    }
 }
@@ -1,8 +1,30 @@
+pub mod animation;
 pub mod basic;
+pub mod bsdf;
 pub mod color;
+pub mod denoise;
+pub mod diagnostics;
+pub mod error;
+#[cfg(feature = "parallel")]
+pub mod distributed;
+pub mod img_compare;
 pub mod img_output;
+pub mod light;
+pub mod light_tree;
 pub mod lin_alg;
 pub mod material;
+pub mod medium;
 pub mod objects;
+pub mod overlay;
+pub mod passes;
 pub mod physics;
+pub mod post_fx;
+pub mod random;
 pub mod raytrace;
+pub mod sampler;
+pub mod scene_builder;
+pub mod sky;
+pub mod stats;
+pub mod texture;
+pub mod validation;
+pub mod volume;
@@ -0,0 +1,533 @@
+use basic::*;
+use objects::aabb::Aabb;
+use validation::*;
+
+/// The light arriving at a point from a light source, see `Light::sample_direction`.
+#[derive(Debug, Copy, Clone)]
+pub struct LightSample {
+    /// The direction from the point towards the light.
+    pub dir: UnitVec3,
+    /// The distance to the light in direction `dir`, infinite for lights without a position.
+    pub distance: Float,
+    /// The light arriving from direction `dir`, ignoring the objects in between.
+    pub radiance: Color,
+    /// The probability density of choosing `dir`.
+    /// It is 1 for lights that reach the point from a single direction.
+    pub pdf: Float,
+}
+
+/// Where a light is and in which directions it shines, as a rough guide to which points it
+/// illuminates most, see `LightTree`.
+#[derive(Debug, Copy, Clone)]
+pub struct LightBounds {
+    /// A box around the light.
+    pub bounds: Aabb,
+    /// The strength of the light, see `Light::power`.
+    pub power: Float,
+    /// If `true`, the light falls off with the square of the distance,
+    /// otherwise it is equally bright at any distance, like `PointLight`.
+    pub falls_off: bool,
+    /// The main direction in which the light shines.
+    pub axis: UnitVec3,
+    /// The largest angle between the axis and the directions of the surface normals of the
+    /// light, `PI` for lights shining in all directions, or 0 for a single one.
+    pub normal_angle: Float,
+    /// The largest angle between a normal of the light and a direction in which it shines.
+    pub emission_angle: Float,
+}
+
+/// Trait for light sources.
+///
+/// The light a source contributes to a point is estimated by averaging
+/// `radiance / pdf` over `sample_count` samples.
+pub trait Light {
+    /// Chooses a direction from `point` towards the light and returns the light from there.
+    /// Returns `None` if the light does not reach the point.
+    ///
+    /// `u` are two random numbers, uniformly distributed in [0, 1),
+    /// which lights with an extent use for choosing a point on them.
+    fn sample_direction(&self, point: Vec3, u: Vec2) -> Option<LightSample>;
+
+    /// Returns the number of samples needed for a good estimate of the light.
+    /// The default is 1, which is enough for lights that reach a point from a single direction.
+    fn sample_count(&self) -> usize {
+        1
+    }
+
+    /// Returns a rough measure of the strength of the light: the luminance of the irradiance
+    /// at a surface facing the light at distance 1 (for lights smaller than that).
+    /// Lights are chosen proportional to it by `LightSampling::Power`.
+    /// The default is 1.
+    fn power(&self) -> Float {
+        1.
+    }
+
+    /// Returns where the light is and in which directions it shines, if it has a position.
+    /// Only lights with bounds can be put into a `LightTree`. The default is `None`.
+    fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
+
+    /// Returns the position of the light, if it has one.
+    /// It is used to warn about lights inside of objects.
+    fn position(&self) -> Option<Vec3> {
+        None
+    }
+
+    /// Checks the light for mistakes like non-finite coordinates or negative colors.
+    /// The default implementation finds none.
+    fn validate(&self) -> Vec<(Severity, Problem)> {
+        Vec::new()
+    }
+}
+
+/// How the lights are chosen for shading a point, see `RenderOptions::light_sampling`.
+///
+/// In scenes with many lights, sampling all of them at every point is slow, while most of
+/// them contribute little. Choosing a few of them at random instead, and dividing their
+/// contributions by the probability of being chosen, gives the same image on average,
+/// just with more noise, which more samples per pixel reduce.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// // The average brightness of an image of a plane lit by 100 lights.
+/// let render = |sampling: LightSampling| {
+///     let mut builder = Scene::builder()
+///         .resolution(8, 8)
+///         .ambient(black())
+///         .add(Plane { normal: Vec3::e3(), offset: -1.0, material: color_material(white()) })
+///         .options(RenderOptions {
+///             light_sampling: sampling,
+///             samples_per_pixel: 64,
+///             ..RenderOptions::default()
+///         });
+///     for i in 0..100 {
+///         let pos = Vec3::new(i as Float - 50.0, 5.0, 5.0);
+///         builder = builder.light(PointLight { pos: pos, col: Color::new_gray(0.01) });
+///     }
+///     let image = builder.build().unwrap().render();
+///     let mut sum = 0.0;
+///     for y in 0..8 {
+///         for x in 0..8 {
+///             sum += image.get(x, y).opaque().red();
+///         }
+///     }
+///     sum / 64.0
+/// };
+/// let all = render(LightSampling::All);
+/// assert!(all > 0.0);
+/// for &sampling in &[LightSampling::Uniform { samples: 4 },
+///                    LightSampling::Power { samples: 4 },
+///                    LightSampling::Tree { samples: 4 }] {
+///     assert!((render(sampling) - all).abs() < 0.05 * all, "{:?}", sampling);
+/// }
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightSampling {
+    /// Every light is sampled at every point, with its `sample_count`.
+    All,
+    /// The given number of lights is chosen at every point, all with the same probability,
+    /// and each is sampled once.
+    Uniform {
+        /// The number of lights chosen per point.
+        samples: usize,
+    },
+    /// Like `Uniform`, but the lights are chosen with a probability proportional to their
+    /// `power`, so bright lights are sampled more often than dim ones.
+    Power {
+        /// The number of lights chosen per point.
+        samples: usize,
+    },
+    /// Like `Power`, but lights close to the point and shining towards it are chosen more
+    /// often, by traversing a hierarchy of the lights like `LightTree`. The hierarchy is built
+    /// once per render, and choosing a light takes logarithmic time in the number of lights.
+    Tree {
+        /// The number of lights chosen per point.
+        samples: usize,
+    },
+}
+
+fn check_vec(problems: &mut Vec<(Severity, Problem)>, field: &'static str, v: Vec3) {
+    if !is_finite_vec(v) {
+        problems.push((Severity::Error, Problem::InvalidLight { field: field }));
+    }
+}
+
+fn check_color(problems: &mut Vec<(Severity, Problem)>, field: &'static str, c: Color) {
+    let (r, g, b) = (c.red(), c.green(), c.blue());
+    if !(r.is_finite() && g.is_finite() && b.is_finite()) {
+        problems.push((Severity::Error, Problem::InvalidLight { field: field }));
+    } else if r < 0. || g < 0. || b < 0. {
+        problems.push((Severity::Warning, Problem::InvalidLight { field: field }));
+    }
+}
+
+/// A light that shines equally in all directions from a point.
+pub struct PointLight {
+    /// The position of the light.
+    pub pos: Vec3,
+    /// The color of the light. It does not fall off with the distance.
+    pub col: Color,
+}
+
+impl Light for PointLight {
+    fn sample_direction(&self, point: Vec3, _u: Vec2) -> Option<LightSample> {
+        let light_vec = self.pos - point;
+        Some(LightSample {
+            dir: light_vec.normalize(),
+            distance: light_vec.norm(),
+            radiance: self.col,
+            pdf: 1.,
+        })
+    }
+
+    fn power(&self) -> Float {
+        self.col.luminance()
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bounds: Aabb::new(self.pos, self.pos),
+            power: self.power(),
+            falls_off: false,
+            axis: Vec3::e3(),
+            normal_angle: PI,
+            emission_angle: PI / 2.,
+        })
+    }
+
+    fn position(&self) -> Option<Vec3> {
+        Some(self.pos)
+    }
+
+    fn validate(&self) -> Vec<(Severity, Problem)> {
+        let mut problems = Vec::new();
+        check_vec(&mut problems, "pos", self.pos);
+        check_color(&mut problems, "col", self.col);
+        problems
+    }
+}
+
+/// A light infinitely far away that shines in a single direction, e.g. the sun.
+pub struct DirectionalLight {
+    /// The direction in which the light travels.
+    pub dir: UnitVec3,
+    /// The color of the light.
+    pub col: Color,
+}
+
+impl Light for DirectionalLight {
+    fn sample_direction(&self, _point: Vec3, _u: Vec2) -> Option<LightSample> {
+        Some(LightSample {
+            dir: -self.dir,
+            distance: Float::INFINITY,
+            radiance: self.col,
+            pdf: 1.,
+        })
+    }
+
+    fn power(&self) -> Float {
+        self.col.luminance()
+    }
+
+    fn validate(&self) -> Vec<(Severity, Problem)> {
+        let mut problems = Vec::new();
+        check_vec(&mut problems, "dir", self.dir.to());
+        check_color(&mut problems, "col", self.col);
+        problems
+    }
+}
+
+/// A point light that only shines into a cone.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let spot = SpotLight {
+///     pos: Vec3::new(0.0, 10.0, 0.0),
+///     dir: Vec3::new(0.0, -1.0, 0.0).normalize(),
+///     inner_angle: 0.2,
+///     outer_angle: 0.4,
+///     col: white(),
+/// };
+/// let below = spot.sample_direction(Vec3::zero(), Vec2::zero()).unwrap();
+/// assert_eq!(below.distance, 10.0);
+/// assert_eq!(below.radiance.red(), 1.0);
+/// assert!(spot.sample_direction(Vec3::new(10.0, 0.0, 0.0), Vec2::zero()).is_none());
+/// ```
+pub struct SpotLight {
+    /// The position of the light.
+    pub pos: Vec3,
+    /// The axis of the cone, pointing away from the light.
+    pub dir: UnitVec3,
+    /// The angle between the axis and the directions in which the light has full intensity.
+    pub inner_angle: Float,
+    /// The angle between the axis and the edge of the cone.
+    /// Between the inner and the outer angle, the light fades out smoothly.
+    pub outer_angle: Float,
+    /// The color of the light at full intensity.
+    pub col: Color,
+}
+
+impl Light for SpotLight {
+    fn sample_direction(&self, point: Vec3, _u: Vec2) -> Option<LightSample> {
+        let light_vec = self.pos - point;
+        let dir = light_vec.normalize();
+        let cos_angle = -(dir * self.dir);
+        let (cos_inner, cos_outer) = (self.inner_angle.cos(), self.outer_angle.cos());
+        if cos_angle <= cos_outer {
+            return None;
+        }
+        let x = ((cos_angle - cos_outer) / (cos_inner - cos_outer)).min(1.);
+        let falloff = x * x * (3. - 2. * x);
+        Some(LightSample {
+            dir: dir,
+            distance: light_vec.norm(),
+            radiance: falloff * self.col,
+            pdf: 1.,
+        })
+    }
+
+    fn power(&self) -> Float {
+        self.col.luminance()
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds {
+            bounds: Aabb::new(self.pos, self.pos),
+            power: self.power(),
+            falls_off: false,
+            axis: self.dir,
+            normal_angle: 0.,
+            emission_angle: self.outer_angle,
+        })
+    }
+
+    fn position(&self) -> Option<Vec3> {
+        Some(self.pos)
+    }
+
+    fn validate(&self) -> Vec<(Severity, Problem)> {
+        let mut problems = Vec::new();
+        check_vec(&mut problems, "pos", self.pos);
+        check_vec(&mut problems, "dir", self.dir.to());
+        if !(self.outer_angle > 0. && self.outer_angle <= PI) {
+            problems.push((Severity::Error, Problem::InvalidLight { field: "outer_angle" }));
+        }
+        if !(self.inner_angle >= 0. && self.inner_angle < self.outer_angle) {
+            problems.push((Severity::Error, Problem::InvalidLight { field: "inner_angle" }));
+        }
+        check_color(&mut problems, "col", self.col);
+        problems
+    }
+}
+
+/// A light emitted by a parallelogram, which casts soft shadows.
+///
+/// The parallelogram emits light on both sides, but is not visible itself.
+pub struct AreaLight {
+    /// A corner of the parallelogram.
+    pub corner: Vec3,
+    /// The edge from `corner` to the second corner.
+    pub edge1: Vec3,
+    /// The edge from `corner` to the fourth corner.
+    pub edge2: Vec3,
+    /// The color of the light emitted by every point of the parallelogram.
+    /// The light falls off with the square of the distance.
+    pub col: Color,
+    /// The number of points on the light used per shading point.
+    /// More samples make the shadows less noisy.
+    pub samples: usize,
+}
+
+impl Light for AreaLight {
+    fn sample_direction(&self, point: Vec3, u: Vec2) -> Option<LightSample> {
+        let normal = self.edge1.cross(self.edge2);
+        let area = normal.norm();
+        let light_vec = self.corner + u.x() * self.edge1 + u.y() * self.edge2 - point;
+        let distance = light_vec.norm();
+        let dir = light_vec.normalize();
+        let cos_light = (dir * normal).abs() / area;
+        if !(cos_light > 0.) {
+            return None;
+        }
+        Some(LightSample {
+            dir: dir,
+            distance: distance,
+            radiance: self.col,
+            pdf: distance * distance / (area * cos_light),
+        })
+    }
+
+    fn sample_count(&self) -> usize {
+        self.samples
+    }
+
+    fn power(&self) -> Float {
+        self.edge1.cross(self.edge2).norm() * self.col.luminance()
+    }
+
+    fn bounds(&self) -> Option<LightBounds> {
+        let mut bounds = Aabb::new(self.corner, self.corner + self.edge1);
+        bounds.grow(self.corner + self.edge2);
+        bounds.grow(self.corner + self.edge1 + self.edge2);
+        // Both sides emit light, so the normals point in opposite directions.
+        let normal = self.edge1.cross(self.edge2);
+        Some(LightBounds {
+            bounds: bounds,
+            power: self.power(),
+            falls_off: true,
+            axis: if normal.norm2() > 0. { normal.normalize() } else { Vec3::e3() },
+            normal_angle: PI,
+            emission_angle: PI / 2.,
+        })
+    }
+
+    fn position(&self) -> Option<Vec3> {
+        Some(self.corner + 0.5 * (self.edge1 + self.edge2))
+    }
+
+    fn validate(&self) -> Vec<(Severity, Problem)> {
+        let mut problems = Vec::new();
+        check_vec(&mut problems, "corner", self.corner);
+        check_vec(&mut problems, "edge1", self.edge1);
+        check_vec(&mut problems, "edge2", self.edge2);
+        check_color(&mut problems, "col", self.col);
+        if self.edge1.cross(self.edge2).norm2() == 0. {
+            problems.push((Severity::Warning, Problem::InvalidLight { field: "edge2" }));
+        }
+        if self.samples == 0 {
+            problems.push((Severity::Warning, Problem::InvalidLight { field: "samples" }));
+        }
+        problems
+    }
+}
+
+/// Light arriving equally from all directions, like from an overcast sky.
+/// Unlike the ambient color of the scene, it is blocked by objects.
+///
+/// The light is not visible itself, the background of the image stays transparent.
+///
+/// In interior scenes, most directions are blocked by the walls, so most samples are wasted.
+/// Portals tell the light where it can enter, e.g. through windows,
+/// and only directions through them are sampled:
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let window = Portal {
+///     corner: Vec3::new(-1.0, 0.0, -5.0),
+///     edge1: 2.0 * Vec3::e1(),
+///     edge2: 2.0 * Vec3::e2(),
+/// };
+/// assert_eq!(window.area(), 4.0);
+/// let light = EnvironmentLight { col: white(), samples: 16, portals: vec![window] };
+/// let sample = light.sample_direction(Vec3::zero(), Vec2::new(0.5, 0.5)).unwrap();
+/// assert!(appr(sample.dir * Vec3::new(0.0, 1.0, -5.0).normalize(), 1.0));
+/// // The portal is small as seen from the point, so the density of its directions is high.
+/// assert!(sample.pdf > 1.0 / (4.0 * PI));
+/// assert!(light.sample_direction(Vec3::new(0.0, 0.0, -5.0), Vec2::new(0.5, 0.5)).is_none());
+/// ```
+pub struct EnvironmentLight {
+    /// The color of the light arriving from every direction.
+    pub col: Color,
+    /// The number of directions used per shading point.
+    /// More samples make the result less noisy.
+    pub samples: usize,
+    /// The openings through which the light enters an interior, e.g. windows.
+    /// If there are any, light from other directions is ignored.
+    pub portals: Vec<Portal>,
+}
+
+/// A parallelogram through which an environment light enters a scene,
+/// see `EnvironmentLight::portals`.
+#[derive(Debug, Copy, Clone)]
+pub struct Portal {
+    /// A corner of the parallelogram.
+    pub corner: Vec3,
+    /// The edge from `corner` to the second corner.
+    pub edge1: Vec3,
+    /// The edge from `corner` to the fourth corner.
+    pub edge2: Vec3,
+}
+
+impl Portal {
+    /// Returns the area of the parallelogram.
+    pub fn area(&self) -> Float {
+        self.edge1.cross(self.edge2).norm()
+    }
+}
+
+impl EnvironmentLight {
+    /// Chooses a point uniformly on the area of all portals and returns the direction towards it.
+    fn sample_portals(&self, point: Vec3, u: Vec2) -> Option<LightSample> {
+        let total_area = self.portals.iter().fold(0., |acc, p| acc + p.area());
+        // Choose a portal according to its area and reuse the rest of `u.x()` on it.
+        let mut x = u.x() * total_area;
+        let mut portal = self.portals[self.portals.len() - 1];
+        for p in self.portals.iter() {
+            if x < p.area() {
+                portal = *p;
+                break;
+            }
+            x -= p.area();
+        }
+        let v = (x / portal.area()).max(0.).min(1.);
+        let normal = portal.edge1.cross(portal.edge2);
+        let light_vec = portal.corner + v * portal.edge1 + u.y() * portal.edge2 - point;
+        let distance = light_vec.norm();
+        let dir = light_vec.normalize();
+        let cos_portal = (dir * normal).abs() / portal.area();
+        if !(cos_portal > 0.) {
+            return None;
+        }
+        Some(LightSample {
+            dir: dir,
+            distance: Float::INFINITY,
+            radiance: self.col,
+            pdf: distance * distance / (total_area * cos_portal),
+        })
+    }
+}
+
+impl Light for EnvironmentLight {
+    fn sample_direction(&self, point: Vec3, u: Vec2) -> Option<LightSample> {
+        if !self.portals.is_empty() {
+            return self.sample_portals(point, u);
+        }
+        // Choose a direction uniformly on the unit sphere.
+        let z = 1. - 2. * u.x();
+        let r = (1. - z * z).max(0.).sqrt();
+        let phi = 2. * PI * u.y();
+        Some(LightSample {
+            dir: Vec3::new(r * phi.cos(), r * phi.sin(), z).normalize(),
+            distance: Float::INFINITY,
+            radiance: self.col,
+            pdf: 1. / (4. * PI),
+        })
+    }
+
+    fn sample_count(&self) -> usize {
+        self.samples
+    }
+
+    fn power(&self) -> Float {
+        // The light arriving from a hemisphere, weighted by the cosine.
+        PI * self.col.luminance()
+    }
+
+    fn validate(&self) -> Vec<(Severity, Problem)> {
+        let mut problems = Vec::new();
+        check_color(&mut problems, "col", self.col);
+        if self.samples == 0 {
+            problems.push((Severity::Warning, Problem::InvalidLight { field: "samples" }));
+        }
+        for portal in self.portals.iter() {
+            check_vec(&mut problems, "portals", portal.corner);
+            check_vec(&mut problems, "portals", portal.edge1);
+            check_vec(&mut problems, "portals", portal.edge2);
+            if portal.area() == 0. {
+                problems.push((Severity::Warning, Problem::InvalidLight { field: "portals" }));
+            }
+        }
+        problems
+    }
+}
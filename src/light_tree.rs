@@ -0,0 +1,312 @@
+use basic::*;
+use light::*;
+use objects::aabb::Aabb;
+use validation::*;
+
+/// The bounds of a group of lights, like `LightBounds` for a single one.
+#[derive(Debug, Copy, Clone)]
+struct NodeBounds {
+    bounds: Aabb,
+    /// The power of the lights that fall off with the square of the distance.
+    falloff_power: Float,
+    /// The power of the lights that are equally bright at any distance.
+    constant_power: Float,
+    axis: UnitVec3,
+    normal_angle: Float,
+    emission_angle: Float,
+}
+
+impl NodeBounds {
+    fn new(light: &LightBounds) -> NodeBounds {
+        let power = light.power.max(0.);
+        NodeBounds {
+            bounds: light.bounds,
+            falloff_power: if light.falls_off { power } else { 0. },
+            constant_power: if light.falls_off { 0. } else { power },
+            axis: light.axis,
+            normal_angle: light.normal_angle,
+            emission_angle: light.emission_angle,
+        }
+    }
+
+    /// Returns the bounds of both groups of lights.
+    fn union(&self, other: &NodeBounds) -> NodeBounds {
+        let (axis, normal_angle) = union_cones((self.axis, self.normal_angle),
+                                               (other.axis, other.normal_angle));
+        NodeBounds {
+            bounds: self.bounds.union(&other.bounds),
+            falloff_power: self.falloff_power + other.falloff_power,
+            constant_power: self.constant_power + other.constant_power,
+            axis: axis,
+            normal_angle: normal_angle,
+            emission_angle: self.emission_angle.max(other.emission_angle),
+        }
+    }
+
+    /// Estimates how much light of the group reaches the point (Conty and Kulla,
+    /// "Importance Sampling of Many Lights with Adaptive Tree Splitting"), without the cosine
+    /// at the lights, which only few lights of this renderer have.
+    fn importance(&self, point: Vec3) -> Float {
+        let center = self.bounds.centroid();
+        let radius = 0.5 * self.bounds.diagonal().norm();
+        let to_point = point - center;
+        let distance2 = to_point.norm2();
+        // The angle between the axis and the direction to the point, reduced by the spread of
+        // the normals and by the angle of the box as seen from the point.
+        if distance2 > radius * radius {
+            let cos = (self.axis * to_point.normalize()).max(-1.).min(1.);
+            let box_angle = (radius / distance2.sqrt()).asin();
+            if cos.acos() - self.normal_angle - box_angle > self.emission_angle {
+                return 0.;
+            }
+        }
+        self.constant_power + self.falloff_power / distance2.max(radius * radius).max(EPS)
+    }
+}
+
+/// Returns a cone containing both cones, each given by its axis and the angle between the axis
+/// and its boundary.
+fn union_cones(a: (UnitVec3, Float), b: (UnitVec3, Float)) -> (UnitVec3, Float) {
+    let (a, b) = if b.1 > a.1 { (b, a) } else { (a, b) };
+    let cos = (a.0 * b.0).max(-1.).min(1.);
+    let between = cos.acos();
+    if (between + b.1).min(PI) <= a.1 {
+        return a;
+    }
+    let angle = 0.5 * (a.1 + between + b.1);
+    let orthogonal = b.0.to() - cos * a.0;
+    if angle >= PI || orthogonal.norm2() < 1e-12 {
+        return (a.0, PI);
+    }
+    // Rotate the axis of `a` towards the one of `b`.
+    let rotation = angle - a.1;
+    let axis = rotation.cos() * a.0 + rotation.sin() * orthogonal.normalize();
+    (axis.normalize(), angle)
+}
+
+/// A node of the tree, with the indices of its children or of its light.
+struct LightNode {
+    bounds: NodeBounds,
+    children: Option<(usize, usize)>,
+    light: usize,
+}
+
+/// The hierarchy of a `LightTree` over lights stored elsewhere, e.g. in `Scene::lights` for
+/// `LightSampling::Tree`.
+#[doc(hidden)]
+pub struct LightHierarchy {
+    nodes: Vec<LightNode>,
+    root: Option<usize>,
+    /// The indices of the lights without bounds and their powers, by which they are chosen.
+    unbounded: Vec<(usize, Float)>,
+    /// The sum of the powers of the lights without bounds.
+    unbounded_power: Float,
+}
+
+impl LightHierarchy {
+    /// Arranges the lights in a hierarchy.
+    pub fn new(lights: &[Box<Light + Sync>]) -> LightHierarchy {
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+        for (i, light) in lights.iter().enumerate() {
+            match light.bounds() {
+                Some(bounds) => bounded.push((i, NodeBounds::new(&bounds))),
+                None => unbounded.push((i, light.power().max(0.))),
+            }
+        }
+        let unbounded_power = unbounded.iter().fold(0., |acc, &(_, power)| acc + power);
+        let mut nodes = Vec::new();
+        let root = if bounded.is_empty() {
+            None
+        } else {
+            Some(LightHierarchy::build(&mut nodes, &mut bounded))
+        };
+        LightHierarchy {
+            nodes: nodes,
+            root: root,
+            unbounded: unbounded,
+            unbounded_power: unbounded_power,
+        }
+    }
+
+    /// Builds the subtree of the given lights and returns the index of its root.
+    /// The lights are split in half along the longest side of the box around their centers.
+    fn build(nodes: &mut Vec<LightNode>, lights: &mut [(usize, NodeBounds)]) -> usize {
+        let bounds = lights[1..].iter().fold(lights[0].1, |acc, light| acc.union(&light.1));
+        let (children, light) = if lights.len() == 1 {
+            (None, lights[0].0)
+        } else {
+            let mut centers = Aabb::empty();
+            for light in lights.iter() {
+                centers.grow(light.1.bounds.centroid());
+            }
+            let (axis, _) = centers.longest_side();
+            lights.sort_by(|a, b| {
+                let (a, b) = (a.1.bounds.centroid()[axis], b.1.bounds.centroid()[axis]);
+                a.partial_cmp(&b).unwrap_or(::std::cmp::Ordering::Equal)
+            });
+            let (left, right) = lights.split_at_mut(lights.len() / 2);
+            let left = LightHierarchy::build(nodes, left);
+            let right = LightHierarchy::build(nodes, right);
+            (Some((left, right)), 0)
+        };
+        nodes.push(LightNode {
+            bounds: bounds,
+            children: children,
+            light: light,
+        });
+        nodes.len() - 1
+    }
+
+    /// Chooses one of the lights that the hierarchy was built for by traversing it for the
+    /// point, using up the random number `x`.
+    /// Returns its index, the probability of choosing it, and what is left of `x`.
+    pub fn choose(&self, point: Vec3, x: Float) -> Option<(usize, Float, Float)> {
+        // Choose between the tree and the lights without bounds first.
+        let root_importance =
+            self.root.map_or(0., |root| self.nodes[root].bounds.importance(point));
+        let total = root_importance + self.unbounded_power;
+        if !(total > 0.) {
+            return None;
+        }
+        let mut rest = x * total;
+        if rest >= root_importance {
+            rest -= root_importance;
+            for &(i, power) in self.unbounded.iter() {
+                if rest < power {
+                    return Some((i, power / total, rest / power));
+                }
+                rest -= power;
+            }
+            // Rounding errors left a rest at the end.
+            let last = self.unbounded.iter().rev().find(|&&(_, power)| power > 0.);
+            if let Some(&(i, power)) = last {
+                return Some((i, power / total, 1. - 1e-7));
+            }
+            rest = root_importance;
+        }
+        let mut node = match self.root {
+            Some(root) => root,
+            None => return None,
+        };
+        let mut probability = root_importance / total;
+        let mut x = (rest / root_importance).min(1. - 1e-7);
+        while let Some((left, right)) = self.nodes[node].children {
+            let left_importance = self.nodes[left].bounds.importance(point);
+            let right_importance = self.nodes[right].bounds.importance(point);
+            let sum = left_importance + right_importance;
+            if !(sum > 0.) {
+                return None;
+            }
+            let p = left_importance / sum;
+            if x < p {
+                x /= p;
+                probability *= p;
+                node = left;
+            } else {
+                x = ((x - p) / (1. - p)).min(1. - 1e-7);
+                probability *= 1. - p;
+                node = right;
+            }
+        }
+        Some((self.nodes[node].light, probability, x))
+    }
+}
+
+/// A group of lights that chooses the lights to sample at a point by their importance:
+/// Lights that are close, bright, and shine towards the point are chosen more often.
+/// This makes scenes with thousands of lights feasible, where sampling every light
+/// at every point would take too long.
+///
+/// The lights are arranged in a hierarchy by their position, power, and the directions in
+/// which they shine (see `Light::bounds`), which is traversed from the top for every sample.
+/// Lights without bounds, e.g. `DirectionalLight`, are chosen by their power.
+/// Every sample chooses one light and samples it once, ignoring its `sample_count`.
+/// `LightSampling::Tree` chooses the lights of a scene the same way.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let mut lights: Vec<Box<Light + Sync>> = Vec::new();
+/// for i in 0..100 {
+///     let pos = Vec3::new(i as Float, 5.0, 0.0);
+///     lights.push(Box::new(PointLight { pos: pos, col: Color::new_gray(0.001 * i as Float) }));
+/// }
+/// let tree = LightTree::new(lights, 1);
+/// // Point lights are equally bright at any distance, so they are chosen proportional to
+/// // their power, and every sample estimates the total light exactly.
+/// for i in 0..10 {
+///     let u = Vec2::new(i as Float / 10.0, 0.5);
+///     let sample = tree.sample_direction(Vec3::zero(), u).unwrap();
+///     assert!((sample.radiance.red() / sample.pdf - 4.95).abs() < 1e-9);
+/// }
+///
+/// // The spot light shines away from the point, so only the point light is chosen.
+/// let tree = LightTree::new(vec![Box::new(SpotLight {
+///                                    pos: Vec3::new(0.0, -1.0, 0.0),
+///                                    dir: Vec3::new(0.0, -1.0, 0.0).normalize(),
+///                                    inner_angle: 0.2,
+///                                    outer_angle: 0.4,
+///                                    col: white(),
+///                                }),
+///                                Box::new(PointLight {
+///                                    pos: Vec3::new(0.0, 5.0, 0.0),
+///                                    col: white(),
+///                                })],
+///                           1);
+/// for i in 0..10 {
+///     let u = Vec2::new(i as Float / 10.0, 0.5);
+///     let sample = tree.sample_direction(Vec3::zero(), u).unwrap();
+///     assert_eq!((sample.distance, sample.pdf), (5.0, 1.0));
+/// }
+/// ```
+pub struct LightTree {
+    lights: Vec<Box<Light + Sync>>,
+    hierarchy: LightHierarchy,
+    /// The number of lights chosen per shading point.
+    pub samples: usize,
+}
+
+impl LightTree {
+    /// Arranges the lights in a tree, which chooses `samples` lights per shading point.
+    pub fn new(lights: Vec<Box<Light + Sync>>, samples: usize) -> LightTree {
+        LightTree {
+            hierarchy: LightHierarchy::new(&lights),
+            lights: lights,
+            samples: samples,
+        }
+    }
+
+    /// Returns the lights in the tree.
+    pub fn lights(&self) -> &[Box<Light + Sync>] {
+        &self.lights
+    }
+}
+
+impl Light for LightTree {
+    fn sample_direction(&self, point: Vec3, u: Vec2) -> Option<LightSample> {
+        let (light, probability, x) = match self.hierarchy.choose(point, u.x()) {
+            Some(choice) => choice,
+            None => return None,
+        };
+        self.lights[light].sample_direction(point, Vec2::new(x, u.y())).map(|sample| {
+            LightSample { pdf: probability * sample.pdf, ..sample }
+        })
+    }
+
+    fn sample_count(&self) -> usize {
+        self.samples
+    }
+
+    fn power(&self) -> Float {
+        self.lights.iter().fold(0., |acc, light| acc + light.power())
+    }
+
+    fn validate(&self) -> Vec<(Severity, Problem)> {
+        let mut problems: Vec<(Severity, Problem)> =
+            self.lights.iter().flat_map(|light| light.validate()).collect();
+        if self.samples == 0 {
+            problems.push((Severity::Warning, Problem::InvalidLight { field: "samples" }));
+        }
+        problems
+    }
+}
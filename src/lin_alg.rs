@@ -1,13 +1,31 @@
+//! Vectors, matrices, and quaternions for the geometry of scenes.
+//!
+//! All computations use the floating point type `Float`. It is `f64` by default. Enabling the
+//! feature `f32` switches to single precision, which halves the memory bandwidth needed for
+//! large meshes.
+
+use error::{Error, Result};
 use std::ops;
 use std::cmp;
 use std::marker::PhantomData;
+
+/// The floating point type used for all computations, see the module documentation.
+#[cfg(not(feature = "f32"))]
+pub type Float = f64;
+/// The floating point type used for all computations, see the module documentation.
+#[cfg(feature = "f32")]
+pub type Float = f32;
+
+#[cfg(not(feature = "f32"))]
 pub use std::f64::consts::PI;
+#[cfg(feature = "f32")]
+pub use std::f32::consts::PI;
 
 /// The smallest difference for values to be considered equal.
 /// This should account for numerical instabilities.
-pub const EPS: f64 = 0.0001;
+pub const EPS: Float = 0.0001;
 
-/// Tests two f64 values for approximate equality (up to `EPS`)
+/// Tests two Float values for approximate equality (up to `EPS`)
 ///
 /// This is done to account for rounding errors.
 /// # Examples
@@ -16,7 +34,7 @@ pub const EPS: f64 = 0.0001;
 /// assert!(appr(0.0, EPS / 2.0));
 /// assert!(!appr(0.0,1.0));
 /// ```
-pub fn appr(f: f64, g: f64) -> bool {
+pub fn appr(f: Float, g: Float) -> bool {
     if (f - g).abs() < EPS {
         true
     } else {
@@ -24,7 +42,7 @@ pub fn appr(f: f64, g: f64) -> bool {
     }
 }
 
-/// Compares two f64 values for approximate value (up to `EPS`)
+/// Compares two Float values for approximate value (up to `EPS`)
 ///
 /// This is done to account for rounding errors.
 /// # Examples
@@ -34,7 +52,7 @@ pub fn appr(f: f64, g: f64) -> bool {
 /// assert_eq!(appr_cmp(0.0, EPS / 2.0), cmp::Ordering::Equal);
 /// assert_eq!(appr_cmp(0.0,1.0), cmp::Ordering::Less);
 /// ```
-pub fn appr_cmp(f: f64, g: f64) -> cmp::Ordering {
+pub fn appr_cmp(f: Float, g: Float) -> cmp::Ordering {
     let diff = f - g;
     if diff < -EPS {
         cmp::Ordering::Less
@@ -45,6 +63,85 @@ pub fn appr_cmp(f: f64, g: f64) -> cmp::Ordering {
     }
 }
 
+/// The storage of the coordinates of a vector.
+#[cfg(not(feature = "simd"))]
+#[derive(Debug, Copy, Clone)]
+struct Lanes([Float; 3]);
+
+/// The storage of the coordinates of a vector, padded to four lanes.
+///
+/// The fourth lane is always zero. Together with the alignment,
+/// this allows the coordinate-wise operations below to be compiled to packed SIMD instructions.
+#[cfg(feature = "simd")]
+#[derive(Debug, Copy, Clone)]
+#[cfg_attr(not(feature = "f32"), repr(C, align(32)))]
+#[cfg_attr(feature = "f32", repr(C, align(16)))]
+struct Lanes([Float; 4]);
+
+impl Lanes {
+    #[cfg(not(feature = "simd"))]
+    #[inline(always)]
+    fn new(x: Float, y: Float, z: Float) -> Lanes {
+        Lanes([x, y, z])
+    }
+
+    #[cfg(feature = "simd")]
+    #[inline(always)]
+    fn new(x: Float, y: Float, z: Float) -> Lanes {
+        Lanes([x, y, z, 0.])
+    }
+
+    /// Sets the padding lane back to zero, e.g. after `f(0)` was stored in it.
+    #[cfg(not(feature = "simd"))]
+    #[inline(always)]
+    fn clear_padding(self) -> Lanes {
+        self
+    }
+
+    #[cfg(feature = "simd")]
+    #[inline(always)]
+    fn clear_padding(mut self) -> Lanes {
+        self.0[3] = 0.;
+        self
+    }
+
+    /// Applies `f` to every coordinate.
+    #[inline(always)]
+    fn map<F: Fn(Float) -> Float>(self, f: F) -> Lanes {
+        let mut res = self.0;
+        // All lanes are computed, so that the loop can be vectorized.
+        for i in 0..res.len() {
+            res[i] = f(self.0[i]);
+        }
+        Lanes(res).clear_padding()
+    }
+
+    /// Combines the corresponding coordinates of `self` and `b` using `f`.
+    #[inline(always)]
+    fn zip_with<F: Fn(Float, Float) -> Float>(self, b: Lanes, f: F) -> Lanes {
+        let mut res = self.0;
+        for i in 0..res.len() {
+            res[i] = f(self.0[i], b.0[i]);
+        }
+        Lanes(res).clear_padding()
+    }
+
+    /// Returns the sum of the coordinates.
+    #[inline(always)]
+    fn sum(self) -> Float {
+        self.0[0] + self.0[1] + self.0[2]
+    }
+}
+
+impl ops::Index<usize> for Lanes {
+    type Output = Float;
+
+    #[inline(always)]
+    fn index(&self, idx: usize) -> &Float {
+        &self.0[idx]
+    }
+}
+
 /// Represents a three-dimensional vector with a type marker `Marker`.
 /// Note that most of the time, you want `Vec3` instead. (Exception: function parameters)
 ///
@@ -55,9 +152,12 @@ pub fn appr_cmp(f: f64, g: f64) -> cmp::Ordering {
 /// **Accept the most general type and return the most specialized type.**
 ///
 /// You can always convert any vector into a `Vec3`, using `Vec3M<M>::to`.
+///
+/// If the feature `simd` is enabled, the coordinates are stored in four aligned lanes
+/// (the last one being padding) to speed up the arithmetic operations.
 #[derive(Debug, Clone)]
 pub struct Vec3M<Marker: Clone> {
-    x: [f64; 3],
+    x: Lanes,
     phantom: PhantomData<Marker>,
 }
 
@@ -80,7 +180,7 @@ impl ops::Neg for UnitVec3 {
 
     fn neg(self) -> UnitVec3 {
         UnitVec3 {
-            x: [-self.x[0], -self.x[1], -self.x[2]],
+            x: self.x.map(|a| -a),
             phantom: PhantomData,
         }
     }
@@ -101,7 +201,10 @@ impl<M, N> ops::Add<Vec3M<N>> for Vec3M<M>
     type Output = Vec3;
 
     fn add(self, b: Vec3M<N>) -> Vec3 {
-        Vec3::new(self[0] + b[0], self[1] + b[1], self[2] + b[2])
+        Vec3 {
+            x: self.x.zip_with(b.x, |a, b| a + b),
+            phantom: PhantomData,
+        }
     }
 }
 
@@ -112,17 +215,23 @@ impl<M, N> ops::Sub<Vec3M<N>> for Vec3M<M>
     type Output = Vec3;
 
     fn sub(self, b: Vec3M<N>) -> Vec3 {
-        Vec3::new(self[0] - b[0], self[1] - b[1], self[2] - b[2])
+        Vec3 {
+            x: self.x.zip_with(b.x, |a, b| a - b),
+            phantom: PhantomData,
+        }
     }
 }
 
-impl<M> ops::Mul<Vec3M<M>> for f64
+impl<M> ops::Mul<Vec3M<M>> for Float
     where M: Clone
 {
     type Output = Vec3;
 
-    fn mul(self: f64, v: Vec3M<M>) -> Vec3 {
-        Vec3::new(self * v[0], self * v[1], self * v[2])
+    fn mul(self: Float, v: Vec3M<M>) -> Vec3 {
+        Vec3 {
+            x: v.x.map(|a| self * a),
+            phantom: PhantomData,
+        }
     }
 }
 
@@ -130,30 +239,91 @@ impl<M, N> ops::Mul<Vec3M<N>> for Vec3M<M>
     where M: Clone,
           N: Clone
 {
-    type Output = f64;
+    type Output = Float;
 
-    fn mul(self: Vec3M<M>, v: Vec3M<N>) -> f64 {
-        self[0] * v[0] + self[1] * v[1] + self[2] * v[2]
+    fn mul(self: Vec3M<M>, v: Vec3M<N>) -> Float {
+        self.x.zip_with(v.x, |a, b| a * b).sum()
     }
 }
 
-impl<M> ops::Div<f64> for Vec3M<M>
+impl<M> ops::Div<Float> for Vec3M<M>
     where M: Clone
 {
     type Output = Vec3;
 
-    fn div(self, s: f64) -> Vec3 {
+    fn div(self, s: Float) -> Vec3 {
         let f = 1.0 / s;
         f * self
     }
 }
 
+/// Scales the vector, like `Float * Vec3M`.
+///
+/// # Examples
+/// ```
+/// use raydiancy::lin_alg::*;
+/// let v = Vec3::new(1.0, 2.0, 3.0);
+/// assert_eq!(v * 2.0, 2.0 * v);
+/// assert_eq!(Vec3::e1() * 2.0, Vec3::new(2.0, 0.0, 0.0));
+/// ```
+impl<M> ops::Mul<Float> for Vec3M<M>
+    where M: Clone
+{
+    type Output = Vec3;
+
+    fn mul(self, s: Float) -> Vec3 {
+        s * self
+    }
+}
+
+/// Adds a vector in place. Unit vectors do not support this since the sum is no unit vector.
+///
+/// # Examples
+/// ```
+/// use raydiancy::lin_alg::*;
+/// let mut sum = Vec3::zero();
+/// for i in 0..3 {
+///     sum += Vec3::e(i);
+/// }
+/// sum -= Vec3::e3();
+/// sum *= 2.0;
+/// sum /= 4.0;
+/// assert_eq!(sum, Vec3::new(0.5, 0.5, 0.0));
+/// ```
+impl<M> ops::AddAssign<Vec3M<M>> for Vec3
+    where M: Clone
+{
+    fn add_assign(&mut self, b: Vec3M<M>) {
+        *self = *self + b;
+    }
+}
+
+impl<M> ops::SubAssign<Vec3M<M>> for Vec3
+    where M: Clone
+{
+    fn sub_assign(&mut self, b: Vec3M<M>) {
+        *self = *self - b;
+    }
+}
+
+impl ops::MulAssign<Float> for Vec3 {
+    fn mul_assign(&mut self, s: Float) {
+        *self = s * *self;
+    }
+}
+
+impl ops::DivAssign<Float> for Vec3 {
+    fn div_assign(&mut self, s: Float) {
+        *self = *self / s;
+    }
+}
+
 impl<M> ops::Index<usize> for Vec3M<M>
     where M: Clone
 {
-    type Output = f64;
+    type Output = Float;
 
-    fn index(&self, idx: usize) -> &f64 {
+    fn index(&self, idx: usize) -> &Float {
         &self.x[idx]
     }
 }
@@ -198,24 +368,24 @@ impl<M, N> cmp::PartialOrd<Vec3M<N>> for Vec3M<M>
 
 impl<M: Clone> Vec3M<M> {
     /// Creates a vector with the given coordinates.
-    pub fn new(x: f64, y: f64, z: f64) -> Vec3 {
+    pub fn new(x: Float, y: Float, z: Float) -> Vec3 {
         Vec3 {
-            x: [x, y, z],
+            x: Lanes::new(x, y, z),
             phantom: PhantomData,
         }
     }
 
     /// Creates a vector with coordinates given in the array of length 3.
-    pub fn from_array(arr: [f64; 3]) -> Vec3 {
-        Vec3 {
-            x: arr,
-            phantom: PhantomData,
-        }
+    pub fn from_array(arr: [Float; 3]) -> Vec3 {
+        Vec3::new(arr[0], arr[1], arr[2])
     }
 
     /// Converts a (possibly) polymorphic vector to a `Vec3`.
     pub fn to(self) -> Vec3 {
-        Vec3::from_array(self.x)
+        Vec3 {
+            x: self.x,
+            phantom: PhantomData,
+        }
     }
 
     /// Creates a `UnitVec3` out of any given vector. Panics if its norm is not 1.0.
@@ -227,6 +397,24 @@ impl<M: Clone> Vec3M<M> {
         }
     }
 
+    /// Creates a `UnitVec3` out of any given vector.
+    /// Returns an error if its norm is not 1.0, use `normalize` to scale it instead.
+    ///
+    /// ```
+    /// use raydiancy::lin_alg::*;
+    /// assert_eq!(Vec3::new(0.6, 0.8, 0.0).try_unit_vector().unwrap(), Vec3::new(0.6, 0.8, 0.0));
+    /// assert!(Vec3::new(1.0, 1.0, 0.0).try_unit_vector().is_err());
+    /// ```
+    pub fn try_unit_vector(self: Vec3M<M>) -> Result<UnitVec3> {
+        if !appr(self.norm2(), 1.) {
+            return Err(Error::NotUnitVector { norm: self.norm() });
+        }
+        Ok(UnitVec3 {
+            x: self.x,
+            phantom: PhantomData,
+        })
+    }
+
     /// Returns the zero vector.
     pub fn zero() -> Vec3 {
         Vec3::new(0.0, 0.0, 0.0)
@@ -260,27 +448,40 @@ impl<M: Clone> Vec3M<M> {
     }
 
     /// Returns the x-coordinate.
-    pub fn x(self) -> f64 {
+    pub fn x(self) -> Float {
         self[0]
     }
 
     /// Returns the y-coordinate.
-    pub fn y(self) -> f64 {
+    pub fn y(self) -> Float {
         self[1]
     }
 
     /// Returns the z-coordinate.
-    pub fn z(self) -> f64 {
+    pub fn z(self) -> Float {
         self[2]
     }
 
     /// Computes the norm of the vector.
-    pub fn norm(self) -> f64 {
-        f64::sqrt(self * self)
+    pub fn norm(self) -> Float {
+        Float::sqrt(self * self)
     }
 
     /// Computes the square of the norm (saves a square root operation compared to `norm()`).
-    pub fn norm2(self) -> f64 {
+    ///
+    /// # Examples
+    /// ```
+    /// use raydiancy::lin_alg::*;
+    /// let v = Vec3::new(1.0, 2.0, 3.0);
+    /// assert_eq!(v.norm2(), 14.0);
+    /// // The same with the feature `simd`, whose padding lane must not take part.
+    /// assert_eq!(v.clamp(0.5, 5.0).norm2(), 14.0);
+    /// assert_eq!((v / 2.0).norm2(), 3.5);
+    /// // Dividing by zero or scaling by infinity leaves no NaN behind in the padding.
+    /// assert!((v / 0.0).norm2().is_infinite());
+    /// assert!((Float::INFINITY * Vec3::ones()).norm2().is_infinite());
+    /// ```
+    pub fn norm2(self) -> Float {
         self * self
     }
 
@@ -293,6 +494,61 @@ impl<M: Clone> Vec3M<M> {
         }
     }
 
+    /// Creates a vector from spherical coordinates with the poles on the y-axis,
+    /// which points upwards in the scenes: `theta` is the angle to the positive y-axis,
+    /// `phi` the angle from the positive x-axis towards the positive z-axis around it,
+    /// and `r` the norm.
+    ///
+    /// # Examples
+    /// ```
+    /// use raydiancy::lin_alg::*;
+    /// assert_eq!(Vec3::from_spherical(0.0, 1.0, 2.0), Vec3::new(0.0, 2.0, 0.0));
+    /// assert_eq!(Vec3::from_spherical(PI / 2.0, PI / 2.0, 1.0), Vec3::new(0.0, 0.0, 1.0));
+    /// let v = Vec3::new(1.0, -2.0, 3.0);
+    /// let (theta, phi, r) = v.to_spherical();
+    /// assert_eq!(Vec3::from_spherical(theta, phi, r), v);
+    /// ```
+    pub fn from_spherical(theta: Float, phi: Float, r: Float) -> Vec3 {
+        let (sin_theta, cos_theta) = (theta.sin(), theta.cos());
+        Vec3::new(r * sin_theta * phi.cos(), r * cos_theta, r * sin_theta * phi.sin())
+    }
+
+    /// Converts the vector to spherical coordinates `(theta, phi, r)`, see `from_spherical`.
+    /// `theta` is between 0 and `PI`, `phi` between `-PI` and `PI`.
+    /// The zero vector has all coordinates 0.
+    pub fn to_spherical(self) -> (Float, Float, Float) {
+        let r = self.norm();
+        if r == 0. {
+            return (0., 0., 0.);
+        }
+        let theta = (self.y() / r).max(-1.).min(1.).acos();
+        (theta, self.z().atan2(self.x()), r)
+    }
+
+    /// Maps the direction to texture coordinates of an equirectangular (latitude-longitude)
+    /// image, e.g. an environment map: `u` is the longitude from 0 to 1, starting at the
+    /// negative x-axis, and `v` the latitude from 0 at the bottom to 1 at the top.
+    /// Spheres use the same mapping for their texture coordinates.
+    ///
+    /// # Examples
+    /// ```
+    /// use raydiancy::lin_alg::*;
+    /// assert_eq!(Vec3::new(1.0, 0.0, 0.0).to_latlong_uv(), Vec2::new(0.5, 0.5));
+    /// assert!(appr(Vec3::new(0.0, 2.0, 0.0).to_latlong_uv().y(), 1.0));
+    /// let dir = Vec3::new(1.0, -2.0, 3.0).normalize();
+    /// assert_eq!(Vec3::from_latlong_uv(dir.to_latlong_uv()), dir);
+    /// ```
+    pub fn to_latlong_uv(self) -> Vec2 {
+        let (theta, phi, _) = self.to_spherical();
+        Vec2::new(0.5 + phi / (2. * PI), 1. - theta / PI)
+    }
+
+    /// Returns the direction with the given equirectangular texture coordinates,
+    /// the inverse of `to_latlong_uv`.
+    pub fn from_latlong_uv(uv: Vec2) -> UnitVec3 {
+        Vec3::from_spherical((1. - uv.y()) * PI, (uv.x() - 0.5) * 2. * PI, 1.).normalize()
+    }
+
     /// Computes the cross product of two vectors
     ///
     /// # Examples
@@ -319,7 +575,10 @@ impl<M: Clone> Vec3M<M> {
         where M: Clone,
               N: Clone
     {
-        Vec3::new(self[0].max(v[0]), self[1].max(v[1]), self[2].max(v[2]))
+        Vec3 {
+            x: self.x.zip_with(v.x, Float::max),
+            phantom: PhantomData,
+        }
     }
 
     /// Returns the coordinate-wise minimum of the two vectors.
@@ -327,7 +586,220 @@ impl<M: Clone> Vec3M<M> {
         where M: Clone,
               N: Clone
     {
-        Vec3::new(self[0].min(v[0]), self[1].min(v[1]), self[2].min(v[2]))
+        Vec3 {
+            x: self.x.zip_with(v.x, Float::min),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns the coordinate-wise (Hadamard) product of the two vectors.
+    ///
+    /// The operator `*` is the dot product, so this is the way to scale each coordinate
+    /// separately.
+    ///
+    /// # Examples
+    /// ```
+    /// use raydiancy::lin_alg::*;
+    /// let v = Vec3::new(1.0, 2.0, 3.0);
+    /// assert_eq!(v.hadamard(Vec3::new(4.0, 5.0, -1.0)), Vec3::new(4.0, 10.0, -3.0));
+    /// assert_eq!(v.hadamard(Vec3::e2()), Vec3::new(0.0, 2.0, 0.0));
+    /// ```
+    pub fn hadamard<N>(self, v: Vec3M<N>) -> Vec3
+        where M: Clone,
+              N: Clone
+    {
+        Vec3 {
+            x: self.x.zip_with(v.x, |a, b| a * b),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns the vector of the absolute values of the coordinates.
+    pub fn abs(self) -> Vec3 {
+        Vec3 {
+            x: self.x.map(Float::abs),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Clamps every coordinate to the interval [`min`, `max`].
+    ///
+    /// # Examples
+    /// ```
+    /// use raydiancy::lin_alg::*;
+    /// let v = Vec3::new(-1.0, 0.5, 2.0);
+    /// assert_eq!(v.clamp(0.0, 1.0), Vec3::new(0.0, 0.5, 1.0));
+    /// assert_eq!(v.abs(), Vec3::new(1.0, 0.5, 2.0));
+    /// // Only the coordinates are clamped, so dot products stay the same with `simd`.
+    /// assert_eq!(v.clamp(0.5, 5.0) * Vec3::ones(), 0.5 + 0.5 + 2.0);
+    /// ```
+    pub fn clamp(self, min: Float, max: Float) -> Vec3 {
+        Vec3 {
+            x: self.x.map(|a| a.max(min).min(max)),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Interpolates linearly between `self` (for `t = 0`) and `v` (for `t = 1`).
+    ///
+    /// # Examples
+    /// ```
+    /// use raydiancy::lin_alg::*;
+    /// let v = Vec3::new(1.0, 2.0, 3.0);
+    /// let w = Vec3::new(3.0, 2.0, -1.0);
+    /// assert_eq!(v.lerp(w, 0.0), v);
+    /// assert_eq!(v.lerp(w, 0.5), Vec3::new(2.0, 2.0, 1.0));
+    /// assert_eq!(v.lerp(w, 1.0), w);
+    /// ```
+    pub fn lerp<N>(self, v: Vec3M<N>, t: Float) -> Vec3
+        where M: Clone,
+              N: Clone
+    {
+        Vec3 {
+            x: self.x.zip_with(v.x, |a, b| a + t * (b - a)),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Reflects the vector about the line through the origin with direction `axis`,
+    /// i.e. returns `2 (v * axis) axis - v`. The norm stays the same.
+    ///
+    /// This differs from `physics::reflect`, which mirrors a ray direction at a surface.
+    ///
+    /// # Examples
+    /// ```
+    /// use raydiancy::lin_alg::*;
+    /// let v = Vec3::new(1.0, 2.0, 3.0);
+    /// assert_eq!(v.reflect_about(Vec3::e2()), Vec3::new(-1.0, 2.0, -3.0));
+    /// let axis = Vec3::new(1.0, 1.0, 0.0).normalize();
+    /// assert_eq!(v.reflect_about(axis), Vec3::new(2.0, 1.0, -3.0));
+    /// ```
+    pub fn reflect_about(self, axis: UnitVec3) -> Vec3M<M> {
+        Vec3M {
+            x: (2. * (self * axis) * axis - self).x,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns the largest coordinate.
+    ///
+    /// # Examples
+    /// ```
+    /// use raydiancy::lin_alg::*;
+    /// let v = Vec3::new(-4.0, 2.0, 1.0);
+    /// assert_eq!(v.max_element(), 2.0);
+    /// assert_eq!(v.min_element(), -4.0);
+    /// assert_eq!(v.abs().max_element(), 4.0);
+    /// ```
+    pub fn max_element(self) -> Float {
+        self.x().max(self.y()).max(self.z())
+    }
+
+    /// Returns the smallest coordinate.
+    pub fn min_element(self) -> Float {
+        self.x().min(self.y()).min(self.z())
+    }
+}
+
+/// 2D vectors, e.g. texture coordinates `(u, v)`.
+///
+/// # Examples
+/// ```
+/// use raydiancy::lin_alg::*;
+/// let a = Vec2::new(1.0, 2.0);
+/// let b = Vec2::new(3.0, 4.0);
+/// assert_eq!(a + b, Vec2::new(4.0, 6.0));
+/// assert_eq!(b - a, 2.0 * Vec2::new(1.0, 1.0));
+/// assert_eq!(a.dot(b), 11.0);
+/// assert_eq!(a.lerp(b, 0.25), Vec2::new(1.5, 2.5));
+/// assert_eq!(Vec2::new(3.0, 4.0).norm(), 5.0);
+/// ```
+#[derive(Debug, Copy, Clone)]
+pub struct Vec2 {
+    x: Float,
+    y: Float,
+}
+
+impl ops::Neg for Vec2 {
+    type Output = Vec2;
+
+    fn neg(self) -> Vec2 {
+        Vec2::new(-self.x, -self.y)
+    }
+}
+
+impl ops::Add for Vec2 {
+    type Output = Vec2;
+
+    fn add(self, v: Vec2) -> Vec2 {
+        Vec2::new(self.x + v.x, self.y + v.y)
+    }
+}
+
+impl ops::Sub for Vec2 {
+    type Output = Vec2;
+
+    fn sub(self, v: Vec2) -> Vec2 {
+        Vec2::new(self.x - v.x, self.y - v.y)
+    }
+}
+
+impl ops::Mul<Vec2> for Float {
+    type Output = Vec2;
+
+    fn mul(self, v: Vec2) -> Vec2 {
+        Vec2::new(self * v.x, self * v.y)
+    }
+}
+
+impl ops::Div<Float> for Vec2 {
+    type Output = Vec2;
+
+    fn div(self, f: Float) -> Vec2 {
+        Vec2::new(self.x / f, self.y / f)
+    }
+}
+
+impl cmp::PartialEq for Vec2 {
+    fn eq(&self, v: &Vec2) -> bool {
+        appr(self.x, v.x) && appr(self.y, v.y)
+    }
+}
+
+impl Vec2 {
+    /// Creates a new vector.
+    pub fn new(x: Float, y: Float) -> Vec2 {
+        Vec2 { x: x, y: y }
+    }
+
+    /// Returns the zero vector.
+    pub fn zero() -> Vec2 {
+        Vec2::new(0.0, 0.0)
+    }
+
+    /// Returns the first coordinate, e.g. `u`.
+    pub fn x(self) -> Float {
+        self.x
+    }
+
+    /// Returns the second coordinate, e.g. `v`.
+    pub fn y(self) -> Float {
+        self.y
+    }
+
+    /// Computes the dot product.
+    pub fn dot(self, v: Vec2) -> Float {
+        self.x * v.x + self.y * v.y
+    }
+
+    /// Computes the norm of the vector.
+    pub fn norm(self) -> Float {
+        self.dot(self).sqrt()
+    }
+
+    /// Interpolates linearly between `self` (for `t = 0`) and `v` (for `t = 1`).
+    pub fn lerp(self, v: Vec2, t: Float) -> Vec2 {
+        self + t * (v - self)
     }
 }
 
@@ -340,17 +812,17 @@ impl<M: Clone> Vec3M<M> {
 /// Only the <tt>( A b )</tt> part (3x4) of the matrix is stored.
 #[derive(Debug, Copy, Clone)]
 pub struct Matrix34 {
-    m: [[f64; 4]; 3],
+    m: [[Float; 4]; 3],
 }
 
 impl ops::Mul<Matrix34> for Matrix34 {
     type Output = Matrix34;
 
     fn mul(self, b: Matrix34) -> Matrix34 {
-        let mut res: [[f64; 4]; 3] = [[0.0; 4]; 3];
+        let mut res: [[Float; 4]; 3] = [[0.0; 4]; 3];
         for i in 0..3 {
             for k in 0..4 {
-                let mut entry: f64 = 0.0;
+                let mut entry: Float = 0.0;
                 for j in 0..3 {
                     entry += self.m[i][j] * b.m[j][k]
                 }
@@ -372,9 +844,9 @@ impl<M> ops::Mul<Vec3M<M>> for Matrix34
     type Output = Vec3;
 
     fn mul(self, v: Vec3M<M>) -> Vec3 {
-        let mut res: [f64; 3] = [0.0; 3];
+        let mut res: [Float; 3] = [0.0; 3];
         for i in 0..3 {
-            let mut entry: f64 = 0.0;
+            let mut entry: Float = 0.0;
             for j in 0..3 {
                 entry += self.m[i][j] * v[j]
             }
@@ -436,7 +908,7 @@ impl Matrix34 {
     /// let rotate_around_e3 = Matrix34::rotate(Vec3::new(0.0,0.0,1.0), PI / 2.0);
     /// assert_eq!(rotate_around_e3 * e1, Vec3::new(0.0,1.0,0.0));
     /// ```
-    pub fn rotate(axis: Vec3, angle: f64) -> Matrix34 {
+    pub fn rotate(axis: Vec3, angle: Float) -> Matrix34 {
         let u = axis.normalize();
         Matrix34 {
             m: [[angle.cos() + u[0] * u[0] * (1.0 - angle.cos()),
@@ -470,7 +942,7 @@ impl Matrix34 {
     /// }
     /// ```
     pub fn transpose(&self) -> Matrix34 {
-        let mut res: [[f64; 4]; 3] = [[0.0; 4]; 3];
+        let mut res: [[Float; 4]; 3] = [[0.0; 4]; 3];
         for i in 0..3 {
             for j in 0..3 {
                 res[i][j] = self.m[j][i]
@@ -496,7 +968,7 @@ impl Matrix34 {
     /// assert_eq!(Matrix34::scale(Vec3::new(1.0,2.0,3.0)).determinant(), 6.0);
     /// assert!(appr(Matrix34::rotate(Vec3::new(1.0,2.0,3.0),1.0).determinant(), 1.0));
     /// ```
-    pub fn determinant(&self) -> f64 {
+    pub fn determinant(&self) -> Float {
         self.m[0][0] * self.m[1][1] * self.m[2][2] + self.m[0][1] * self.m[1][2] * self.m[2][0] +
         self.m[0][2] * self.m[1][0] * self.m[2][1] -
         self.m[0][0] * self.m[1][2] * self.m[2][1] -
@@ -540,4 +1012,421 @@ impl Matrix34 {
         b[2][3] = -(b[2][0] * a[0][3] + b[2][1] * a[1][3] + b[2][2] * a[2][3]);
         Matrix34 { m: b }
     }
+
+    /// Creates the rigid transformation that places an object at `eye`, facing `target`.
+    ///
+    /// The local z-axis points away from the target (like for a camera looking along -z),
+    /// the local y-axis points upwards as close to `up` as possible, and the origin is moved
+    /// to `eye`. The inverse, e.g. the view matrix of a camera, is `rigid_inverse`.
+    ///
+    /// # Examples
+    /// ```
+    /// use raydiancy::lin_alg::*;
+    /// let eye = Vec3::new(1.0, 2.0, 3.0);
+    /// let m = Matrix34::look_at(eye, Vec3::new(1.0, 2.0, 0.0), Vec3::new(0.0, 1.0, 0.0));
+    /// assert_eq!(m, Matrix34::translate(eye));
+    /// let m = Matrix34::look_at(eye, Vec3::zero(), Vec3::new(0.0, 1.0, 0.0));
+    /// assert_eq!(m * Vec3::zero(), eye);
+    /// assert_eq!(m * Vec3::new(0.0, 0.0, -eye.norm()), Vec3::zero());
+    /// assert_eq!(m.rigid_inverse(), m.invert());
+    /// assert_eq!(m.rigid_inverse() * eye, Vec3::zero());
+    /// ```
+    pub fn look_at(eye: Vec3, target: Vec3, up: Vec3) -> Matrix34 {
+        let z = (eye - target).normalize();
+        let x = up.cross(z).normalize();
+        let y = z.cross(x);
+        Matrix34 {
+            m: [[x[0], y[0], z[0], eye[0]],
+                [x[1], y[1], z[1], eye[1]],
+                [x[2], y[2], z[2], eye[2]]],
+        }
+    }
+
+    /// Inverts a rigid transformation (a rotation followed by a translation) like the ones
+    /// created by `look_at`, which is faster and more accurate than `invert`.
+    /// The result is wrong for other transformations.
+    pub fn rigid_inverse(&self) -> Matrix34 {
+        let mut res = self.transpose();
+        for i in 0..3 {
+            res.m[i][3] = -(res.m[i][0] * self.m[0][3] + res.m[i][1] * self.m[1][3] +
+                            res.m[i][2] * self.m[2][3]);
+        }
+        res
+    }
+}
+
+/// Quaternions <tt>w + xi + yj + zk</tt>, used for representing rotations.
+///
+/// Unlike rotation matrices, unit quaternions can be interpolated smoothly (see `slerp`),
+/// e.g. for animations. Multiplying two quaternions composes their rotations:
+/// `(p * q).rotate(v)` is `p.rotate(q.rotate(v))`.
+///
+/// # Examples
+/// ```
+/// use raydiancy::lin_alg::*;
+/// let q = Quaternion::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), PI / 2.0);
+/// assert_eq!(q.rotate(Vec3::new(1.0, 0.0, 0.0)), Vec3::new(0.0, 1.0, 0.0));
+/// assert_eq!(q.to_matrix(), Matrix34::rotate(Vec3::new(0.0, 0.0, 1.0), PI / 2.0));
+/// assert_eq!((q * q).rotate(Vec3::new(1.0, 0.0, 0.0)), Vec3::new(-1.0, 0.0, 0.0));
+/// assert_eq!(q * q.conjugate(), Quaternion::identity());
+/// ```
+#[derive(Debug, Copy, Clone)]
+pub struct Quaternion {
+    w: Float,
+    x: Float,
+    y: Float,
+    z: Float,
+}
+
+impl ops::Mul<Quaternion> for Quaternion {
+    type Output = Quaternion;
+
+    /// The Hamilton product.
+    fn mul(self, q: Quaternion) -> Quaternion {
+        Quaternion {
+            w: self.w * q.w - self.x * q.x - self.y * q.y - self.z * q.z,
+            x: self.w * q.x + self.x * q.w + self.y * q.z - self.z * q.y,
+            y: self.w * q.y - self.x * q.z + self.y * q.w + self.z * q.x,
+            z: self.w * q.z + self.x * q.y - self.y * q.x + self.z * q.w,
+        }
+    }
+}
+
+impl cmp::PartialEq for Quaternion {
+    /// Compares the components approximately.
+    /// Note that `q` and `-q` represent the same rotation but are not equal.
+    fn eq(&self, q: &Quaternion) -> bool {
+        appr(self.w, q.w) && appr(self.x, q.x) && appr(self.y, q.y) && appr(self.z, q.z)
+    }
+}
+
+impl Quaternion {
+    /// Creates the quaternion <tt>w + xi + yj + zk</tt>.
+    ///
+    /// Note that glTF stores rotations in the order `[x, y, z, w]`.
+    pub fn new(w: Float, x: Float, y: Float, z: Float) -> Quaternion {
+        Quaternion {
+            w: w,
+            x: x,
+            y: y,
+            z: z,
+        }
+    }
+
+    /// The quaternion representing no rotation.
+    pub fn identity() -> Quaternion {
+        Quaternion::new(1.0, 0.0, 0.0, 0.0)
+    }
+
+    /// Creates the rotation by `angle` (in radians) around `axis`,
+    /// counterclockwise when looking against the axis, like `Matrix34::rotate`.
+    pub fn from_axis_angle(axis: Vec3, angle: Float) -> Quaternion {
+        let u = axis.normalize();
+        let s = (angle / 2.0).sin();
+        Quaternion::new((angle / 2.0).cos(), s * u[0], s * u[1], s * u[2])
+    }
+
+    /// Creates the rotation by the angle `x` around the x-axis, followed by `y` around the
+    /// y-axis and `z` around the z-axis (in radians).
+    ///
+    /// # Examples
+    /// ```
+    /// use raydiancy::lin_alg::*;
+    /// let (x, y, z) = (0.1, 0.2, 0.3);
+    /// assert_eq!(Quaternion::from_euler(x, y, z).to_matrix(),
+    ///            Matrix34::rotate(Vec3::new(0.0, 0.0, 1.0), z) *
+    ///            Matrix34::rotate(Vec3::new(0.0, 1.0, 0.0), y) *
+    ///            Matrix34::rotate(Vec3::new(1.0, 0.0, 0.0), x));
+    /// ```
+    pub fn from_euler(x: Float, y: Float, z: Float) -> Quaternion {
+        Quaternion::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), z) *
+        Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), y) *
+        Quaternion::from_axis_angle(Vec3::new(1.0, 0.0, 0.0), x)
+    }
+
+    /// Returns the real part `w`.
+    pub fn w(&self) -> Float {
+        self.w
+    }
+
+    /// Returns the imaginary part `(x, y, z)` as a vector.
+    pub fn xyz(&self) -> Vec3 {
+        Vec3::new(self.x, self.y, self.z)
+    }
+
+    /// Computes the dot product of the quaternions as 4D vectors.
+    pub fn dot(&self, q: Quaternion) -> Float {
+        self.w * q.w + self.x * q.x + self.y * q.y + self.z * q.z
+    }
+
+    /// Computes the norm of the quaternion as a 4D vector.
+    pub fn norm(&self) -> Float {
+        self.dot(*self).sqrt()
+    }
+
+    /// Returns the quaternion scaled to norm 1.
+    /// Only unit quaternions represent rotations.
+    pub fn normalize(&self) -> Quaternion {
+        let n = self.norm();
+        Quaternion::new(self.w / n, self.x / n, self.y / n, self.z / n)
+    }
+
+    /// Returns the conjugate <tt>w - xi - yj - zk</tt>,
+    /// which is the inverse rotation for unit quaternions.
+    pub fn conjugate(&self) -> Quaternion {
+        Quaternion::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    /// Rotates the vector (assuming that the quaternion has norm 1).
+    pub fn rotate<M: Clone>(&self, v: Vec3M<M>) -> Vec3 {
+        // v + 2w (u x v) + 2 u x (u x v) for the imaginary part u.
+        let u = self.xyz();
+        let t = 2.0 * u.cross(v);
+        v.to() + self.w * t + u.cross(t)
+    }
+
+    /// Converts the rotation to a matrix (assuming that the quaternion has norm 1).
+    pub fn to_matrix(&self) -> Matrix34 {
+        let (w, x, y, z) = (self.w, self.x, self.y, self.z);
+        Matrix34 {
+            m: [[1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - w * z), 2.0 * (x * z + w * y), 0.0],
+                [2.0 * (x * y + w * z), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - w * x), 0.0],
+                [2.0 * (x * z - w * y), 2.0 * (y * z + w * x), 1.0 - 2.0 * (x * x + y * y), 0.0]],
+        }
+    }
+
+    /// Interpolates spherically between the rotations `self` (for `t = 0`) and `q` (`t = 1`),
+    /// rotating with constant speed along the shortest path.
+    ///
+    /// # Examples
+    /// ```
+    /// use raydiancy::lin_alg::*;
+    /// let axis = Vec3::new(1.0, 2.0, 3.0);
+    /// let p = Quaternion::from_axis_angle(axis, 0.2);
+    /// let q = Quaternion::from_axis_angle(axis, 1.0);
+    /// assert_eq!(p.slerp(q, 0.25), Quaternion::from_axis_angle(axis, 0.4));
+    /// assert_eq!(p.slerp(q, 1.0), q);
+    /// // The shortest path, even if the signs of the quaternions differ:
+    /// let minus_q = Quaternion::new(-q.w(), -q.xyz().x(), -q.xyz().y(), -q.xyz().z());
+    /// assert_eq!(p.slerp(minus_q, 0.25), Quaternion::from_axis_angle(axis, 0.4));
+    /// ```
+    pub fn slerp(&self, q: Quaternion, t: Float) -> Quaternion {
+        let mut d = self.dot(q);
+        // q and -q are the same rotation, take the one closer to self.
+        let q = if d < 0.0 {
+            d = -d;
+            Quaternion::new(-q.w, -q.x, -q.y, -q.z)
+        } else {
+            q
+        };
+        let (a, b) = if d > 1.0 - EPS {
+            // Nearly the same rotation: interpolate linearly to avoid dividing by sin(0).
+            (1.0 - t, t)
+        } else {
+            let theta = d.acos();
+            let s = theta.sin();
+            (((1.0 - t) * theta).sin() / s, (t * theta).sin() / s)
+        };
+        Quaternion::new(a * self.w + b * q.w,
+                        a * self.x + b * q.x,
+                        a * self.y + b * q.y,
+                        a * self.z + b * q.z)
+            .normalize()
+    }
+}
+
+/// General 4x4 matrices acting on homogeneous coordinates.
+///
+/// Unlike `Matrix34`, they can represent projections, e.g. `Matrix4::perspective`.
+/// Points are transformed with `transform_point`, which divides by the homogeneous
+/// coordinate, and directions with `transform_vector`, which ignores the translation.
+///
+/// # Examples
+/// ```
+/// use raydiancy::lin_alg::*;
+/// let m = Matrix4::from(Matrix34::translate(Vec3::new(1.0, 2.0, 3.0)));
+/// let v = Vec3::new(1.0, 0.0, 0.0);
+/// assert_eq!(m.transform_point(v), Vec3::new(2.0, 2.0, 3.0));
+/// assert_eq!(m.transform_vector(v), v);
+/// assert_eq!(m.invert().unwrap() * m, Matrix4::identity());
+/// ```
+#[derive(Debug, Copy, Clone)]
+pub struct Matrix4 {
+    m: [[Float; 4]; 4],
+}
+
+impl ops::Mul<Matrix4> for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, b: Matrix4) -> Matrix4 {
+        let mut res = [[0.0; 4]; 4];
+        for i in 0..4 {
+            for k in 0..4 {
+                for j in 0..4 {
+                    res[i][k] += self.m[i][j] * b.m[j][k];
+                }
+            }
+        }
+        Matrix4 { m: res }
+    }
+}
+
+impl cmp::PartialEq for Matrix4 {
+    fn eq(&self, b: &Matrix4) -> bool {
+        for i in 0..4 {
+            for j in 0..4 {
+                if !appr(self.m[i][j], b.m[i][j]) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+impl From<Matrix34> for Matrix4 {
+    /// Adds the row <tt>( 0 0 0 1 )</tt>.
+    fn from(a: Matrix34) -> Matrix4 {
+        Matrix4 { m: [a.m[0], a.m[1], a.m[2], [0.0, 0.0, 0.0, 1.0]] }
+    }
+}
+
+impl Matrix4 {
+    /// Creates a matrix from its rows.
+    pub fn from_rows(rows: [[Float; 4]; 4]) -> Matrix4 {
+        Matrix4 { m: rows }
+    }
+
+    /// Returns the entry in row `i` and column `j`.
+    pub fn get(&self, i: usize, j: usize) -> Float {
+        self.m[i][j]
+    }
+
+    /// Identity matrix
+    pub fn identity() -> Matrix4 {
+        Matrix4::from(Matrix34::identity())
+    }
+
+    /// Creates a perspective projection for a camera at the origin looking along -z
+    /// with y pointing upwards (like OpenGL).
+    ///
+    /// `vertical_fov` is the vertical field of view in radians and `aspect_ratio` the quotient
+    /// `width / height`. Points between the distances `near` and `far` in the field of view
+    /// are mapped to the cube from -1 to 1, with the near plane at z = -1.
+    ///
+    /// # Examples
+    /// ```
+    /// use raydiancy::lin_alg::*;
+    /// let p = Matrix4::perspective(PI / 2.0, 2.0, 1.0, 10.0);
+    /// assert_eq!(p.transform_point(Vec3::new(0.0, 0.0, -1.0)), Vec3::new(0.0, 0.0, -1.0));
+    /// assert_eq!(p.transform_point(Vec3::new(0.0, 0.0, -10.0)), Vec3::new(0.0, 0.0, 1.0));
+    /// // The top right corner of the field of view:
+    /// let corner = p.transform_point(Vec3::new(4.0, 2.0, -2.0));
+    /// assert!(appr(corner.x(), 1.0) && appr(corner.y(), 1.0));
+    /// ```
+    pub fn perspective(vertical_fov: Float,
+                       aspect_ratio: Float,
+                       near: Float,
+                       far: Float)
+                       -> Matrix4 {
+        let f = 1.0 / (vertical_fov / 2.0).tan();
+        Matrix4 {
+            m: [[f / aspect_ratio, 0.0, 0.0, 0.0],
+                [0.0, f, 0.0, 0.0],
+                [0.0, 0.0, (far + near) / (near - far), 2.0 * far * near / (near - far)],
+                [0.0, 0.0, -1.0, 0.0]],
+        }
+    }
+
+    /// Transforms the point, dividing by the resulting homogeneous coordinate.
+    pub fn transform_point<M: Clone>(&self, v: Vec3M<M>) -> Vec3 {
+        let mut res = [0.0; 4];
+        for i in 0..4 {
+            res[i] = self.m[i][0] * v[0] + self.m[i][1] * v[1] + self.m[i][2] * v[2] + self.m[i][3];
+        }
+        Vec3::new(res[0] / res[3], res[1] / res[3], res[2] / res[3])
+    }
+
+    /// Transforms the direction, i.e. applies the upper left 3x3 part of the matrix.
+    pub fn transform_vector<M: Clone>(&self, v: Vec3M<M>) -> Vec3 {
+        let mut res = [0.0; 3];
+        for i in 0..3 {
+            res[i] = self.m[i][0] * v[0] + self.m[i][1] * v[1] + self.m[i][2] * v[2];
+        }
+        Vec3::from_array(res)
+    }
+
+    /// Transforms the normal of a surface transformed by this matrix,
+    /// i.e. applies the transposed inverse of the upper left 3x3 part.
+    /// The result is not normalized.
+    /// Returns `None` if the matrix is not invertible.
+    ///
+    /// # Examples
+    /// ```
+    /// use raydiancy::lin_alg::*;
+    /// // The plane x + y = 0 stretched along x becomes the plane x + 2y = 0:
+    /// let m = Matrix4::from(Matrix34::scale(Vec3::new(2.0, 1.0, 1.0)));
+    /// let n = m.transform_normal(Vec3::new(1.0, 1.0, 0.0)).unwrap();
+    /// assert_eq!(n, Vec3::new(0.5, 1.0, 0.0));
+    /// ```
+    pub fn transform_normal<M: Clone>(&self, n: Vec3M<M>) -> Option<Vec3> {
+        self.invert().map(|inv| inv.transpose().transform_vector(n))
+    }
+
+    /// Transposes the matrix.
+    pub fn transpose(&self) -> Matrix4 {
+        let mut res = [[0.0; 4]; 4];
+        for i in 0..4 {
+            for j in 0..4 {
+                res[i][j] = self.m[j][i];
+            }
+        }
+        Matrix4 { m: res }
+    }
+
+    /// Inverts the matrix by Gauss-Jordan elimination with partial pivoting.
+    /// Returns `None` if the matrix is (numerically) singular.
+    ///
+    /// # Examples
+    /// ```
+    /// use raydiancy::lin_alg::*;
+    /// let p = Matrix4::perspective(1.0, 1.5, 0.1, 100.0);
+    /// assert_eq!(p * p.invert().unwrap(), Matrix4::identity());
+    /// let m = Matrix34::rotate(Vec3::new(1.0, 2.0, 3.0), 0.5) *
+    ///         Matrix34::translate(Vec3::new(1.0, 2.0, 3.0));
+    /// assert_eq!(Matrix4::from(m).invert(), Some(Matrix4::from(m.invert())));
+    /// assert!(Matrix4::from(Matrix34::scale(Vec3::zero())).invert().is_none());
+    /// ```
+    pub fn invert(&self) -> Option<Matrix4> {
+        let mut a = self.m;
+        let mut b = Matrix4::identity().m;
+        for col in 0..4 {
+            let mut pivot = col;
+            for i in col + 1..4 {
+                if a[i][col].abs() > a[pivot][col].abs() {
+                    pivot = i;
+                }
+            }
+            if !(a[pivot][col].abs() > 1e-12) {
+                return None;
+            }
+            a.swap(col, pivot);
+            b.swap(col, pivot);
+            let factor = 1.0 / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= factor;
+                b[col][j] *= factor;
+            }
+            for i in 0..4 {
+                if i != col {
+                    let f = a[i][col];
+                    for j in 0..4 {
+                        a[i][j] -= f * a[col][j];
+                        b[i][j] -= f * b[col][j];
+                    }
+                }
+            }
+        }
+        Some(Matrix4 { m: b })
+    }
 }
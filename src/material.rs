@@ -1,4 +1,125 @@
+use bsdf::Bsdf;
 use color::*;
+use lin_alg::Float;
+use texture::Texture;
+
+/// The model used for computing the diffuse reflection of a material.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DiffuseModel {
+    /// Lambert's cosine law (ideal matte surface).
+    Lambert,
+    /// The Oren-Nayar model for rough surfaces like clay or plaster.
+    OrenNayar {
+        /// The standard deviation of the microfacet slope angle in radians (0 is Lambert).
+        roughness: Float,
+    },
+    /// An approximation of subsurface scattering for translucent materials like skin, wax,
+    /// or marble, where light enters the surface, scatters, and leaves it elsewhere.
+    ///
+    /// Light wraps around the terminator to soften it (wrap lighting),
+    /// and light from behind shines through thin parts of the object,
+    /// attenuated by the distance it travels inside.
+    /// Other objects behind it still cast their shadows:
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let model = DiffuseModel::Subsurface { wrap: 0.5, tint: white(), mean_free_path: 100.0 };
+    /// let sheet = || Plane {
+    ///     normal: Vec3::e3(),
+    ///     offset: 0.0,
+    ///     material: Material { diffuse_model: model, ..wax(white()) },
+    /// };
+    /// let wall = || Triangle {
+    ///     a: Vec3::new(-10.0, -10.0, -5.0),
+    ///     b: Vec3::new(10.0, -10.0, -5.0),
+    ///     c: Vec3::new(0.0, 10.0, -5.0),
+    ///     material: color_material(white()),
+    /// };
+    /// let lamp = PointLight { pos: Vec3::new(0.0, 0.0, -10.0), col: white() };
+    /// let lit = Scene::builder().add(sheet()).add(wall()).light(lamp).build().unwrap();
+    /// let dark = Scene::builder().add(sheet()).add(wall()).build().unwrap();
+    /// // The wall between the lamp and the sheet blocks the light shining through.
+    /// let ray = Ray::new(Vec3::new(0.0, 0.0, 5.0), -Vec3::e3());
+    /// assert_eq!(lit.trace(&ray).opaque(), dark.trace(&ray).opaque());
+    /// ```
+    Subsurface {
+        /// How far the light wraps around the terminator, from 0 (Lambert) to 1.
+        wrap: Float,
+        /// The color of the light that shines through the object.
+        tint: Color,
+        /// The distance inside the object over which the light shining through
+        /// falls off to 1/e.
+        mean_free_path: Float,
+    },
+}
+
+/// The model used for computing the specular highlights of a material.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SpecularModel {
+    /// The Blinn-Phong model using the material's `specular` and `shininess` values.
+    BlinnPhong,
+    /// The Cook-Torrance microfacet model with GGX distribution.
+    /// The material's `specular` and `shininess` values are ignored.
+    Ggx {
+        /// The surface roughness, between 0 (smooth) and 1 (rough).
+        roughness: Float,
+        /// The reflectance at normal incidence (about 0.04 for dielectrics).
+        f0: Float,
+    },
+}
+
+/// The wavelength in micrometers at which the `refraction_index` of a material is given
+/// (the sodium D line).
+pub const REFERENCE_WAVELENGTH: Float = 0.5893;
+/// The wavelengths in micrometers used for the red, green, and blue channel
+/// when computing the refraction of dispersive materials.
+pub const CHANNEL_WAVELENGTHS: [Float; 3] = [0.65, 0.55, 0.45];
+
+/// The dependence of the refraction index of a material on the wavelength of the light.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Dispersion {
+    /// The refraction index is the same for all wavelengths.
+    None,
+    /// Cauchy's equation n(l) = A + B / l^2 for the wavelength l.
+    /// A is chosen such that the material's `refraction_index` is attained at the
+    /// `REFERENCE_WAVELENGTH`.
+    Cauchy {
+        /// The coefficient B in square micrometers (about 0.004 for crown glass).
+        b: Float,
+    },
+    /// The Sellmeier equation n(l)^2 = 1 + sum of B_i * l^2 / (l^2 - C_i) for the wavelength l.
+    /// The material's `refraction_index` is ignored.
+    Sellmeier {
+        /// The coefficients B_i.
+        b: [Float; 3],
+        /// The coefficients C_i in square micrometers.
+        c: [Float; 3],
+    },
+}
+
+/// Which sides of a surface are visible, see `Material::sides`.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let material = Material::builder().sides(Sides::Front).build();
+/// let floor = Plane { normal: Vec3::e2(), offset: 0.0, material: material };
+/// let down = Ray::new(Vec3::new(0.0, 1.0, 0.0), -Vec3::e2());
+/// assert!(floor.is_hit_by(&down, Float::INFINITY));
+/// let up = Ray::new(Vec3::new(0.0, -1.0, 0.0), Vec3::e2());
+/// assert!(!floor.is_hit_by(&up, Float::INFINITY));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Sides {
+    /// Both sides are visible and shaded alike, e.g. for leaves or walls made of a single
+    /// polygon, whose normals may point either way.
+    Both,
+    /// Only the front side is visible, and rays pass through the back side.
+    /// The front side is the outside of spheres, the side that the normal of planes points to,
+    /// and the side from which the vertices of triangles are counterclockwise, like in OBJ files.
+    /// Culling the back sides of closed, opaque objects is faster, since rays skip them early.
+    /// Refractive objects need their back sides for the rays that leave them.
+    Front,
+}
 
 /// Represents a material of an object.
 #[derive(Debug, Copy, Clone)]
@@ -6,21 +127,258 @@ pub struct Material {
     /// Color of the material.
     pub color: Color,
     /// Ambient reflection constant.
-    pub ambient: f64,
+    pub ambient: Float,
     /// Diffuse reflection constant.
-    pub diffuse: f64,
+    pub diffuse: Float,
+    /// The model used for diffuse reflection.
+    pub diffuse_model: DiffuseModel,
     /// Specular reflection constant.
-    pub specular: f64,
+    pub specular: Float,
     /// Shininess/specular exponent.
     /// When it is large, the specular highlight is small.
     /// It is larger for smoother and mirror-like surfaces.
-    pub shininess: f64,
-    /// Mirror reflectance. 0 means no reflection, 1 means perfect mirror.
-    pub reflectance: f64,
-    /// Refractivity. 0 means no refraction, 1 means only recfraction.
-    pub refractivity: f64,
+    pub shininess: Float,
+    /// The model used for specular highlights.
+    pub specular_model: SpecularModel,
+    /// Mirror reflectance for each channel. Black means no reflection, white means perfect mirror.
+    /// Colored values tint the reflections, e.g. for gold.
+    pub reflectance: Color,
+    /// Refractivity for each channel. Black means no refraction, white means only refraction.
+    /// Colored values act as a color filter, e.g. for stained glass.
+    pub refractivity: Color,
     /// Refraction index. 1 is vacuum.
-    pub refraction_index: f64,
+    pub refraction_index: Float,
+    /// The dependence of the refraction index on the wavelength.
+    /// Dispersive materials split light into its colors, like prisms.
+    pub dispersion: Dispersion,
+    /// Absorption coefficient per unit distance for each channel.
+    /// Light traveling a distance `d` inside the object is attenuated by `exp(-absorption * d)`
+    /// (Beer-Lambert law). Black means no absorption.
+    pub absorption: Color,
+    /// The light emitted by the material, independent of the lights in the scene.
+    /// Channels may exceed 1 for strong light sources.
+    pub emission: Color,
+    /// A custom shading model, e.g. for car paint or velvet.
+    /// It replaces the diffuse and specular reflection and the emission of the material.
+    /// `None` uses the values of the material itself.
+    pub bsdf: Option<&'static (Bsdf + Sync)>,
+    /// A texture that `color` is multiplied with, e.g. a photo of wood.
+    pub color_texture: Option<&'static (Texture + Sync)>,
+    /// A texture whose red channel the roughness of the diffuse and specular model
+    /// is multiplied with.
+    pub roughness_texture: Option<&'static (Texture + Sync)>,
+    /// A normal map: the red, green, and blue channels from 0 to 1 encode the coordinates
+    /// from -1 to 1 of the normal along the tangent, bitangent, and unperturbed normal.
+    pub normal_texture: Option<&'static (Texture + Sync)>,
+    /// Which sides of the surface are visible.
+    pub sides: Sides,
+    /// If set, the surface cuts a transparent hole into the image where it is visible,
+    /// also in reflections and refractions, e.g. to composite the image with other layers.
+    /// It still casts shadows.
+    pub holdout: bool,
+}
+
+impl Material {
+    /// Returns a builder for a material, starting from a white diffuse material.
+    ///
+    /// ```
+    /// use raydiancy::color::*;
+    /// use raydiancy::material::*;
+    /// let material = Material::builder()
+    ///     .color(Color::new(0.8, 0.1, 0.1))
+    ///     .specular(0.5, 100.0)
+    ///     .reflectance(0.1)
+    ///     .build();
+    /// assert_eq!(material.shininess, 100.0);
+    /// assert_eq!(material.reflectance.green(), 0.1);
+    /// assert_eq!(material.diffuse, color_material(white()).diffuse);
+    /// ```
+    pub fn builder() -> MaterialBuilder {
+        MaterialBuilder { material: color_material(white()) }
+    }
+
+    /// Returns `true` if the refraction index depends on the wavelength.
+    pub fn is_dispersive(&self) -> bool {
+        self.dispersion != Dispersion::None
+    }
+
+    /// Computes the refraction index for light of the given wavelength in micrometers.
+    pub fn refraction_index_at(&self, wavelength: Float) -> Float {
+        match self.dispersion {
+            Dispersion::None => self.refraction_index,
+            Dispersion::Cauchy { b } => {
+                let a = self.refraction_index - b / (REFERENCE_WAVELENGTH * REFERENCE_WAVELENGTH);
+                a + b / (wavelength * wavelength)
+            }
+            Dispersion::Sellmeier { b, c } => {
+                let l2 = wavelength * wavelength;
+                let sum: Float = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                (1. + sum).sqrt()
+            }
+        }
+    }
+}
+
+/// Sets the fields of a `Material` one by one, see `Material::builder`.
+#[derive(Debug, Copy, Clone)]
+pub struct MaterialBuilder {
+    material: Material,
+}
+
+impl MaterialBuilder {
+    /// Sets the color.
+    pub fn color(mut self, color: Color) -> MaterialBuilder {
+        self.material.color = color;
+        self
+    }
+
+    /// Sets the ambient reflection constant.
+    pub fn ambient(mut self, ambient: Float) -> MaterialBuilder {
+        self.material.ambient = ambient;
+        self
+    }
+
+    /// Sets the diffuse reflection constant.
+    pub fn diffuse(mut self, diffuse: Float) -> MaterialBuilder {
+        self.material.diffuse = diffuse;
+        self
+    }
+
+    /// Uses the Oren-Nayar model with the given roughness for diffuse reflection.
+    pub fn rough(mut self, roughness: Float) -> MaterialBuilder {
+        self.material.diffuse_model = DiffuseModel::OrenNayar { roughness: roughness };
+        self
+    }
+
+    /// Approximates subsurface scattering, see `DiffuseModel::Subsurface`.
+    pub fn subsurface(mut self,
+                      wrap: Float,
+                      tint: Color,
+                      mean_free_path: Float)
+                      -> MaterialBuilder {
+        self.material.diffuse_model = DiffuseModel::Subsurface {
+            wrap: wrap,
+            tint: tint,
+            mean_free_path: mean_free_path,
+        };
+        self
+    }
+
+    /// Sets the specular reflection constant and the shininess of Blinn-Phong highlights.
+    pub fn specular(mut self, specular: Float, shininess: Float) -> MaterialBuilder {
+        self.material.specular = specular;
+        self.material.shininess = shininess;
+        self.material.specular_model = SpecularModel::BlinnPhong;
+        self
+    }
+
+    /// Uses physically based (GGX) highlights with the given roughness and reflectance
+    /// at normal incidence.
+    pub fn ggx(mut self, roughness: Float, f0: Float) -> MaterialBuilder {
+        self.material.specular_model = SpecularModel::Ggx {
+            roughness: roughness,
+            f0: f0,
+        };
+        self
+    }
+
+    /// Sets the mirror reflectance to the same value for all channels.
+    pub fn reflectance(self, reflectance: Float) -> MaterialBuilder {
+        self.tinted_reflectance(Color::new_gray(reflectance))
+    }
+
+    /// Sets the mirror reflectance of each channel.
+    pub fn tinted_reflectance(mut self, reflectance: Color) -> MaterialBuilder {
+        self.material.reflectance = reflectance;
+        self
+    }
+
+    /// Makes the material transparent with the given refractivity and refraction index.
+    pub fn refraction(mut self, refractivity: Color, refraction_index: Float) -> MaterialBuilder {
+        self.material.refractivity = refractivity;
+        self.material.refraction_index = refraction_index;
+        self
+    }
+
+    /// Sets the dependence of the refraction index on the wavelength.
+    pub fn dispersion(mut self, dispersion: Dispersion) -> MaterialBuilder {
+        self.material.dispersion = dispersion;
+        self
+    }
+
+    /// Sets the absorption coefficient per unit distance inside the material.
+    pub fn absorption(mut self, absorption: Color) -> MaterialBuilder {
+        self.material.absorption = absorption;
+        self
+    }
+
+    /// Sets the emitted light.
+    pub fn emission(mut self, emission: Color) -> MaterialBuilder {
+        self.material.emission = emission;
+        self
+    }
+
+    /// Sets a custom shading model.
+    pub fn bsdf(mut self, bsdf: &'static (Bsdf + Sync)) -> MaterialBuilder {
+        self.material.bsdf = Some(bsdf);
+        self
+    }
+
+    /// Sets the texture that the color is multiplied with.
+    pub fn color_texture(mut self, texture: &'static (Texture + Sync)) -> MaterialBuilder {
+        self.material.color_texture = Some(texture);
+        self
+    }
+
+    /// Sets the texture that the roughness is multiplied with.
+    pub fn roughness_texture(mut self, texture: &'static (Texture + Sync)) -> MaterialBuilder {
+        self.material.roughness_texture = Some(texture);
+        self
+    }
+
+    /// Sets the normal map.
+    pub fn normal_texture(mut self, texture: &'static (Texture + Sync)) -> MaterialBuilder {
+        self.material.normal_texture = Some(texture);
+        self
+    }
+
+    /// Sets which sides of the surface are visible.
+    pub fn sides(mut self, sides: Sides) -> MaterialBuilder {
+        self.material.sides = sides;
+        self
+    }
+
+    /// Sets whether the surface is a holdout, see `Material::holdout`.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let scene = Scene::builder()
+    ///     .resolution(9, 9)
+    ///     .add(Plane {
+    ///         normal: Vec3::e3(),
+    ///         offset: -5.0,
+    ///         material: color_material(white()),
+    ///     })
+    ///     .add(Sphere {
+    ///         center: Vec3::zero(),
+    ///         radius: 2.0,
+    ///         material: Material::builder().holdout(true).build(),
+    ///     })
+    ///     .build()
+    ///     .unwrap();
+    /// let image = scene.render();
+    /// assert_eq!(image.get(4, 4).transparency(), 1.0);
+    /// assert_eq!(image.get(0, 0).transparency(), 0.0);
+    /// ```
+    pub fn holdout(mut self, holdout: bool) -> MaterialBuilder {
+        self.material.holdout = holdout;
+        self
+    }
+
+    /// Returns the material.
+    pub fn build(self) -> Material {
+        self.material
+    }
 }
 
 /// Creates a material that behaves like nothing.
@@ -29,23 +387,43 @@ pub fn vacuum() -> Material {
         color: black(),
         ambient: 0.,
         diffuse: 0.,
+        diffuse_model: DiffuseModel::Lambert,
         specular: 0.,
         shininess: 1.,
-        reflectance: 0.,
-        refractivity: 1.,
+        specular_model: SpecularModel::BlinnPhong,
+        reflectance: black(),
+        refractivity: white(),
         refraction_index: 1.,
+        dispersion: Dispersion::None,
+        absorption: black(),
+        emission: black(),
+        bsdf: None,
+        color_texture: None,
+        roughness_texture: None,
+        normal_texture: None,
+        sides: Sides::Both,
+        holdout: false,
     }
 }
 
 /// Creates a mirror-like material of the given `reflectance` with the given `color`.
-pub fn reflective_material(reflectance: f64, color: Color) -> Material {
+pub fn reflective_material(reflectance: Float, color: Color) -> Material {
+    Material {
+        reflectance: Color::new_gray(reflectance),
+        ..tinted_mirror(color, Color::new_gray(reflectance))
+    }
+}
+
+/// Creates a mirror-like material with the given `color` whose reflections are tinted,
+/// e.g. by the color of gold.
+pub fn tinted_mirror(color: Color, reflectance: Color) -> Material {
     Material {
         color: color,
         reflectance: reflectance,
-        diffuse: 0.9 - reflectance,
+        diffuse: 0.9 - reflectance.max_channel(),
         specular: 0.1,
         shininess: 50.,
-        refractivity: 0.,
+        refractivity: black(),
         ..vacuum()
     }
 }
@@ -54,7 +432,7 @@ pub fn reflective_material(reflectance: f64, color: Color) -> Material {
 pub fn glass() -> Material {
     Material {
         refraction_index: 1.5,
-        refractivity: 0.9,
+        refractivity: Color::new_gray(0.9),
         specular: 0.1,
         shininess: 200.,
         color: white(),
@@ -62,17 +440,89 @@ pub fn glass() -> Material {
     }
 }
 
+/// Creates colored glass that acts as a color filter, independent of its thickness.
+pub fn colored_glass(tint: Color) -> Material {
+    Material { refractivity: 0.9 * tint, ..glass() }
+}
+
+/// Creates dense flint glass (Schott SF11), which is strongly dispersive and
+/// therefore well suited for prisms.
+pub fn flint_glass() -> Material {
+    Material {
+        refraction_index: 1.785,
+        dispersion: Dispersion::Sellmeier {
+            b: [1.73759695, 0.313747346, 1.89878101],
+            c: [0.013188707, 0.0623068142, 155.23629],
+        },
+        ..glass()
+    }
+}
+
+/// Creates a material that looks like diamond, with its characteristic rainbow fringes.
+pub fn diamond() -> Material {
+    Material {
+        refraction_index: 2.417,
+        dispersion: Dispersion::Cauchy { b: 0.023 },
+        refractivity: Color::new_gray(0.95),
+        specular: 0.2,
+        shininess: 500.,
+        ..glass()
+    }
+}
+
+/// Creates glass that absorbs light inside, so that thick parts are tinted more strongly.
+///
+/// `absorption` is the absorption coefficient per unit distance for each channel.
+/// Light is absorbed inside of any closed object, e.g. of a mesh:
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let cube = "v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\n\
+///             v -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1\n\
+///             f 1 3 2\nf 1 4 3\nf 5 6 7\nf 5 7 8\nf 1 2 6\nf 1 6 5\nf 4 8 7\nf 4 7 3\n\
+///             f 1 5 8\nf 1 8 4\nf 2 3 7\nf 2 7 6\n";
+/// // Without refraction and reflection, only the absorption changes the light.
+/// let absorbing = Material { absorption: Color::new_gray(1.0), ..vacuum() };
+/// let lamp = || {
+///     Plane { normal: Vec3::e3(), offset: -5.0, material: emissive_material(white(), 1.0) }
+/// };
+/// let empty = Scene::builder().add(lamp()).build().unwrap();
+/// let scene = Scene::builder()
+///     .add(Mesh::from_obj_reader(cube.as_bytes(), absorbing).unwrap())
+///     .add(lamp())
+///     .build()
+///     .unwrap();
+/// // The light travels 2 units through the cube.
+/// let ray = Ray::new(Vec3::new(0.1, 0.2, 5.0), -Vec3::e3());
+/// let ratio = scene.trace(&ray).opaque().red() / empty.trace(&ray).opaque().red();
+/// assert!((ratio - (-2.0 as Float).exp()).abs() < 1e-3);
+/// ```
+pub fn absorbing_glass(absorption: Color) -> Material {
+    Material { absorption: absorption, ..glass() }
+}
+
 /// Creates a diffuse black material.
 pub fn neutral_material() -> Material {
     Material {
         color: black(),
         ambient: 0.2,
         diffuse: 0.6,
+        diffuse_model: DiffuseModel::Lambert,
         specular: 0.2,
         shininess: 10.,
-        reflectance: 0.,
-        refractivity: 0.,
+        specular_model: SpecularModel::BlinnPhong,
+        reflectance: black(),
+        refractivity: black(),
         refraction_index: 1.,
+        dispersion: Dispersion::None,
+        absorption: black(),
+        emission: black(),
+        bsdf: None,
+        color_texture: None,
+        roughness_texture: None,
+        normal_texture: None,
+        sides: Sides::Both,
+        holdout: false,
     }
 }
 
@@ -80,3 +530,274 @@ pub fn neutral_material() -> Material {
 pub fn color_material(c: Color) -> Material {
     Material { color: c, ..neutral_material() }
 }
+
+/// Creates a material that glows in the given color.
+/// The `strength` scales the emitted light and may exceed 1.
+pub fn emissive_material(c: Color, strength: Float) -> Material {
+    Material {
+        color: c,
+        emission: strength * c,
+        ..neutral_material()
+    }
+}
+
+/// Creates a rough matte material of the given color, like clay or plaster.
+/// The `roughness` is the standard deviation of the microfacet slope angle in radians.
+pub fn matte_material(c: Color, roughness: Float) -> Material {
+    Material {
+        color: c,
+        ambient: 0.2,
+        diffuse: 0.8,
+        diffuse_model: DiffuseModel::OrenNayar { roughness: roughness },
+        specular: 0.,
+        ..neutral_material()
+    }
+}
+
+/// Creates a material of the given color with physically based (GGX) specular highlights.
+/// The `roughness` ranges from 0 (smooth and shiny) to 1 (rough and dull).
+pub fn glossy_material(c: Color, roughness: Float) -> Material {
+    Material {
+        color: c,
+        specular_model: SpecularModel::Ggx {
+            roughness: roughness,
+            f0: 0.04,
+        },
+        ..neutral_material()
+    }
+}
+
+/// Creates shiny plastic of the given color, with white highlights and faint reflections.
+///
+/// Like the other presets, it can be adjusted with the struct update syntax:
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let presets = [plastic(white()), rubber(white()), chrome(), gold(), frosted_glass(), water(),
+///                wax(white()), marble()];
+/// assert!(presets.iter().all(|m| validate_material(m).is_empty()));
+/// let matte_red = Material { specular: 0.1, ..plastic(Color::new(1.0, 0.0, 0.0)) };
+/// ```
+pub fn plastic(c: Color) -> Material {
+    Material {
+        color: c,
+        ambient: 0.1,
+        diffuse: 0.7,
+        specular: 0.4,
+        shininess: 100.,
+        reflectance: Color::new_gray(0.05),
+        ..neutral_material()
+    }
+}
+
+/// Creates wax of the given color, which lets light through its thin parts.
+pub fn wax(c: Color) -> Material {
+    Material {
+        color: c,
+        ambient: 0.1,
+        diffuse: 0.8,
+        diffuse_model: DiffuseModel::Subsurface {
+            wrap: 0.5,
+            tint: c,
+            mean_free_path: 0.5,
+        },
+        specular: 0.2,
+        shininess: 30.,
+        ..neutral_material()
+    }
+}
+
+/// Creates polished white marble, with a soft translucency.
+pub fn marble() -> Material {
+    Material {
+        color: Color::new(0.9, 0.88, 0.85),
+        ambient: 0.1,
+        diffuse: 0.75,
+        diffuse_model: DiffuseModel::Subsurface {
+            wrap: 0.3,
+            tint: Color::new(0.9, 0.85, 0.75),
+            mean_free_path: 0.2,
+        },
+        specular: 0.3,
+        shininess: 200.,
+        reflectance: Color::new_gray(0.03),
+        ..neutral_material()
+    }
+}
+
+/// Creates matte rubber of the given color, with broad, faint highlights.
+pub fn rubber(c: Color) -> Material {
+    Material {
+        color: c,
+        ambient: 0.1,
+        diffuse: 0.8,
+        diffuse_model: DiffuseModel::OrenNayar { roughness: 0.3 },
+        specular: 0.05,
+        shininess: 5.,
+        ..neutral_material()
+    }
+}
+
+/// Creates polished chrome, a nearly perfect colorless mirror.
+pub fn chrome() -> Material {
+    Material {
+        specular: 0.3,
+        shininess: 500.,
+        ..tinted_mirror(Color::new_gray(0.55), Color::new_gray(0.8))
+    }
+}
+
+/// Creates polished gold, a mirror with yellow reflections.
+pub fn gold() -> Material {
+    let color = Color::new(1.0, 0.77, 0.34);
+    Material {
+        specular: 0.3,
+        shininess: 300.,
+        ..tinted_mirror(color, 0.8 * color)
+    }
+}
+
+/// Creates frosted glass, which lets light through but scatters part of it diffusely.
+///
+/// The renderer only traces sharp refractions, so the frosting is approximated by a
+/// diffuse white part and broad highlights.
+pub fn frosted_glass() -> Material {
+    Material {
+        refractivity: Color::new_gray(0.6),
+        ambient: 0.1,
+        diffuse: 0.3,
+        specular_model: SpecularModel::Ggx {
+            roughness: 0.5,
+            f0: 0.04,
+        },
+        ..glass()
+    }
+}
+
+/// Creates clear water, which absorbs red light inside and therefore looks blue-green
+/// in deep parts.
+pub fn water() -> Material {
+    Material {
+        refraction_index: 1.333,
+        refractivity: Color::new_gray(0.95),
+        absorption: Color::new(0.3, 0.05, 0.02),
+        specular: 0.2,
+        shininess: 300.,
+        ..glass()
+    }
+}
+
+/// Represents a material in the metallic/roughness model of physically based rendering,
+/// as used by glTF, for example.
+///
+/// It can be converted from and to a `Material`:
+///
+/// ```
+/// use raydiancy::color::*;
+/// use raydiancy::material::*;
+/// let gold = PbrMaterial {
+///     base_color: Color::new(1.0, 0.77, 0.34),
+///     metallic: 1.0,
+///     roughness: 0.3,
+///     ..PbrMaterial::default()
+/// };
+/// let material = gold.to_material();
+/// let converted = PbrMaterial::from(material);
+/// assert!((converted.roughness - 0.3).abs() < 1e-6);
+/// assert_eq!(converted.metallic, 1.0);
+/// ```
+#[derive(Debug, Copy, Clone)]
+pub struct PbrMaterial {
+    /// The albedo of dielectrics and the reflectance of metals.
+    pub base_color: Color,
+    /// 0 for dielectrics, 1 for metals. Values in between blend the two.
+    pub metallic: Float,
+    /// The perceptual roughness, between 0 (smooth) and 1 (rough).
+    pub roughness: Float,
+    /// The index of refraction (used for the reflectance of dielectrics).
+    pub ior: Float,
+    /// The light emitted by the material.
+    pub emission: Color,
+}
+
+impl Default for PbrMaterial {
+    /// Returns a white, moderately rough dielectric.
+    fn default() -> PbrMaterial {
+        PbrMaterial {
+            base_color: white(),
+            metallic: 0.,
+            roughness: 0.5,
+            ior: 1.5,
+            emission: black(),
+        }
+    }
+}
+
+impl PbrMaterial {
+    /// Converts the material to the representation used by the renderer.
+    ///
+    /// Metals become mirrors whose reflectance decreases with the roughness.
+    /// Since specular highlights are not tinted, metals lose some of their color.
+    pub fn to_material(&self) -> Material {
+        let dielectric_f0 = ((self.ior - 1.) / (self.ior + 1.)).powi(2);
+        let f0 = dielectric_f0 + self.metallic * (self.base_color.luminance() - dielectric_f0);
+        let dielectric = 1. - self.metallic;
+        Material {
+            color: self.base_color,
+            ambient: 0.2 * dielectric,
+            diffuse: 0.8 * dielectric,
+            diffuse_model: DiffuseModel::Lambert,
+            specular: 0.,
+            shininess: 1.,
+            specular_model: SpecularModel::Ggx {
+                roughness: self.roughness,
+                f0: f0,
+            },
+            reflectance: (self.metallic * (1. - self.roughness)) * self.base_color,
+            refractivity: black(),
+            refraction_index: self.ior,
+            dispersion: Dispersion::None,
+            absorption: black(),
+            emission: self.emission,
+            bsdf: None,
+            color_texture: None,
+            roughness_texture: None,
+            normal_texture: None,
+            sides: Sides::Both,
+            holdout: false,
+        }
+    }
+}
+
+impl From<Material> for PbrMaterial {
+    /// Approximates a material by a physically based one.
+    ///
+    /// The fraction of mirror reflection among all reflected light is interpreted as metalness.
+    /// For Blinn-Phong materials, the roughness is derived from the shininess.
+    fn from(m: Material) -> PbrMaterial {
+        let mut roughness = match m.specular_model {
+            SpecularModel::Ggx { roughness, .. } => roughness,
+            SpecularModel::BlinnPhong => {
+                // Usual mapping of the Blinn-Phong exponent to the GGX alpha (= roughness^2).
+                (2. / (m.shininess + 2.)).sqrt().sqrt()
+            }
+        };
+        let reflectance = m.reflectance.max_channel();
+        let metallic = if reflectance > 0. {
+            reflectance / (reflectance + m.diffuse.max(0.))
+        } else {
+            0.
+        };
+        if metallic > 0. {
+            // Sharp mirror reflections indicate a smooth metal (see `to_material`).
+            roughness = roughness.min(1. - reflectance / metallic);
+        }
+        PbrMaterial {
+            base_color: m.color,
+            metallic: metallic,
+            roughness: roughness.max(0.),
+            ior: m.refraction_index,
+            emission: m.emission,
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use basic::*;
+use objects::aabb::Aabb;
+
+/// Trait for regions filled with a medium that absorbs and scatters light,
+/// like smoke, clouds, or haze.
+///
+/// The renderer marches along the rays through the region,
+/// see `Scene::medium` and `Scene::volumes`.
+pub trait Volume {
+    /// Returns the interval of distances of the ray from `t_min` to `t_max` inside the region,
+    /// if it is nonempty.
+    fn segment(&self, ray: &Ray, t_max: Float) -> Option<(Float, Float)>;
+
+    /// Returns the absorption and scattering coefficients at the point,
+    /// i.e. the fractions of the light absorbed and scattered per unit of distance.
+    fn coefficients(&self, point: Vec3) -> (Color, Color);
+
+    /// Returns the number of points per ray where the medium is evaluated.
+    /// More steps take longer, but reduce the noise.
+    fn steps(&self) -> usize;
+
+    /// Returns the fraction of light that passes through the region
+    /// along the ray from `t_min` to `t_max`.
+    ///
+    /// The default implementation evaluates the coefficients at `steps()` points.
+    fn transmittance(&self, ray: &Ray, t_max: Float) -> Color {
+        let (start, end) = match self.segment(ray, t_max) {
+            Some(segment) => segment,
+            None => return white(),
+        };
+        let steps = self.steps().max(1);
+        let step = (end - start) / steps as Float;
+        let mut optical_depth = black();
+        for i in 0..steps {
+            let (absorption, scattering) =
+                self.coefficients(ray.origin() + (start + (i as Float + 0.5) * step) * ray.dir());
+            optical_depth = optical_depth + step * (absorption + scattering);
+        }
+        Color::transmittance(optical_depth, 1.)
+    }
+}
+
+/// A homogeneous participating medium like smoke, haze, or murky water, see `Scene::medium`.
+///
+/// Light passing through the medium is attenuated according to the Beer-Lambert law,
+/// and the medium scatters the light of the light sources towards the viewer,
+/// which makes light shafts visible.
+/// The scattering is computed by ray marching with a single scattering event,
+/// which scatters the light equally in all directions.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let medium = Medium {
+///     absorption: Color::new_gray(0.1),
+///     scattering: Color::new(0.1, 0.2, 0.3),
+///     region: Some(Aabb::new(Vec3::zero(), Vec3::ones())),
+///     steps: 16,
+/// };
+/// assert!(appr(medium.extinction().blue(), 0.4));
+/// let ray = Ray::newn(Vec3::new(-1.0, 0.5, 0.5), Vec3::e1().to());
+/// assert_eq!(medium.segment(&ray, Float::INFINITY), Some((1.0, 2.0)));
+/// assert_eq!(medium.segment(&ray, 0.5), None);
+/// // Only the part of the ray in the region attenuates the light.
+/// assert!(appr(medium.transmittance(&ray, 10.0).blue(), (-0.4 as Float).exp()));
+///
+/// // Without a region, infinite rays end where the light is gone.
+/// let haze = Medium { region: None, ..medium };
+/// let (_, end) = haze.segment(&ray, Float::INFINITY).unwrap();
+/// assert!(end.is_finite() && haze.transmittance(&ray, end).red() < 0.01);
+/// assert_eq!(haze.transmittance(&ray, Float::INFINITY).red(), 0.0);
+/// ```
+#[derive(Debug, Copy, Clone)]
+pub struct Medium {
+    /// The fraction of the light absorbed per unit of distance, per color channel.
+    pub absorption: Color,
+    /// The fraction of the light scattered per unit of distance, per color channel.
+    pub scattering: Color,
+    /// The box filled with the medium, or `None` if it fills the whole scene.
+    pub region: Option<Aabb>,
+    /// The number of points per ray where the scattered light is computed.
+    /// More steps take longer, but reduce the noise.
+    pub steps: usize,
+}
+
+impl Medium {
+    /// Returns the extinction coefficients, i.e. the fraction of the light
+    /// that is absorbed or scattered away per unit of distance.
+    pub fn extinction(&self) -> Color {
+        self.absorption + self.scattering
+    }
+}
+
+impl Volume for Medium {
+    /// If the medium fills the whole scene and the ray is infinite, the interval ends
+    /// where the medium has absorbed all but a negligible fraction of the light.
+    fn segment(&self, ray: &Ray, t_max: Float) -> Option<(Float, Float)> {
+        match self.region {
+            Some(ref region) => region.clip_ray(ray, ray.t_min(), t_max),
+            None => {
+                let e = self.extinction();
+                let length = [e.red(), e.green(), e.blue()]
+                    .iter()
+                    .filter(|&&e| e > 0.)
+                    .map(|&e| -NEGLIGIBLE_TRANSMITTANCE.ln() / e)
+                    .fold(0., Float::max);
+                let end = t_max.min(length);
+                if ray.t_min() < end {
+                    Some((ray.t_min(), end))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    fn coefficients(&self, _point: Vec3) -> (Color, Color) {
+        (self.absorption, self.scattering)
+    }
+
+    fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// The transmittance of the homogeneous medium is computed exactly.
+    fn transmittance(&self, ray: &Ray, t_max: Float) -> Color {
+        if self.region.is_none() {
+            // The ray may be longer than its segment, which ends once the light is gone.
+            // (Avoid 0 * infinity for channels without extinction.)
+            return Color::transmittance(self.extinction(), t_max.min(Float::MAX));
+        }
+        match self.segment(ray, t_max) {
+            Some((start, end)) => Color::transmittance(self.extinction(), end - start),
+            None => white(),
+        }
+    }
+}
+
+/// The transmittance below which the light of a ray through the medium is ignored.
+const NEGLIGIBLE_TRANSMITTANCE: Float = 1e-3;
+
+/// The phase function of isotropic scattering, i.e. the probability density
+/// of the scattering directions if all directions are equally likely.
+pub const ISOTROPIC_PHASE: Float = 1. / (4. * PI);
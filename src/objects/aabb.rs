@@ -1,29 +1,5 @@
-use std::f64;
 use basic::*;
 
-/// Helper function for intersection test.
-fn sign(f: f64) -> usize {
-    if f < 0. {
-        1
-    } else {
-        0
-    }
-}
-
-/// The material used when drawing bounding boxes for debugging purposes.
-fn bounding_box_material() -> Material {
-    Material {
-        color: Color::new(1.0, 0.2, 0.2),
-        ambient: 0.025,
-        diffuse: 0.025,
-        specular: 0.,
-        shininess: 1.,
-        reflectance: 0.,
-        refractivity: 0.95,
-        refraction_index: 1.,
-    }
-}
-
 /// Represents an axis-aligned bounding box.
 #[derive(Copy, Clone, Debug)]
 pub struct Aabb {
@@ -39,7 +15,7 @@ impl Aabb {
     /// Creates an empty axis-aligned bounding box.
     /// It is the neutral element of `Aabb::union`.
     pub fn empty() -> Aabb {
-        Aabb { vertices: [f64::INFINITY * Vec3::ones(), -f64::INFINITY * Vec3::ones()] }
+        Aabb { vertices: [Float::INFINITY * Vec3::ones(), -Float::INFINITY * Vec3::ones()] }
     }
 
     /// Returns the vertex with smallest coordinates.
@@ -52,6 +28,11 @@ impl Aabb {
         self.vertices[1]
     }
 
+    /// Returns whether the box is empty, e.g. `Aabb::empty()`.
+    pub fn is_empty(&self) -> bool {
+        (0..3).any(|i| self.vertices[0][i] > self.vertices[1][i])
+    }
+
     /// Returns whether `self` contains the other bounding box.
     ///
     /// ```
@@ -70,6 +51,45 @@ impl Aabb {
         b.contains(self)
     }
 
+    /// Returns whether the two boxes have a common point, including touching boundaries.
+    ///
+    /// ```
+    /// use raydiancy::basic::*;
+    /// use raydiancy::objects::aabb::*;
+    /// let a = Aabb::new(Vec3::zero(), Vec3::ones());
+    /// let b = Aabb::new(Vec3::ones(), 2.0 * Vec3::ones());
+    /// let c = Aabb::new(Vec3::new(1.5, 0.0, 0.0), Vec3::new(2.0, 1.0, 1.0));
+    /// assert!(a.intersects(&b) && b.intersects(&a));
+    /// assert!(!a.intersects(&c) && b.intersects(&c));
+    /// assert!(!a.intersects(&Aabb::empty()));
+    /// assert!(a.contains_point(0.5 * Vec3::ones()) && a.contains_point(Vec3::ones()));
+    /// assert!(!a.contains_point(Vec3::new(1.1, 0.5, 0.5)));
+    /// assert!(a.padded(0.2).contains_point(Vec3::new(1.1, 0.5, 0.5)));
+    /// assert!(a.padded(0.5).intersects(&c));
+    /// assert!(Aabb::empty().padded(1.0).is_empty());
+    /// ```
+    pub fn intersects(&self, b: &Aabb) -> bool {
+        (0..3).all(|i| {
+            self.vertices[0][i] <= b.vertices[1][i] && b.vertices[0][i] <= self.vertices[1][i]
+        })
+    }
+
+    /// Returns whether the point lies inside the box or on its boundary.
+    pub fn contains_point(&self, point: Vec3) -> bool {
+        (0..3).all(|i| self.vertices[0][i] <= point[i] && point[i] <= self.vertices[1][i])
+    }
+
+    /// Returns the box enlarged by `eps` in every direction.
+    ///
+    /// Use it to make the queries above robust against rounding errors, e.g. with `EPS`.
+    pub fn padded(&self, eps: Float) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+        let pad = eps * Vec3::ones();
+        Aabb { vertices: [self.vertices[0] - pad, self.vertices[1] + pad] }
+    }
+
     /// Returns the tightest bounding box around the union of the two given ones.
     pub fn union(&self, b: &Aabb) -> Aabb {
         Aabb {
@@ -77,6 +97,16 @@ impl Aabb {
         }
     }
 
+    /// Enlarges the box such that it contains `point`.
+    pub fn grow(&mut self, point: Vec3) {
+        self.vertices = [self.vertices[0].min(point), self.vertices[1].max(point)];
+    }
+
+    /// Enlarges the box such that it contains the other box, i.e. replaces it by the union.
+    pub fn grow_aabb(&mut self, b: &Aabb) {
+        *self = self.union(b);
+    }
+
     /// Given an iterator of bounding boxes, returns the tightest box around their union.
     pub fn union_all<T>(boxes: &mut T) -> Aabb
         where T: Iterator<Item = Aabb>
@@ -89,8 +119,44 @@ impl Aabb {
         self.vertices[1] - self.vertices[0]
     }
 
+    /// Returns the surface area of the box, which is 0 for the empty box.
+    ///
+    /// The surface area heuristic uses it to estimate the probability that a ray hits the box.
+    ///
+    /// ```
+    /// use raydiancy::basic::*;
+    /// use raydiancy::objects::aabb::*;
+    /// let mut aabb = Aabb::empty();
+    /// assert!(aabb.is_empty());
+    /// assert_eq!(aabb.surface_area(), 0.0);
+    /// aabb.grow(Vec3::ones());
+    /// assert!(!aabb.is_empty());
+    /// assert_eq!((aabb.min(), aabb.max()), (Vec3::ones(), Vec3::ones()));
+    /// assert_eq!(aabb.surface_area(), 0.0);
+    /// assert_eq!(aabb.centroid(), Vec3::ones());
+    /// aabb.grow(Vec3::new(2.0, 3.0, 4.0));
+    /// assert_eq!(aabb.surface_area(), 2.0 * (2.0 + 6.0 + 3.0));
+    /// assert_eq!(aabb.centroid(), Vec3::new(1.5, 2.0, 2.5));
+    /// aabb.grow_aabb(&Aabb::empty());
+    /// assert_eq!((aabb.min(), aabb.max()), (Vec3::ones(), Vec3::new(2.0, 3.0, 4.0)));
+    /// aabb.grow_aabb(&Aabb::new(Vec3::zero(), Vec3::ones()));
+    /// assert_eq!(aabb.min(), Vec3::zero());
+    /// ```
+    pub fn surface_area(&self) -> Float {
+        if self.is_empty() {
+            return 0.;
+        }
+        let d = self.diagonal();
+        2. * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
+    /// Returns the center of the box. It is not defined for the empty box.
+    pub fn centroid(&self) -> Vec3 {
+        0.5 * (self.vertices[0] + self.vertices[1])
+    }
+
     /// Returns the direction of the longest side (0 for x, 1 for y, 2 for z) and its length.
-    pub fn longest_side(&self) -> (usize, f64) {
+    pub fn longest_side(&self) -> (usize, Float) {
         let dim = self.diagonal();
         let mut max = (0, dim.x());
         if dim.y() > max.1 {
@@ -102,21 +168,83 @@ impl Aabb {
         max
     }
 
-    /// Checks wether the intersection of the ray from t=EPS to t=t1 and the box is nonempty.
+    /// Returns the tightest axis-aligned bounding box around the box transformed by `m`.
+    ///
+    /// This is the bounding box of the eight transformed vertices.
+    /// The empty box stays empty.
+    ///
+    /// ```
+    /// use raydiancy::basic::*;
+    /// use raydiancy::objects::aabb::*;
+    /// let aabb = Aabb::new(Vec3::zero(), Vec3::new(2.0, 1.0, 1.0));
+    /// let moved = aabb.transformed(&Matrix34::translate(Vec3::new(1.0, 0.0, 0.0)));
+    /// assert_eq!((moved.min(), moved.max()), (Vec3::e1().to(), Vec3::new(3.0, 1.0, 1.0)));
+    /// let m = Matrix34::rotate(Vec3::e3().to(), PI / 2.0);
+    /// let rotated = aabb.transformed(&m);
+    /// assert_eq!((rotated.min(), rotated.max()),
+    ///            (Vec3::new(-1.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 1.0)));
+    /// assert!(Aabb::empty().transformed(&m).min().x().is_infinite());
+    /// ```
+    pub fn transformed(&self, m: &Matrix34) -> Aabb {
+        let mut result = Aabb::empty();
+        if self.is_empty() {
+            return result;
+        }
+        for i in 0..8 {
+            let vertex = Vec3::new(self.vertices[i & 1].x(),
+                                   self.vertices[(i >> 1) & 1].y(),
+                                   self.vertices[(i >> 2) & 1].z());
+            result.grow(*m * vertex);
+        }
+        result
+    }
+
+    /// Returns the part of the ray from t=`t0` to t=`t1` that is inside the box
+    /// as the interval of its distances, if it is nonempty.
+    ///
+    /// ```
+    /// use raydiancy::basic::*;
+    /// use raydiancy::objects::aabb::*;
+    /// let aabb = Aabb::new(Vec3::zero(), Vec3::ones());
+    /// let ray = Ray::newn(Vec3::new(-1.0, 0.5, 0.5), Vec3::e1().to());
+    /// assert_eq!(aabb.clip_ray(&ray, 0.0, Float::INFINITY), Some((1.0, 2.0)));
+    /// assert_eq!(aabb.clip_ray(&ray, 1.5, 10.0), Some((1.5, 2.0)));
+    /// assert_eq!(aabb.clip_ray(&ray, 0.0, 0.5), None);
+    /// assert_eq!(Aabb::empty().clip_ray(&ray, 0.0, 10.0), None);
+    /// ```
+    pub fn clip_ray(&self, r: &Ray, t0: Float, t1: Float) -> Option<(Float, Float)> {
+        if self.is_empty() {
+            return None;
+        }
+        let r_inv = r.inv_dir();
+        let (mut tmin, mut tmax) = (t0, t1);
+        for i in 0..3 {
+            let a = (self.vertices[0][i] - r.origin()[i]) * r_inv[i];
+            let b = (self.vertices[1][i] - r.origin()[i]) * r_inv[i];
+            tmin = tmin.max(a.min(b));
+            tmax = tmax.min(a.max(b));
+        }
+        if tmin <= tmax {
+            Some((tmin, tmax))
+        } else {
+            None
+        }
+    }
+
+    /// Checks wether the intersection of the ray from `t_min` to t=t1 and the box is nonempty.
     ///
     /// In contrast to is_hit_by, this also returns true
     /// if this part of the ray is completely inside the box.
-    pub fn passes_through(&self, r: &Ray, t1: f64) -> bool {
+    pub fn passes_through(&self, r: &Ray, t1: Float) -> bool {
         // This an adaption of the code from the paper
         // "An Efficient and Robust Ray–Box Intersection Algorithm" by Williams et. al.
         // http://www.cs.utah.edu/~awilliam/box/
-        // TODO: Maybe store the inverse vector and sign inside struct Ray?
-        let r_inv = Vec3::new(1. / r.dir[0], 1. / r.dir[1], 1. / r.dir[2]);
-        let sign = [sign(r_inv[0]), sign(r_inv[1]), sign(r_inv[2])];
-        let mut tmin = (self.vertices[sign[0]].x() - r.origin.x()) * r_inv.x();
-        let mut tmax = (self.vertices[1 - sign[0]].x() - r.origin.x()) * r_inv.x();
-        let tymin = (self.vertices[sign[1]].y() - r.origin.y()) * r_inv.y();
-        let tymax = (self.vertices[1 - sign[1]].y() - r.origin.y()) * r_inv.y();
+        let r_inv = r.inv_dir();
+        let sign = r.sign();
+        let mut tmin = (self.vertices[sign[0]].x() - r.origin().x()) * r_inv.x();
+        let mut tmax = (self.vertices[1 - sign[0]].x() - r.origin().x()) * r_inv.x();
+        let tymin = (self.vertices[sign[1]].y() - r.origin().y()) * r_inv.y();
+        let tymax = (self.vertices[1 - sign[1]].y() - r.origin().y()) * r_inv.y();
         if (tmin > tymax) || (tymin > tmax) {
             return false;
         }
@@ -126,8 +254,8 @@ impl Aabb {
         if tymax < tmax {
             tmax = tymax;
         }
-        let tzmin = (self.vertices[sign[2]].z() - r.origin.z()) * r_inv.z();
-        let tzmax = (self.vertices[1 - sign[2]].z() - r.origin.z()) * r_inv.z();
+        let tzmin = (self.vertices[sign[2]].z() - r.origin().z()) * r_inv.z();
+        let tzmax = (self.vertices[1 - sign[2]].z() - r.origin().z()) * r_inv.z();
         if (tmin > tzmax) || (tzmin > tmax) {
             return false;
         }
@@ -137,24 +265,23 @@ impl Aabb {
         if tzmax < tmax {
             tmax = tzmax;
         }
-        tmin < t1 && tmax > EPS
+        tmin < t1 && tmax > r.t_min()
     }
 
     /// Computes the distance of the nearest intersection point if its less than `t1`,
-    /// and `f64::INFINITY` otherwise.
-    pub fn distance(&self, r: &Ray, t1: f64) -> f64 {
+    /// and `Float::INFINITY` otherwise.
+    pub fn distance(&self, r: &Ray, t1: Float) -> Float {
         // This an adaption of the code from the paper
         // "An Efficient and Robust Ray–Box Intersection Algorithm" by Williams et. al.
         // http://www.cs.utah.edu/~awilliam/box/
-        // TODO: Maybe store the inverse vector and sign inside struct Ray?
-        let r_inv = Vec3::new(1. / r.dir[0], 1. / r.dir[1], 1. / r.dir[2]);
-        let sign = [sign(r_inv[0]), sign(r_inv[1]), sign(r_inv[2])];
-        let mut tmin = (self.vertices[sign[0]].x() - r.origin.x()) * r_inv.x();
-        let mut tmax = (self.vertices[1 - sign[0]].x() - r.origin.x()) * r_inv.x();
-        let tymin = (self.vertices[sign[1]].y() - r.origin.y()) * r_inv.y();
-        let tymax = (self.vertices[1 - sign[1]].y() - r.origin.y()) * r_inv.y();
+        let r_inv = r.inv_dir();
+        let sign = r.sign();
+        let mut tmin = (self.vertices[sign[0]].x() - r.origin().x()) * r_inv.x();
+        let mut tmax = (self.vertices[1 - sign[0]].x() - r.origin().x()) * r_inv.x();
+        let tymin = (self.vertices[sign[1]].y() - r.origin().y()) * r_inv.y();
+        let tymax = (self.vertices[1 - sign[1]].y() - r.origin().y()) * r_inv.y();
         if (tmin > tymax) || (tymin > tmax) {
-            return f64::INFINITY;
+            return Float::INFINITY;
         }
         if tymin > tmin {
             tmin = tymin
@@ -162,10 +289,10 @@ impl Aabb {
         if tymax < tmax {
             tmax = tymax;
         }
-        let tzmin = (self.vertices[sign[2]].z() - r.origin.z()) * r_inv.z();
-        let tzmax = (self.vertices[1 - sign[2]].z() - r.origin.z()) * r_inv.z();
+        let tzmin = (self.vertices[sign[2]].z() - r.origin().z()) * r_inv.z();
+        let tzmax = (self.vertices[1 - sign[2]].z() - r.origin().z()) * r_inv.z();
         if (tmin > tzmax) || (tzmin > tmax) {
-            return f64::INFINITY;
+            return Float::INFINITY;
         }
         if tzmin > tmin {
             tmin = tzmin;
@@ -173,32 +300,35 @@ impl Aabb {
         if tzmax < tmax {
             tmax = tzmax;
         }
-        if tmin < EPS && tmax > t1 {
+        if tmin < r.t_min() && tmax > t1 {
             0.0
-        } else if EPS < tmin && tmin < t1 {
+        } else if r.t_min() < tmin && tmin < t1 {
             tmin
-        } else if EPS < tmax && tmax < t1 {
+        } else if r.t_min() < tmax && tmax < t1 {
             tmax
         } else {
-            f64::INFINITY
+            Float::INFINITY
         }
     }
 
-    /// Computes the intersection with this bounding box.
-    /// This is supposed to be used for debugging only.
-    pub fn intersect(&self, r: &Ray, t1: f64) -> Option<DelayedIntersection> {
+    /// Computes the intersection with this bounding box, made of the given material.
+    /// This is supposed to be used for debugging only, see `BvhOverlay`.
+    pub fn intersect(&self,
+                     r: &Ray,
+                     t1: Float,
+                     material: Material)
+                     -> Option<DelayedIntersection> {
         // This an adaption of the code from the paper
         // "An Efficient and Robust Ray–Box Intersection Algorithm" by Williams et. al.
         // http://www.cs.utah.edu/~awilliam/box/
-        // TODO: Maybe store the inverse vector and sign inside struct Ray?
-        let r_inv = Vec3::new(1. / r.dir[0], 1. / r.dir[1], 1. / r.dir[2]);
-        let sign = [sign(r_inv[0]), sign(r_inv[1]), sign(r_inv[2])];
+        let r_inv = r.inv_dir();
+        let sign = r.sign();
         let mut imin = 0;
         let mut imax = 0;
-        let mut tmin = (self.vertices[sign[0]].x() - r.origin.x()) * r_inv.x();
-        let mut tmax = (self.vertices[1 - sign[0]].x() - r.origin.x()) * r_inv.x();
-        let tymin = (self.vertices[sign[1]].y() - r.origin.y()) * r_inv.y();
-        let tymax = (self.vertices[1 - sign[1]].y() - r.origin.y()) * r_inv.y();
+        let mut tmin = (self.vertices[sign[0]].x() - r.origin().x()) * r_inv.x();
+        let mut tmax = (self.vertices[1 - sign[0]].x() - r.origin().x()) * r_inv.x();
+        let tymin = (self.vertices[sign[1]].y() - r.origin().y()) * r_inv.y();
+        let tymax = (self.vertices[1 - sign[1]].y() - r.origin().y()) * r_inv.y();
         if (tmin > tymax) || (tymin > tmax) {
             return None;
         }
@@ -210,8 +340,8 @@ impl Aabb {
             tmax = tymax;
             imax = 1;
         }
-        let tzmin = (self.vertices[sign[2]].z() - r.origin.z()) * r_inv.z();
-        let tzmax = (self.vertices[1 - sign[2]].z() - r.origin.z()) * r_inv.z();
+        let tzmin = (self.vertices[sign[2]].z() - r.origin().z()) * r_inv.z();
+        let tzmax = (self.vertices[1 - sign[2]].z() - r.origin().z()) * r_inv.z();
         if (tmin > tzmax) || (tzmin > tmax) {
             return None;
         }
@@ -223,18 +353,18 @@ impl Aabb {
             tmax = tzmax;
             imax = 2;
         }
-        if tmax < EPS || tmin > t1 {
+        if tmax < r.t_min() || tmin > t1 {
             return None;
         }
-        let (normal, t) = if tmin > EPS {
+        let (normal, t) = if tmin > r.t_min() {
             (-Vec3::e(imin), tmin)
         } else if tmax < t1 {
             (Vec3::e(imax), tmax)
         } else {
-            // The part from EPS to t1 of the ray is completely inside the box:
+            // The part from `t_min` to t1 of the ray is completely inside the box:
             return None;
         };
-        let inter = Intersection::new(r, t, normal.assert_unit_vector(), bounding_box_material());
+        let inter = Intersection::new(r, t, normal.assert_unit_vector(), material);
         Some(DelayedIntersection::new(t, move || inter))
     }
 }
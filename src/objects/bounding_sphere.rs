@@ -0,0 +1,59 @@
+use basic::*;
+use objects::aabb::Aabb;
+
+/// Represents a sphere containing an object, see `Surface::bounding_sphere`.
+///
+/// ```
+/// use raydiancy::basic::*;
+/// use raydiancy::objects::aabb::*;
+/// use raydiancy::objects::bounding_sphere::*;
+/// let sphere = BoundingSphere::from_aabb(&Aabb::new(-Vec3::ones(), Vec3::ones()));
+/// assert_eq!(sphere.center, Vec3::zero());
+/// assert!(appr(sphere.radius, (3.0 as Float).sqrt()));
+/// assert!(sphere.contains_point(0.9 * Vec3::ones()) && !sphere.contains_point(2.0 * Vec3::e1()));
+///
+/// let ray = Ray::new(Vec3::new(0.0, 1.5, -5.0), Vec3::e3());
+/// assert!(sphere.is_hit_by(&ray, Float::INFINITY));
+/// assert!(!sphere.is_hit_by(&ray, 3.0));
+/// assert!(!sphere.is_hit_by(&Ray::new(Vec3::new(0.0, 2.0, -5.0), Vec3::e3()), 10.0));
+/// // Rays starting inside hit the sphere, too.
+/// assert!(sphere.is_hit_by(&Ray::new(Vec3::zero(), Vec3::e1()), 0.5));
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct BoundingSphere {
+    /// The center of the sphere.
+    pub center: Vec3,
+    /// The radius of the sphere.
+    pub radius: Float,
+}
+
+impl BoundingSphere {
+    /// Creates the smallest sphere containing the (non-empty) bounding box.
+    pub fn from_aabb(aabb: &Aabb) -> BoundingSphere {
+        BoundingSphere {
+            center: aabb.centroid(),
+            radius: 0.5 * aabb.diagonal().norm(),
+        }
+    }
+
+    /// Returns the volume of the sphere.
+    pub fn volume(&self) -> Float {
+        4. / 3. * PI * self.radius * self.radius * self.radius
+    }
+
+    /// Returns whether the point lies inside the sphere or on its boundary.
+    pub fn contains_point(&self, point: Vec3) -> bool {
+        (point - self.center).norm2() <= self.radius * self.radius
+    }
+
+    /// Checks whether the ray from `t_min` to t=t_max passes through the sphere.
+    ///
+    /// Like `Aabb::passes_through`, this also returns true
+    /// if this part of the ray is completely inside the sphere.
+    /// It errs on the side of `true`, so it is a safe pre-check before exact intersection tests.
+    pub fn is_hit_by(&self, ray: &Ray, t_max: Float) -> bool {
+        let t = ((self.center - ray.origin()) * ray.dir()).max(ray.t_min()).min(t_max);
+        let radius = self.radius + EPS;
+        (ray.origin() + t * ray.dir() - self.center).norm2() <= radius * radius
+    }
+}
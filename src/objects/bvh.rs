@@ -1,19 +1,155 @@
 use basic::*;
 use objects::surface::*;
+use stats::count_node_visit;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use validation::{Problem, Severity};
 
 /// The maximum depth for a bounding volume hierarchy.
 const MAX_DEPTH: usize = 15;
 /// The maximum number of objects in a BVH node.
 /// Above this threshold, the node will be split if the depth limit allows it.
 const COUNT_THRESHOLD: usize = 5;
-/// If `true`, makes the BVH boxes visible (transparent red).
-const DEBUG_BVH: bool = false;
+
+/// Makes the boxes of bounding volume hierarchies visible as transparent objects,
+/// see `RenderOptions::bvh_overlay`.
+///
+/// Rays hit the box of every node from `min_depth` to `max_depth` (the root has depth 0)
+/// that they pass through. Shadow rays ignore the boxes.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let cube = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 1\nv 1 0 1\nv 1 1 1\nv 0 1 1\n\
+///             f 1 3 2\nf 1 4 3\nf 5 6 7\nf 5 7 8\nf 1 2 6\nf 1 6 5\n\
+///             f 4 7 3\nf 4 8 7\nf 1 5 8\nf 1 8 4\nf 2 3 7\nf 2 7 6\n";
+/// let scene = |overlay| {
+///     Scene::builder()
+///         .resolution(16, 16)
+///         .look_at(Vec3::new(0.5, 0.5, 5.0), Vec3::new(0.5, 0.5, 0.0))
+///         .add(Mesh::from_obj_reader(cube.as_bytes(), color_material(white())).unwrap())
+///         .options(RenderOptions { bvh_overlay: overlay, ..RenderOptions::default() })
+///         .build()
+///         .unwrap()
+/// };
+/// let render = |overlay| scene(overlay).render();
+/// let red = BvhOverlay::new(Color::new(1.0, 0.0, 0.0), 0.5);
+/// let plain = render(None).get(8, 8).opaque();
+/// let tinted = render(Some(red)).get(8, 8).opaque();
+/// assert!(tinted.red() > tinted.green() && !(plain.red() > plain.green()));
+/// // Single rays show the boxes, too.
+/// let ray = Ray::new(Vec3::new(0.5, 0.5, 5.0), -Vec3::e3());
+/// let traced = scene(Some(red)).trace(&ray).opaque();
+/// assert!(traced.red() > traced.green());
+/// // Nodes deeper than the tree are not shown.
+/// let deep = BvhOverlay { min_depth: 100, ..BvhOverlay::default() };
+/// assert_eq!(render(Some(deep)).get(8, 8).opaque().red(), plain.red());
+/// ```
+#[derive(Debug, Copy, Clone)]
+pub struct BvhOverlay {
+    /// The material of the boxes.
+    pub material: Material,
+    /// The depth of the shallowest nodes that are shown.
+    pub min_depth: usize,
+    /// The depth of the deepest nodes that are shown.
+    pub max_depth: usize,
+}
+
+impl BvhOverlay {
+    /// Shows the boxes of all nodes in the color, mostly transparent for a low `opacity`.
+    pub fn new(color: Color, opacity: Float) -> BvhOverlay {
+        BvhOverlay {
+            material: Material {
+                color: color,
+                ambient: 0.5 * opacity,
+                diffuse: 0.5 * opacity,
+                diffuse_model: DiffuseModel::Lambert,
+                specular: 0.,
+                shininess: 1.,
+                specular_model: SpecularModel::BlinnPhong,
+                reflectance: black(),
+                refractivity: Color::new_gray(1. - opacity),
+                refraction_index: 1.,
+                dispersion: Dispersion::None,
+                absorption: black(),
+                emission: black(),
+                bsdf: None,
+                color_texture: None,
+                roughness_texture: None,
+                normal_texture: None,
+                sides: Sides::Both,
+                holdout: false,
+            },
+            min_depth: 0,
+            max_depth: MAX_DEPTH,
+        }
+    }
+}
+
+impl Default for BvhOverlay {
+    /// Shows the boxes of all nodes in a faint, transparent red.
+    fn default() -> BvhOverlay {
+        BvhOverlay::new(Color::new(1.0, 0.2, 0.2), 0.05)
+    }
+}
+
+thread_local! {
+    /// The overlay of the render on this thread, if any.
+    static OVERLAY: Cell<Option<BvhOverlay>> = Cell::new(None);
+}
+
+/// The number of threads with an overlay, so that the other renders do not have to look it up
+/// in every intersection.
+static OVERLAY_THREADS: AtomicUsize = AtomicUsize::new(0);
+
+/// Shows the boxes of the hierarchies in the rays traced on the current thread from now on,
+/// or stops showing them.
+#[doc(hidden)]
+pub fn set_bvh_overlay(overlay: Option<BvhOverlay>) {
+    let previous = OVERLAY.with(|current| current.replace(overlay));
+    match (previous.is_some(), overlay.is_some()) {
+        (false, true) => {
+            OVERLAY_THREADS.fetch_add(1, Ordering::Relaxed);
+        }
+        (true, false) => {
+            OVERLAY_THREADS.fetch_sub(1, Ordering::Relaxed);
+        }
+        _ => {}
+    }
+}
+
+/// Returns the overlay of the current thread, if any.
+fn current_overlay() -> Option<BvhOverlay> {
+    if OVERLAY_THREADS.load(Ordering::Relaxed) == 0 {
+        None
+    } else {
+        OVERLAY.with(|overlay| overlay.get())
+    }
+}
+
+/// The shape of a bounding volume hierarchy, see `Bvh::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BvhStats {
+    /// The number of nodes, including the leaves.
+    pub nodes: usize,
+    /// The number of leaves.
+    pub leaves: usize,
+    /// The depth of the deepest leaf, 0 if the root is a leaf.
+    pub max_depth: usize,
+    /// The number of objects in the leaves. Objects overlapping both halves of a node are
+    /// in both of its children, so this may be more than the number of objects.
+    pub references: usize,
+    /// The number of objects without bounding boxes, which are not in the hierarchy.
+    pub unbounded: usize,
+}
 
 /// Represents a bounding volume hierarchy.
 pub struct Bvh<ContainerType: SurfaceContainer> {
     unbounded_objects: Vec<usize>, // TODO: Intersect those, too.
     container: ContainerType,
     root_node: BvhNode,
+    /// A sphere around all objects, if it is smaller than the box of the root node.
+    /// It is checked before the hierarchy to quickly skip rays missing round objects.
+    bounding_sphere: Option<BoundingSphere>,
 }
 
 struct BvhNode {
@@ -24,6 +160,8 @@ struct BvhNode {
 enum BvhTreeNode {
     Leaf {
         objects: Vec<usize>,
+        /// The number of the leaf, counting the leaves from left to right.
+        index: usize,
     },
     Branch {
         left: BvhNode,
@@ -36,6 +174,66 @@ impl<ContainerType> Bvh<ContainerType>
 {
     /// Creates a BVH from a container.
     pub fn new(container: ContainerType) -> Bvh<ContainerType> {
+        let (unbounded_objects, root_node, bounding_sphere) = Bvh::build(&container);
+        Bvh {
+            unbounded_objects: unbounded_objects,
+            container: container,
+            root_node: root_node,
+            bounding_sphere: bounding_sphere,
+        }
+    }
+
+    /// Rebuilds the hierarchy, e.g. after objects of the container were moved.
+    pub fn rebuild(&mut self) {
+        let (unbounded_objects, root_node, bounding_sphere) = Bvh::build(&self.container);
+        self.unbounded_objects = unbounded_objects;
+        self.root_node = root_node;
+        self.bounding_sphere = bounding_sphere;
+    }
+
+    /// Updates the boxes of the hierarchy bottom-up after objects of the container were moved,
+    /// without rebuilding it. This is much faster than `rebuild`, e.g. for the frames of
+    /// a deforming mesh, but the hierarchy gets slower to traverse the more the objects move
+    /// away from where they were when it was built. Objects must stay bounded or unbounded.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+    /// let mut mesh = Mesh::from_obj_reader(obj.as_bytes(), glass()).unwrap();
+    /// let ray = Ray::new(Vec3::new(0.25, 0.25, 5.0), -Vec3::e3());
+    /// assert!(appr(mesh.intersect(&ray, Float::INFINITY).unwrap().t, 5.0));
+    /// // Lift the triangle; the old boxes would make the ray miss it.
+    /// let lifted: Vec<Vec3> = (0..3).map(|i| mesh.container().vertex(i) + Vec3::e3()).collect();
+    /// mesh.container_mut().set_vertex_positions(&lifted);
+    /// mesh.refit();
+    /// assert!(appr(mesh.intersect(&ray, Float::INFINITY).unwrap().t, 4.0));
+    /// assert_eq!(mesh.bounding_box().unwrap().max().z(), 1.0);
+    /// ```
+    pub fn refit(&mut self) {
+        let mut aabbs = vec![];
+        self.root_node.refit(&self.container, &mut aabbs);
+        self.bounding_sphere = tight_bounding_sphere(&aabbs);
+    }
+
+    /// Returns statistics about the shape of the hierarchy, e.g. for benchmarks.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let mesh = Mesh::from_obj_reader("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n".as_bytes(),
+    ///                                  glass())
+    ///     .unwrap();
+    /// let stats = mesh.stats();
+    /// assert_eq!((stats.nodes, stats.leaves, stats.max_depth), (1, 1, 0));
+    /// assert_eq!((stats.references, stats.unbounded), (1, 0));
+    /// ```
+    pub fn stats(&self) -> BvhStats {
+        let mut stats = BvhStats { unbounded: self.unbounded_objects.len(), ..BvhStats::default() };
+        self.root_node.add_stats(0, &mut stats);
+        stats
+    }
+
+    /// Returns the unbounded objects, the root node, and the bounding sphere for a container.
+    fn build(container: &ContainerType) -> (Vec<usize>, BvhNode, Option<BoundingSphere>) {
         let mut unbounded_objects = vec![];
         let mut aabbs = vec![];
         for i in 0..container.count() {
@@ -45,57 +243,105 @@ impl<ContainerType> Bvh<ContainerType>
                 Some(aabb) => aabbs.push((i, aabb)),
             }
         }
-        let root_node = BvhNode::new(&container, aabbs, MAX_DEPTH);
-        Bvh {
-            unbounded_objects: unbounded_objects,
-            container: container,
-            root_node: root_node,
+        let bounding_sphere = tight_bounding_sphere(&aabbs);
+        let root_node = BvhNode::new(container, aabbs, MAX_DEPTH, &mut 0);
+        (unbounded_objects, root_node, bounding_sphere)
+    }
+
+    /// Returns the container of the objects.
+    pub fn container(&self) -> &ContainerType {
+        &self.container
+    }
+
+    /// Returns the container of the objects for modifying them.
+    /// The hierarchy is not rebuilt automatically, so changes that move the objects
+    /// must be followed by `rebuild`. Other properties like materials may be changed freely.
+    pub fn container_mut(&mut self) -> &mut ContainerType {
+        &mut self.container
+    }
+
+    /// Like `node_is_hit_by`, but returns the index of the object that the ray hits.
+    fn node_hit_part(&self, node: &BvhNode, ray: &Ray, t_max: Float) -> Option<usize> {
+        count_node_visit();
+        if !node.bounding_box.passes_through(ray, t_max) {
+            return None;
+        }
+        match *node.node {
+            BvhTreeNode::Leaf { ref objects, .. } => {
+                objects.iter().cloned().find(|&i| self.container.elem_is_hit_by(i, ray, t_max))
+            }
+            BvhTreeNode::Branch { ref left, ref right } => {
+                self.node_hit_part(left, ray, t_max)
+                    .or_else(|| self.node_hit_part(right, ray, t_max))
+            }
         }
     }
 
-    fn node_is_hit_by(&self, node: &BvhNode, ray: &Ray, t_max: f64) -> bool {
+    fn node_is_hit_by(&self, node: &BvhNode, ray: &Ray, t_max: Float) -> bool {
+        count_node_visit();
         if !node.bounding_box.passes_through(ray, t_max) {
             return false;
         }
         match *node.node {
-            BvhTreeNode::Leaf { ref objects } => {
+            BvhTreeNode::Leaf { ref objects, .. } => {
                 objects.iter().any(|&i| self.container.elem_is_hit_by(i, ray, t_max))
             }
             BvhTreeNode::Branch { ref left, ref right } => {
                 // TODO: Optimize: Check nearest node first and use t value for cutoff
-                self.node_is_hit_by(left, ray, t_max) && self.node_is_hit_by(right, ray, t_max)
+                self.node_is_hit_by(left, ray, t_max) || self.node_is_hit_by(right, ray, t_max)
             }
         }
     }
 
+    /// Collects the indices of the objects in the leaves that the ray passes through.
+    fn node_candidates(&self, node: &BvhNode, ray: &Ray, t_max: Float, result: &mut Vec<usize>) {
+        count_node_visit();
+        if !node.bounding_box.passes_through(ray, t_max) {
+            return;
+        }
+        match *node.node {
+            BvhTreeNode::Leaf { ref objects, .. } => result.extend_from_slice(objects),
+            BvhTreeNode::Branch { ref left, ref right } => {
+                self.node_candidates(left, ray, t_max, result);
+                self.node_candidates(right, ray, t_max, result);
+            }
+        }
+    }
+
+    /// Returns the nearest intersection in the node at the given depth and the index of its leaf.
+    /// With an overlay, the box of the node counts as an intersection, too.
     fn node_intersect<'a>(&'a self,
                           node: &'a BvhNode,
                           ray: &'a Ray,
-                          t_max: f64)
-                          -> Option<DelayedIntersection> {
+                          t_max: Float,
+                          overlay: Option<&BvhOverlay>,
+                          depth: usize)
+                          -> Option<(DelayedIntersection<'a>, Option<usize>)> {
+        count_node_visit();
         if !node.bounding_box.passes_through(ray, t_max) {
             return None;
         }
-        let (t_max, no_intersection) = if DEBUG_BVH {
-            let i = node.bounding_box.intersect(ray, t_max);
-            match i {
-                Some(i) => (i.t, Some(i)),
-                None => (t_max, None),
+        let box_inter = match overlay {
+            Some(overlay) if overlay.min_depth <= depth && depth <= overlay.max_depth => {
+                node.bounding_box.intersect(ray, t_max, overlay.material)
             }
-        } else {
-            (t_max, None)
+            _ => None,
+        };
+        let (t_max, no_intersection) = match box_inter {
+            Some(i) => (i.t, Some((i, None))),
+            None => (t_max, None),
         };
         match *node.node {
-            BvhTreeNode::Leaf { ref objects } => {
+            BvhTreeNode::Leaf { ref objects, index } => {
                 let mut nearest_t = t_max;
                 let mut nearest_inter = no_intersection;
                 for &i in objects {
                     match self.container.elem_intersect(i, ray, nearest_t) {
                         None => continue,
                         Some(inter) => {
-                            if inter.t < nearest_t {
+                            if inter.t <= nearest_t {
                                 nearest_t = inter.t;
-                                nearest_inter = Some(inter);
+                                nearest_inter = Some((inter, Some(index)));
                             }
                         }
                     }
@@ -109,10 +355,13 @@ impl<ContainerType> Bvh<ContainerType>
                 } else {
                     (right, left)
                 };
-                match self.node_intersect(near, ray, t_max) {
-                    None => self.node_intersect(far, ray, t_max).or(no_intersection),
+                match self.node_intersect(near, ray, t_max, overlay, depth + 1) {
+                    None => {
+                        self.node_intersect(far, ray, t_max, overlay, depth + 1)
+                            .or(no_intersection)
+                    }
                     Some(near_inter) => {
-                        match self.node_intersect(far, ray, near_inter.t) {
+                        match self.node_intersect(far, ray, near_inter.0.t, overlay, depth + 1) {
                             None => Some(near_inter),
                             Some(far_inter) => Some(far_inter),
                         }
@@ -126,12 +375,60 @@ impl<ContainerType> Bvh<ContainerType>
 impl<ContainerType> Surface for Bvh<ContainerType>
     where ContainerType: SurfaceContainer
 {
-    fn is_hit_by(&self, ray: &Ray, t_max: f64) -> bool {
+    fn is_hit_by(&self, ray: &Ray, t_max: Float) -> bool {
+        if let Some(ref sphere) = self.bounding_sphere {
+            if !sphere.is_hit_by(ray, t_max) {
+                return false;
+            }
+        }
         self.node_is_hit_by(&self.root_node, ray, t_max)
     }
 
-    fn intersect<'a>(&'a self, ray: &'a Ray, t_max: f64) -> Option<DelayedIntersection> {
-        self.node_intersect(&self.root_node, ray, t_max)
+    fn hit_part(&self, ray: &Ray, t_max: Float) -> Option<usize> {
+        if let Some(ref sphere) = self.bounding_sphere {
+            if !sphere.is_hit_by(ray, t_max) {
+                return None;
+            }
+        }
+        self.node_hit_part(&self.root_node, ray, t_max)
+    }
+
+    /// Tests only the object of the container with the index `part`.
+    fn part_is_hit_by(&self, part: usize, ray: &Ray, t_max: Float) -> bool {
+        part < self.container.count() && self.container.elem_is_hit_by(part, ray, t_max)
+    }
+
+    fn intersect<'a>(&'a self, ray: &'a Ray, t_max: Float) -> Option<DelayedIntersection<'a>> {
+        let overlay = current_overlay();
+        if let Some(ref sphere) = self.bounding_sphere {
+            // The boxes of the overlay are outside of the sphere.
+            if overlay.is_none() && !sphere.is_hit_by(ray, t_max) {
+                return None;
+            }
+        }
+        let root = self.node_intersect(&self.root_node, ray, t_max, overlay.as_ref(), 0);
+        root.map(|(inter, leaf)| {
+            DelayedIntersection::new(inter.t, move || {
+                let mut inter = inter.eval();
+                // Hierarchies inside the objects, e.g. of instances, set their leaves first.
+                inter.bvh_leaf = inter.bvh_leaf.or(leaf);
+                inter
+            })
+        })
+    }
+
+    fn intersect_all<'a>(&'a self, ray: &'a Ray, t_max: Float) -> Vec<DelayedIntersection<'a>> {
+        let mut candidates = Vec::new();
+        self.node_candidates(&self.root_node, ray, t_max, &mut candidates);
+        // Objects overlapping both halves of a node are in both of its children.
+        candidates.sort();
+        candidates.dedup();
+        let mut intersections = Vec::new();
+        for i in candidates {
+            intersections.extend(self.container.elem_intersect_all(i, ray, t_max));
+        }
+        sort_by_distance(&mut intersections);
+        intersections
     }
 
     fn bounding_box(&self) -> Option<Aabb> {
@@ -141,19 +438,104 @@ impl<ContainerType> Surface for Bvh<ContainerType>
             None
         }
     }
+
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        if !self.unbounded_objects.is_empty() {
+            return None;
+        }
+        self.bounding_sphere
+            .or_else(|| Some(BoundingSphere::from_aabb(&self.root_node.bounding_box)))
+    }
+
+    fn validate(&self) -> Vec<(Severity, Problem)> {
+        self.container.validate()
+    }
+
+    fn contains(&self, point: Vec3) -> bool {
+        self.container.contains(point)
+    }
+}
+
+/// Returns a sphere around the given boxes, centered at the center of their union,
+/// if its volume is smaller than the volume of the union.
+fn tight_bounding_sphere(aabbs: &[(usize, Aabb)]) -> Option<BoundingSphere> {
+    let aabb = Aabb::union_all(&mut aabbs.iter().map(|&(_, b)| b));
+    if aabb.is_empty() {
+        return None;
+    }
+    let center = aabb.centroid();
+    // The farthest vertex of a box is the one whose coordinates are farther from `center`.
+    let radius = aabbs.iter()
+        .map(|&(_, b)| (b.min() - center).abs().max((b.max() - center).abs()).norm())
+        .fold(0., Float::max);
+    let d = aabb.diagonal();
+    let sphere = BoundingSphere {
+        center: center,
+        radius: radius,
+    };
+    if sphere.volume() < d.x() * d.y() * d.z() {
+        Some(sphere)
+    } else {
+        None
+    }
 }
 
 impl BvhNode {
+    /// Adds the nodes of the subtree at the given depth to the statistics.
+    fn add_stats(&self, depth: usize, stats: &mut BvhStats) {
+        stats.nodes += 1;
+        match *self.node {
+            BvhTreeNode::Leaf { ref objects, .. } => {
+                stats.leaves += 1;
+                stats.max_depth = stats.max_depth.max(depth);
+                stats.references += objects.len();
+            }
+            BvhTreeNode::Branch { ref left, ref right } => {
+                left.add_stats(depth + 1, stats);
+                right.add_stats(depth + 1, stats);
+            }
+        }
+    }
+
+    /// Recomputes the bounding boxes of the node and its children from the objects
+    /// and adds the boxes of the objects in the leaves to `aabbs`.
+    fn refit<ContainerType>(&mut self, container: &ContainerType, aabbs: &mut Vec<(usize, Aabb)>)
+        where ContainerType: SurfaceContainer
+    {
+        self.bounding_box = match *self.node {
+            BvhTreeNode::Leaf { ref objects, .. } => {
+                let start = aabbs.len();
+                for &i in objects {
+                    if let Some(aabb) = container.elem_bounding_box(i) {
+                        aabbs.push((i, aabb));
+                    }
+                }
+                Aabb::union_all(&mut aabbs[start..].iter().map(|&(_, b)| b))
+            }
+            BvhTreeNode::Branch { ref mut left, ref mut right } => {
+                left.refit(container, aabbs);
+                right.refit(container, aabbs);
+                left.bounding_box.union(&right.bounding_box)
+            }
+        };
+    }
+
     /// Creates a bounding volume hierarchy node,
     /// given a list of object indices with their bounding boxes.
     /// The node will recursively split until a depth of `max_depth`.
+    /// The leaves are numbered from `next_leaf` on, which is advanced past them.
     pub fn new<ContainerType>(container: &ContainerType,
                               aabbs: Vec<(usize, Aabb)>,
-                              max_depth: usize)
+                              max_depth: usize,
+                              next_leaf: &mut usize)
                               -> BvhNode {
         let aabb = Aabb::union_all(&mut aabbs.iter().map(|&(_, b)| b));
         let tree_node = if aabbs.len() < COUNT_THRESHOLD || max_depth <= 0 {
-            Box::new(BvhTreeNode::Leaf { objects: aabbs.iter().map(|x| x.0).collect() })
+            *next_leaf += 1;
+            Box::new(BvhTreeNode::Leaf {
+                objects: aabbs.iter().map(|x| x.0).collect(),
+                index: *next_leaf - 1,
+            })
         } else {
             let max = aabb.longest_side();
             let half = 0.5 * max.1 * Vec3::e(max.0);
@@ -170,8 +552,8 @@ impl BvhNode {
                 }
             }
             Box::new(BvhTreeNode::Branch {
-                left: BvhNode::new(container, left_objects, max_depth - 1),
-                right: BvhNode::new(container, right_objects, max_depth - 1),
+                left: BvhNode::new(container, left_objects, max_depth - 1, next_leaf),
+                right: BvhNode::new(container, right_objects, max_depth - 1, next_leaf),
             })
         };
         BvhNode {
@@ -0,0 +1,175 @@
+use basic::*;
+use objects::surface::*;
+use std::sync::Arc;
+use validation::*;
+
+/// A shared object placed in the scene by a transformation, e.g. one of many copies of a mesh.
+///
+/// The object is stored once, with its own hierarchy (the bottom level), and every instance
+/// only keeps a reference and its transformation. A `Bvh` over the instances (the top level)
+/// then finds the instances that a ray passes through, which trace the ray in the space of the
+/// object. This way, a city of instanced buildings needs neither the memory nor the build time
+/// of a single hierarchy over all of their triangles.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// use std::sync::Arc;
+/// let cube = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 1\nv 1 0 1\nv 1 1 1\nv 0 1 1\n\
+///             f 1 3 2\nf 1 4 3\nf 5 6 7\nf 5 7 8\nf 1 2 6\nf 1 6 5\n\
+///             f 4 7 3\nf 4 8 7\nf 1 5 8\nf 1 8 4\nf 2 3 7\nf 2 7 6\n";
+/// let building: Arc<Surface + Send + Sync> =
+///     Arc::new(Mesh::from_obj_reader(cube.as_bytes(), glass()).unwrap());
+/// let mut instances = Vec::new();
+/// for i in 0..20 {
+///     for j in 0..20 {
+///         let height = 1.0 + ((i + j) % 4) as Float;
+///         let place = Matrix34::translate(Vec3::new(3.0 * i as Float, 0.0, 3.0 * j as Float));
+///         instances.push(Instance::new(building.clone(),
+///                                      place * Matrix34::scale(Vec3::new(1.0, height, 1.0))));
+///     }
+/// }
+/// let city = Bvh::new(instances);
+/// assert_eq!(city.bounding_box().unwrap().max(), Vec3::new(58.0, 4.0, 58.0));
+/// // The building at (3, 0, 6) is 4 high.
+/// let ray = Ray::new(Vec3::new(3.5, 10.0, 6.5), -Vec3::e2());
+/// let inter = city.intersect(&ray, Float::INFINITY).unwrap().eval();
+/// assert!(appr(inter.t, 6.0));
+/// assert!(appr(inter.normal * Vec3::e2(), 1.0));
+/// // A ray through the building enters it at the top and leaves it at the bottom.
+/// let ray = Ray::new(Vec3::new(3.3, 10.0, 6.6), -Vec3::e2());
+/// let all: Vec<_> =
+///     city.intersect_all(&ray, Float::INFINITY).into_iter().map(|i| i.eval()).collect();
+/// assert_eq!(all.len(), 2);
+/// assert!(appr(all[1].t, 10.0) && appr(all[1].point[1], 0.0));
+/// // Between the buildings, the ray misses them.
+/// let ray = Ray::new(Vec3::new(2.0, 10.0, 6.5), -Vec3::e2());
+/// assert!(!city.is_hit_by(&ray, Float::INFINITY));
+/// ```
+pub struct Instance {
+    object: Arc<Surface + Send + Sync>,
+    transform: Matrix34,
+    /// The transformation from the scene into the space of the object.
+    to_object: Matrix4,
+    to_world: Matrix4,
+    /// The transposed inverse of the transformation, for the normals.
+    normal_matrix: Matrix4,
+}
+
+impl Instance {
+    /// Places the object in the scene by the transformation from its space into the scene.
+    pub fn new(object: Arc<Surface + Send + Sync>, transform: Matrix34) -> Instance {
+        let to_object = Matrix4::from(transform.invert());
+        Instance {
+            object: object,
+            transform: transform,
+            to_object: to_object,
+            to_world: Matrix4::from(transform),
+            normal_matrix: to_object.transpose(),
+        }
+    }
+
+    /// Returns the shared object.
+    pub fn object(&self) -> &Arc<Surface + Send + Sync> {
+        &self.object
+    }
+
+    /// Returns the transformation from the space of the object into the scene.
+    pub fn transform(&self) -> Matrix34 {
+        self.transform
+    }
+
+    /// Moves the instance. A hierarchy over the instances has to be updated, see `Bvh::refit`.
+    pub fn set_transform(&mut self, transform: Matrix34) {
+        *self = Instance::new(self.object.clone(), transform);
+    }
+
+    /// Returns the ray in the space of the object and the factor by which distances
+    /// along it are longer than in the scene.
+    fn local_ray(&self, ray: &Ray) -> (Ray, Float) {
+        let dir = self.to_object.transform_vector(ray.dir());
+        let scale = dir.norm();
+        let local = Ray::new(self.to_object.transform_point(ray.origin()), dir.normalize());
+        (local.with_t_min(ray.t_min() * scale), scale)
+    }
+
+    /// Transforms an intersection in the space of the object into the scene.
+    fn world_intersection(&self, ray: &Ray, t: Float, local: Intersection) -> Intersection {
+        let to_world = |v: UnitVec3| self.to_world.transform_vector(v).normalize();
+        Intersection {
+            t: t,
+            point: ray.origin() + t * ray.dir(),
+            normal: self.normal_matrix.transform_vector(local.normal).normalize(),
+            front_face: local.front_face,
+            material: local.material,
+            uv: local.uv,
+            tangents: local.tangents.map(|(tangent, bitangent)| {
+                (to_world(tangent), to_world(bitangent))
+            }),
+            barycentric: local.barycentric,
+            bvh_leaf: local.bvh_leaf,
+        }
+    }
+}
+
+impl Surface for Instance {
+    fn intersect<'a>(&'a self, ray: &'a Ray, t_max: Float) -> Option<DelayedIntersection<'a>> {
+        let (local, scale) = self.local_ray(ray);
+        let t_max_local = t_max * scale;
+        // The intersection borrows the local ray, which does not outlive this call,
+        // so it is evaluated right away. Only the transformation into the scene is delayed.
+        self.object.intersect(&local, t_max_local).map(|inter| {
+            let t = inter.t / scale;
+            let inter = inter.eval();
+            DelayedIntersection::new(t, move || self.world_intersection(ray, t, inter))
+        })
+    }
+
+    fn is_hit_by(&self, ray: &Ray, t_max: Float) -> bool {
+        let (local, scale) = self.local_ray(ray);
+        self.object.is_hit_by(&local, t_max * scale)
+    }
+
+    fn hit_part(&self, ray: &Ray, t_max: Float) -> Option<usize> {
+        let (local, scale) = self.local_ray(ray);
+        self.object.hit_part(&local, t_max * scale)
+    }
+
+    fn part_is_hit_by(&self, part: usize, ray: &Ray, t_max: Float) -> bool {
+        let (local, scale) = self.local_ray(ray);
+        self.object.part_is_hit_by(part, &local, t_max * scale)
+    }
+
+    fn intersect_all<'a>(&'a self, ray: &'a Ray, t_max: Float) -> Vec<DelayedIntersection<'a>> {
+        let (local, scale) = self.local_ray(ray);
+        let t_max_local = t_max * scale;
+        // Like in `intersect`, the intersections are evaluated right away.
+        self.object
+            .intersect_all(&local, t_max_local)
+            .into_iter()
+            .map(|inter| {
+                let t = inter.t / scale;
+                let inter = inter.eval();
+                DelayedIntersection::new(t, move || self.world_intersection(ray, t, inter))
+            })
+            .collect()
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box().map(|aabb| aabb.transformed(&self.transform))
+    }
+
+    fn validate(&self) -> Vec<(Severity, Problem)> {
+        let mut problems = self.object.validate();
+        let determinant = self.transform.determinant();
+        if !determinant.is_finite() {
+            problems.push((Severity::Error, Problem::NonFiniteGeometry));
+        } else if determinant == 0. {
+            problems.push((Severity::Error, Problem::DegenerateGeometry));
+        }
+        problems
+    }
+
+    fn contains(&self, point: Vec3) -> bool {
+        self.object.contains(self.to_object.transform_point(point))
+    }
+}
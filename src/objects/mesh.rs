@@ -1,76 +1,1213 @@
 use basic::*;
-use std::f64;
+use error::{Error, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+#[cfg(feature = "fs")]
 use std::fs::File;
 use std::io::{self, BufRead};
+use std::ops::{Add, Mul};
 use std::str::FromStr;
 use objects::bvh::*;
 use objects::surface::*;
-use objects::triangle::{intersect_triangle, is_triangle_hit_by};
+use objects::triangle::{intersect_triangle_watertight, is_triangle_hit_by_watertight};
+use validation::*;
 
-/// Represents a triangle that is part of a mesh.
+/// The attributes of a triangle that is part of a mesh.
+/// Its vertex indices are stored separately, see `Mesh`.
+#[derive(Clone)]
 struct Face {
-    pub vertex_indices: (usize, usize, usize),
+    /// The indices of the normals at the vertices, if the face is smooth.
+    pub normal_indices: Option<[u32; 3]>,
+    /// The indices of the texture coordinates at the vertices, if the face has them.
+    pub texcoord_indices: Option<[u32; 3]>,
+    /// The indices of the tangents at the vertices, if the face has texture coordinates.
+    pub tangent_indices: Option<[u32; 3]>,
+    /// The index of the face's material in the material table of the mesh.
+    pub material: u32,
 }
 
 impl Face {
-    fn new(i: usize, j: usize, k: usize) -> Face {
-        Face { vertex_indices: (i, j, k) }
+    fn new() -> Face {
+        Face {
+            normal_indices: None,
+            texcoord_indices: None,
+            tangent_indices: None,
+            material: 0,
+        }
+    }
+}
+
+/// The positions of the vertices of a mesh, with each coordinate in its own array.
+#[derive(Clone)]
+struct Positions {
+    x: Vec<Float>,
+    y: Vec<Float>,
+    z: Vec<Float>,
+}
+
+impl Positions {
+    fn new() -> Positions {
+        Positions {
+            x: vec![],
+            y: vec![],
+            z: vec![],
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.x.len()
+    }
+
+    fn get(&self, i: u32) -> Vec3 {
+        let i = i as usize;
+        Vec3::new(self.x[i], self.y[i], self.z[i])
+    }
+
+    fn push(&mut self, p: Vec3) {
+        self.x.push(p.x());
+        self.y.push(p.y());
+        self.z.push(p.z());
+    }
+
+    /// Returns the vertices of the triangle with the given indices.
+    fn triangle(&self, indices: [u32; 3]) -> (Vec3, Vec3, Vec3) {
+        (self.get(indices[0]), self.get(indices[1]), self.get(indices[2]))
+    }
+}
+
+/// Computes the cross products of the edge vectors of the triangles, i.e. their normals with
+/// twice their areas as lengths.
+fn geometric_normals(positions: &Positions, triangles: &[[u32; 3]]) -> Vec<Vec3> {
+    triangles.iter()
+        .map(|&indices| {
+            let (a, b, c) = positions.triangle(indices);
+            (b - a).cross(c - a)
+        })
+        .collect()
+}
+
+/// Computes the offsets of the planes of the triangles along their `face_normals`, so that
+/// the points `p` of the plane of face `f` are those with `face_normals[f] * p == offsets[f]`.
+fn plane_offsets(positions: &Positions,
+                 triangles: &[[u32; 3]],
+                 face_normals: &[Vec3])
+                 -> Vec<Float> {
+    triangles.iter()
+        .zip(face_normals.iter())
+        .map(|(&indices, &normal)| normal * positions.get(indices[0]))
+        .collect()
+}
+
+/// The OBJ directives that are known, but not needed for meshes, so they are ignored silently.
+const IGNORED_OBJ_DIRECTIVES: [&'static str; 4] = ["vp", "o", "g", "mtllib"];
+/// The maximum number of problems in an OBJ file that are recorded in detail.
+/// All of them are counted, though.
+const MAX_RECORDED_OBJ_ISSUES: usize = 100;
+/// The number of bytes of an OBJ file between two progress reports.
+const OBJ_PROGRESS_INTERVAL: u64 = 1 << 20;
+
+/// How malformed lines in OBJ files are treated.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ObjParseMode {
+    /// Malformed lines are skipped and reported.
+    Lenient,
+    /// The first malformed line is an error.
+    Strict,
+}
+
+/// What is wrong with a line of an OBJ file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjProblem {
+    /// A vertex (`v`) does not have three numeric coordinates. It is skipped,
+    /// so the indices of the following vertices are off by one.
+    MalformedVertex,
+    /// The color of a vertex (`v x y z r g b`) is not numeric or outside of the range from
+    /// 0 to 1. The vertex is white.
+    MalformedVertexColor,
+    /// A normal (`vn`) does not have three numeric coordinates. It is skipped.
+    MalformedNormal,
+    /// Texture coordinates (`vt`) do not have two numeric coordinates. They are skipped.
+    MalformedTexCoord,
+    /// A face (`f`) has less than three vertices or a vertex that is not a number.
+    /// It is skipped.
+    MalformedFace,
+    /// A smoothing group (`s`) is neither a number nor `off`.
+    /// The following faces stay in the previous group.
+    MalformedSmoothingGroup,
+    /// A material (`usemtl`) has no name.
+    /// The following faces keep the previous material.
+    MalformedMaterial,
+    /// A face has more than three vertices. Only the triangle of the first three is used.
+    PolygonTruncated {
+        /// The number of vertices of the face.
+        vertices: usize,
+    },
+}
+
+impl fmt::Display for ObjProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ObjProblem::MalformedVertex => write!(f, "malformed vertex"),
+            ObjProblem::MalformedVertexColor => write!(f, "malformed vertex color"),
+            ObjProblem::MalformedNormal => write!(f, "malformed normal"),
+            ObjProblem::MalformedTexCoord => write!(f, "malformed texture coordinates"),
+            ObjProblem::MalformedFace => write!(f, "malformed face"),
+            ObjProblem::MalformedSmoothingGroup => write!(f, "malformed smoothing group"),
+            ObjProblem::MalformedMaterial => write!(f, "material without name"),
+            ObjProblem::PolygonTruncated { vertices } => {
+                write!(f, "face with {} vertices truncated to a triangle", vertices)
+            }
+        }
+    }
+}
+
+/// A problem in a line of an OBJ file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjIssue {
+    /// The number of the line, starting at 1.
+    pub line: usize,
+    /// What is wrong with the line.
+    pub problem: ObjProblem,
+}
+
+/// The problems found while parsing an OBJ file in lenient mode.
+#[derive(Debug, Clone, Default)]
+pub struct ObjReport {
+    /// The total number of malformed lines.
+    pub issue_count: usize,
+    /// The first malformed lines (at most 100 of them).
+    pub issues: Vec<ObjIssue>,
+    /// The number of faces that were skipped.
+    pub skipped_faces: usize,
+    /// The unknown or unsupported directives (e.g. lines `l`) and how often they occur.
+    pub unknown_directives: BTreeMap<String, usize>,
+}
+
+impl ObjReport {
+    /// Returns `true` if the file contains no malformed lines and no unknown directives.
+    pub fn is_ok(&self) -> bool {
+        self.issue_count == 0 && self.unknown_directives.is_empty()
+    }
+
+    /// Records a malformed line.
+    fn record(&mut self, issue: ObjIssue) {
+        self.issue_count += 1;
+        if self.issues.len() < MAX_RECORDED_OBJ_ISSUES {
+            self.issues.push(issue);
+        }
+    }
+}
+
+impl fmt::Display for ObjReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_ok() {
+            return write!(f, "no problems found");
+        }
+        try!(write!(f,
+                    "{} malformed lines, {} faces skipped",
+                    self.issue_count,
+                    self.skipped_faces));
+        for issue in self.issues.iter() {
+            try!(write!(f, "\n  line {}: {}", issue.line, issue.problem));
+        }
+        for (directive, count) in self.unknown_directives.iter() {
+            try!(write!(f, "\n  unknown directive `{}`: {} times", directive, count));
+        }
+        Ok(())
     }
 }
 
+/// Parses a vertex reference of a face, i.e. `i`, `i/t`, `i//n`, or `i/t/n`, and returns
+/// the vertex index `i`, the texture coordinate index `t`, and the normal index `n`, if given.
+fn parse_vertex_ref(token: &str) -> Option<(isize, Option<isize>, Option<isize>)> {
+    let mut parts = token.split('/');
+    let vertex = match parts.next().and_then(|i| i.parse().ok()) {
+        Some(i) => i,
+        None => return None,
+    };
+    let mut optional_index = || match parts.next() {
+        None | Some("") => Some(None),
+        Some(index) => index.parse().ok().map(Some),
+    };
+    let texcoord = match optional_index() {
+        Some(texcoord) => texcoord,
+        None => return None,
+    };
+    optional_index().map(|normal| (vertex, texcoord, normal))
+}
+
+/// Converts the (one-based or negative) index of a vertex or normal reference to an index
+/// into the elements read so far. Returns `None` if the element does not exist.
+fn resolve_vertex_ref(index: isize, vertex_count: usize) -> Option<usize> {
+    if index > 0 && index as usize <= vertex_count {
+        Some(index as usize - 1)
+    } else if index < 0 && ((-index) as usize) <= vertex_count {
+        Some(vertex_count - (-index) as usize)
+    } else {
+        None
+    }
+}
+
+/// Computes the normals of the smooth faces without normals in the OBJ file.
+///
+/// Faces of the same smoothing group share the area-weighted average normal at common
+/// vertices, so they appear smooth, while the edges between groups stay sharp.
+/// Faces without smoothing group stay flat.
+fn generate_normals(triangles: &[[u32; 3]],
+                    face_normals: &[Vec3],
+                    faces: &mut [Face],
+                    smoothing_groups: &[Option<u32>],
+                    normals: &mut Vec<Vec3>) {
+    let mut slots: HashMap<(u32, u32), u32> = HashMap::new();
+    for (f, (face, &group)) in faces.iter_mut().zip(smoothing_groups.iter()).enumerate() {
+        let group = match group {
+            Some(group) if face.normal_indices.is_none() => group,
+            _ => continue,
+        };
+        // The length of the cross product is twice the area.
+        let weighted_normal = face_normals[f];
+        let mut add = |vertex: u32| {
+            let slot = *slots.entry((vertex, group)).or_insert_with(|| {
+                normals.push(Vec3::zero());
+                (normals.len() - 1) as u32
+            });
+            normals[slot as usize] = normals[slot as usize] + weighted_normal;
+            slot
+        };
+        let [i, j, k] = triangles[f];
+        face.normal_indices = Some([add(i), add(j), add(k)]);
+    }
+}
+
+/// Computes the tangents at the vertices of the faces with texture coordinates,
+/// similar to MikkTSpace, and returns them with the sign of the bitangent,
+/// which is `sign * normal.cross(tangent)`.
+///
+/// The tangent of a face points in the direction in which `u` increases.
+/// Faces that share a vertex with the same texture coordinates, normal, and orientation
+/// of the texture share the area-weighted average tangent there,
+/// while seams and mirrored parts of the texture keep separate tangents.
+fn generate_tangents(positions: &Positions,
+                     triangles: &[[u32; 3]],
+                     face_normals: &[Vec3],
+                     texcoords: &[Vec2],
+                     faces: &mut [Face])
+                     -> Vec<(Vec3, Float)> {
+    let mut tangents: Vec<(Vec3, Float)> = vec![];
+    let mut slots: HashMap<(u32, u32, Option<u32>, Option<usize>, bool), u32> = HashMap::new();
+    for (f, face) in faces.iter_mut().enumerate() {
+        let [ti, tj, tk] = match face.texcoord_indices {
+            Some(indices) => indices,
+            None => continue,
+        };
+        let [i, j, k] = triangles[f];
+        let (a, b, c) = positions.triangle(triangles[f]);
+        let (e1, e2) = (b - a, c - a);
+        let (ti, tj, tk) = (ti as usize, tj as usize, tk as usize);
+        let (d1, d2) = (texcoords[tj] - texcoords[ti], texcoords[tk] - texcoords[ti]);
+        let det = d1.x() * d2.y() - d2.x() * d1.y();
+        let face_normal = face_normals[f];
+        let tangent = d2.y() * e1 - d1.y() * e2;
+        let bitangent = d1.x() * e2 - d2.x() * e1;
+        if det == 0. || tangent.norm2() == 0. || face_normal.norm2() == 0. {
+            // The texture is degenerate on this face, so it has no tangents.
+            continue;
+        }
+        // The tangent points in the direction of increasing u even if the texture is mirrored.
+        let tangent = det.signum() * tangent;
+        let positive = face_normal.cross(tangent) * (det.signum() * bitangent) >= 0.;
+        // The length of the cross product is twice the area.
+        let weighted_tangent = face_normal.norm() * tangent.normalize();
+        let mut add = |c: usize, vertex: u32, texcoord: usize| {
+            // Flat faces do not share their normals, so they do not share tangents either.
+            let normal = face.normal_indices.map(|n| n[c]);
+            let flat_face = if normal.is_none() { Some(f) } else { None };
+            let key = (vertex, texcoord as u32, normal, flat_face, positive);
+            let slot = *slots.entry(key).or_insert_with(|| {
+                tangents.push((Vec3::zero(), if positive { 1. } else { -1. }));
+                (tangents.len() - 1) as u32
+            });
+            let tangent = &mut tangents[slot as usize].0;
+            *tangent = *tangent + weighted_tangent;
+            slot
+        };
+        let indices = [add(0, i, ti), add(1, j, tj), add(2, k, tk)];
+        face.tangent_indices = Some(indices);
+    }
+    tangents
+}
+
 /// Represents a triangle mesh.
 ///
-/// It is usually constructed from an OBJ file using `Mesh::from_obj_file`.
+/// It is usually constructed from an OBJ file using `Mesh::from_obj_file`
+/// or `Mesh::from_obj_reader`.
+///
+/// Every face has one of the materials in the material table of the mesh.
+/// The first material is the one given when loading the mesh.
+/// Each material named by `usemtl` in the OBJ file gets its own entry,
+/// which is initially a copy of the first one and can be replaced with `set_material`.
+///
+/// Vertices may have colors, which are interpolated and multiplied with the color of the
+/// material, e.g. for scanned models.
+///
+/// Faces with texture coordinates also have tangents, which normal maps are relative to.
+/// The intersections with other faces have their barycentric coordinates as texture
+/// coordinates and no tangents.
+///
+/// To keep large meshes small, the faces refer to their vertices by 32-bit indices.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\n\
+///            vt 0 0\nvt 0 1\nvt 1 0\nvt 1 1\n\
+///            f 1/1 2/3 3/2\nf 2/3 4/4 3/2\n";
+/// let mesh = Mesh::from_obj_reader(obj.as_bytes(), glass()).unwrap();
+/// let ray = Ray::new(Vec3::new(0.75, 0.5, 1.0), -Vec3::e3());
+/// let inter = mesh.intersect(&ray, Float::INFINITY).unwrap().eval();
+/// assert!(appr(inter.uv.x(), 0.75) && appr(inter.uv.y(), 0.5));
+/// let (tangent, bitangent) = inter.tangents.unwrap();
+/// assert_eq!((tangent, bitangent), (Vec3::e1(), Vec3::e2()));
+/// ```
+#[derive(Clone)]
 pub struct Mesh {
-    vertices: Vec<Vec3>,
+    positions: Positions,
+    /// The colors of the vertices, if any.
+    vertex_colors: Option<Vec<Color>>,
+    /// The normals at the vertices of smooth faces, normalized.
+    normals: Vec<Vec3>,
+    /// The texture coordinates referenced by the faces.
+    texcoords: Vec<Vec2>,
+    /// The tangents at the vertices of faces with texture coordinates,
+    /// with the sign of the bitangent, see `generate_tangents`.
+    tangents: Vec<(Vec3, Float)>,
+    /// The indices of the vertices of the faces.
+    triangles: Vec<[u32; 3]>,
+    /// The cross products of the edge vectors of the faces, see `geometric_normals`.
+    face_normals: Vec<Vec3>,
+    /// The offsets of the planes of the faces along `face_normals`, see `plane_offsets`.
+    face_offsets: Vec<Float>,
+    /// The other attributes of the faces.
     faces: Vec<Face>,
-    material: Material,
+    materials: Vec<Material>,
+    /// The names of the materials (empty for the first one).
+    material_names: Vec<String>,
 }
 
 impl Mesh {
-    fn face_vertices(&self, f: &Face) -> [&Vec3; 3] {
-        let (i, j, k) = f.vertex_indices;
-        [&self.vertices[i], &self.vertices[j], &self.vertices[k]]
+    /// Checks whether the ray hits the back side of the face `f` and it is invisible.
+    fn is_culled(&self, f: usize, ray: &Ray) -> bool {
+        self.face_normals[f] * ray.dir() > 0. &&
+        self.materials[self.faces[f].material as usize].sides == Sides::Front
+    }
+
+    /// Checks with the plane of the face `f` whether the ray certainly misses the face up to
+    /// `t_max`. This only needs the precomputed data of the face instead of its vertices,
+    /// so it rejects faces that are behind the ray or too far away before the full test.
+    /// The margin for rounding errors is generous, so it never rejects a face that the full
+    /// test would find.
+    fn misses_plane(&self, f: usize, ray: &Ray, t_max: Float) -> bool {
+        let normal = self.face_normals[f];
+        // The ray meets the plane at `t = distance / speed`.
+        let speed = normal * ray.dir();
+        let distance = self.face_offsets[f] - normal * ray.origin();
+        let (distance, speed) = if speed < 0. { (-distance, -speed) } else { (distance, speed) };
+        let scale = normal.abs() * ray.origin().abs() + self.face_offsets[f].abs();
+        let margin = |t: Float| 1e-3 * (scale + t * normal.norm());
+        distance < ray.t_min() * speed - margin(ray.t_min()) ||
+        distance > t_max * speed + margin(t_max)
+    }
+
+    /// Returns the vertices of the face with index `f`.
+    fn face_vertices(&self, f: usize) -> (Vec3, Vec3, Vec3) {
+        self.positions.triangle(self.triangles[f])
+    }
+
+    /// Interpolates the values at the indices of the vertices of a face at the point with the
+    /// barycentric coordinates `u` and `v` of the second and third vertex.
+    fn interpolate<T>(values: &[T], indices: [u32; 3], u: Float, v: Float) -> T
+        where T: Copy + Add<Output = T>,
+              Float: Mul<T, Output = T>
+    {
+        let [i, j, k] = indices;
+        (1. - u - v) * values[i as usize] + u * values[j as usize] + v * values[k as usize]
+    }
+
+    /// Computes the normal of the face `f` at the point with the barycentric coordinates `u`
+    /// and `v` of the second and third vertex. Smooth faces interpolate the normals of the
+    /// vertices.
+    fn face_normal(&self, f: usize, u: Float, v: Float, ray: &Ray) -> UnitVec3 {
+        let geometric = self.face_normals[f];
+        let mut normal = geometric;
+        if let Some(indices) = self.faces[f].normal_indices {
+            let interpolated = Mesh::interpolate(&self.normals, indices, u, v);
+            // Opposite normals of the vertices may cancel out.
+            if interpolated.norm2() > 1e-12 {
+                normal = if interpolated * geometric < 0. {
+                    -interpolated
+                } else {
+                    interpolated
+                };
+            }
+        }
+        // Make the normal vector point to the origin of the ray.
+        // This is important for the epsilon displacement for shadow and reflection rays.
+        if geometric * ray.dir() < 0. {
+            normal.normalize()
+        } else {
+            -normal.normalize()
+        }
+    }
+
+    /// Returns the texture coordinates of the face `f` at the point with the barycentric
+    /// coordinates `u` and `v` of the second and third vertex, or `(u, v)` itself if the face
+    /// has none.
+    fn face_uv(&self, f: usize, u: Float, v: Float) -> Vec2 {
+        match self.faces[f].texcoord_indices {
+            Some(indices) => Mesh::interpolate(&self.texcoords, indices, u, v),
+            None => Vec2::new(u, v),
+        }
+    }
+
+    /// Returns the tangent and the bitangent of the face `f` at the point with the barycentric
+    /// coordinates `u` and `v`, orthogonal to the (interpolated) normal.
+    fn face_tangents(&self,
+                     f: usize,
+                     u: Float,
+                     v: Float,
+                     normal: UnitVec3)
+                     -> Option<(UnitVec3, UnitVec3)> {
+        let indices = match self.faces[f].tangent_indices {
+            Some(indices) => indices,
+            None => return None,
+        };
+        let [i, j, k] = indices;
+        let (i, j, k) = (i as usize, j as usize, k as usize);
+        let interpolated = (1. - u - v) * self.tangents[i].0 + u * self.tangents[j].0 +
+                           v * self.tangents[k].0;
+        let tangent = interpolated - (interpolated * normal) * normal;
+        if tangent.norm2() < 1e-12 {
+            return None;
+        }
+        let tangent = tangent.normalize();
+        // The normal points towards the ray, so the sign refers to the side of the face.
+        let side = if self.face_normals[f] * normal >= 0. { 1. } else { -1. };
+        let bitangent = (side * self.tangents[i].1 * normal.cross(tangent)).normalize();
+        Some((tangent, bitangent))
+    }
+
+    /// Returns the intersection with the face `f` at the point with the barycentric coordinates
+    /// `u` and `v` of the second and third vertex at distance `t` on the ray.
+    fn face_intersection(&self, f: usize, u: Float, v: Float, ray: &Ray, t: Float) -> Intersection {
+        let normal = self.face_normal(f, u, v, ray);
+        Intersection::new(ray, t, normal, self.face_material(f, u, v))
+            .with_front_face(self.face_normals[f] * ray.dir() < 0.)
+            .with_uv(self.face_uv(f, u, v))
+            .with_tangents(self.face_tangents(f, u, v, normal))
+            .with_barycentric(u, v)
+    }
+
+    /// Returns the material of the face `f` at the point with the barycentric coordinates `u`
+    /// and `v` of the second and third vertex, tinted by the interpolated vertex colors.
+    fn face_material(&self, f: usize, u: Float, v: Float) -> Material {
+        let mut material = self.materials[self.faces[f].material as usize];
+        if let Some(ref colors) = self.vertex_colors {
+            let color = Mesh::interpolate(colors, self.triangles[f], u, v);
+            material.color = color * material.color;
+        }
+        material
+    }
+
+    /// Returns the number of vertices.
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Returns the vertex with index `i`.
+    pub fn vertex(&self, i: usize) -> Vec3 {
+        self.positions.get(i as u32)
+    }
+
+    /// Returns the texture coordinates referenced by the faces.
+    pub fn texcoords(&self) -> &[Vec2] {
+        &self.texcoords
+    }
+
+    /// Returns the colors of the vertices, if any.
+    pub fn vertex_colors(&self) -> Option<&[Color]> {
+        self.vertex_colors.as_ref().map(|colors| &colors[..])
+    }
+
+    /// Sets the colors of the vertices (in the order of their indices) or removes them (`None`).
+    /// Panics if the number of colors and vertices differ.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let obj = "v 0 0 0 1 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+    /// let mut mesh = Mesh::from_obj_reader(obj.as_bytes(), glass()).unwrap();
+    /// assert_eq!(mesh.container().vertex_colors().unwrap()[1].blue(), 1.);
+    /// let heights: Vec<Color> = (0..mesh.container().vertex_count())
+    ///     .map(|i| Color::new_gray(mesh.container().vertex(i).y()))
+    ///     .collect();
+    /// mesh.container_mut().set_vertex_colors(Some(heights));
+    /// ```
+    pub fn set_vertex_colors(&mut self, colors: Option<Vec<Color>>) {
+        if let Some(ref colors) = colors {
+            assert!(colors.len() == self.positions.len(),
+                    "the number of colors does not match the number of vertices");
+        }
+        self.vertex_colors = colors;
+    }
+
+    /// Moves the vertices to the given positions (in the order of their indices), e.g. for the
+    /// frames of a deforming mesh. Panics if the number of positions and vertices differ.
+    ///
+    /// The normals at the vertices are not changed, so smooth normals should be computed
+    /// afterwards with `compute_normals`. The tangents are updated.
+    /// The hierarchy has to be updated, see `Bvh::refit`.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\n\
+    ///            vt 0 0\nvt 0 1\nvt 1 0\nvt 1 1\n\
+    ///            f 1/1 2/3 3/2\nf 2/3 4/4 3/2\n";
+    /// let mut mesh = Mesh::from_obj_reader(obj.as_bytes(), glass()).unwrap();
+    /// // Mirror the square at its diagonal, so that u increases along the y-axis.
+    /// let mirrored: Vec<Vec3> = (0..4)
+    ///     .map(|i| mesh.container().vertex(i))
+    ///     .map(|p| Vec3::new(p.y(), p.x(), p.z()))
+    ///     .collect();
+    /// mesh.container_mut().set_vertex_positions(&mirrored);
+    /// mesh.refit();
+    /// let ray = Ray::new(Vec3::new(0.5, 0.75, 1.0), -Vec3::e3());
+    /// let (tangent, _) = mesh.intersect(&ray, Float::INFINITY).unwrap().eval().tangents.unwrap();
+    /// assert!(appr(tangent * Vec3::e2(), 1.0));
+    /// ```
+    pub fn set_vertex_positions(&mut self, positions: &[Vec3]) {
+        assert!(positions.len() == self.positions.len(),
+                "the number of positions does not match the number of vertices");
+        let mut new_positions = Positions::new();
+        for &p in positions {
+            new_positions.push(p);
+        }
+        self.positions = new_positions;
+        self.face_normals = geometric_normals(&self.positions, &self.triangles);
+        self.face_offsets = plane_offsets(&self.positions, &self.triangles, &self.face_normals);
+        if !self.tangents.is_empty() {
+            for face in self.faces.iter_mut() {
+                face.tangent_indices = None;
+            }
+            self.tangents = generate_tangents(&self.positions,
+                                              &self.triangles,
+                                              &self.face_normals,
+                                              &self.texcoords,
+                                              &mut self.faces);
+        }
+    }
+
+    /// Returns the material table.
+    pub fn materials(&self) -> &[Material] {
+        &self.materials
+    }
+
+    /// Returns the names of the materials in the table.
+    /// The first material has an empty name, the others are named by `usemtl` or `add_material`.
+    pub fn material_names(&self) -> &[String] {
+        &self.material_names
+    }
+
+    /// Replaces the material with the given name.
+    /// Returns `false` if the table contains no material of this name.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\nusemtl red\nf 3 2 1\n";
+    /// let mut mesh = Mesh::from_obj_reader(obj.as_bytes(), glass()).unwrap();
+    /// assert_eq!(mesh.container().material_names(), ["", "red"]);
+    /// assert!(mesh.container_mut().set_material("red", color_material(Color::new(1., 0., 0.))));
+    /// assert!(!mesh.container_mut().set_material("blue", glass()));
+    /// ```
+    pub fn set_material(&mut self, name: &str, material: Material) -> bool {
+        match self.material_names.iter().position(|n| n == name) {
+            Some(i) => {
+                self.materials[i] = material;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds a material to the table and returns its index, e.g. for `set_face_material`.
+    pub fn add_material(&mut self, name: &str, material: Material) -> usize {
+        self.materials.push(material);
+        self.material_names.push(name.to_string());
+        self.materials.len() - 1
+    }
+
+    /// Assigns the material with the given index in the table to the face with the given index.
+    /// Panics if either index is out of range.
+    pub fn set_face_material(&mut self, face: usize, material: usize) {
+        assert!(material < self.materials.len(), "material index out of range");
+        self.faces[face].material = material as u32;
+    }
+
+    /// Merges vertices that are at most `epsilon` apart, e.g. the copies of the vertices
+    /// at the edges between the parts of an exported model, and returns how many were removed.
+    ///
+    /// Each group of merged vertices is replaced by its first vertex, and the faces refer to it
+    /// instead. The normals are not changed, so smooth normals should be computed afterwards
+    /// with `compute_normals`. Since vertices may move slightly, the hierarchy has to be
+    /// rebuilt, see `Bvh::rebuild`.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// // Two triangles with separate copies of their common edge, one of them a bit off.
+    /// let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 0 0\nv 1 1 0\nv 0 1.0000001 0\n\
+    ///            f 1 2 3\nf 4 5 6\n";
+    /// let mut mesh = Mesh::from_obj_reader(obj.as_bytes(), glass()).unwrap();
+    /// assert_eq!(mesh.container_mut().weld(0.0), 1);
+    /// assert_eq!(mesh.container_mut().weld(1e-6), 1);
+    /// assert_eq!(mesh.container().vertex_count(), 4);
+    /// mesh.rebuild();
+    /// let ray = Ray::new(Vec3::new(0.5, 0.99, 1.0), -Vec3::e3());
+    /// assert!(mesh.is_hit_by(&ray, Float::INFINITY));
+    /// ```
+    pub fn weld(&mut self, epsilon: Float) -> usize {
+        // Vertices within epsilon are in the same or neighboring cells of a grid.
+        let cell_size = if epsilon > 0. { epsilon } else { 1. };
+        let cell = |p: Vec3| {
+            ((p.x() / cell_size).floor() as i64,
+             (p.y() / cell_size).floor() as i64,
+             (p.z() / cell_size).floor() as i64)
+        };
+        let mut grid: HashMap<(i64, i64, i64), Vec<u32>> = HashMap::new();
+        let mut kept: Vec<u32> = vec![];
+        let mut new_index: Vec<u32> = Vec::with_capacity(self.positions.len());
+        for i in 0..self.positions.len() as u32 {
+            let p = self.positions.get(i);
+            let (x, y, z) = cell(p);
+            let mut found = None;
+            'search: for dx in -1..2 {
+                for dy in -1..2 {
+                    for dz in -1..2 {
+                        if let Some(candidates) = grid.get(&(x + dx, y + dy, z + dz)) {
+                            for &c in candidates.iter() {
+                                if (self.positions.get(kept[c as usize]) - p).norm() <= epsilon {
+                                    found = Some(c);
+                                    break 'search;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            new_index.push(match found {
+                Some(c) => c,
+                None => {
+                    kept.push(i);
+                    let c = (kept.len() - 1) as u32;
+                    grid.entry((x, y, z)).or_insert_with(Vec::new).push(c);
+                    c
+                }
+            });
+        }
+        let removed = self.positions.len() - kept.len();
+        let mut positions = Positions::new();
+        for &i in kept.iter() {
+            positions.push(self.positions.get(i));
+        }
+        self.positions = positions;
+        if let Some(ref mut colors) = self.vertex_colors {
+            *colors = kept.iter().map(|&i| colors[i as usize]).collect();
+        }
+        for indices in self.triangles.iter_mut() {
+            for i in indices.iter_mut() {
+                *i = new_index[*i as usize];
+            }
+        }
+        for face in self.faces.iter_mut() {
+            face.tangent_indices = None;
+        }
+        self.face_normals = geometric_normals(&self.positions, &self.triangles);
+        self.face_offsets = plane_offsets(&self.positions, &self.triangles, &self.face_normals);
+        self.tangents = generate_tangents(&self.positions,
+                                          &self.triangles,
+                                          &self.face_normals,
+                                          &self.texcoords,
+                                          &mut self.faces);
+        removed
+    }
+
+    /// Replaces the normals of all faces, e.g. from the OBJ file or its smoothing groups,
+    /// by normals computed from the geometry, and updates the tangents accordingly.
+    ///
+    /// At every vertex, a face gets the area-weighted average normal of the faces around the
+    /// vertex whose normals differ from its own by at most `smoothing_angle` (in radians).
+    /// So faces meeting at a smaller angle appear smooth, while sharper edges stay hard:
+    /// 0 makes all faces flat, and pi makes the whole mesh smooth.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// // Two faces meeting at a right angle along the y-axis.
+    /// let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nf 1 2 3\nf 1 3 4\n";
+    /// let mut mesh = Mesh::from_obj_reader(obj.as_bytes(), glass()).unwrap();
+    /// let ray = Ray::new(Vec3::new(0.1, 0.1, 1.0), -Vec3::e3());
+    /// mesh.container_mut().compute_normals((30.0 as Float).to_radians());
+    /// assert_eq!(mesh.intersect(&ray, Float::INFINITY).unwrap().eval().normal, Vec3::e3());
+    /// mesh.container_mut().compute_normals((100.0 as Float).to_radians());
+    /// assert!(mesh.intersect(&ray, Float::INFINITY).unwrap().eval().normal.x() > 0.5);
+    /// ```
+    pub fn compute_normals(&mut self, smoothing_angle: Float) {
+        let face_normals = &self.face_normals;
+        let mut faces_at_vertex: Vec<Vec<usize>> = vec![vec![]; self.positions.len()];
+        for (f, indices) in self.triangles.iter().enumerate() {
+            for &vertex in indices.iter() {
+                faces_at_vertex[vertex as usize].push(f);
+            }
+        }
+        let cos_threshold = smoothing_angle.cos();
+        let mut normals: Vec<Vec3> = vec![];
+        // Corners that average the same faces share their normal.
+        let mut slots: HashMap<(u32, Vec<usize>), u32> = HashMap::new();
+        for (f, face) in self.faces.iter_mut().enumerate() {
+            let n = face_normals[f];
+            let mut add = |vertex: u32| {
+                let similar: Vec<usize> = faces_at_vertex[vertex as usize]
+                    .iter()
+                    .cloned()
+                    .filter(|&g| {
+                        let m = face_normals[g];
+                        g == f || n * m >= cos_threshold * n.norm() * m.norm()
+                    })
+                    .collect();
+                let sum = similar.iter().fold(Vec3::zero(), |acc, &g| acc + face_normals[g]);
+                *slots.entry((vertex, similar)).or_insert_with(|| {
+                    normals.push(if sum.norm2() > 0. { sum.normalize().to() } else { sum });
+                    (normals.len() - 1) as u32
+                })
+            };
+            let [i, j, k] = self.triangles[f];
+            face.normal_indices = Some([add(i), add(j), add(k)]);
+            face.tangent_indices = None;
+        }
+        self.normals = normals;
+        self.tangents = generate_tangents(&self.positions,
+                                          &self.triangles,
+                                          &self.face_normals,
+                                          &self.texcoords,
+                                          &mut self.faces);
+    }
+
+    /// Finds the points of the surface at the centers of the texels of a texture with the given
+    /// dimensions, by their texture coordinates, e.g. for baking lightmaps.
+    /// Returns the column, the row, and the surface at the point for each texel covered by a
+    /// face, with the normal on the front side of the face.
+    ///
+    /// Faces without texture coordinates are skipped. If faces overlap in the texture,
+    /// the texel belongs to the last one.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let obj = "v 0 0 0\nv 2 0 0\nv 0 2 0\nvt 0 0\nvt 1 0\nvt 0 1\nf 1/1 2/2 3/3\n";
+    /// let mesh = Mesh::from_obj_reader(obj.as_bytes(), glass()).unwrap();
+    /// let texels = mesh.container().rasterize_uv(4, 4);
+    /// // The lower left half of the texture, including the diagonal.
+    /// assert_eq!(texels.len(), 10);
+    /// let &(_, _, ref inter) = texels.iter().find(|t| (t.0, t.1) == (0, 3)).unwrap();
+    /// assert!((inter.point - Vec3::new(0.25, 0.25, 0.0)).norm() < 1e-9);
+    /// assert_eq!(inter.normal, Vec3::e3());
+    /// ```
+    pub fn rasterize_uv(&self, width: usize, height: usize) -> Vec<(usize, usize, Intersection)> {
+        let (w, h) = (width as Float, height as Float);
+        let mut texels: Vec<Option<Intersection>> = (0..width * height).map(|_| None).collect();
+        for (f, face) in self.faces.iter().enumerate() {
+            let indices = match face.texcoord_indices {
+                Some(indices) => indices,
+                None => continue,
+            };
+            // The corners in texel units, with rows counted from the top like in textures.
+            let corner = |i: u32| {
+                let uv = self.texcoords[i as usize];
+                Vec2::new(uv.x() * w, (1. - uv.y()) * h)
+            };
+            let (a, b, c) = (corner(indices[0]), corner(indices[1]), corner(indices[2]));
+            let (ab, ac) = (b - a, c - a);
+            let det = ab.x() * ac.y() - ab.y() * ac.x();
+            if det.abs() < 1e-12 {
+                continue;
+            }
+            // The texels overlapping the bounding box of the corners.
+            let range = |p: Float, q: Float, r: Float, limit: Float| {
+                let (min, max) = (p.min(q).min(r), p.max(q).max(r));
+                (min.floor().max(0.) as usize, max.ceil().min(limit) as usize)
+            };
+            let columns = range(a.x(), b.x(), c.x(), w);
+            let rows = range(a.y(), b.y(), c.y(), h);
+            let ray = Ray::newn(Vec3::zero(), -self.face_normals[f]);
+            for y in rows.0..rows.1 {
+                for x in columns.0..columns.1 {
+                    let p = Vec2::new(x as Float + 0.5, y as Float + 0.5) - a;
+                    // The barycentric coordinates of the second and third vertex.
+                    let u = (p.x() * ac.y() - p.y() * ac.x()) / det;
+                    let v = (ab.x() * p.y() - ab.y() * p.x()) / det;
+                    if u < 0. || v < 0. || u + v > 1. {
+                        continue;
+                    }
+                    let (p0, p1, p2) = self.face_vertices(f);
+                    let point = (1. - u - v) * p0 + u * p1 + v * p2;
+                    let mut inter = self.face_intersection(f, u, v, &ray, 0.);
+                    inter.point = point;
+                    texels[y * width + x] = Some(inter);
+                }
+            }
+        }
+        texels.into_iter()
+            .enumerate()
+            .filter_map(|(i, inter)| inter.map(|inter| (i % width, i / width, inter)))
+            .collect()
     }
 
     /// Builds a mesh from the OBJ file `path` and out of the given `material`.
+    ///
+    /// Malformed lines are skipped, see `from_obj_file_with` for a report of them.
+    #[cfg(feature = "fs")]
     pub fn from_obj_file(path: &str, material: Material) -> io::Result<Bvh<Mesh>> {
-        let mut vertices: Vec<Vec3> = vec![];
-        let mut normals: Vec<Vec3> = vec![];
-        let mut faces: Vec<Face> = vec![];
+        let file = try!(File::open(path));
+        Mesh::from_obj_reader(io::BufReader::new(file), material)
+    }
 
+    /// Builds a mesh from the OBJ file `path` and out of the given `material`
+    /// and reports the problems found while parsing it, see `from_obj_reader_with`.
+    #[cfg(feature = "fs")]
+    pub fn from_obj_file_with(path: &str,
+                              material: Material,
+                              mode: ObjParseMode)
+                              -> Result<(Bvh<Mesh>, ObjReport)> {
         let file = try!(File::open(path));
-        let buf_reader = io::BufReader::new(file);
+        Mesh::from_obj_reader_with(io::BufReader::new(file), material, mode)
+    }
+
+    /// Builds a mesh from OBJ data, e.g. embedded with `include_bytes!`,
+    /// and out of the given `material`.
+    ///
+    /// Byte slices can be passed directly. Other sources implementing `io::Read`,
+    /// like network streams or files inside archives, have to be wrapped in an `io::BufReader`.
+    /// The data need not be UTF-8, e.g. comments in Latin-1 are fine.
+    ///
+    /// Malformed lines are skipped, see `from_obj_reader_with` for a report of them.
+    /// Fails with `InvalidData` if a face refers to a vertex that does not exist.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// use std::io::{BufReader, Cursor};
+    /// let obj: &[u8] = b"v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+    /// let mesh = Mesh::from_obj_reader(obj, glass()).unwrap();
+    /// assert!(mesh.bounding_box().is_some());
+    /// let stream = Cursor::new(obj.to_vec()); // stands for any `io::Read`
+    /// assert!(Mesh::from_obj_reader(BufReader::new(stream), glass()).is_ok());
+    /// let latin1: &[u8] = b"# caf\xe9\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+    /// let strict = Mesh::from_obj_reader_with(latin1, glass(), ObjParseMode::Strict);
+    /// let (_, report) = strict.unwrap();
+    /// assert!(report.is_ok());
+    /// ```
+    pub fn from_obj_reader<R: BufRead>(reader: R, material: Material) -> io::Result<Bvh<Mesh>> {
+        Mesh::from_obj_reader_with(reader, material, ObjParseMode::Lenient)
+            .map(|(mesh, _)| mesh)
+            .map_err(io::Error::from)
+    }
+
+    /// Builds a mesh from OBJ data and out of the given `material`
+    /// and reports the problems found while parsing it.
+    ///
+    /// Vertices may have a color (`v x y z r g b` with channels from 0 to 1).
+    /// Faces may refer to vertices as `i`, `i/t`, `i//n`, or `i/t/n`,
+    /// where negative indices count backwards from the last vertex.
+    /// Faces whose vertices all have texture coordinates `t` (`vt u v`) are textured.
+    /// Faces whose vertices all have normals `n` are smooth.
+    /// For the other faces, the normals are computed from the smoothing groups (`s`):
+    /// Faces in the same group are smooth across their common vertices,
+    /// faces without group (`s off`) are flat.
+    /// For files without normals or smoothing groups, see `compute_normals`.
+    /// Malformed lines are skipped in lenient mode and are an error in strict mode.
+    /// Faces that refer to a vertex that does not exist are an error in both modes.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0\nf 1 2 3\nf -1 -2 -3 1\nf 1 2\nl 1 2\n";
+    /// let (_, report) =
+    ///     Mesh::from_obj_reader_with(obj.as_bytes(), glass(), ObjParseMode::Lenient).unwrap();
+    /// assert_eq!(report.issues,
+    ///            vec![ObjIssue { line: 4, problem: ObjProblem::MalformedVertex },
+    ///                 ObjIssue { line: 6, problem: ObjProblem::PolygonTruncated { vertices: 4 } },
+    ///                 ObjIssue { line: 7, problem: ObjProblem::MalformedFace }]);
+    /// assert_eq!(report.skipped_faces, 1);
+    /// assert_eq!(report.unknown_directives.get("l"), Some(&1));
+    /// assert!(Mesh::from_obj_reader_with(obj.as_bytes(), glass(), ObjParseMode::Strict).is_err());
+    /// ```
+    pub fn from_obj_reader_with<R: BufRead>(reader: R,
+                                            material: Material,
+                                            mode: ObjParseMode)
+                                            -> Result<(Bvh<Mesh>, ObjReport)> {
+        Mesh::from_obj_reader_with_progress(reader, material, mode, |_| ())
+    }
+
+    /// Builds a mesh like `from_obj_reader_with` and calls `progress` with the number of bytes
+    /// read so far after every mebibyte and at the end, e.g. to show how far loading a large
+    /// file got compared to its size from `std::fs::metadata`.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n";
+    /// let mut bytes_read = 0;
+    /// Mesh::from_obj_reader_with_progress(obj.as_bytes(),
+    ///                                     glass(),
+    ///                                     ObjParseMode::Strict,
+    ///                                     |bytes| bytes_read = bytes)
+    ///     .unwrap();
+    /// assert_eq!(bytes_read, obj.len() as u64);
+    /// ```
+    pub fn from_obj_reader_with_progress<R, F>(mut reader: R,
+                                               material: Material,
+                                               mode: ObjParseMode,
+                                               mut progress: F)
+                                               -> Result<(Bvh<Mesh>, ObjReport)>
+        where R: BufRead,
+              F: FnMut(u64)
+    {
+        let mut positions = Positions::new();
+        let mut vertex_colors: Vec<Color> = vec![];
+        let mut has_vertex_colors = false;
+        let mut normals: Vec<Vec3> = vec![];
+        let mut texcoords: Vec<Vec2> = vec![];
+        let mut triangles: Vec<[u32; 3]> = vec![];
+        let mut faces: Vec<Face> = vec![];
+        let mut smoothing_groups: Vec<Option<u32>> = vec![];
+        let mut smoothing_group = None;
+        let mut materials = vec![material];
+        let mut material_names = vec![String::new()];
+        let mut current_material = 0;
+        let mut report = ObjReport::default();
 
-        for line in buf_reader.lines() {
-            let line = try!(line);
+        // The buffer is reused for all lines, so large files are read without allocations
+        // per line. Bytes that are not UTF-8, e.g. in comments written by older exporters,
+        // only make their line malformed instead of failing the whole mesh.
+        let mut buffer = Vec::new();
+        let mut number = 0;
+        let mut bytes_read = 0;
+        let mut next_report = OBJ_PROGRESS_INTERVAL;
+        loop {
+            buffer.clear();
+            let length = try!(reader.read_until(b'\n', &mut buffer));
+            if length == 0 {
+                break;
+            }
+            number += 1;
+            bytes_read += length as u64;
+            if bytes_read >= next_report {
+                progress(bytes_read);
+                next_report = bytes_read + OBJ_PROGRESS_INTERVAL;
+            }
+            let line = String::from_utf8_lossy(&buffer);
             let mut tokens = line.split_whitespace();
-            match tokens.next() {
+            let problem = match tokens.next() {
                 Some("v") =>
-                    match Mesh::parse3::<_,f64>(&mut tokens) {
-                        Some((x,y,z)) => vertices.push(Vec3::new(x,y,z)),
-                        None => continue
+                    match Mesh::parse3::<_,Float>(&mut tokens) {
+                        Some((x,y,z)) => {
+                            positions.push(Vec3::new(x,y,z));
+                            // Three more values are a color, a single one is the weight w.
+                            let rest = [tokens.next(), tokens.next(), tokens.next()];
+                            if rest[2].is_some() && tokens.next().is_none() {
+                                let mut rest = rest.iter().filter_map(|&token| token);
+                                let color = Mesh::parse3::<_,Float>(&mut rest)
+                                    .and_then(|(r,g,b)| Color::try_new(r,g,b).ok());
+                                has_vertex_colors = true;
+                                vertex_colors.push(color.unwrap_or(white()));
+                                color.map_or(Some(ObjProblem::MalformedVertexColor), |_| None)
+                            } else {
+                                vertex_colors.push(white());
+                                None
+                            }
+                        },
+                        None => Some(ObjProblem::MalformedVertex)
                     },
                 Some("vn") =>
-                    match Mesh::parse3::<_,f64>(&mut tokens) {
-                        Some((x,y,z)) => normals.push(Vec3::new(x,y,z)),
-                        None => continue
+                    match Mesh::parse3::<_,Float>(&mut tokens) {
+                        Some((x,y,z)) => { normals.push(Vec3::new(x,y,z)); None },
+                        None => Some(ObjProblem::MalformedNormal)
                     },
-                Some("f") =>
-                    // TODO: Handle normal vectors
-                    match Mesh::parse3::<_,usize>(&mut tokens) {
-                        Some((i,j,k)) =>
-                            faces.push(Face::new(i - 1, j - 1, k - 1)),
-                        None => continue
+                Some("vt") => {
+                    let u = tokens.next().and_then(|s| s.parse::<Float>().ok());
+                    let v = tokens.next().and_then(|s| s.parse::<Float>().ok());
+                    match (u, v) {
+                        (Some(u), Some(v)) => { texcoords.push(Vec2::new(u, v)); None },
+                        _ => Some(ObjProblem::MalformedTexCoord)
+                    }
+                },
+                Some("s") =>
+                    match tokens.next() {
+                        Some("off") | Some("0") => { smoothing_group = None; None },
+                        Some(group) =>
+                            match group.parse() {
+                                Ok(group) => { smoothing_group = Some(group); None },
+                                Err(_) => Some(ObjProblem::MalformedSmoothingGroup)
+                            },
+                        None => Some(ObjProblem::MalformedSmoothingGroup)
                     },
-                _ => continue
+                Some("usemtl") =>
+                    match tokens.next() {
+                        Some(name) => {
+                            current_material =
+                                match material_names.iter().position(|n| n == name) {
+                                    Some(i) => i,
+                                    None => {
+                                        materials.push(material);
+                                        material_names.push(name.to_string());
+                                        materials.len() - 1
+                                    }
+                                };
+                            None
+                        },
+                        None => Some(ObjProblem::MalformedMaterial)
+                    },
+                Some("f") => {
+                    // Only the first three vertices are kept, the others are just counted.
+                    let mut refs = [None; 3];
+                    let mut count = 0;
+                    let mut malformed = false;
+                    for token in tokens {
+                        let r = parse_vertex_ref(token);
+                        malformed = malformed || r.is_none();
+                        if count < 3 {
+                            refs[count] = r;
+                        }
+                        count += 1;
+                    }
+                    if count < 3 || malformed {
+                        report.skipped_faces += 1;
+                        Some(ObjProblem::MalformedFace)
+                    } else if positions.len().max(texcoords.len()) > u32::MAX as usize ||
+                              normals.len() + 3 * (faces.len() + 1) > u32::MAX as usize {
+                        // Generated normals and tangents need indices, too.
+                        return Err(Error::Parse {
+                            line: number,
+                            message: "too many elements for 32-bit indices".to_string(),
+                        });
+                    } else {
+                        let mut indices = [0; 3];
+                        let mut normal_indices = Some([0; 3]);
+                        let mut texcoord_indices = Some([0; 3]);
+                        for (c, r) in refs.iter().enumerate() {
+                            let (vertex, texcoord, normal) = r.unwrap();
+                            indices[c] = match resolve_vertex_ref(vertex, positions.len()) {
+                                Some(index) => index as u32,
+                                None => {
+                                    return Err(Error::Parse {
+                                        line: number,
+                                        message: format!("vertex {} does not exist", vertex),
+                                    })
+                                }
+                            };
+                            match (normal, normal_indices.as_mut()) {
+                                (Some(n), Some(normal_indices)) => {
+                                    normal_indices[c] =
+                                        match resolve_vertex_ref(n, normals.len()) {
+                                            Some(index) => index as u32,
+                                            None => {
+                                                return Err(Error::Parse {
+                                                    line: number,
+                                                    message: format!("normal {} does not exist",
+                                                                     n),
+                                                })
+                                            }
+                                        }
+                                }
+                                _ => normal_indices = None,
+                            }
+                            match (texcoord, texcoord_indices.as_mut()) {
+                                (Some(t), Some(texcoord_indices)) => {
+                                    texcoord_indices[c] =
+                                        match resolve_vertex_ref(t, texcoords.len()) {
+                                            Some(index) => index as u32,
+                                            None => {
+                                                return Err(Error::Parse {
+                                                    line: number,
+                                                    message: format!("texture coordinates {} \
+                                                                      do not exist",
+                                                                     t),
+                                                })
+                                            }
+                                        }
+                                }
+                                _ => texcoord_indices = None,
+                            }
+                        }
+                        let mut face = Face::new();
+                        face.normal_indices = normal_indices;
+                        face.texcoord_indices = texcoord_indices;
+                        face.material = current_material as u32;
+                        triangles.push(indices);
+                        faces.push(face);
+                        smoothing_groups.push(smoothing_group);
+                        if count > 3 {
+                            Some(ObjProblem::PolygonTruncated { vertices: count })
+                        } else {
+                            None
+                        }
+                    }
+                }
+                Some(directive) if directive.starts_with('#') => None,
+                Some(directive) if IGNORED_OBJ_DIRECTIVES.contains(&directive) => None,
+                Some(directive) => {
+                    if let Some(count) = report.unknown_directives.get_mut(directive) {
+                        *count += 1;
+                    } else {
+                        report.unknown_directives.insert(directive.to_string(), 1);
+                    }
+                    None
+                }
+                None => None
+            };
+            if let Some(problem) = problem {
+                if mode == ObjParseMode::Strict {
+                    return Err(Error::Parse {
+                        line: number,
+                        message: problem.to_string(),
+                    });
+                }
+                report.record(ObjIssue {
+                    line: number,
+                    problem: problem,
+                });
+            }
+        }
+        progress(bytes_read);
+        let face_normals = geometric_normals(&positions, &triangles);
+        generate_normals(&triangles, &face_normals, &mut faces, &smoothing_groups, &mut normals);
+        for n in normals.iter_mut() {
+            if n.norm2() > 0. {
+                *n = n.normalize().to();
             }
         }
-        Ok(Bvh::new(Mesh {
-            vertices: vertices,
+        let tangents =
+            generate_tangents(&positions, &triangles, &face_normals, &texcoords, &mut faces);
+        let face_offsets = plane_offsets(&positions, &triangles, &face_normals);
+        let mesh = Bvh::new(Mesh {
+            positions: positions,
+            vertex_colors: if has_vertex_colors {
+                Some(vertex_colors)
+            } else {
+                None
+            },
+            normals: normals,
+            texcoords: texcoords,
+            tangents: tangents,
+            triangles: triangles,
+            face_normals: face_normals,
+            face_offsets: face_offsets,
             faces: faces,
-            material: material,
-        }))
+            materials: materials,
+            material_names: material_names,
+        });
+        Ok((mesh, report))
     }
 
     fn parse3<'a, I, T>(tokens: &mut I) -> Option<(T, T, T)>
@@ -84,57 +1221,42 @@ impl Mesh {
         })
     }
 
-    /// Computes the bounding box for the given face.
-    fn bounding_box_face(&self, f: &Face) -> Aabb {
-        let min = self.face_vertices(f)
-            .iter()
-            .fold(f64::INFINITY * Vec3::ones(), |acc, &&item| acc.max(item));
-        let max = self.face_vertices(f)
-            .iter()
-            .fold(-f64::INFINITY * Vec3::ones(), |acc, &&item| acc.max(item));
-        Aabb::new(min, max)
-    }
 }
 
 impl Surface for Mesh {
-    fn intersect<'a>(&'a self, ray: &'a Ray, t_max: f64) -> Option<DelayedIntersection> {
+    fn intersect<'a>(&'a self, ray: &'a Ray, t_max: Float) -> Option<DelayedIntersection<'a>> {
         let mut t_min = t_max;
         let mut nearest_face = None;
-        for face in self.faces.iter() {
-            let vertices = self.face_vertices(face);
-            let a = *vertices[0];
-            let b = *vertices[1];
-            let c = *vertices[2];
-            intersect_triangle(a, b, c, ray, t_min).map(|(_, _, _, _, t)| {
+        for f in 0..self.triangles.len() {
+            if self.is_culled(f, ray) {
+                continue;
+            }
+            let (a, b, c) = self.face_vertices(f);
+            intersect_triangle_watertight(a, b, c, ray, t_min).map(|(u, v, t)| {
                 t_min = t;
-                nearest_face = Some(face);
+                nearest_face = Some((f, u, v));
             });
         }
-        nearest_face.map(|f| {
-            DelayedIntersection::new(t_min, move || {
-                let vertices = self.face_vertices(f);
-                // TODO: Interpolate normal if vertex normals are given.
-                let normal =
-                    (*vertices[1] - *vertices[0]).cross(*vertices[2] - *vertices[0]).normalize();
-                // Make the normal vector point to the origin of the ray.
-                // This is important for the epsilon displacement for shadow and reflection rays.
-                let normal = if normal * ray.dir < 0. {
-                    normal
-                } else {
-                    -normal
-                };
-                Intersection::new(ray, t_min, normal, self.material)
-            })
+        nearest_face.map(|(f, u, v)| {
+            DelayedIntersection::new(t_min, move || self.face_intersection(f, u, v, ray, t_min))
         })
     }
 
-    fn is_hit_by(&self, ray: &Ray, t_max: f64) -> bool {
-        for face in self.faces.iter() {
-            let vertices = self.face_vertices(face);
-            let a = *vertices[0];
-            let b = *vertices[1];
-            let c = *vertices[2];
-            if is_triangle_hit_by(a, b, c, ray, t_max) {
+    fn intersect_all<'a>(&'a self, ray: &'a Ray, t_max: Float) -> Vec<DelayedIntersection<'a>> {
+        let mut intersections: Vec<_> = (0..self.triangles.len())
+            .filter_map(|i| self.elem_intersect(i, ray, t_max))
+            .collect();
+        sort_by_distance(&mut intersections);
+        intersections
+    }
+
+    fn is_hit_by(&self, ray: &Ray, t_max: Float) -> bool {
+        for f in 0..self.triangles.len() {
+            if self.is_culled(f, ray) {
+                continue;
+            }
+            let (a, b, c) = self.face_vertices(f);
+            if is_triangle_hit_by_watertight(a, b, c, ray, t_max) {
                 return true;
             }
         }
@@ -142,64 +1264,92 @@ impl Surface for Mesh {
     }
 
     fn bounding_box(&self) -> Option<Aabb> {
-        if self.faces.len() == 0 {
+        if self.triangles.len() == 0 {
             return None;
         }
-        let min = f64::INFINITY * Vec3::ones();
-        let max = -f64::INFINITY * Vec3::ones();
-        let (min, max): (Vec3, Vec3) =
-            self.faces.iter().fold((min, max), |(acc_min, acc_max), item| {
-                let aabb = self.bounding_box_face(item);
-                (acc_min.min(aabb.min()), acc_max.max(aabb.max()))
-            });
-        Some(Aabb::new(min, max))
+        let mut aabb = Aabb::empty();
+        for f in 0..self.triangles.len() {
+            let (a, b, c) = self.face_vertices(f);
+            aabb.grow(a);
+            aabb.grow(b);
+            aabb.grow(c);
+        }
+        Some(aabb)
+    }
+
+    fn validate(&self) -> Vec<(Severity, Problem)> {
+        SurfaceContainer::validate(self)
+    }
+
+    fn contains(&self, point: Vec3) -> bool {
+        SurfaceContainer::contains(self, point)
     }
 }
 
 impl SurfaceContainer for Mesh {
-    fn elem_is_hit_by(&self, i: usize, ray: &Ray, t_max: f64) -> bool {
-        let vertices = self.face_vertices(&self.faces[i]);
-        return is_triangle_hit_by(*vertices[0], *vertices[1], *vertices[2], ray, t_max);
+    fn elem_is_hit_by(&self, i: usize, ray: &Ray, t_max: Float) -> bool {
+        if self.is_culled(i, ray) || self.misses_plane(i, ray, t_max) {
+            return false;
+        }
+        let (a, b, c) = self.face_vertices(i);
+        is_triangle_hit_by_watertight(a, b, c, ray, t_max)
     }
 
     fn elem_intersect<'a>(&'a self,
                           i: usize,
                           ray: &'a Ray,
-                          t_max: f64)
-                          -> Option<DelayedIntersection> {
-        let ref face = self.faces[i];
-        let vertices = self.face_vertices(face);
-        let a = *vertices[0];
-        let b = *vertices[1];
-        let c = *vertices[2];
-        intersect_triangle(a, b, c, ray, t_max).map(|(_, _, _, _, t)| {
-            DelayedIntersection::new(t, move || {
-                // TODO: Interpolate normal if vertex normals are given.
-                let normal = (b - a).cross(c - a).normalize();
-                // Make the normal vector point to the origin of the ray.
-                // This is important for the epsilon displacement for shadow and reflection rays.
-                let normal = if normal * ray.dir < 0. {
-                    normal
-                } else {
-                    -normal
-                };
-                Intersection::new(ray, t, normal, self.material)
-            })
+                          t_max: Float)
+                          -> Option<DelayedIntersection<'a>> {
+        if self.is_culled(i, ray) || self.misses_plane(i, ray, t_max) {
+            return None;
+        }
+        let (a, b, c) = self.face_vertices(i);
+        intersect_triangle_watertight(a, b, c, ray, t_max).map(|(u, v, t)| {
+            DelayedIntersection::new(t, move || self.face_intersection(i, u, v, ray, t))
         })
     }
 
     fn elem_bounding_box(&self, i: usize) -> Option<Aabb> {
-        let ref face = self.faces[i];
-        let vertices = self.face_vertices(face);
-        let a = *vertices[0];
-        let b = *vertices[1];
-        let c = *vertices[2];
+        let (a, b, c) = self.face_vertices(i);
         let min = a.min(b).min(c);
         let max = a.max(b).max(c);
         Some(Aabb::new(min, max))
     }
 
     fn count(&self) -> usize {
-        self.faces.len()
+        self.triangles.len()
+    }
+
+    fn validate(&self) -> Vec<(Severity, Problem)> {
+        let mut problems = Vec::new();
+        for material in self.materials.iter() {
+            problems.extend(validate_material(material));
+        }
+        if !(0..self.positions.len()).all(|i| is_finite_vec(self.positions.get(i as u32))) {
+            problems.push((Severity::Error, Problem::NonFiniteGeometry));
+        }
+        let degenerate = self.face_normals.iter().filter(|n| n.norm2() == 0.).count();
+        if degenerate > 0 {
+            problems.push((Severity::Warning, Problem::DegenerateFaces { count: degenerate }));
+        }
+        problems
+    }
+
+    /// Counts the faces crossed by rays from the point in a few directions.
+    /// The point is inside if the counts are odd, which requires a closed mesh.
+    fn contains(&self, point: Vec3) -> bool {
+        let directions = [Vec3::new(0.48, 0.61, 0.63),
+                          Vec3::new(-0.72, 0.29, -0.63),
+                          Vec3::new(0.17, -0.83, 0.53)];
+        directions.iter().all(|&dir| {
+            let ray = Ray::newn(point, dir);
+            let crossings = (0..self.triangles.len())
+                .filter(|&f| {
+                    let (a, b, c) = self.face_vertices(f);
+                    intersect_triangle_watertight(a, b, c, &ray, Float::INFINITY).is_some()
+                })
+                .count();
+            crossings % 2 == 1
+        })
     }
 }
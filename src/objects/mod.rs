@@ -1,5 +1,7 @@
 pub mod aabb;
+pub mod bounding_sphere;
 mod bvh;
+mod instance;
 mod mesh;
 mod plane;
 mod sphere;
@@ -7,6 +9,7 @@ pub mod surface;
 mod triangle;
 
 pub use objects::bvh::*;
+pub use objects::instance::*;
 pub use objects::mesh::*;
 pub use objects::plane::*;
 pub use objects::sphere::*;
@@ -1,24 +1,34 @@
 use basic::*;
 use objects::surface::*;
+use physics::orthonormal_basis;
+use validation::*;
 
 /// Representation of a plane.
 pub struct Plane {
     /// Normal vector of the plane.
     pub normal: UnitVec3,
     /// The offset is normal * x for any point x on the plane.
-    pub offset: f64,
+    pub offset: Float,
     /// The material of the plane.
     pub material: Material,
 }
 
+impl Plane {
+    /// Checks whether a ray with the given dot product `nd` of the normal and its direction
+    /// hits the back side, which is invisible.
+    fn is_culled(&self, nd: Float) -> bool {
+        nd > 0. && self.material.sides == Sides::Front
+    }
+}
+
 impl Surface for Plane {
-    fn intersect<'a>(&'a self, ray: &'a Ray, t_max: f64) -> Option<DelayedIntersection> {
-        let nd = self.normal * ray.dir;
-        if f64::abs(nd) < EPS {
+    fn intersect<'a>(&'a self, ray: &'a Ray, t_max: Float) -> Option<DelayedIntersection<'a>> {
+        let nd = self.normal * ray.dir();
+        if Float::abs(nd) < EPS || self.is_culled(nd) {
             return None;
         }
-        let t = (self.offset - self.normal * ray.origin) / nd;
-        if t < EPS || t > t_max {
+        let t = (self.offset - self.normal * ray.origin()) / nd;
+        if t < ray.t_min() || t > t_max {
             return None;
         }
         Some(DelayedIntersection::new(t, move || {
@@ -29,23 +39,32 @@ impl Surface for Plane {
             } else {
                 -self.normal
             };
-            Intersection::new(ray, t, normal, self.material)
+            // Texture coordinates are the coordinates of the point in the plane.
+            let inter = Intersection::new(ray, t, normal, self.material).with_front_face(nd < 0.);
+            let (tangent, bitangent) = orthonormal_basis(self.normal);
+            let uv = Vec2::new(inter.point * tangent, inter.point * bitangent);
+            inter.with_uv(uv)
         }))
     }
 
-    fn is_hit_by(&self, ray: &Ray, t_max: f64) -> bool {
-        let nd = self.normal * ray.dir;
-        if f64::abs(nd) < EPS {
-            return false;
-        }
-        let t = (self.offset - self.normal * ray.origin) / nd;
-        if t < EPS {
+    fn is_hit_by(&self, ray: &Ray, t_max: Float) -> bool {
+        let nd = self.normal * ray.dir();
+        if Float::abs(nd) < EPS || self.is_culled(nd) {
             return false;
         }
-        t < t_max
+        let t = (self.offset - self.normal * ray.origin()) / nd;
+        t >= ray.t_min() && t <= t_max
     }
 
     fn bounding_box(&self) -> Option<Aabb> {
         None
     }
+
+    fn validate(&self) -> Vec<(Severity, Problem)> {
+        let mut problems = validate_material(&self.material);
+        if !is_finite_vec(self.normal.to()) || !self.offset.is_finite() {
+            problems.push((Severity::Error, Problem::NonFiniteGeometry));
+        }
+        problems
+    }
 }
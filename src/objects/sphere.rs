@@ -1,49 +1,119 @@
 use basic::*;
 use objects::surface::*;
+use validation::*;
 
 /// Representation of a sphere.
+///
+/// A ray starting inside of the sphere, e.g. one refracted into it, hits it where it leaves,
+/// unless only the front side of the material is visible.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let sphere = Sphere { center: Vec3::zero(), radius: 2.0, material: glass() };
+/// let ray = Ray::new(Vec3::zero(), Vec3::e1());
+/// assert!((sphere.intersect(&ray, 10.0).unwrap().t - 2.0).abs() < 1e-6);
+/// assert!(sphere.is_hit_by(&ray, 10.0));
+/// assert!(!sphere.is_hit_by(&ray, 1.0));
+/// let front = Sphere { material: Material { sides: Sides::Front, ..glass() }, ..sphere };
+/// assert!(front.intersect(&ray, 10.0).is_none());
+/// ```
 pub struct Sphere {
     pub center: Vec3,
-    pub radius: f64,
+    pub radius: Float,
     pub material: Material,
 }
 
-impl Surface for Sphere {
-    fn intersect<'a>(&'a self, ray: &'a Ray, t_max: f64) -> Option<DelayedIntersection> {
-        let x = ray.origin - self.center;
-        let b = 2.0 * x * ray.dir;
+impl Sphere {
+    /// Returns the distances at which the ray enters and leaves the sphere, if it hits it.
+    fn hit_distances(&self, ray: &Ray) -> Option<(Float, Float)> {
+        let x = ray.origin() - self.center;
+        let b = 2.0 * x * ray.dir();
         let c = x.norm2() - self.radius * self.radius;
         let discriminant = b * b - 4.0 * c;
         if discriminant < 0.0 {
             return None;
         }
-        let t = (-b - f64::sqrt(discriminant)) / 2.0;
-        if t < EPS || t > t_max {
+        let root = Float::sqrt(discriminant);
+        Some(((-b - root) / 2.0, (-b + root) / 2.0))
+    }
+
+    fn intersection_at<'a>(&'a self, ray: &'a Ray, t: Float) -> DelayedIntersection<'a> {
+        DelayedIntersection::new(t, move || {
+            let normal = (ray.origin() + t * ray.dir() - self.center).normalize();
+            // Texture coordinates are longitude and latitude, with the poles on the y-axis.
+            let uv = normal.to_latlong_uv();
+            Intersection::new(ray, t, normal, self.material).with_uv(uv)
+        })
+    }
+}
+
+impl Surface for Sphere {
+    fn intersect<'a>(&'a self, ray: &'a Ray, t_max: Float) -> Option<DelayedIntersection<'a>> {
+        let (t1, t2) = match self.hit_distances(ray) {
+            Some(ts) => ts,
+            None => return None,
+        };
+        // If the ray starts inside of the sphere, it hits the sphere when leaving it,
+        // unless only the outside is visible.
+        let t = if t1 >= ray.t_min() || self.material.sides == Sides::Front {
+            t1
+        } else {
+            t2
+        };
+        if t < ray.t_min() || t > t_max {
             return None;
         }
-        Some(DelayedIntersection::new(t, move || {
-            let normal = (x + t * ray.dir).normalize();
-            Intersection::new(ray, t, normal, self.material)
-        }))
+        Some(self.intersection_at(ray, t))
     }
 
-    fn is_hit_by(&self, ray: &Ray, t_max: f64) -> bool {
-        let x = ray.origin - self.center;
-        let b = 2.0 * x * ray.dir;
-        let c = x.norm2() - self.radius * self.radius;
-        let discriminant = b * b - 4.0 * c;
-        if discriminant < 0.0 {
-            return false;
+    fn intersect_all<'a>(&'a self, ray: &'a Ray, t_max: Float) -> Vec<DelayedIntersection<'a>> {
+        match self.hit_distances(ray) {
+            None => Vec::new(),
+            Some((t1, t2)) => {
+                let visible = if self.material.sides == Sides::Front { 1 } else { 2 };
+                [t1, t2]
+                    .iter()
+                    .take(visible)
+                    .filter(|&&t| t >= ray.t_min() && t <= t_max)
+                    .map(|&t| self.intersection_at(ray, t))
+                    .collect()
+            }
         }
-        let t = (-b - f64::sqrt(discriminant)) / 2.0;
-        if t < EPS {
-            return false;
+    }
+
+    fn is_hit_by(&self, ray: &Ray, t_max: Float) -> bool {
+        match self.hit_distances(ray) {
+            Some((t1, t2)) => {
+                (t1 >= ray.t_min() && t1 <= t_max) ||
+                (self.material.sides == Sides::Both && t2 >= ray.t_min() && t2 <= t_max)
+            }
+            None => false,
         }
-        t < t_max
     }
 
     fn bounding_box(&self) -> Option<Aabb> {
         Some(Aabb::new(self.center - self.radius * Vec3::ones(),
                        self.center + self.radius * Vec3::ones()))
     }
+
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        Some(BoundingSphere {
+            center: self.center,
+            radius: self.radius,
+        })
+    }
+
+    fn validate(&self) -> Vec<(Severity, Problem)> {
+        let mut problems = validate_material(&self.material);
+        if !is_finite_vec(self.center) || !self.radius.is_finite() {
+            problems.push((Severity::Error, Problem::NonFiniteGeometry));
+        } else if !(self.radius > 0.) {
+            problems.push((Severity::Error, Problem::DegenerateGeometry));
+        }
+        problems
+    }
+
+    fn contains(&self, point: Vec3) -> bool {
+        (point - self.center).norm2() < self.radius * self.radius
+    }
 }
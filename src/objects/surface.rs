@@ -1,5 +1,7 @@
 use basic::*;
 pub use objects::aabb::*;
+pub use objects::bounding_sphere::*;
+use validation::{Problem, Severity};
 
 /// Trait for finding ray intersections.
 /// Instances **must** satisfy the law:
@@ -7,36 +9,228 @@ pub use objects::aabb::*;
 /// ```text
 /// s.is_hit_by(ray, tmax) == s.intersect(ray, tmax).is_some()
 /// ```
+///
+/// Both only consider intersections at distances `t` with `ray.t_min() <= t <= t_max`.
+/// Similarly, the first of the intersections returned by `intersect_all`
+/// must be the one returned by `intersect`.
+/// The following checks these laws for all surfaces of this crate:
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// use std::sync::Arc;
+///
+/// let cube = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 0 0 1\nv 1 0 1\nv 1 1 1\nv 0 1 1\n\
+///             f 1 3 2\nf 1 4 3\nf 5 6 7\nf 5 7 8\nf 1 2 6\nf 1 6 5\n\
+///             f 4 7 3\nf 4 8 7\nf 1 5 8\nf 1 8 4\nf 2 3 7\nf 2 7 6\n";
+/// let surfaces: Vec<Box<Surface>> = vec![
+///     Box::new(Sphere { center: Vec3::new(0.5, 0.5, 0.5), radius: 0.7, material: glass() }),
+///     Box::new(Plane { normal: Vec3::e(1), offset: 0.5, material: glass() }),
+///     Box::new(Triangle {
+///         a: Vec3::zero(),
+///         b: Vec3::new(1., 0., 0.),
+///         c: Vec3::new(0., 1., 1.),
+///         material: glass(),
+///     }),
+///     Box::new(Mesh::from_obj_reader(cube.as_bytes(), glass()).unwrap()),
+///     Box::new(Instance::new(Arc::new(Mesh::from_obj_reader(cube.as_bytes(), glass()).unwrap()),
+///                            Matrix34::rotate(Vec3::new(1., 2., 3.), 0.5) *
+///                            Matrix34::scale(Vec3::new(1.5, 0.5, 1.)))),
+/// ];
+/// let (mut hits, mut all_hits) = (0, 0);
+/// for s in surfaces.iter() {
+///     for i in 0..1000 {
+///         let i = i as Float;
+///         let origin = 3. * Vec3::new((i * 0.37).sin(), (i * 0.71).sin(), (i * 0.13).sin());
+///         let target = Vec3::new((i * 0.53).cos(), (i * 0.29).cos(), (i * 0.91).cos());
+///         let ray = Ray::newn(origin, target - origin);
+///         let t = s.intersect(&ray, Float::INFINITY).map(|inter| inter.t);
+///         for &t_max in [Float::INFINITY, 1., t.unwrap_or(2.), t.unwrap_or(2.) - EPS].iter() {
+///             let inter = s.intersect(&ray, t_max);
+///             assert_eq!(s.is_hit_by(&ray, t_max), inter.is_some());
+///             let all = s.intersect_all(&ray, t_max);
+///             assert_eq!(all.first().map(|i| i.t), inter.as_ref().map(|i| i.t));
+///             assert!(all.windows(2).all(|w| w[0].t <= w[1].t));
+///             all_hits += all.len();
+///             if let Some(inter) = inter {
+///                 assert!(EPS <= inter.t && inter.t <= t_max);
+///                 hits += 1;
+///             }
+///         }
+///     }
+/// }
+/// assert!(hits > 1000);
+/// assert!(all_hits > hits);
+/// ```
 pub trait Surface {
     /// Returns information about the intersection of the object and the ray, if one exists.
-    /// If the distance is greater that `t_max`, it returns `None`.
-    fn intersect<'a>(&'a self, ray: &'a Ray, t_max: f64) -> Option<DelayedIntersection<'a>>;
+    /// If the distance is greater than `t_max`, it returns `None`.
+    fn intersect<'a>(&'a self, ray: &'a Ray, t_max: Float) -> Option<DelayedIntersection<'a>>;
 
     /// Checks whether the ray intersects the object, computes no additional information.
     /// If the distance is greater than `t_max`, it returns `false`.
-    fn is_hit_by(&self, ray: &Ray, t_max: f64) -> bool;
+    fn is_hit_by(&self, ray: &Ray, t_max: Float) -> bool;
+
+    /// Like `is_hit_by`, but returns the part of the object that the ray hits, e.g. the index
+    /// of a triangle of a mesh, so that it can be tested first with `part_is_hit_by`.
+    ///
+    /// The default implementation treats the whole object as part 0.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 5 0 0\nv 6 0 0\nv 5 1 0\nf 1 2 3\nf 4 5 6\n";
+    /// let mesh = Mesh::from_obj_reader(obj.as_bytes(), glass()).unwrap();
+    /// let ray = Ray::new(Vec3::new(5.2, 0.2, 1.0), -Vec3::e3());
+    /// assert_eq!(mesh.hit_part(&ray, 10.0), Some(1));
+    /// assert!(mesh.part_is_hit_by(1, &ray, 10.0));
+    /// assert!(!mesh.part_is_hit_by(0, &ray, 10.0));
+    /// ```
+    fn hit_part(&self, ray: &Ray, t_max: Float) -> Option<usize> {
+        if self.is_hit_by(ray, t_max) { Some(0) } else { None }
+    }
+
+    /// Checks whether the ray hits the part of the object returned by `hit_part`.
+    fn part_is_hit_by(&self, _part: usize, ray: &Ray, t_max: Float) -> bool {
+        self.is_hit_by(ray, t_max)
+    }
+
+    /// Returns all intersections of the object and the ray up to `t_max`, nearest first.
+    /// The first one is the intersection returned by `intersect`.
+    ///
+    /// The default implementation only returns that one,
+    /// which is correct for objects that a ray hits at most once, like planes.
+    fn intersect_all<'a>(&'a self, ray: &'a Ray, t_max: Float) -> Vec<DelayedIntersection<'a>> {
+        self.intersect(ray, t_max).into_iter().collect()
+    }
 
     /// Returns a finite (!) axis-aligned bounding box if one exists.
     fn bounding_box(&self) -> Option<Aabb>;
+
+    /// Returns a sphere containing the object if one exists.
+    ///
+    /// The default implementation is the sphere around the bounding box.
+    /// Objects that it fits poorly, like round ones, should return a smaller sphere.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let sphere = Sphere { center: Vec3::ones(), radius: 2.0, material: glass() };
+    /// assert_eq!(sphere.bounding_sphere().unwrap().radius, 2.0);
+    /// let plane = Plane { normal: Vec3::e(1), offset: 0.5, material: glass() };
+    /// assert!(plane.bounding_sphere().is_none());
+    ///
+    /// // The unit sphere without its poles: the sphere around its box has a radius above 1.6.
+    /// let mut obj = String::new();
+    /// for i in 0..64 {
+    ///     for j in 1..16 {
+    ///         let v = Vec3::from_spherical(j as Float * PI / 16.0, i as Float * PI / 32.0, 1.0);
+    ///         obj += &format!("v {} {} {}\n", v.x(), v.y(), v.z());
+    ///     }
+    /// }
+    /// for i in 0..64 {
+    ///     let (a, b) = (15 * i + 1, 15 * ((i + 1) % 64) + 1);
+    ///     for j in 0..14 {
+    ///         obj += &format!("f {} {} {}\nf {} {} {}\n", a + j, b + j, b + j + 1,
+    ///                         a + j, b + j + 1, a + j + 1);
+    ///     }
+    /// }
+    /// let mesh = Mesh::from_obj_reader(obj.as_bytes(), glass()).unwrap();
+    /// let bound = mesh.bounding_sphere().unwrap();
+    /// assert!(bound.radius < 1.2);
+    /// assert!(bound.contains_point(Vec3::e1().to()) && bound.contains_point(-Vec3::e3().to()));
+    /// ```
+    fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        self.bounding_box().map(|aabb| BoundingSphere::from_aabb(&aabb))
+    }
+
+    /// Checks the object for mistakes like non-finite coordinates or invalid materials.
+    /// The default implementation finds none.
+    fn validate(&self) -> Vec<(Severity, Problem)> {
+        Vec::new()
+    }
+
+    /// Returns `true` if the point is inside the object.
+    /// Only closed objects have an inside, so the default implementation returns `false`.
+    fn contains(&self, _point: Vec3) -> bool {
+        false
+    }
 }
 
 /// Represents a container type which contains `Surfaces`s, for example a triangle mesh.
 pub trait SurfaceContainer {
     /// Returns information about the intersection of the object and the ray, if one exists.
-    /// If the distance is greater that `t_max`, it returns `None`.
+    /// If the distance is greater than `t_max`, it returns `None`.
     fn elem_intersect<'a>(&'a self,
                           idx: usize,
                           ray: &'a Ray,
-                          t_max: f64)
+                          t_max: Float)
                           -> Option<DelayedIntersection<'a>>;
 
     /// Checks whether the ray intersects the object, computes no additional information.
     /// If the distance is greater than `t_max`, it returns `false`.
-    fn elem_is_hit_by(&self, idx: usize, ray: &Ray, t_max: f64) -> bool;
+    fn elem_is_hit_by(&self, idx: usize, ray: &Ray, t_max: Float) -> bool;
+
+    /// Returns all intersections of the object and the ray up to `t_max`, nearest first.
+    /// The default implementation only returns the nearest one, see `Surface::intersect_all`.
+    fn elem_intersect_all<'a>(&'a self,
+                              idx: usize,
+                              ray: &'a Ray,
+                              t_max: Float)
+                              -> Vec<DelayedIntersection<'a>> {
+        self.elem_intersect(idx, ray, t_max).into_iter().collect()
+    }
 
     /// Returns a finite (!) axis-aligned bounding box if one exists.
     fn elem_bounding_box(&self, idx: usize) -> Option<Aabb>;
 
     /// Returns the number of objects in the container.
     fn count(&self) -> usize;
+
+    /// Checks the objects in the container for mistakes, see `Surface::validate`.
+    fn validate(&self) -> Vec<(Severity, Problem)> {
+        Vec::new()
+    }
+
+    /// Returns `true` if the point is inside the container, see `Surface::contains`.
+    fn contains(&self, _point: Vec3) -> bool {
+        false
+    }
+}
+
+/// A list of surfaces is a container, e.g. the instances of a two-level hierarchy,
+/// see `Instance`.
+impl<S: Surface> SurfaceContainer for Vec<S> {
+    fn elem_intersect<'a>(&'a self,
+                          idx: usize,
+                          ray: &'a Ray,
+                          t_max: Float)
+                          -> Option<DelayedIntersection<'a>> {
+        self[idx].intersect(ray, t_max)
+    }
+
+    fn elem_is_hit_by(&self, idx: usize, ray: &Ray, t_max: Float) -> bool {
+        self[idx].is_hit_by(ray, t_max)
+    }
+
+    fn elem_intersect_all<'a>(&'a self,
+                              idx: usize,
+                              ray: &'a Ray,
+                              t_max: Float)
+                              -> Vec<DelayedIntersection<'a>> {
+        self[idx].intersect_all(ray, t_max)
+    }
+
+    fn elem_bounding_box(&self, idx: usize) -> Option<Aabb> {
+        self[idx].bounding_box()
+    }
+
+    fn count(&self) -> usize {
+        self.len()
+    }
+
+    fn validate(&self) -> Vec<(Severity, Problem)> {
+        self.iter().flat_map(|surface| surface.validate()).collect()
+    }
+
+    fn contains(&self, point: Vec3) -> bool {
+        self.iter().any(|surface| surface.contains(point))
+    }
 }
@@ -1,5 +1,7 @@
 use basic::*;
 use objects::surface::*;
+use stats::count_triangle_test;
+use validation::*;
 
 /// Represents a triangle.
 pub struct Triangle {
@@ -13,27 +15,38 @@ pub struct Triangle {
     pub material: Material,
 }
 
+impl Triangle {
+    /// Checks whether the ray hits the back side, which is invisible.
+    fn is_culled(&self, ray: &Ray) -> bool {
+        self.material.sides == Sides::Front &&
+        (self.b - self.a).cross(self.c - self.a) * ray.dir() > 0.
+    }
+}
+
 impl Surface for Triangle {
     /// Intersects a ray with a triangle.
-    fn intersect<'a>(&'a self, ray: &'a Ray, t_max: f64) -> Option<DelayedIntersection> {
-        intersect_triangle(self.a, self.b, self.c, ray, t_max).map(|(e, f, _, _, t)| {
+    fn intersect<'a>(&'a self, ray: &'a Ray, t_max: Float) -> Option<DelayedIntersection<'a>> {
+        if self.is_culled(ray) {
+            return None;
+        }
+        intersect_triangle(self.a, self.b, self.c, ray, t_max).map(|(e, f, u, v, t)| {
             DelayedIntersection::new(t, move || {
                 let normal = e.cross(f).normalize();
+                let front_face = normal * ray.dir() < 0.;
                 // Make the normal vector point to the origin of the ray.
                 // This is important for the epsilon displacement for shadow and reflection rays.
-                let normal = if normal * ray.dir < 0. {
-                    normal
-                } else {
-                    -normal
-                };
+                let normal = if front_face { normal } else { -normal };
                 Intersection::new(ray, t, normal, self.material)
+                    .with_front_face(front_face)
+                    .with_uv(Vec2::new(u, v))
+                    .with_barycentric(u, v)
             })
         })
     }
 
     /// Checks whether the ray hits the triangle.
-    fn is_hit_by(&self, ray: &Ray, t_max: f64) -> bool {
-        is_triangle_hit_by(self.a, self.b, self.c, ray, t_max)
+    fn is_hit_by(&self, ray: &Ray, t_max: Float) -> bool {
+        !self.is_culled(ray) && is_triangle_hit_by(self.a, self.b, self.c, ray, t_max)
     }
 
     fn bounding_box(&self) -> Option<Aabb> {
@@ -41,6 +54,17 @@ impl Surface for Triangle {
         let max = self.a.max(self.b).max(self.c);
         Some(Aabb::new(min, max))
     }
+
+    fn validate(&self) -> Vec<(Severity, Problem)> {
+        let mut problems = validate_material(&self.material);
+        if !is_finite_vec(self.a) || !is_finite_vec(self.b) || !is_finite_vec(self.c) {
+            problems.push((Severity::Error, Problem::NonFiniteGeometry));
+        } else if (self.b - self.a).cross(self.c - self.a).norm2() == 0. {
+            // Invisible, but harmless.
+            problems.push((Severity::Warning, Problem::DegenerateGeometry));
+        }
+        problems
+    }
 }
 
 #[inline(always)]
@@ -49,16 +73,18 @@ pub fn intersect_triangle(a: Vec3,
                           b: Vec3,
                           c: Vec3,
                           ray: &Ray,
-                          t_max: f64)
-                          -> Option<(Vec3, Vec3, f64, f64, f64)> {
-    let d = ray.dir;
+                          t_max: Float)
+                          -> Option<(Vec3, Vec3, Float, Float, Float)> {
+    count_triangle_test();
+    let d = ray.dir();
     let e = b - a;
     let f = c - a;
-    let g = ray.origin - a;
+    let g = ray.origin() - a;
     let p = d.cross(f);
     let det = p * e;
-    // If the determinant is close to 0, the ray misses the triangle.
-    if det.abs() < EPS {
+    // If the determinant is close to 0, the ray is parallel to the triangle and misses it.
+    // It grows with the edges, so it is compared relative to them for any size of triangles.
+    if det.abs() < EPS * (e.norm2() * f.norm2()).sqrt() {
         return None;
     }
     let u = p * g / det;
@@ -71,7 +97,7 @@ pub fn intersect_triangle(a: Vec3,
         return None;
     }
     let t = q * f / det;
-    if t < EPS || t > t_max {
+    if t < ray.t_min() || t > t_max {
         return None;
     }
     return Some((e, f, u, v, t));
@@ -79,15 +105,17 @@ pub fn intersect_triangle(a: Vec3,
 
 #[inline(always)]
 #[doc(hidden)]
-pub fn is_triangle_hit_by(a: Vec3, b: Vec3, c: Vec3, ray: &Ray, t_max: f64) -> bool {
-    let d = ray.dir;
+pub fn is_triangle_hit_by(a: Vec3, b: Vec3, c: Vec3, ray: &Ray, t_max: Float) -> bool {
+    count_triangle_test();
+    let d = ray.dir();
     let e = b - a;
     let f = c - a;
-    let g = ray.origin - a;
+    let g = ray.origin() - a;
     let p = d.cross(f);
     let det = p * e;
-    // If the determinant is close to 0, the ray misses the triangle.
-    if det.abs() < EPS {
+    // If the determinant is close to 0, the ray is parallel to the triangle and misses it.
+    // It grows with the edges, so it is compared relative to them for any size of triangles.
+    if det.abs() < EPS * (e.norm2() * f.norm2()).sqrt() {
         return false;
     }
     let u = p * g / det;
@@ -100,8 +128,74 @@ pub fn is_triangle_hit_by(a: Vec3, b: Vec3, c: Vec3, ray: &Ray, t_max: f64) -> b
         return false;
     }
     let t = q * f / det;
-    if t < EPS {
-        return false;
+    t >= ray.t_min() && t <= t_max
+}
+
+/// Watertight ray-triangle intersection test.
+///
+/// This is the algorithm from the paper
+/// "Watertight Ray/Triangle Intersection" by Woop, Benthin and Wald.
+/// In contrast to `intersect_triangle`, rays cannot slip through the shared edge
+/// of two adjacent triangles, which is why meshes use this version.
+/// Returns the barycentric coordinates of `b` and `c` and the distance `t`.
+#[inline(always)]
+#[doc(hidden)]
+pub fn intersect_triangle_watertight(a: Vec3,
+                                     b: Vec3,
+                                     c: Vec3,
+                                     ray: &Ray,
+                                     t_max: Float)
+                                     -> Option<(Float, Float, Float)> {
+    count_triangle_test();
+    let d = ray.dir();
+    // Choose the dimension where the ray direction is maximal as the z-axis
+    // and swap x and y to preserve the winding direction of the triangle.
+    let mut kz = 0;
+    for i in 1..3 {
+        if d[i].abs() > d[kz].abs() {
+            kz = i;
+        }
     }
-    t < t_max
+    let (kx, ky) = if d[kz] < 0. {
+        ((kz + 2) % 3, (kz + 1) % 3)
+    } else {
+        ((kz + 1) % 3, (kz + 2) % 3)
+    };
+    // Shear constants that transform the ray direction to the unit z-axis:
+    let sx = d[kx] / d[kz];
+    let sy = d[ky] / d[kz];
+    let sz = 1. / d[kz];
+    // Vertices relative to the ray origin:
+    let a = a - ray.origin();
+    let b = b - ray.origin();
+    let c = c - ray.origin();
+    // Shear and scale the vertices:
+    let ax = a[kx] - sx * a[kz];
+    let ay = a[ky] - sy * a[kz];
+    let bx = b[kx] - sx * b[kz];
+    let by = b[ky] - sy * b[kz];
+    let cx = c[kx] - sx * c[kz];
+    let cy = c[ky] - sy * c[kz];
+    // Scaled barycentric coordinates:
+    let u = cx * by - cy * bx;
+    let v = ax * cy - ay * cx;
+    let w = bx * ay - by * ax;
+    if (u < 0. || v < 0. || w < 0.) && (u > 0. || v > 0. || w > 0.) {
+        return None;
+    }
+    let det = u + v + w;
+    if det == 0. {
+        return None;
+    }
+    let t = (u * sz * a[kz] + v * sz * b[kz] + w * sz * c[kz]) / det;
+    if t < ray.t_min() || t > t_max {
+        return None;
+    }
+    Some((v / det, w / det, t))
+}
+
+#[inline(always)]
+#[doc(hidden)]
+pub fn is_triangle_hit_by_watertight(a: Vec3, b: Vec3, c: Vec3, ray: &Ray, t_max: Float) -> bool {
+    intersect_triangle_watertight(a, b, c, ray, t_max).is_some()
 }
@@ -0,0 +1,144 @@
+use basic::*;
+use img_output::*;
+use raytrace::Camera;
+
+/// The number of grid lines on each side of the origin.
+const GRID_LINES: i32 = 10;
+/// The length of the axes of the gizmo in pixels.
+const GIZMO_LENGTH: Float = 30.;
+/// The distance of the gizmo's origin from the left and bottom edge of the image in pixels.
+const GIZMO_MARGIN: Float = 45.;
+
+/// Strokes of the axis labels, drawn in a box of 4x6 pixels (y pointing down).
+const LABEL_X: [((Float, Float), (Float, Float)); 2] = [((0., 0.), (4., 6.)),
+                                                        ((4., 0.), (0., 6.))];
+const LABEL_Y: [((Float, Float), (Float, Float)); 3] = [((0., 0.), (2., 3.)),
+                                                        ((4., 0.), (2., 3.)),
+                                                        ((2., 3.), (2., 6.))];
+const LABEL_Z: [((Float, Float), (Float, Float)); 3] = [((0., 0.), (4., 0.)),
+                                                        ((4., 0.), (0., 6.)),
+                                                        ((0., 6.), (4., 6.))];
+
+/// Returns the color used for the axis `i` (x red, y green, z blue).
+fn axis_color(i: usize) -> AColor {
+    match i {
+        0 => AColor::new(1., 0., 0.),
+        1 => AColor::new(0., 1., 0.),
+        _ => AColor::new(0., 0., 1.),
+    }
+}
+
+/// Draws the line between two points given in pixel coordinates.
+///
+/// Parts of the line outside of the image are clipped.
+pub fn draw_line(img: &mut Image, from: (Float, Float), to: (Float, Float), col: AColor) {
+    // Clip the line to the image (Liang-Barsky algorithm):
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let (mut t0, mut t1): (Float, Float) = (0., 1.);
+    let checks = [(-dx, from.0),
+                  (dx, img.width as Float - 1. - from.0),
+                  (-dy, from.1),
+                  (dy, img.height as Float - 1. - from.1)];
+    for &(p, q) in checks.iter() {
+        if p == 0. {
+            if q < 0. {
+                return;
+            }
+        } else if p < 0. {
+            t0 = t0.max(q / p);
+        } else {
+            t1 = t1.min(q / p);
+        }
+    }
+    if t0 > t1 {
+        return;
+    }
+    let (x0, y0) = (from.0 + t0 * dx, from.1 + t0 * dy);
+    let (x1, y1) = (from.0 + t1 * dx, from.1 + t1 * dy);
+    // Step through the clipped line pixel by pixel:
+    let steps = (x1 - x0).abs().max((y1 - y0).abs()).ceil().max(1.);
+    for i in 0..(steps as usize + 1) {
+        let s = i as Float / steps;
+        let x = (x0 + s * (x1 - x0)).round();
+        let y = (y0 + s * (y1 - y0)).round();
+        if x >= 0. && y >= 0. && (x as usize) < img.width && (y as usize) < img.height {
+            img.set(x as usize, y as usize, col);
+        }
+    }
+}
+
+/// Draws the line between two points in world coordinates as seen by the camera.
+///
+/// Parts of the line behind the camera are clipped.
+pub fn draw_world_line(img: &mut Image, camera: &Camera, from: Vec3, to: Vec3, col: AColor) {
+    let camera_dir = (camera.look_at - camera.pos).normalize();
+    let near = 2. * EPS;
+    let (d0, d1) = ((from - camera.pos) * camera_dir, (to - camera.pos) * camera_dir);
+    if d0 < near && d1 < near {
+        return;
+    }
+    // Move the end point behind the camera to the near plane:
+    let clip = |behind: Vec3, d_behind: Float, front: Vec3, d_front: Float| {
+        let s = (d_front - near) / (d_front - d_behind);
+        front + s * (behind - front)
+    };
+    let (from, to) = if d0 < near {
+        (clip(from, d0, to, d1), to)
+    } else if d1 < near {
+        (from, clip(to, d1, from, d0))
+    } else {
+        (from, to)
+    };
+    if let (Some(a), Some(b)) = (camera.project(from), camera.project(to)) {
+        draw_line(img, a, b, col);
+    }
+}
+
+/// Draws a grid with the given spacing on the plane y = 0.
+///
+/// The lines through the origin are drawn in the colors of the x- and z-axis.
+pub fn draw_grid(img: &mut Image, camera: &Camera, spacing: Float) {
+    let extent = GRID_LINES as Float * spacing;
+    for i in -GRID_LINES..GRID_LINES + 1 {
+        let offset = i as Float * spacing;
+        let (col_x, col_z) = if i == 0 {
+            (axis_color(0), axis_color(2))
+        } else {
+            (AColor::new(0.5, 0.5, 0.5), AColor::new(0.5, 0.5, 0.5))
+        };
+        draw_world_line(img,
+                        camera,
+                        Vec3::new(-extent, 0., offset),
+                        Vec3::new(extent, 0., offset),
+                        col_x);
+        draw_world_line(img,
+                        camera,
+                        Vec3::new(offset, 0., -extent),
+                        Vec3::new(offset, 0., extent),
+                        col_z);
+    }
+}
+
+/// Draws the world axes as seen from the camera, labeled with their names,
+/// in the bottom-left corner of the image.
+pub fn draw_axis_gizmo(img: &mut Image, camera: &Camera) {
+    let camera_dir = (camera.look_at - camera.pos).normalize();
+    let right = camera_dir.cross(camera.up).normalize();
+    let up = right.cross(camera_dir).normalize();
+    let origin = (GIZMO_MARGIN, img.height as Float - GIZMO_MARGIN);
+    let labels: [&[((Float, Float), (Float, Float))]; 3] = [&LABEL_X, &LABEL_Y, &LABEL_Z];
+    for i in 0..3 {
+        let axis = Vec3::e(i);
+        let (dx, dy) = (axis * right, -(axis * up));
+        let tip = (origin.0 + GIZMO_LENGTH * dx, origin.1 + GIZMO_LENGTH * dy);
+        draw_line(img, origin, tip, axis_color(i));
+        // Put the label a bit beyond the tip, centered on the axis:
+        let label_pos = (tip.0 + 8. * dx - 2., tip.1 + 8. * dy - 3.);
+        for &(a, b) in labels[i].iter() {
+            draw_line(img,
+                      (label_pos.0 + a.0, label_pos.1 + a.1),
+                      (label_pos.0 + b.0, label_pos.1 + b.1),
+                      axis_color(i));
+        }
+    }
+}
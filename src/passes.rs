@@ -0,0 +1,65 @@
+use color::*;
+use img_output::*;
+
+/// Auxiliary images (also known as AOVs) describing what the primary rays hit,
+/// e.g. for compositing or for feeding denoisers.
+///
+/// Pixels where the primary ray hits nothing are transparent in every image.
+/// The values are not restricted to the range of displayable colors,
+/// so they should be saved with `write_pfm_file` to preserve them.
+pub struct RenderPasses {
+    /// The distance of the hit point from the camera along the viewing direction (Z-depth),
+    /// stored in all three color channels.
+    pub depth: Image,
+    /// The world-space normal at the hit point, with x, y, z stored in red, green, blue.
+    /// The components range from -1 to 1.
+    pub normal: Image,
+    /// The color of the material at the hit point, without any lighting.
+    pub albedo: Image,
+    /// The index in `Scene::objects` of the object hit by each pixel, stored line by line.
+    pub object_ids: Vec<Option<usize>>,
+}
+
+impl RenderPasses {
+    /// Creates empty passes for an image of the given dimensions.
+    pub fn new(width: usize, height: usize) -> RenderPasses {
+        RenderPasses {
+            depth: Image::new(width, height),
+            normal: Image::new(width, height),
+            albedo: Image::new(width, height),
+            object_ids: vec![None; width * height],
+        }
+    }
+
+    /// Returns the index of the object hit by the pixel at (x,y).
+    pub fn object_id(&self, x: usize, y: usize) -> Option<usize> {
+        self.object_ids[y * self.depth.width + x]
+    }
+
+    /// Returns the passes of the rectangle with the top-left pixel (x, y)
+    /// and the given dimensions.
+    pub fn crop(&self, x: usize, y: usize, width: usize, height: usize) -> RenderPasses {
+        let full_width = self.depth.width;
+        let mut object_ids = Vec::with_capacity(width * height);
+        for row in y..y + height {
+            object_ids.extend_from_slice(&self.object_ids[row * full_width + x..][..width]);
+        }
+        RenderPasses {
+            depth: self.depth.crop(x, y, width, height),
+            normal: self.normal.crop(x, y, width, height),
+            albedo: self.albedo.crop(x, y, width, height),
+            object_ids: object_ids,
+        }
+    }
+
+    /// Returns a mask of the given object: white where it is hit, transparent elsewhere.
+    pub fn object_mask(&self, object: usize) -> Image {
+        let mut mask = Image::new(self.depth.width, self.depth.height);
+        for (x, y, col) in mask.iter_mut() {
+            if self.object_id(x, y) == Some(object) {
+                *col = white().with_alpha();
+            }
+        }
+        mask
+    }
+}
@@ -4,10 +4,278 @@ use lin_alg::*;
 pub fn compute_specular(ray_origin_dir: UnitVec3,
                         light_dir: UnitVec3,
                         normal: UnitVec3,
-                        shininess: f64)
-                        -> f64 {
+                        shininess: Float)
+                        -> Float {
     let halfway = (light_dir + ray_origin_dir).normalize();
-    f64::max(0.0, halfway * normal).powf(shininess)
+    Float::max(0.0, halfway * normal).powf(shininess)
+}
+
+/// Computes the diffuse coefficient of the (qualitative) Oren-Nayar model.
+///
+/// `view_dir` points to the viewer and `light_dir` to the light source.
+/// `roughness` is the standard deviation of the microfacet slope angle in radians.
+/// For a roughness of 0, this is the same as Lambert's cosine law.
+pub fn oren_nayar(view_dir: UnitVec3,
+                  light_dir: UnitVec3,
+                  normal: UnitVec3,
+                  roughness: Float)
+                  -> Float {
+    let nl = light_dir * normal;
+    if nl <= 0. {
+        return 0.;
+    }
+    let nv = (view_dir * normal).max(0.);
+    let s2 = roughness * roughness;
+    let a = 1. - 0.5 * s2 / (s2 + 0.33);
+    let b = 0.45 * s2 / (s2 + 0.09);
+    // Cosine of the azimuthal angle between the two directions projected onto the surface:
+    let light_proj = light_dir - nl * normal;
+    let view_proj = view_dir - nv * normal;
+    let denominator = light_proj.norm() * view_proj.norm();
+    let cos_phi = if denominator < EPS {
+        0.
+    } else {
+        (light_proj * view_proj / denominator).max(0.)
+    };
+    let (theta_l, theta_v) = (nl.min(1.).acos(), nv.min(1.).acos());
+    let (alpha, beta) = (theta_l.max(theta_v), theta_l.min(theta_v));
+    nl * (a + b * cos_phi * alpha.sin() * beta.tan())
+}
+
+/// Computes the diffuse coefficient of wrap lighting, which lets light wrap around the
+/// terminator, as a cheap approximation of subsurface scattering.
+///
+/// `wrap` ranges from 0 (Lambert's cosine law) to 1 (light reaches almost all of a sphere).
+///
+/// ```
+/// use raydiancy::physics::*;
+/// use raydiancy::lin_alg::*;
+/// let n = Vec3::e3();
+/// let side = Vec3::e1();
+/// assert_eq!(wrap_lighting(n, n, 0.5), 1.0);
+/// assert_eq!(wrap_lighting(side, n, 0.0), 0.0);
+/// assert!(appr(wrap_lighting(side, n, 0.5), 1.0 / 3.0));
+/// assert_eq!(wrap_lighting(-n, n, 0.5), 0.0);
+/// ```
+pub fn wrap_lighting(light_dir: UnitVec3, normal: UnitVec3, wrap: Float) -> Float {
+    ((light_dir * normal + wrap) / (1. + wrap)).max(0.)
+}
+
+/// Computes the GGX (Trowbridge-Reitz) normal distribution function
+/// for the halfway vector `h` and the surface roughness `roughness` (between 0 and 1).
+pub fn ggx_distribution(h: UnitVec3, normal: UnitVec3, roughness: Float) -> Float {
+    let a2 = roughness.powi(4); // alpha = roughness^2
+    let nh = h * normal;
+    if nh <= 0. {
+        return 0.;
+    }
+    let d = nh * nh * (a2 - 1.) + 1.;
+    a2 / (PI * d * d)
+}
+
+/// Computes the Smith masking function for GGX for the direction `v`.
+pub fn smith_g1(v: UnitVec3, normal: UnitVec3, roughness: Float) -> Float {
+    let a2 = roughness.powi(4);
+    let nv = v * normal;
+    if nv <= 0. {
+        return 0.;
+    }
+    2. * nv / (nv + (a2 + (1. - a2) * nv * nv).sqrt())
+}
+
+/// The directional albedo `E` of the GGX model without Fresnel (i.e. the fraction of the light
+/// from one direction that is reflected once), for the roughness `i / 15` (row `i`) and
+/// the cosine of the angle to the normal `j / 15` (column `j`).
+/// Computed numerically from `ggx_distribution` and `smith_g1`.
+const GGX_ALBEDO: [[Float; 16]; 16] =
+    [[0.9258, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000,
+      1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000],
+     [0.9016, 0.9970, 0.9992, 0.9996, 0.9998, 0.9999, 0.9999, 1.0000,
+      1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000, 1.0000],
+     [0.9148, 0.9579, 0.9882, 0.9947, 0.9970, 0.9980, 0.9986, 0.9989,
+      0.9992, 0.9994, 0.9995, 0.9996, 0.9996, 0.9999, 1.0000, 1.0000],
+     [0.9171, 0.8933, 0.9490, 0.9742, 0.9850, 0.9903, 0.9932, 0.9950,
+      0.9961, 0.9969, 0.9974, 0.9978, 0.9981, 0.9983, 0.9982, 0.9980],
+     [0.9174, 0.8728, 0.9003, 0.9341, 0.9564, 0.9699, 0.9782, 0.9834,
+      0.9868, 0.9891, 0.9905, 0.9912, 0.9924, 0.9933, 0.9940, 0.9939],
+     [0.9161, 0.8759, 0.8731, 0.8933, 0.9161, 0.9348, 0.9485, 0.9580,
+      0.9649, 0.9708, 0.9752, 0.9783, 0.9806, 0.9826, 0.9841, 0.9854],
+     [0.9119, 0.8793, 0.8624, 0.8646, 0.8765, 0.8911, 0.9062, 0.9202,
+      0.9313, 0.9400, 0.9475, 0.9532, 0.9580, 0.9619, 0.9651, 0.9677],
+     [0.9026, 0.8750, 0.8529, 0.8424, 0.8422, 0.8511, 0.8624, 0.8740,
+      0.8858, 0.8964, 0.9058, 0.9141, 0.9212, 0.9274, 0.9326, 0.9372],
+     [0.8867, 0.8612, 0.8371, 0.8202, 0.8142, 0.8140, 0.8183, 0.8252,
+      0.8338, 0.8429, 0.8521, 0.8609, 0.8691, 0.8767, 0.8837, 0.8899],
+     [0.8640, 0.8386, 0.8133, 0.7953, 0.7833, 0.7766, 0.7741, 0.7750,
+      0.7784, 0.7834, 0.7896, 0.7964, 0.8035, 0.8107, 0.8177, 0.8245],
+     [0.8347, 0.8081, 0.7821, 0.7627, 0.7469, 0.7353, 0.7273, 0.7225,
+      0.7202, 0.7201, 0.7216, 0.7244, 0.7282, 0.7327, 0.7377, 0.7430],
+     [0.7993, 0.7707, 0.7443, 0.7222, 0.7038, 0.6885, 0.6762, 0.6665,
+      0.6592, 0.6539, 0.6504, 0.6483, 0.6475, 0.6478, 0.6489, 0.6508],
+     [0.7584, 0.7274, 0.6995, 0.6753, 0.6543, 0.6361, 0.6203, 0.6068,
+      0.5953, 0.5856, 0.5775, 0.5708, 0.5654, 0.5610, 0.5577, 0.5552],
+     [0.7129, 0.6794, 0.6496, 0.6233, 0.5999, 0.5792, 0.5608, 0.5443,
+      0.5297, 0.5166, 0.5049, 0.4945, 0.4852, 0.4769, 0.4696, 0.4631],
+     [0.6640, 0.6282, 0.5964, 0.5680, 0.5426, 0.5199, 0.4994, 0.4808,
+      0.4640, 0.4486, 0.4345, 0.4216, 0.4097, 0.3988, 0.3887, 0.3794],
+     [0.6131, 0.5753, 0.5415, 0.5114, 0.4845, 0.4603, 0.4384, 0.4184,
+      0.4002, 0.3836, 0.3682, 0.3541, 0.3409, 0.3288, 0.3174, 0.3069]];
+
+/// The average of `GGX_ALBEDO` over the hemisphere, weighted by the cosine,
+/// for the roughness `i / 15`.
+const GGX_AVERAGE_ALBEDO: [Float; 16] =
+    [1.0000, 0.9999, 0.9986, 0.9940, 0.9840, 0.9663, 0.9393, 0.9020,
+     0.8544, 0.7971, 0.7319, 0.6610, 0.5873, 0.5137, 0.4428, 0.3766];
+
+/// Interpolates linearly in a table whose entries belong to `i / 15`.
+/// Returns the lower and the upper index and the weight of the upper one.
+fn table_position(x: Float) -> (usize, usize, Float) {
+    let t = (x * 15.).max(0.).min(15.);
+    let i = (t as usize).min(14);
+    (i, i + 1, t - i as Float)
+}
+
+/// Returns the directional albedo of the GGX model (without Fresnel) for the cosine `cos`
+/// of the angle between the direction and the normal, i.e. the fraction of the light that is
+/// reflected by a single scattering event on the microfacets. The rest is reflected after
+/// scattering several times, which `ggx_specular` adds to keep the energy.
+///
+/// ```
+/// use raydiancy::physics::*;
+/// assert!(ggx_albedo(1.0, 0.05) > 0.99);
+/// assert!(ggx_albedo(1.0, 1.0) < ggx_albedo(1.0, 0.5));
+/// assert!(ggx_average_albedo(1.0) < 0.5);
+/// ```
+pub fn ggx_albedo(cos: Float, roughness: Float) -> Float {
+    let (r0, r1, s) = table_position(roughness);
+    let (c0, c1, t) = table_position(cos);
+    let row = |r: usize| (1. - t) * GGX_ALBEDO[r][c0] + t * GGX_ALBEDO[r][c1];
+    (1. - s) * row(r0) + s * row(r1)
+}
+
+/// Returns the average of `ggx_albedo` over all directions of the hemisphere,
+/// weighted by the cosine of the angle to the normal.
+pub fn ggx_average_albedo(roughness: Float) -> Float {
+    let (r0, r1, s) = table_position(roughness);
+    (1. - s) * GGX_AVERAGE_ALBEDO[r0] + s * GGX_AVERAGE_ALBEDO[r1]
+}
+
+/// Computes the specular coefficient of the Cook-Torrance microfacet model with GGX distribution.
+///
+/// `view_dir` points to the viewer and `light_dir` to the light source.
+/// `f0` is the reflectance at normal incidence (about 0.04 for dielectrics).
+/// The result already includes the cosine factor of the light direction.
+///
+/// The light that is scattered several times between the microfacets is added with the
+/// compensation by Kulla and Conty, so rough surfaces do not lose energy:
+/// a white furnace test (a perfect mirror of any roughness lit uniformly) reflects everything.
+///
+/// ```
+/// use raydiancy::physics::*;
+/// use raydiancy::raytrace::*;
+/// let normal = Vec3::e3();
+/// let view_dir = Vec3::new(0.6, 0.0, 0.8).normalize();
+/// let n = 256;
+/// for &roughness in &[0.4, 0.6, 0.8, 1.0] {
+///     // Integrates over the hemisphere with cosine-weighted light directions.
+///     let mut sum = 0.0;
+///     for i in 0..n {
+///         for j in 0..n {
+///             let u = Vec2::new((i as Float + 0.5) / n as Float,
+///                               (j as Float + 0.5) / n as Float);
+///             let light_dir = cosine_weighted_direction(normal, u);
+///             let cos = light_dir * normal;
+///             sum += ggx_specular(view_dir, light_dir, normal, roughness, 1.0) / cos;
+///         }
+///     }
+///     let reflected = PI * sum / (n * n) as Float;
+///     assert!((reflected - 1.0).abs() < 0.03, "{}: {}", roughness, reflected);
+/// }
+/// ```
+pub fn ggx_specular(view_dir: UnitVec3,
+                    light_dir: UnitVec3,
+                    normal: UnitVec3,
+                    roughness: Float,
+                    f0: Float)
+                    -> Float {
+    let nv = view_dir * normal;
+    let nl = light_dir * normal;
+    if nv <= 0. || nl <= 0. {
+        return 0.;
+    }
+    // Very small roughness values make the highlight degenerate to a point.
+    let roughness = roughness.max(0.02);
+    let halfway = (light_dir + view_dir).normalize();
+    let d = ggx_distribution(halfway, normal, roughness);
+    let g = smith_g1(view_dir, normal, roughness) * smith_g1(light_dir, normal, roughness);
+    let f = schlick(f0, halfway * view_dir);
+    let single = d * g * f / (4. * nv);
+    // Multiple scattering (Kulla and Conty), tinted by the average Fresnel reflectance.
+    let average = ggx_average_albedo(roughness);
+    let multiple = (1. - ggx_albedo(nv, roughness)) * (1. - ggx_albedo(nl, roughness)) * nl /
+                   (PI * (1. - average).max(EPS));
+    let f_average = f0 + (1. - f0) / 21.;
+    let tint = f_average * f_average * average / (1. - f_average * (1. - average));
+    single + multiple * tint
+}
+
+/// A right-handed orthonormal basis (tangent, bitangent, normal) of a surface,
+/// e.g. for converting directions sampled around the z-axis to world space.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let normal = Vec3::new(1.0, -2.0, 3.0).normalize();
+/// let basis = OrthonormalBasis::from_normal(normal);
+/// assert!(appr(basis.tangent * normal, 0.0) && appr(basis.bitangent * normal, 0.0));
+/// assert_eq!(basis.tangent.cross(basis.bitangent), normal);
+/// assert_eq!(basis.to_world(Vec3::new(0.0, 0.0, 2.0)), 2.0 * normal);
+/// let v = Vec3::new(0.3, 0.4, 0.5);
+/// assert_eq!(basis.to_local(basis.to_world(v)), v);
+/// ```
+#[derive(Debug, Copy, Clone)]
+pub struct OrthonormalBasis {
+    /// The first tangent direction, the local x-axis.
+    pub tangent: UnitVec3,
+    /// The second tangent direction, the local y-axis.
+    pub bitangent: UnitVec3,
+    /// The normal, the local z-axis.
+    pub normal: UnitVec3,
+}
+
+impl OrthonormalBasis {
+    /// Completes the normal to a basis.
+    ///
+    /// This uses the branchless construction by Duff et al. ("Building an Orthonormal Basis,
+    /// Revisited"), so the tangents change continuously with the normal,
+    /// except where its z-coordinate changes the sign.
+    pub fn from_normal(normal: UnitVec3) -> OrthonormalBasis {
+        let (x, y, z) = (normal.x(), normal.y(), normal.z());
+        let sign = if z >= 0. { 1. } else { -1. };
+        let a = -1. / (sign + z);
+        let b = x * y * a;
+        OrthonormalBasis {
+            tangent: Vec3::new(1. + sign * x * x * a, sign * b, -sign * x).normalize(),
+            bitangent: Vec3::new(b, sign + y * y * a, -y).normalize(),
+            normal: normal,
+        }
+    }
+
+    /// Converts coordinates with respect to the basis to world coordinates.
+    pub fn to_world<M: Clone>(&self, local: Vec3M<M>) -> Vec3 {
+        local.x() * self.tangent + local.y() * self.bitangent + local.z() * self.normal
+    }
+
+    /// Converts world coordinates to coordinates with respect to the basis.
+    pub fn to_local<M: Clone>(&self, world: Vec3M<M>) -> Vec3 {
+        Vec3::new(world * self.tangent, world * self.bitangent, world * self.normal)
+    }
+}
+
+/// Returns two unit vectors that form an orthonormal basis together with the normal,
+/// e.g. the tangent and bitangent of a surface, see `OrthonormalBasis`.
+pub fn orthonormal_basis(normal: UnitVec3) -> (UnitVec3, UnitVec3) {
+    let basis = OrthonormalBasis::from_normal(normal);
+    (basis.tangent, basis.bitangent)
 }
 
 /// Computes the reflection of i along n.
@@ -17,7 +285,7 @@ pub fn reflect(i: UnitVec3, n: UnitVec3) -> UnitVec3 {
 
 /// Computes the Fresnel reflection of i on a surface with normal n and index of refraction r.
 /// Precisely speaking, r = ior of material being entered / ior of material being exited.
-pub fn fresnel(i: UnitVec3, n: UnitVec3, r: f64) -> f64 {
+pub fn fresnel(i: UnitVec3, n: UnitVec3, r: Float) -> Float {
     let c = -i * n; // = cos(angle of incidence)
     let g = (r * r + c * c - 1.).sqrt(); // = r * cos(angle of refraction)
     let gpc = g + c;
@@ -29,9 +297,40 @@ pub fn fresnel(i: UnitVec3, n: UnitVec3, r: f64) -> f64 {
     // = average of reflectance for orthogonal and parallel polarization
 }
 
+/// Computes Schlick's approximation of the Fresnel reflectance,
+/// given the reflectance `r0` at normal incidence and the cosine of the angle of incidence.
+pub fn schlick(r0: Float, cos: Float) -> Float {
+    r0 + (1. - r0) * (1. - cos).powi(5)
+}
+
+/// Computes Schlick's approximation of `fresnel(i, n, r)`.
+/// It is cheaper but slightly less accurate.
+///
+/// ```
+/// use raydiancy::lin_alg::*;
+/// use raydiancy::physics::*;
+/// let i = Vec3::new(1.0, -1.0, 0.0).normalize();
+/// let n = Vec3::e2();
+/// assert!((fresnel_schlick(i, n, 1.5) - fresnel(i, n, 1.5)).abs() < 0.01);
+/// assert_eq!(fresnel_schlick(i, n, 1. / 1.5), 1.); // total internal reflection
+/// ```
+pub fn fresnel_schlick(i: UnitVec3, n: UnitVec3, r: Float) -> Float {
+    let r0 = ((1. - r) / (1. + r)).powi(2);
+    let c = -i * n; // = cos(angle of incidence)
+    if r >= 1. {
+        return schlick(r0, c);
+    }
+    // When entering an optically thinner material, the angle of refraction has to be used.
+    let sin2 = (1. - c * c) / (r * r); // = sin(angle of refraction)^2
+    if sin2 > 1. {
+        return 1.; // total internal reflection
+    }
+    schlick(r0, (1. - sin2).sqrt())
+}
+
 /// Calculates the refraction of i on a surface with normal n and refraction index quotient r.
 /// Precisely speaking, r = ior of material being entered / ior of material being exited.
-pub fn refract(i: UnitVec3, n: UnitVec3, r: f64) -> Option<UnitVec3> {
+pub fn refract(i: UnitVec3, n: UnitVec3, r: Float) -> Option<UnitVec3> {
     let r = 1. / r;
     let w = -r * i * n;
     let k = 1. + (w + r) * (w - r);
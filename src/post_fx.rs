@@ -0,0 +1,70 @@
+use color::*;
+use img_output::*;
+use lin_alg::Float;
+
+/// An effect applied to the final image, e.g. for a more photographic or stylized look.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PostEffect {
+    /// Darkens the image towards the corners.
+    Vignette {
+        /// The darkening at the corners, from 0 (none) to 1 (black).
+        strength: Float,
+    },
+    /// Lateral chromatic aberration of a lens: the color channels are scaled
+    /// differently around the center, which produces colored fringes towards the edges.
+    ChromaticAberration {
+        /// The relative magnification of the red channel, e.g. 0.005.
+        /// The blue channel is scaled by the same amount in the opposite direction.
+        strength: Float,
+    },
+}
+
+/// Returns the color at the given (fractional) pixel coordinates, interpolated bilinearly.
+/// Coordinates outside of the image are clamped to the border.
+/// An empty image is transparent everywhere.
+fn sample_bilinear(img: &Image, x: Float, y: Float) -> AColor {
+    if img.width == 0 || img.height == 0 {
+        return AColor::transparent();
+    }
+    let x = x.max(0.).min((img.width - 1) as Float);
+    let y = y.max(0.).min((img.height - 1) as Float);
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(img.width - 1), (y0 + 1).min(img.height - 1));
+    let (fx, fy) = (x - x0 as Float, y - y0 as Float);
+    (1. - fy) * ((1. - fx) * img.get(x0, y0) + fx * img.get(x1, y0)) +
+    fy * ((1. - fx) * img.get(x0, y1) + fx * img.get(x1, y1))
+}
+
+impl PostEffect {
+    /// Applies the effect to the image and returns the result.
+    pub fn apply(&self, img: &Image) -> Image {
+        let center = ((img.width as Float - 1.) / 2., (img.height as Float - 1.) / 2.);
+        let mut result = img.clone();
+        match *self {
+            PostEffect::Vignette { strength } => {
+                let max_distance2 = center.0 * center.0 + center.1 * center.1;
+                for (x, y, col) in result.iter_mut() {
+                    let (dx, dy) = (x as Float - center.0, y as Float - center.1);
+                    let r2 = (dx * dx + dy * dy) / max_distance2.max(1.);
+                    *col = col.scale_radiance(1. - strength * r2);
+                }
+            }
+            PostEffect::ChromaticAberration { strength } => {
+                let sample = |x: usize, y: usize, scale: Float| {
+                    sample_bilinear(img,
+                                    center.0 + scale * (x as Float - center.0),
+                                    center.1 + scale * (y as Float - center.1))
+                };
+                for (x, y, col) in result.iter_mut() {
+                    // Sampling closer to the center magnifies a channel, so red appears larger.
+                    let red = sample(x, y, 1. - strength).opaque().red();
+                    let blue = sample(x, y, 1. + strength).opaque().blue();
+                    let c = col.opaque();
+                    *col = Color::new_unchecked(red, c.green(), blue)
+                        .with_transparency(col.transparency());
+                }
+            }
+        }
+        result
+    }
+}
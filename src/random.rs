@@ -0,0 +1,64 @@
+use lin_alg::Float;
+
+/// A small and fast pseudo-random number generator (PCG-XSH-RR by Melissa O'Neill).
+///
+/// Generators created with the same seed and stream produce the same sequence of numbers,
+/// which makes renders with stochastic effects reproducible.
+///
+/// ```
+/// use raydiancy::random::Pcg32;
+/// let mut a = Pcg32::new(42, 7);
+/// let mut b = Pcg32::new(42, 7);
+/// let mut c = Pcg32::new(42, 8);
+/// let x = a.next_u32();
+/// assert_eq!(x, b.next_u32());
+/// assert!(x != c.next_u32());
+/// let f = a.next_float();
+/// assert!(0. <= f && f < 1.);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Pcg32 {
+    state: u64,
+    inc: u64,
+}
+
+const MULTIPLIER: u64 = 6364136223846793005;
+
+/// Scrambles the bits of `x` (the finalizer of SplitMix64).
+/// This decorrelates generators whose seeds or streams differ only slightly.
+fn mix(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+impl Pcg32 {
+    /// Creates a generator from a seed and a stream number.
+    /// Different streams with the same seed produce independent sequences.
+    pub fn new(seed: u64, stream: u64) -> Pcg32 {
+        let mut rng = Pcg32 {
+            state: 0,
+            inc: (mix(stream) << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(mix(seed));
+        rng.next_u32();
+        rng
+    }
+
+    /// Returns the next random number, uniformly distributed over all `u32` values.
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(MULTIPLIER).wrapping_add(self.inc);
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rot = (old >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    /// Returns the next random number, uniformly distributed in [0, 1).
+    pub fn next_float(&mut self) -> Float {
+        // Only use as many bits as fit into the mantissa so that the result is less than 1.
+        (self.next_u32() >> 8) as Float / (1u32 << 24) as Float
+    }
+}
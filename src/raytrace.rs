@@ -1,17 +1,38 @@
+pub use animation::*;
 pub use basic::*;
+pub use bsdf::*;
 pub use img_output::*;
+pub use light::*;
+pub use light_tree::*;
 pub use physics::*;
+pub use post_fx::*;
 pub use objects::*;
-use std::f64;
+pub use denoise::*;
+pub use diagnostics::*;
+pub use passes::*;
+pub use random::*;
+pub use sampler::*;
+pub use stats::*;
+pub use medium::*;
+pub use volume::*;
+pub use scene_builder::*;
+pub use sky::*;
+pub use texture::*;
+pub use validation::*;
+use overlay;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Instant;
 
+#[cfg(feature = "parallel")]
 extern crate simple_parallel;
 
-// TODO: Move these constants in a struct `RenderOptions`.
-const INTENSITY_THRESHOLD: f64 = 1. / 256.;
-const MAX_DEPTH: usize = 10;
-const NUM_THREADS: usize = 8;
-
 /// Contains information about camera, like position, direction etc.
+#[derive(Debug, Copy, Clone)]
 pub struct Camera {
     /// The position of the camera.
     pub pos: Vec3,
@@ -20,19 +41,497 @@ pub struct Camera {
     /// A vector pointing upwards, i.e. to the top in the image.
     pub up: Vec3,
     /// The horizontal field of view. Range: strictly between 0 and `PI`.
-    pub horizontal_fov: f64,
+    pub horizontal_fov: Float,
     /// The quotient `width / height` of the image.
-    pub aspect_ratio: f64,
+    pub aspect_ratio: Float,
     /// The width of the image in pixels.
     pub width: usize,
     /// The height of the image in pixels.
     pub height: usize,
 }
 
-/// Information about a light source.
-pub struct LightSource {
-    pub pos: Vec3,
-    pub col: Color,
+impl Camera {
+    /// Checks the values of the camera, see `Scene::validate`.
+    pub fn validate(&self) -> Vec<(Severity, Problem)> {
+        let mut problems = Vec::new();
+        {
+            let mut report = |field| {
+                problems.push((Severity::Error, Problem::InvalidCamera { field: field }));
+            };
+            let vectors = [("pos", self.pos), ("look_at", self.look_at), ("up", self.up)];
+            for &(field, v) in vectors.iter() {
+                if !is_finite_vec(v) {
+                    report(field);
+                }
+            }
+            if !(self.horizontal_fov > 0. && self.horizontal_fov < PI) {
+                report("horizontal_fov");
+            }
+            if !(self.aspect_ratio > 0. && self.aspect_ratio.is_finite()) {
+                report("aspect_ratio");
+            }
+            if self.width == 0 {
+                report("width");
+            }
+            if self.height == 0 {
+                report("height");
+            }
+            if self.look_at == self.pos {
+                report("look_at");
+            }
+        }
+        let view = self.look_at - self.pos;
+        let sin2 = view.cross(self.up).norm2() / (view.norm2() * self.up.norm2());
+        if problems.is_empty() && !(sin2 > 1e-12) {
+            problems.push((Severity::Error, Problem::CameraUpParallelToView));
+        }
+        problems
+    }
+
+    /// Returns the viewing direction and the vectors spanning the image plane.
+    ///
+    /// The image plane is at distance 1 in viewing direction.
+    /// The returned `right` and `up` vectors reach from its center to its right and top edge.
+    fn screen_basis(&self) -> (UnitVec3, Vec3, Vec3) {
+        let horizontal = (self.horizontal_fov / 2.0).tan();
+        let camera_dir = (self.look_at - self.pos).normalize();
+        let right = horizontal * camera_dir.cross(self.up).normalize();
+        let up = right.cross(camera_dir).normalize();
+        let up = horizontal / self.aspect_ratio * up;
+        (camera_dir, right, up)
+    }
+
+    /// Returns the camera for an image with `pixels` more pixels on each side, whose frustum
+    /// extends the one of this camera. The pixel (x, y) of this camera corresponds to the pixel
+    /// (x + pixels, y + pixels) of the returned one.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let camera = Camera {
+    ///     pos: Vec3::zero(),
+    ///     look_at: -Vec3::e3().to(),
+    ///     up: Vec3::e2().to(),
+    ///     horizontal_fov: PI / 2.0,
+    ///     aspect_ratio: 2.0,
+    ///     width: 40,
+    ///     height: 20,
+    /// };
+    /// let extended = camera.with_overscan(5);
+    /// assert_eq!((extended.width, extended.height), (50, 30));
+    /// for &(x, y) in [(0, 0), (3, 17), (39, 19)].iter() {
+    ///     let ray = camera.ray_for_pixel(x, y, Vec2::new(0.5, 0.5));
+    ///     assert_eq!(extended.ray_for_pixel(x + 5, y + 5, Vec2::new(0.5, 0.5)).dir(), ray.dir());
+    /// }
+    /// ```
+    pub fn with_overscan(&self, pixels: usize) -> Camera {
+        let (width, height) = (self.width + 2 * pixels, self.height + 2 * pixels);
+        let scale = width as Float / self.width as Float;
+        let horizontal = (self.horizontal_fov / 2.0).tan() * scale;
+        Camera {
+            horizontal_fov: 2.0 * horizontal.atan(),
+            aspect_ratio: self.aspect_ratio * scale * self.height as Float / height as Float,
+            width: width,
+            height: height,
+            ..*self
+        }
+    }
+
+    /// Returns the primary ray through the given (fractional) pixel coordinates.
+    pub fn pixel_ray(&self, left: Float, down: Float) -> Ray {
+        let (camera_dir, right, up) = self.screen_basis();
+        let x = (left / self.width as Float) - 0.5;
+        let y = 0.5 - (down / self.height as Float);
+        Ray::newn(self.pos, camera_dir + x * right + y * up)
+    }
+
+    /// Returns the primary ray through the pixel in column `x` and row `y`.
+    ///
+    /// `subpixel_offset` is the position within the pixel, from (0, 0) at its top-left corner
+    /// to (1, 1) at its bottom-right corner. Rendering uses (0, 0) for a single sample per pixel.
+    pub fn ray_for_pixel(&self, x: usize, y: usize, subpixel_offset: Vec2) -> Ray {
+        self.pixel_ray(x as Float + subpixel_offset.x(), y as Float + subpixel_offset.y())
+    }
+
+    /// Projects a point onto the image.
+    ///
+    /// Returns the (fractional) pixel coordinates
+    /// or `None` if the point is not in front of the camera.
+    pub fn project(&self, point: Vec3) -> Option<(Float, Float)> {
+        let (camera_dir, right, up) = self.screen_basis();
+        let v = point - self.pos;
+        let depth = v * camera_dir;
+        if depth < EPS {
+            return None;
+        }
+        let x = v * right / (depth * right.norm2());
+        let y = v * up / (depth * up.norm2());
+        Some(((x + 0.5) * self.width as Float, (0.5 - y) * self.height as Float))
+    }
+}
+
+/// The formula used for computing the Fresnel reflectance.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FresnelModel {
+    /// The exact formula (for unpolarized light).
+    Exact,
+    /// Schlick's approximation.
+    Schlick,
+}
+
+/// Determines how objects between a point and a light source affect the light.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ShadowMode {
+    /// Every object blocks the light completely.
+    Opaque,
+    /// Transparent objects let light through, filtered by their refractivity and absorption.
+    /// This makes glass cast light, tinted shadows. Refraction of the light is ignored.
+    Transmissive,
+}
+
+/// A quick way of shading for diagnosing problems with geometry, see `RenderOptions::debug_view`.
+/// Only the nearest object along the primary rays is shown, without lights and reflections.
+/// Rays that miss all objects are transparent.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let triangle = "v -1 -1 0\nv 1 -1 0\nv 0 1 0\nf 1 2 3\n";
+/// let scene = |view, objects: &[&str]| {
+///     let mut builder = Scene::builder()
+///         .resolution(16, 16)
+///         .options(RenderOptions { debug_view: Some(view), ..RenderOptions::default() });
+///     for obj in objects {
+///         builder = builder.add(Mesh::from_obj_reader(obj.as_bytes(), color_material(white()))
+///             .unwrap());
+///     }
+///     builder.build().unwrap().render()
+/// };
+/// let render = |view| scene(view, &[triangle]);
+/// // The barycentric coordinates add up to 1.
+/// let col = render(DebugView::Barycentric).get(8, 8).opaque();
+/// assert!(appr(col.red() + col.green() + col.blue(), 1.0));
+/// // The triangle faces the camera, which looks along -z.
+/// let col = render(DebugView::Normal).get(8, 8).opaque();
+/// assert_eq!((col.red(), col.green(), col.blue()), (0.5, 0.5, 1.0));
+/// // The normal of a triangle seen from behind points away from the camera.
+/// let back = "v -1 -1 0\nv 0 1 0\nv 1 -1 0\nf 1 2 3\n";
+/// let col = scene(DebugView::Normal, &[back]).get(8, 8).opaque();
+/// assert_eq!((col.red(), col.green(), col.blue()), (0.5, 0.5, 0.0));
+/// // The single triangle is in the only leaf of the mesh's hierarchy.
+/// let img = render(DebugView::BvhLeaf);
+/// assert!(img.get(8, 8).opaque().luminance() > 0.0);
+/// assert_eq!(img.get(0, 0).transparency(), 1.0);
+/// // The first leaves of different meshes have different colors.
+/// let left = "v -2 -1 0\nv -0.5 -1 0\nv -0.5 1 0\nf 1 2 3\n";
+/// let right = "v 0.5 -1 0\nv 2 -1 0\nv 0.5 1 0\nf 1 2 3\n";
+/// let img = scene(DebugView::BvhLeaf, &[left, right]);
+/// assert_eq!(img.get(6, 9).transparency() + img.get(9, 9).transparency(), 0.0);
+/// assert!(img.get(6, 9).opaque() != img.get(9, 9).opaque());
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DebugView {
+    /// The normal on the side it points to, not towards the camera, its coordinates mapped
+    /// from [-1, 1] to the red, green, and blue [0, 1].
+    Normal,
+    /// The texture coordinates u in red and v in green, repeating for every unit.
+    Uv,
+    /// The distance along the view direction in false color, see `false_color`.
+    Depth {
+        /// The distance shown in blue.
+        near: Float,
+        /// The distance shown in red.
+        far: Float,
+    },
+    /// A random color for every leaf of the bounding volume hierarchy of each object,
+    /// the innermost one for nested hierarchies. Objects outside of hierarchies are black.
+    BvhLeaf,
+    /// The barycentric coordinates of the first, second, and third vertex of triangles
+    /// in red, green, and blue. Other objects are black.
+    Barycentric,
+}
+
+/// How far rays leaving a surface, e.g. for shadows and reflections, start from it, so that
+/// rounding errors do not make them hit the surface again, see `RenderOptions::ray_epsilon`.
+/// The rays also ignore intersections closer than this distance.
+///
+/// The default of `EPS` suits scenes measuring a few units. Rounding errors grow with the
+/// coordinates and distances, so large scenes, e.g. buildings in millimeters, need `relative`
+/// distances. Small scenes, e.g. jewelry in meters, need a smaller `absolute` distance,
+/// or rays skip parts thinner than it.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let ray = Ray::new(Vec3::new(1e5, 0.0, 0.0), Vec3::e1());
+/// let inter = Intersection::new(&ray, 100.0, -Vec3::e1(), glass());
+/// assert_eq!(RayEpsilon::default().at(&inter), EPS);
+/// let relative = RayEpsilon { absolute: 0.0, relative: 1e-7 };
+/// assert!(appr(relative.at(&inter), 1e-7 * (100.0 + 1e5 + 100.0)));
+///
+/// // A sphere that is thinner than `EPS` is missed from the inside unless rays ignore
+/// // only closer intersections.
+/// let sphere = Sphere { center: Vec3::zero(), radius: 1e-5, material: glass() };
+/// let ray = Ray::new(Vec3::zero(), Vec3::e1());
+/// assert!(!sphere.is_hit_by(&ray, Float::INFINITY));
+/// assert!(sphere.is_hit_by(&ray.with_t_min(1e-8), Float::INFINITY));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RayEpsilon {
+    /// The distance in the units of the scene.
+    pub absolute: Float,
+    /// The fraction of the distance of the intersection point from the origin of the ray
+    /// plus its largest coordinate, which is added to `absolute`.
+    pub relative: Float,
+}
+
+impl RayEpsilon {
+    /// Returns the distance for rays leaving the intersection point.
+    pub fn at(&self, inter: &Intersection) -> Float {
+        let p = inter.point;
+        let magnitude = p.x().abs().max(p.y().abs()).max(p.z().abs());
+        self.absolute + self.relative * (inter.t + magnitude)
+    }
+}
+
+impl Default for RayEpsilon {
+    fn default() -> RayEpsilon {
+        RayEpsilon {
+            absolute: EPS,
+            relative: 0.,
+        }
+    }
+}
+
+/// The log-average luminance that auto exposure maps the image to (middle gray).
+const AUTO_EXPOSURE_KEY: Float = 0.18;
+
+/// Determines how the brightness of the rendered image is adjusted before it is output.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Exposure {
+    /// Scales the image by `2^ev`. 0 leaves it unchanged, each step doubles or halves it.
+    Manual {
+        /// The exposure value.
+        ev: Float,
+    },
+    /// Picks the exposure such that the log-average luminance of the image is middle gray,
+    /// then adds the exposure compensation `ev`.
+    ///
+    /// This needs the whole image, so `render_streaming` and `render_cube_map`,
+    /// which output parts of the image as soon as they are rendered,
+    /// only apply the compensation like `Manual`.
+    Auto {
+        /// The exposure compensation.
+        ev: Float,
+    },
+}
+
+/// Adapts the colors of the rendered image to the light illuminating the scene,
+/// such that white objects look white, like the white balance of a camera.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let sunset = Sky::daylight(SunPosition::Angles { azimuth: 0.0, elevation: 0.1 }, 3.0);
+/// let scene = Scene::builder()
+///     .add(Sphere {
+///         center: Vec3::new(0.0, 0.0, 5.0),
+///         radius: 1.0,
+///         material: color_material(white()),
+///     })
+///     .sky(sunset)
+///     .options(RenderOptions {
+///         white_balance: Some(WhiteBalance::Sky),
+///         ..RenderOptions::default()
+///     })
+///     .build()
+///     .unwrap();
+/// // The orange light of the sunset is balanced to a neutral color.
+/// let filter = scene.white_balance_filter().unwrap();
+/// let balanced = filter * sunset.illuminant();
+/// assert!(sunset.illuminant().red() > 1.5 * sunset.illuminant().blue());
+/// assert!(balanced.red() < 1.2 * balanced.blue() && balanced.blue() < 1.2 * balanced.red());
+/// assert!((balanced.luminance() / sunset.illuminant().luminance() - 1.0).abs() < 0.1);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WhiteBalance {
+    /// Balances for a black body of the given temperature in Kelvin, see
+    /// `Color::from_temperature`, e.g. 3000 for indoor light or 6500 for an overcast sky.
+    Temperature {
+        /// The color temperature that becomes neutral.
+        kelvin: Float,
+    },
+    /// Balances for the correlated color temperature of the sun and the sky of the scene,
+    /// see `Sky::correlated_color_temperature`, e.g. to avoid orange sunsets.
+    /// The image is left unchanged if the scene has no sky or the sun has set.
+    Sky,
+}
+
+/// Fog that fills the whole scene, see `Scene::fog`.
+///
+/// The color of a ray that travels the distance `d` is blended towards the fog color,
+/// keeping the fraction `transmittance(d)` of the original color.
+/// Rays that hit nothing travel infinitely far, so they get the fog color.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let linear = Fog::Linear { color: white(), start: 10.0, end: 20.0 };
+/// assert_eq!(linear.transmittance(5.0), 1.0);
+/// assert_eq!(linear.transmittance(15.0), 0.5);
+/// assert_eq!(linear.transmittance(Float::INFINITY), 0.0);
+/// let exponential = Fog::Exponential { color: white(), density: 0.1 };
+/// assert_eq!(exponential.transmittance(0.0), 1.0);
+/// assert!(appr(exponential.transmittance(10.0), (-1.0 as Float).exp()));
+/// assert_eq!(exponential.transmittance(Float::INFINITY), 0.0);
+/// // Fog without density does not hide anything, not even the background.
+/// let clear = Fog::Exponential { color: white(), density: 0.0 };
+/// assert_eq!(clear.transmittance(Float::INFINITY), 1.0);
+/// ```
+#[derive(Debug, Copy, Clone)]
+pub enum Fog {
+    /// Fog that starts at the distance `start` and hides everything beyond `end` completely,
+    /// increasing linearly in between.
+    Linear {
+        /// The color of the fog.
+        color: Color,
+        /// The distance where the fog starts.
+        start: Float,
+        /// The distance where the fog is opaque.
+        end: Float,
+    },
+    /// Fog of uniform density, which lets the fraction `exp(-density * d)` of the light through.
+    Exponential {
+        /// The color of the fog.
+        color: Color,
+        /// The density of the fog, i.e. the fraction of light absorbed per unit of distance.
+        density: Float,
+    },
+}
+
+impl Fog {
+    /// Returns the color of the fog.
+    pub fn color(&self) -> Color {
+        match *self {
+            Fog::Linear { color, .. } |
+            Fog::Exponential { color, .. } => color,
+        }
+    }
+
+    /// Returns the fraction of the color of a ray that remains after traveling `distance`.
+    pub fn transmittance(&self, distance: Float) -> Float {
+        match *self {
+            Fog::Linear { start, end, .. } => {
+                if distance <= start {
+                    1.
+                } else if distance >= end {
+                    0.
+                } else {
+                    (end - distance) / (end - start)
+                }
+            }
+            Fog::Exponential { density, .. } => {
+                // Avoid 0 * infinity for rays that hit nothing.
+                if density == 0. { 1. } else { (-density * distance).exp() }
+            }
+        }
+    }
+
+    /// Blends the color of a ray that traveled `distance` towards the fog color.
+    fn apply(&self, color: AColor, distance: Float) -> AColor {
+        let transmittance = self.transmittance(distance);
+        transmittance * color + ((1. - transmittance) * self.color()).with_alpha()
+    }
+}
+
+/// Options that control the rendering process.
+pub struct RenderOptions {
+    /// Rays whose contribution to the pixel color is below this threshold are not traced.
+    pub intensity_threshold: Float,
+    /// The maximum recursion depth for reflection and refraction rays.
+    pub max_depth: usize,
+    /// The number of threads used for rendering.
+    pub num_threads: usize,
+    /// The formula used for the ratio of reflection and refraction at transparent objects.
+    pub fresnel: FresnelModel,
+    /// If `true`, mirror reflections get stronger at grazing angles (Schlick's approximation).
+    /// The material's reflectance is used as the reflectance at normal incidence.
+    pub fresnel_reflections: bool,
+    /// The way shadows are computed.
+    pub shadows: ShadowMode,
+    /// The way the lights are chosen for shading a point, e.g. for scenes with many lights.
+    pub light_sampling: LightSampling,
+    /// If `true`, draws the world axes (x red, y green, z blue) in the bottom-left corner.
+    pub axis_gizmo: bool,
+    /// If set, draws a grid with the given spacing on the plane y = 0 on top of the image.
+    pub grid_spacing: Option<Float>,
+    /// If set, the rendered image is denoised with these options,
+    /// guided by the normals, albedo, and depth of the primary rays.
+    pub denoise: Option<DenoiseOptions>,
+    /// If set, the colors of the rendered image are balanced before the exposure.
+    pub white_balance: Option<WhiteBalance>,
+    /// The exposure applied to the rendered image.
+    pub exposure: Exposure,
+    /// Effects applied in this order to the image after the exposure.
+    pub post_effects: Vec<PostEffect>,
+    /// If set, the radiance of every sample is clamped to this value (keeping its hue).
+    /// This suppresses fireflies, i.e. single bright pixels caused by rare high-energy paths,
+    /// at the cost of a slight bias.
+    pub max_radiance: Option<Float>,
+    /// The seed for the random numbers used by stochastic effects.
+    /// Rendering a scene twice with the same seed produces the same image,
+    /// independent of the number of threads.
+    pub seed: u64,
+    /// The number of rays traced per pixel, whose colors are averaged.
+    /// With more than one sample, the rays are spread over the pixel for anti-aliasing.
+    pub samples_per_pixel: usize,
+    /// If set, noisy pixels get more samples than `samples_per_pixel`, see `AdaptiveSampling`.
+    pub adaptive_sampling: Option<AdaptiveSampling>,
+    /// The sampler choosing the positions of the samples within the pixels
+    /// and the random numbers of stochastic effects.
+    pub sampler: SamplerKind,
+    /// The maximum ratio of the length to the width of the footprint of a pixel on a texture
+    /// viewed at a grazing angle, see `ImageTexture`. Longer footprints are made wider.
+    /// The filter takes up to twice as many samples along the footprint, so larger values
+    /// keep such textures sharper but take longer.
+    /// 1 filters like plain mipmaps, and 0 disables the filtering of textures.
+    pub max_anisotropy: usize,
+    /// The number of extra pixels rendered on each side of the image, which are cropped
+    /// after denoising and post effects, so that these do not see the edges of the image.
+    pub overscan: usize,
+    /// If set, the objects are shaded by this debug view instead of lighting them.
+    pub debug_view: Option<DebugView>,
+    /// If set, the boxes of the bounding volume hierarchies are drawn as transparent objects
+    /// in the rendered images and in `Scene::trace`, but not in lightmaps.
+    pub bvh_overlay: Option<BvhOverlay>,
+    /// How far rays leaving surfaces start from them, to be adapted to very large
+    /// or very small scenes.
+    pub ray_epsilon: RayEpsilon,
+}
+
+impl Default for RenderOptions {
+    fn default() -> RenderOptions {
+        RenderOptions {
+            intensity_threshold: 1. / 256.,
+            max_depth: 10,
+            num_threads: 8,
+            fresnel: FresnelModel::Exact,
+            fresnel_reflections: false,
+            shadows: ShadowMode::Opaque,
+            light_sampling: LightSampling::All,
+            axis_gizmo: false,
+            grid_spacing: None,
+            denoise: None,
+            white_balance: None,
+            exposure: Exposure::Manual { ev: 0. },
+            post_effects: Vec::new(),
+            max_radiance: None,
+            seed: 0,
+            samples_per_pixel: 1,
+            adaptive_sampling: None,
+            sampler: SamplerKind::Independent,
+            max_anisotropy: 16,
+            overscan: 0,
+            debug_view: None,
+            bvh_overlay: None,
+            ray_epsilon: RayEpsilon::default(),
+        }
+    }
 }
 
 /// Contains all the information about a scene: camera and objects.
@@ -42,13 +541,257 @@ pub struct Scene {
     /// The objects in the scene.
     pub objects: Vec<Box<Surface + Sync>>,
     /// The lights in the scene.
-    pub lights: Vec<LightSource>,
+    pub lights: Vec<Box<Light + Sync>>,
     /// The color of ambient light in the scene.
     pub ambient_color: Color,
+    /// If set, the scene is filled with fog, which hides distant objects.
+    pub fog: Option<Fog>,
+    /// If set, the scene or a part of it is filled with a medium that scatters light.
+    pub medium: Option<Medium>,
+    /// Regions filled with media of varying density, like smoke or clouds.
+    pub volumes: Vec<Box<Volume + Sync>>,
+    /// If set, rays that miss all objects show the sky instead of a transparent background.
+    pub sky: Option<Sky>,
+    /// The options used for rendering the scene.
+    pub options: RenderOptions,
+}
+
+/// Information about the pixel being rendered, passed along while tracing its rays.
+struct TraceContext<'a> {
+    /// The pixel (x, y).
+    pixel: (usize, usize),
+    /// Collects the problems detected while rendering.
+    diagnostics: &'a Mutex<RenderDiagnostics>,
+    /// The sampler of the pixel, derived from the seed and the pixel position.
+    sampler: RefCell<Box<Sampler>>,
+    /// The occluders of recent shadow rays, shared by the pixels of a tile.
+    shadow_cache: &'a ShadowCache,
+    /// The numbers of rays traced for the pixels of a tile.
+    rays: &'a RayCounts,
+    /// How the lights are chosen for shading.
+    light_selection: &'a LightSelection,
+    /// The distributions of the sky, prepared once per render.
+    sky: Option<&'a SkyDistribution>,
+    /// What the last primary ray hit, for the render passes.
+    primary_hit: Cell<Option<PrimaryHit>>,
+}
+
+/// What a primary ray hit, see `RenderPasses`.
+#[derive(Copy, Clone)]
+struct PrimaryHit {
+    /// The index of the object.
+    object: usize,
+    /// The direction of the ray.
+    dir: UnitVec3,
+    /// The distance of the hit point along the ray.
+    t: Float,
+    /// The normal at the hit point.
+    normal: UnitVec3,
+    /// The color of the material at the hit point.
+    albedo: Color,
+}
+
+impl PrimaryHit {
+    fn new(object: usize, ray: &Ray, inter: &Intersection) -> PrimaryHit {
+        PrimaryHit {
+            object: object,
+            dir: ray.dir(),
+            t: inter.t,
+            normal: inter.normal,
+            albedo: inter.material.color,
+        }
+    }
+
+    /// Stores the hit in the passes at pixel (x, y).
+    fn write_to(&self, passes: &mut RenderPasses, x: usize, y: usize, camera_dir: UnitVec3) {
+        let depth = self.t * (self.dir * camera_dir);
+        let n = self.normal;
+        let depth_col = Color::new_unchecked(depth, depth, depth);
+        let normal_col = Color::new_unchecked(n.x(), n.y(), n.z());
+        passes.depth.set(x, y, depth_col.with_alpha());
+        passes.normal.set(x, y, normal_col.with_alpha());
+        passes.albedo.set(x, y, self.albedo.with_alpha());
+        let width = passes.depth.width;
+        passes.object_ids[y * width + x] = Some(self.object);
+    }
+}
+
+impl<'a> TraceContext<'a> {
+    /// Returns the next dimension of the current sample, uniformly distributed in [0, 1).
+    fn random(&self) -> Float {
+        self.sampler.borrow_mut().next_1d()
+    }
+
+    /// Counts a traced ray with the given recursion depth.
+    fn count_ray(&self, depth: usize) {
+        let counter = if depth == 0 { &self.rays.primary } else { &self.rays.secondary };
+        counter.set(counter.get() + 1);
+    }
+
+    /// Counts a ray towards a light source.
+    fn count_shadow_ray(&self) {
+        self.rays.shadow.set(self.rays.shadow.get() + 1);
+    }
 }
 
+/// What is prepared once per render to choose the lights for shading, see `LightSampling`.
+enum LightSelection {
+    /// Every light is sampled.
+    All,
+    /// The lights are chosen at random with probabilities proportional to their weights.
+    Weighted {
+        samples: usize,
+        weights: Vec<Float>,
+        /// The sums of the weights up to every light, searched for the chosen one.
+        cumulative: Vec<Float>,
+        /// The last light with a positive weight.
+        last: usize,
+    },
+    /// The lights are chosen by traversing their hierarchy.
+    Tree {
+        samples: usize,
+        tree: LightHierarchy,
+    },
+}
+
+impl LightSelection {
+    fn new(lights: &[Box<Light + Sync>], sampling: LightSampling) -> LightSelection {
+        let (samples, by_power) = match sampling {
+            LightSampling::All => return LightSelection::All,
+            LightSampling::Uniform { samples } => (samples, false),
+            LightSampling::Power { samples } => (samples, true),
+            LightSampling::Tree { samples } => {
+                return LightSelection::Tree {
+                    samples: samples,
+                    tree: LightHierarchy::new(lights),
+                };
+            }
+        };
+        let weights: Vec<Float> = lights.iter()
+            .map(|light| if by_power { light.power().max(0.) } else { 1. })
+            .collect();
+        let mut cumulative = Vec::with_capacity(weights.len());
+        let mut total = 0.;
+        for &w in weights.iter() {
+            total += w;
+            cumulative.push(total);
+        }
+        let last = weights.iter().rposition(|&w| w > 0.).unwrap_or(0);
+        LightSelection::Weighted {
+            // Without any weight, no light can be chosen.
+            samples: if total > 0. { samples } else { 0 },
+            weights: weights,
+            cumulative: cumulative,
+            last: last,
+        }
+    }
+}
+
+/// The lights chosen for shading a point, see `Scene::choose_lights`.
+struct ChosenLights<'a> {
+    lights: &'a [Box<Light + Sync>],
+    ctx: &'a TraceContext<'a>,
+    point: Vec3,
+    /// The index of the current light for `LightSelection::All`, otherwise the number of
+    /// lights chosen so far.
+    next: usize,
+    /// The number of samples of the current light so far for `LightSelection::All`.
+    sample: usize,
+}
+
+impl<'a> Iterator for ChosenLights<'a> {
+    type Item = (&'a (Light + Sync), Float);
+
+    fn next(&mut self) -> Option<(&'a (Light + Sync), Float)> {
+        match *self.ctx.light_selection {
+            LightSelection::All => {
+                while self.next < self.lights.len() {
+                    let light = &*self.lights[self.next];
+                    let count = light.sample_count();
+                    if self.sample < count {
+                        self.sample += 1;
+                        return Some((light, count as Float));
+                    }
+                    self.next += 1;
+                    self.sample = 0;
+                }
+                None
+            }
+            LightSelection::Weighted { samples, ref weights, ref cumulative, last } => {
+                if self.next >= samples {
+                    return None;
+                }
+                self.next += 1;
+                let total = cumulative[cumulative.len() - 1];
+                let x = self.ctx.random() * total;
+                // The first light whose weights add up to more than x.
+                // Rounding errors may leave x at the total, which goes to the last possible light.
+                let i = match cumulative.binary_search_by(|&c| {
+                    if c <= x { cmp::Ordering::Less } else { cmp::Ordering::Greater }
+                }) {
+                    Ok(i) | Err(i) => i.min(last),
+                };
+                Some((&*self.lights[i], samples as Float * weights[i] / total))
+            }
+            LightSelection::Tree { samples, ref tree } => {
+                while self.next < samples {
+                    self.next += 1;
+                    let choice = tree.choose(self.point, self.ctx.random());
+                    if let Some((i, probability, _)) = choice {
+                        return Some((&*self.lights[i], samples as Float * probability));
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+/// The numbers of rays of each kind traced for the pixels of a tile, see `RenderStats`.
+struct RayCounts {
+    primary: Cell<u64>,
+    secondary: Cell<u64>,
+    shadow: Cell<u64>,
+}
+
+impl RayCounts {
+    fn new() -> RayCounts {
+        RayCounts {
+            primary: Cell::new(0),
+            secondary: Cell::new(0),
+            shadow: Cell::new(0),
+        }
+    }
+
+    fn total(&self) -> u64 {
+        self.primary.get() + self.secondary.get() + self.shadow.get()
+    }
+}
+
+/// Remembers the object that blocked the last shadow ray towards each light source,
+/// and which part of it, e.g. the triangle of a mesh (see `Surface::hit_part`).
+/// Nearby points are usually in the shadow of the same triangle, so testing it first
+/// skips the hierarchy of the mesh and the other objects for most occluded shadow rays.
+struct ShadowCache {
+    /// The index of the last occluder and its part, by the address of the light source.
+    occluders: RefCell<HashMap<usize, (usize, usize)>>,
+}
+
+impl ShadowCache {
+    fn new() -> ShadowCache {
+        ShadowCache { occluders: RefCell::new(HashMap::new()) }
+    }
+
+    fn key(light: &(Light + Sync)) -> usize {
+        light as *const (Light + Sync) as *const u8 as usize
+    }
+}
+
+/// The width and height of the tiles that are rendered in parallel.
+const RENDER_TILE_SIZE: usize = 32;
+
 // Parallelize rendering using the simple_parallel library.
-// TODO: Do this properly by chunking the image.
+#[cfg(feature = "parallel")]
 fn render_parallel<Iter, F>(num_threads: usize, iter: Iter, f: F)
     where Iter: IntoIterator + Send,
           Iter::Item: Send,
@@ -58,44 +801,1084 @@ fn render_parallel<Iter, F>(num_threads: usize, iter: Iter, f: F)
     pool.for_(iter, f);
 }
 
+// Without threads (e.g. in WebAssembly), render one tile after another.
+#[cfg(not(feature = "parallel"))]
+fn render_parallel<Iter, F>(_: usize, iter: Iter, f: F)
+    where Iter: IntoIterator,
+          F: Fn(Iter::Item)
+{
+    for item in iter {
+        f(item);
+    }
+}
+
+/// Returns a random but fixed color for the pair of numbers, to tell apart e.g. the leaves
+/// of the BVHs of several objects.
+fn id_color(a: usize, b: usize) -> Color {
+    let hash = ((a as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ (b as u64 + 1))
+        .wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    let channel = |shift: u64| ((hash >> shift) & 0xff) as Float / 255.;
+    Color::new(channel(40), channel(48), channel(56))
+}
+
 impl Scene {
+    /// Checks the scene for common mistakes, e.g. non-finite coordinates, spheres with radius 0,
+    /// invalid materials, lights inside objects, or a camera whose up vector is parallel
+    /// to the viewing direction.
+    ///
+    /// Scenes with errors should not be rendered, warnings hint at unintended results.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let scene = Scene {
+    ///     camera: Camera {
+    ///         pos: Vec3::new(0.0, 10.0, 0.0),
+    ///         look_at: Vec3::zero(),
+    ///         up: Vec3::new(0.0, 1.0, 0.0),
+    ///         horizontal_fov: 1.0,
+    ///         aspect_ratio: 1.0,
+    ///         width: 10,
+    ///         height: 10,
+    ///     },
+    ///     objects: vec![Box::new(Sphere {
+    ///                       center: Vec3::zero(),
+    ///                       radius: 2.0,
+    ///                       material: color_material(white()),
+    ///                   })],
+    ///     ambient_color: white(),
+    ///     fog: None,
+    ///     medium: None,
+    ///     volumes: vec![],
+    ///     sky: None,
+    ///     lights: vec![Box::new(PointLight {
+    ///                      pos: Vec3::new(0.0, 1.0, 0.0),
+    ///                      col: white(),
+    ///                  })],
+    ///     options: RenderOptions::default(),
+    /// };
+    /// let report = scene.validate();
+    /// assert!(report.has_errors());
+    /// assert_eq!(report.issues,
+    ///            vec![ValidationIssue {
+    ///                     severity: Severity::Error,
+    ///                     element: SceneElement::Camera,
+    ///                     problem: Problem::CameraUpParallelToView,
+    ///                 },
+    ///                 ValidationIssue {
+    ///                     severity: Severity::Warning,
+    ///                     element: SceneElement::Light(0),
+    ///                     problem: Problem::LightInsideObject { object: 0 },
+    ///                 }]);
+    /// ```
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        report.add(SceneElement::Camera, self.camera.validate());
+        for (i, object) in self.objects.iter().enumerate() {
+            report.add(SceneElement::Object(i), object.validate());
+        }
+        for (i, light) in self.lights.iter().enumerate() {
+            let mut problems = light.validate();
+            if let Some(pos) = light.position() {
+                for (j, object) in self.objects.iter().enumerate() {
+                    if object.contains(pos) {
+                        problems.push((Severity::Warning,
+                                       Problem::LightInsideObject { object: j }));
+                    }
+                }
+            }
+            report.add(SceneElement::Light(i), problems);
+        }
+        report
+    }
+
     /// Renders the scene and returns an image.
     pub fn render(&self) -> Image {
-        let (w, h) = (self.camera.width as f64, self.camera.height as f64);
-        let horizontal = (self.camera.horizontal_fov / 2.0).tan();
-        let camera_dir = (self.camera.look_at - self.camera.pos).normalize();
-        let right = horizontal * camera_dir.cross(self.camera.up).normalize();
-        let up = right.cross(camera_dir).normalize();
-        let up = horizontal / self.camera.aspect_ratio * up;
-
-        let mut img = Image::new(self.camera.width, self.camera.height);
-        render_parallel(NUM_THREADS, img.iter_mut(), |(left, down, col)| {
-            let (x, y) = ((left as f64 / w) - 0.5, 0.5 - (down as f64 / h));
-            let ray_dir = camera_dir + x * right + y * up;
-            let ray = Ray::newn(self.camera.pos, ray_dir);
-            *col = self.trace_ray(&ray, 1.0, 0, f64::INFINITY);
+        self.render_with_diagnostics().0
+    }
+
+    /// Renders the scene and returns an image
+    /// together with a report of the problems detected while rendering.
+    pub fn render_with_diagnostics(&self) -> (Image, RenderDiagnostics) {
+        let diagnostics = Mutex::new(RenderDiagnostics::new());
+        let camera = self.overscan_camera();
+        let mut img = Image::new(camera.width, camera.height);
+        let passes = self.denoising_passes(&camera);
+        self.render_region(&camera, &mut img, (0, 0), &diagnostics, None, passes.as_ref());
+        let passes = passes.map(|passes| passes.into_inner().unwrap());
+        let img = self.denoise_image(img, passes.as_ref());
+        (self.post_process(img), diagnostics.into_inner().unwrap())
+    }
+
+    /// Renders the scene and returns an image together with statistics about the rays,
+    /// the traversals of the hierarchies, and the time of each phase, to see where the time
+    /// goes. Collecting them makes rendering a bit slower.
+    /// The traversals are only counted with the feature `stats`.
+    ///
+    /// Measuring time needs a clock, which targets like `wasm32-unknown-unknown` do not have.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let triangle = "v -1 -1 0\nv 1 -1 0\nv 0 1 0\nf 1 2 3\n";
+    /// let scene = Scene::builder()
+    ///     .resolution(16, 16)
+    ///     .add(Mesh::from_obj_reader(triangle.as_bytes(), color_material(white())).unwrap())
+    ///     .light(PointLight { pos: Vec3::new(0.0, 0.0, 10.0), col: white() })
+    ///     .build()
+    ///     .unwrap();
+    /// let (_, stats) = scene.render_with_stats();
+    /// assert_eq!(stats.primary_rays, 16 * 16);
+    /// // Every ray that hits the triangle is followed by a shadow ray.
+    /// assert!(stats.shadow_rays > 0 && stats.shadow_rays < 16 * 16);
+    /// if cfg!(feature = "stats") {
+    ///     assert!(stats.bvh_node_visits >= stats.primary_rays && stats.triangle_tests > 0);
+    /// }
+    /// assert_eq!(stats.phases[0].0, "tracing");
+    /// ```
+    pub fn render_with_stats(&self) -> (Image, RenderStats) {
+        let diagnostics = Mutex::new(RenderDiagnostics::new());
+        let camera = self.overscan_camera();
+        let stats = Mutex::new(RenderStats {
+            pixel_costs: vec![0; camera.width * camera.height],
+            width: camera.width,
+            ..RenderStats::new()
         });
-        return img;
+        let mut img = Image::new(camera.width, camera.height);
+        let passes = self.denoising_passes(&camera);
+        let start_time = Instant::now();
+        self.render_region(&camera,
+                           &mut img,
+                           (0, 0),
+                           &diagnostics,
+                           Some(&stats),
+                           passes.as_ref());
+        let mut stats = stats.into_inner().unwrap();
+        stats.phases.push(("tracing", start_time.elapsed()));
+        if let Some(passes) = passes {
+            let start_time = Instant::now();
+            img = self.denoise_image(img, Some(&passes.into_inner().unwrap()));
+            stats.phases.push(("denoising", start_time.elapsed()));
+        }
+        let start_time = Instant::now();
+        img = self.post_process(img);
+        stats.phases.push(("post-processing", start_time.elapsed()));
+        (img, stats)
     }
 
-    /// Traces the ray through the scene and returns its color.
-    fn trace_ray(&self, ray: &Ray, intensity: f64, depth: usize, t_max: f64) -> AColor {
-        let mut nearest: Option<DelayedIntersection> = None;
-        let mut nearest_t: f64 = t_max;
-        for obj in self.objects.iter() {
+    /// Returns the camera that renders the image including the overscan,
+    /// see `RenderOptions::overscan`.
+    pub fn overscan_camera(&self) -> Camera {
+        self.camera.with_overscan(self.options.overscan)
+    }
+
+    /// Renders the scene into `buffer` as 8-bit RGBA values, line by line,
+    /// encoding the colors with the given transfer function.
+    ///
+    /// This needs neither a file system nor threads (if the feature `parallel` is disabled),
+    /// so it can be used to display the image in a browser, for example.
+    /// Panics if the buffer does not have exactly `4 * width * height` bytes of the camera.
+    pub fn render_into_rgba(&self,
+                            buffer: &mut [u8],
+                            transfer: TransferFunction)
+                            -> RenderDiagnostics {
+        let (img, diagnostics) = self.render_with_diagnostics();
+        img.write_rgba(buffer, transfer);
+        diagnostics
+    }
+
+    /// Renders the rectangle of the image including the overscan (see `overscan_camera`)
+    /// with the top-left pixel (x, y) and the given dimensions, without any post-processing.
+    pub fn render_tile(&self, x: usize, y: usize, width: usize, height: usize) -> Image {
+        let diagnostics = Mutex::new(RenderDiagnostics::new());
+        let mut img = Image::new(width, height);
+        let camera = self.overscan_camera();
+        self.render_region(&camera, &mut img, (x, y), &diagnostics, None, None);
+        img
+    }
+
+    /// Applies the post-processing to the rendered image including the overscan:
+    /// denoising, exposure, post effects, cropping the overscan, and overlays.
+    ///
+    /// Since the image was rendered elsewhere (e.g. by `render_tile`), denoising traces the
+    /// primary rays again for the passes that guide it.
+    pub fn finish_image(&self, img: Image) -> Image {
+        let img = self.denoise_image(img, None);
+        self.post_process(img)
+    }
+
+    /// Returns empty passes for the camera if they are needed for denoising,
+    /// to be filled while rendering.
+    fn denoising_passes(&self, camera: &Camera) -> Option<Mutex<RenderPasses>> {
+        self.options
+            .denoise
+            .as_ref()
+            .map(|_| Mutex::new(RenderPasses::new(camera.width, camera.height)))
+    }
+
+    /// Denoises the rendered image including the overscan, if enabled,
+    /// guided by the given passes or else by passes computed for it.
+    fn denoise_image(&self, img: Image, passes: Option<&RenderPasses>) -> Image {
+        match self.options.denoise {
+            Some(ref options) => {
+                match passes {
+                    Some(passes) => denoise(&img, passes, options),
+                    None => denoise(&img, &self.compute_passes(&self.overscan_camera()), options),
+                }
+            }
+            None => img,
+        }
+    }
+
+    /// Returns the factors for the color channels that implement the white balance,
+    /// which keep the luminance of the balanced illuminant unchanged.
+    /// Returns `None` if the image is left unchanged.
+    pub fn white_balance_filter(&self) -> Option<Color> {
+        let kelvin = match self.options.white_balance {
+            Some(WhiteBalance::Temperature { kelvin }) => kelvin,
+            Some(WhiteBalance::Sky) => {
+                match self.sky.and_then(|sky| sky.correlated_color_temperature()) {
+                    Some(kelvin) => kelvin,
+                    None => return None,
+                }
+            }
+            None => return None,
+        };
+        let white = Color::from_temperature(kelvin);
+        let luminance = white.luminance();
+        Some(Color::new_unchecked(luminance / white.red().max(0.01),
+                                  luminance / white.green().max(0.01),
+                                  luminance / white.blue().max(0.01)))
+    }
+
+    /// Applies the post-processing after denoising, see `finish_image`.
+    fn post_process(&self, img: Image) -> Image {
+        let mut img = img;
+        if let Some(filter) = self.white_balance_filter() {
+            img.filter_radiance(filter);
+        }
+        let factor = match self.options.exposure {
+            Exposure::Manual { ev } => ev.exp2(),
+            Exposure::Auto { ev } => {
+                let average = img.log_average_luminance();
+                if average > 0. {
+                    AUTO_EXPOSURE_KEY / average * ev.exp2()
+                } else {
+                    ev.exp2()
+                }
+            }
+        };
+        if factor != 1. {
+            img.scale_radiance(factor);
+        }
+        for effect in self.options.post_effects.iter() {
+            img = effect.apply(&img);
+        }
+        let overscan = self.options.overscan;
+        if overscan > 0 {
+            img = img.crop(overscan, overscan, self.camera.width, self.camera.height);
+        }
+        if let Some(spacing) = self.options.grid_spacing {
+            overlay::draw_grid(&mut img, &self.camera, spacing);
+        }
+        if self.options.axis_gizmo {
+            overlay::draw_axis_gizmo(&mut img, &self.camera);
+        }
+        img
+    }
+
+    /// Renders the scene band by band and writes each band as soon as it is completed,
+    /// so only a few rows of the image are kept in memory, even for huge images.
+    /// Returns a report of the problems detected while rendering.
+    ///
+    /// All formats except PFM are supported, see `ScanlineWriter`.
+    /// Denoising, post effects, and overlays need the whole image and are not applied,
+    /// so there is no overscan either.
+    /// Automatic exposure only applies its compensation, see `Exposure::Auto`.
+    pub fn render_streaming<W: Write>(&self,
+                                      out: W,
+                                      format: ImageFormat,
+                                      transfer: TransferFunction)
+                                      -> io::Result<RenderDiagnostics> {
+        let (width, height) = (self.camera.width, self.camera.height);
+        let mut writer = try!(ScanlineWriter::new(out, width, height, format, transfer));
+        let diagnostics = Mutex::new(RenderDiagnostics::new());
+        let factor = match self.options.exposure {
+            Exposure::Manual { ev } | Exposure::Auto { ev } => ev.exp2(),
+        };
+        for first_row in (0..height).step_by(RENDER_TILE_SIZE) {
+            let mut band = Image::new(width, RENDER_TILE_SIZE.min(height - first_row));
+            self.render_region(&self.camera,
+                               &mut band,
+                               (0, first_row),
+                               &diagnostics,
+                               None,
+                               None);
+            if let Some(filter) = self.white_balance_filter() {
+                band.filter_radiance(filter);
+            }
+            if factor != 1. {
+                band.scale_radiance(factor);
+            }
+            try!(writer.write_rows(&band));
+        }
+        try!(writer.finish());
+        Ok(diagnostics.into_inner().unwrap())
+    }
+
+    /// Renders the six faces of a cube map of `size` times `size` pixels seen from `pos`,
+    /// e.g. to create an environment map of the scene, see `CubeMap`.
+    ///
+    /// Each face is rendered with a 90° field of view, ignoring the camera of the scene.
+    /// Like `render_streaming`, no denoising, post effects, or overlays are applied,
+    /// and automatic exposure only applies its compensation.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let scene = Scene::builder()
+    ///     .add(Sphere {
+    ///         center: Vec3::new(5.0, 0.0, 0.0),
+    ///         radius: 1.0,
+    ///         material: color_material(white()),
+    ///     })
+    ///     .add(Sphere {
+    ///         center: Vec3::new(0.0, -5.0, 0.0),
+    ///         radius: 1.0,
+    ///         material: color_material(white()),
+    ///     })
+    ///     .build()
+    ///     .unwrap();
+    /// let cube_map = scene.render_cube_map(Vec3::zero(), 8);
+    /// assert_eq!(cube_map.faces[0].get(4, 4).transparency(), 0.0);
+    /// assert_eq!(cube_map.faces[1].get(4, 4).transparency(), 1.0);
+    /// assert_eq!(cube_map.faces[2].get(4, 4).transparency(), 1.0);
+    /// assert_eq!(cube_map.faces[3].get(4, 4).transparency(), 0.0);
+    /// let cross = cube_map.to_cross();
+    /// assert_eq!((cross.width, cross.height), (32, 24));
+    /// assert_eq!(cross.get(20, 12).transparency(), 0.0);
+    /// ```
+    pub fn render_cube_map(&self, pos: Vec3, size: usize) -> CubeMap {
+        let (x, y, z) = (Vec3::e1().to(), Vec3::e2().to(), Vec3::e3().to());
+        let orientations = [(x, y), (-x, y), (y, z), (-y, -z), (z, y), (-z, y)];
+        let diagnostics = Mutex::new(RenderDiagnostics::new());
+        let factor = match self.options.exposure {
+            Exposure::Manual { ev } | Exposure::Auto { ev } => ev.exp2(),
+        };
+        let faces = orientations.iter()
+            .map(|&(dir, up)| {
+                let camera = Camera {
+                    pos: pos,
+                    look_at: pos + dir,
+                    up: up,
+                    horizontal_fov: PI / 2.,
+                    aspect_ratio: 1.,
+                    width: size,
+                    height: size,
+                };
+                let mut img = Image::new(size, size);
+                self.render_region(&camera, &mut img, (0, 0), &diagnostics, None, None);
+                if let Some(filter) = self.white_balance_filter() {
+                    img.filter_radiance(filter);
+                }
+                if factor != 1. {
+                    img.scale_radiance(factor);
+                }
+                img
+            })
+            .collect();
+        CubeMap { faces: faces }
+    }
+
+    /// Bakes the light arriving at the surface of a mesh into a lightmap of the given
+    /// dimensions, laid out by the texture coordinates of the mesh (see `Mesh::rasterize_uv`),
+    /// e.g. for game engines.
+    ///
+    /// Each texel stores the irradiance from the ambient light and the light sources,
+    /// including shadows, as a linear color without exposure, so that multiplying it by the
+    /// color of a diffuse material gives the reflected light. Floating point formats like
+    /// HDR or PFM keep its full range. Every texel gets `samples_per_pixel` samples,
+    /// e.g. for soft shadows. Texels not covered by the mesh stay transparent, except for
+    /// `padding` texels around the covered ones, see `Image::dilate`.
+    ///
+    /// The mesh only casts shadows onto itself if it is part of the scene, too.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let obj = "v 0 0 0\nv 4 0 0\nv 4 4 0\nv 0 4 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+    ///            f 1/1 2/2 3/3\nf 1/1 3/3 4/4\n";
+    /// let floor = Mesh::from_obj_reader(obj.as_bytes(), color_material(white())).unwrap();
+    /// let scene = Scene::builder()
+    ///     .ambient(black())
+    ///     .light(PointLight {
+    ///         pos: Vec3::new(2.0, 2.0, 4.0),
+    ///         col: white(),
+    ///     })
+    ///     .add(Sphere {
+    ///         center: Vec3::new(1.0, 1.0, 2.0),
+    ///         radius: 0.5,
+    ///         material: color_material(white()),
+    ///     })
+    ///     .build()
+    ///     .unwrap();
+    /// let lightmap = scene.bake_lightmap(floor.container(), 8, 8, 0);
+    /// // The texel at (1.75, 2.25) is lit almost perpendicularly,
+    /// // the one at (0.25, 0.25) is in the shadow of the sphere.
+    /// assert!(lightmap.get(3, 3).opaque().red() > 0.9);
+    /// assert_eq!(lightmap.get(0, 7).opaque().red(), 0.0);
+    /// ```
+    pub fn bake_lightmap(&self, mesh: &Mesh, width: usize, height: usize, padding: usize) -> Image {
+        let mut texels: Vec<Option<Intersection>> = (0..width * height).map(|_| None).collect();
+        for (x, y, inter) in mesh.rasterize_uv(width, height) {
+            texels[y * width + x] = Some(inter);
+        }
+        let diagnostics = Mutex::new(RenderDiagnostics::new());
+        let light_selection = self.light_selection();
+        let sky = self.sky.as_ref().map(Sky::distribution);
+        let mut img = Image::new(width, height);
+        {
+            let tiles = img.tiles_mut(RENDER_TILE_SIZE, RENDER_TILE_SIZE);
+            let texels = &texels;
+            render_parallel(self.options.num_threads, tiles, |mut tile| {
+                let shadow_cache = ShadowCache::new();
+                let rays = RayCounts::new();
+                for (x, y, col) in tile.iter_mut() {
+                    let inter = match texels[y * width + x] {
+                        Some(ref inter) => inter,
+                        None => continue,
+                    };
+                    let count = self.options.samples_per_pixel.max(1);
+                    let index = (y * width + x) as u64;
+                    let sampler = self.options.sampler.create(self.options.seed, index, count);
+                    let ctx = TraceContext {
+                        pixel: (x, y),
+                        diagnostics: &diagnostics,
+                        sampler: RefCell::new(sampler),
+                        shadow_cache: &shadow_cache,
+                        rays: &rays,
+                        light_selection: &light_selection,
+                        sky: sky.as_ref(),
+                        primary_hit: Cell::new(None),
+                    };
+                    let mut sum = black();
+                    for index in 0..count {
+                        ctx.sampler.borrow_mut().start_sample(index);
+                        sum = sum + self.compute_irradiance(&ctx, inter);
+                    }
+                    *col = ((1. / count as Float) * sum).with_alpha();
+                }
+            });
+        }
+        img.dilate(padding)
+    }
+
+    /// Renders the part of the image of the camera whose top-left pixel is at `offset`
+    /// into `img` in parallel. If `stats` are given, the traced rays and the traversals of the
+    /// hierarchies are counted there. If `passes` for the whole image of the camera are given,
+    /// the primary hits of the first sample of each pixel are stored there.
+    fn render_region(&self,
+                     camera: &Camera,
+                     img: &mut Image,
+                     offset: (usize, usize),
+                     diagnostics: &Mutex<RenderDiagnostics>,
+                     stats: Option<&Mutex<RenderStats>>,
+                     passes: Option<&Mutex<RenderPasses>>) {
+        let light_selection = self.light_selection();
+        let sky = self.sky.as_ref().map(Sky::distribution);
+        let tiles = img.tiles_mut(RENDER_TILE_SIZE, RENDER_TILE_SIZE);
+        render_parallel(self.options.num_threads, tiles, |mut tile| {
+            let shadow_cache = ShadowCache::new();
+            let rays = RayCounts::new();
+            let mut pixel_costs = Vec::new();
+            let mut primary_hits = Vec::new();
+            let counting = stats.is_some();
+            if counting {
+                start_traversal_counts();
+            }
+            set_bvh_overlay(self.options.bvh_overlay);
+            for (column, row, col) in tile.iter_mut() {
+                let counts_before = traversal_counts();
+                let (left, down) = (offset.0 + column, offset.1 + row);
+                let (count, max_count) = match self.options.adaptive_sampling {
+                    Some(adaptive) => {
+                        let count = self.options.samples_per_pixel.max(2);
+                        (count, adaptive.max_samples.max(count))
+                    }
+                    None => {
+                        let count = self.options.samples_per_pixel.max(1);
+                        (count, count)
+                    }
+                };
+                let sampler = self.options.sampler.create(self.options.seed,
+                                                          (down * camera.width + left) as u64,
+                                                          max_count);
+                let ctx = TraceContext {
+                    pixel: (left, down),
+                    diagnostics: diagnostics,
+                    sampler: RefCell::new(sampler),
+                    shadow_cache: &shadow_cache,
+                    rays: &rays,
+                    light_selection: &light_selection,
+                    sky: sky.as_ref(),
+                    primary_hit: Cell::new(None),
+                };
+                let weight = 1. / count as Float;
+                let trace_sample = |index| {
+                    ctx.sampler.borrow_mut().start_sample(index);
+                    // A single sample goes through the corner of the pixel like before,
+                    // so that images without anti-aliasing stay the same.
+                    let subpixel_offset = if max_count > 1 {
+                        ctx.sampler.borrow_mut().next_2d()
+                    } else {
+                        Vec2::zero()
+                    };
+                    let ray = camera.ray_for_pixel(left, down, subpixel_offset);
+                    let (x, y) = (left as Float + subpixel_offset.x(),
+                                  down as Float + subpixel_offset.y());
+                    let neighbors = [camera.pixel_ray(x + 1., y),
+                                     camera.pixel_ray(x - 1., y),
+                                     camera.pixel_ray(x, y + 1.),
+                                     camera.pixel_ray(x, y - 1.)];
+                    let mut sample = match self.options.debug_view {
+                        Some(view) => self.debug_shade(&ctx, camera, &ray, view),
+                        None => {
+                            self.trace_ray_filtered(&ctx,
+                                                    &ray,
+                                                    Some(&neighbors),
+                                                    white(),
+                                                    0,
+                                                    Float::INFINITY)
+                        }
+                    };
+                    if let Some(max) = self.options.max_radiance {
+                        sample = sample.clamp_radiance(max);
+                    }
+                    sample
+                };
+                let mut stats = SampleStatistics::new();
+                let first = trace_sample(0);
+                if passes.is_some() {
+                    if let Some(hit) = ctx.primary_hit.get() {
+                        primary_hits.push((left, down, hit));
+                    }
+                }
+                stats.add(first.opaque().luminance());
+                // Weighting before adding keeps the transparency from saturating.
+                let mut sum = weight * first;
+                for index in 1..count {
+                    let sample = trace_sample(index);
+                    stats.add(sample.opaque().luminance());
+                    sum = sum + weight * sample;
+                }
+                if let Some(adaptive) = self.options.adaptive_sampling {
+                    while stats.count() < max_count && stats.error() > adaptive.threshold {
+                        let sample = trace_sample(stats.count());
+                        stats.add(sample.opaque().luminance());
+                        // Update the mean, again without saturating the transparency.
+                        let n = stats.count() as Float;
+                        sum = ((n - 1.) / n) * sum + (1. / n) * sample;
+                    }
+                }
+                *col = sum;
+                if counting {
+                    let counts = traversal_counts();
+                    let cost = counts.0 + counts.1 - counts_before.0 - counts_before.1;
+                    pixel_costs.push((left, down, cost));
+                }
+            }
+            set_bvh_overlay(None);
+            diagnostics.lock().unwrap().ray_count += rays.total() as usize;
+            if let Some(passes) = passes {
+                let camera_dir = (camera.look_at - camera.pos).normalize();
+                let mut passes = passes.lock().unwrap();
+                for (x, y, hit) in primary_hits {
+                    hit.write_to(&mut passes, x, y, camera_dir);
+                }
+            }
+            if let Some(stats) = stats {
+                let (node_visits, triangle_tests) = finish_traversal_counts();
+                let mut stats = stats.lock().unwrap();
+                stats.primary_rays += rays.primary.get();
+                stats.secondary_rays += rays.secondary.get();
+                stats.shadow_rays += rays.shadow.get();
+                stats.bvh_node_visits += node_visits;
+                stats.triangle_tests += triangle_tests;
+                for (x, y, cost) in pixel_costs {
+                    let index = y * stats.width + x;
+                    stats.pixel_costs[index] = cost;
+                }
+            }
+        });
+    }
+
+    /// Renders the scene and returns an image
+    /// together with auxiliary images describing what the primary rays hit.
+    /// They are recorded while rendering, for the first sample of each pixel,
+    /// and also guide the denoiser if it is enabled.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let scene = Scene::builder()
+    ///     .resolution(16, 16)
+    ///     .add(Sphere {
+    ///         center: Vec3::new(0.0, 0.0, -5.0),
+    ///         radius: 1.0,
+    ///         material: color_material(white()),
+    ///     })
+    ///     .options(RenderOptions { overscan: 2, ..RenderOptions::default() })
+    ///     .build()
+    ///     .unwrap();
+    /// let (img, passes) = scene.render_with_passes();
+    /// assert_eq!((passes.depth.width, passes.depth.height), (img.width, img.height));
+    /// assert_eq!(passes.object_id(8, 8), Some(0));
+    /// assert_eq!(passes.object_id(0, 0), None);
+    /// // The camera is at z = 10, so the front of the sphere is 14 away.
+    /// assert!((passes.depth.get(8, 8).opaque().red() - 14.0).abs() < 0.1);
+    /// ```
+    pub fn render_with_passes(&self) -> (Image, RenderPasses) {
+        let diagnostics = Mutex::new(RenderDiagnostics::new());
+        let camera = self.overscan_camera();
+        let mut img = Image::new(camera.width, camera.height);
+        let passes = Mutex::new(RenderPasses::new(camera.width, camera.height));
+        self.render_region(&camera, &mut img, (0, 0), &diagnostics, None, Some(&passes));
+        let passes = passes.into_inner().unwrap();
+        let img = self.post_process(self.denoise_image(img, Some(&passes)));
+        let overscan = self.options.overscan;
+        (img, passes.crop(overscan, overscan, self.camera.width, self.camera.height))
+    }
+
+    /// Shades the nearest object along the primary ray by the debug view.
+    fn debug_shade(&self,
+                   ctx: &TraceContext,
+                   camera: &Camera,
+                   ray: &Ray,
+                   view: DebugView)
+                   -> AColor {
+        let (object, inter) = match self.closest_hit(ray, Float::INFINITY) {
+            Some((object, inter)) => {
+                ctx.primary_hit.set(Some(PrimaryHit::new(object, ray, &inter)));
+                (object, inter)
+            }
+            None => return AColor::transparent(),
+        };
+        let col = match view {
+            DebugView::Normal => {
+                // The normal of the intersection points towards the ray.
+                let n = if inter.front_face { inter.normal } else { -inter.normal };
+                Color::new(0.5 * (n.x() + 1.), 0.5 * (n.y() + 1.), 0.5 * (n.z() + 1.))
+            }
+            DebugView::Uv => {
+                let (u, v) = (inter.uv.x(), inter.uv.y());
+                Color::new(u - u.floor(), v - v.floor(), 0.)
+            }
+            DebugView::Depth { near, far } => {
+                let depth = inter.t * (ray.dir() * (camera.look_at - camera.pos).normalize());
+                false_color((depth - near) / (far - near))
+            }
+            DebugView::BvhLeaf => inter.bvh_leaf.map_or(black(), |leaf| id_color(object, leaf)),
+            DebugView::Barycentric => {
+                inter.barycentric.map_or(black(), |b| {
+                    Color::new((1. - b.x() - b.y()).max(0.), b.x().max(0.), b.y().max(0.))
+                })
+            }
+        };
+        col.with_alpha()
+    }
+
+    /// Computes the auxiliary images describing what the primary rays of the camera hit,
+    /// tracing them through the corners of the pixels.
+    fn compute_passes(&self, camera: &Camera) -> RenderPasses {
+        let (width, height) = (camera.width, camera.height);
+        let camera_dir = (camera.look_at - camera.pos).normalize();
+        let mut passes = RenderPasses::new(width, height);
+        for down in 0..height {
+            for left in 0..width {
+                let ray = camera.ray_for_pixel(left, down, Vec2::zero());
+                if let Some((object, inter)) = self.closest_hit(&ray, Float::INFINITY) {
+                    let hit = PrimaryHit::new(object, &ray, &inter);
+                    hit.write_to(&mut passes, left, down, camera_dir);
+                }
+            }
+        }
+        passes
+    }
+
+    /// Returns all intersections of the ray with the objects up to `t_max`, nearest first,
+    /// together with the index of the hit object.
+    /// This is useful e.g. for finding the objects at a pixel.
+    /// Unlike `closest_hit`, textures are not applied, see `Intersection::apply_textures`.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let scene = Scene {
+    ///     camera: Camera {
+    ///         pos: Vec3::zero(),
+    ///         look_at: Vec3::new(0.0, 0.0, -1.0),
+    ///         up: Vec3::new(0.0, 1.0, 0.0),
+    ///         horizontal_fov: 1.0,
+    ///         aspect_ratio: 1.0,
+    ///         width: 10,
+    ///         height: 10,
+    ///     },
+    ///     objects: vec![Box::new(Sphere {
+    ///                       center: Vec3::new(0.0, 0.0, -5.0),
+    ///                       radius: 1.0,
+    ///                       material: glass(),
+    ///                   }),
+    ///                   Box::new(Plane {
+    ///                       normal: Vec3::e3(),
+    ///                       offset: -10.0,
+    ///                       material: color_material(white()),
+    ///                   })],
+    ///     ambient_color: white(),
+    ///     fog: None,
+    ///     medium: None,
+    ///     volumes: vec![],
+    ///     sky: None,
+    ///     lights: vec![],
+    ///     options: RenderOptions::default(),
+    /// };
+    /// let ray = Ray::newn(Vec3::zero(), Vec3::new(0.0, 0.0, -1.0));
+    /// let hits: Vec<(usize, Float)> = scene.intersect_all(&ray, Float::INFINITY)
+    ///     .iter()
+    ///     .map(|&(i, ref inter)| (i, inter.t))
+    ///     .collect();
+    /// assert_eq!(hits, vec![(0, 4.0), (0, 6.0), (1, 10.0)]);
+    /// ```
+    pub fn intersect_all<'a>(&'a self,
+                             ray: &'a Ray,
+                             t_max: Float)
+                             -> Vec<(usize, DelayedIntersection<'a>)> {
+        let mut intersections = Vec::new();
+        for (i, obj) in self.objects.iter().enumerate() {
+            intersections.extend(obj.intersect_all(ray, t_max).into_iter().map(|inter| (i, inter)));
+        }
+        // A degenerate primitive may report a NaN distance, which must not abort the query.
+        intersections.sort_by(|a, b| a.1.t.partial_cmp(&b.1.t).unwrap_or(cmp::Ordering::Equal));
+        intersections
+    }
+
+    /// Returns the object visible at the pixel in column `x` and row `y`
+    /// and the intersection of the primary ray with it, i.e. the same ray as in `render`.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let scene = Scene {
+    ///     camera: Camera {
+    ///         pos: Vec3::zero(),
+    ///         look_at: Vec3::new(0.0, 0.0, -1.0),
+    ///         up: Vec3::new(0.0, 1.0, 0.0),
+    ///         horizontal_fov: 1.0,
+    ///         aspect_ratio: 1.0,
+    ///         width: 10,
+    ///         height: 10,
+    ///     },
+    ///     objects: vec![Box::new(Sphere {
+    ///                       center: Vec3::new(0.0, 0.0, -5.0),
+    ///                       radius: 1.0,
+    ///                       material: glass(),
+    ///                   })],
+    ///     ambient_color: white(),
+    ///     fog: None,
+    ///     medium: None,
+    ///     volumes: vec![],
+    ///     sky: None,
+    ///     lights: vec![],
+    ///     options: RenderOptions::default(),
+    /// };
+    /// let (object, inter) = scene.pick(5, 5).unwrap();
+    /// assert_eq!(object, 0);
+    /// assert!((inter.point - Vec3::new(0.0, 0.0, -4.0)).norm() < 1e-4);
+    /// assert!(scene.pick(0, 0).is_none());
+    /// ```
+    pub fn pick(&self, x: usize, y: usize) -> Option<(usize, Intersection)> {
+        let ray = self.camera.ray_for_pixel(x, y, Vec2::zero());
+        self.closest_hit(&ray, Float::INFINITY)
+    }
+
+    /// Traces the ray through the scene and returns its color, shaded like in `render`.
+    ///
+    /// Non-finite colors are replaced by black like in `render`, but not reported.
+    ///
+    /// Every surface weights what is seen through it or in it once, however deep it is:
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let filter = Material { refractivity: Color::new_gray(0.5), ..vacuum() };
+    /// let scene = Scene::builder()
+    ///     .add(Plane { normal: Vec3::e3(), offset: -1.0, material: filter.clone() })
+    ///     .add(Plane { normal: Vec3::e3(), offset: -2.0, material: filter.clone() })
+    ///     .add(Plane { normal: Vec3::e3(), offset: -3.0, material: filter })
+    ///     .build()
+    ///     .unwrap();
+    /// // The transparent background is seen through three panes that let half of it through.
+    /// let col = scene.trace(&Ray::new(Vec3::zero(), -Vec3::e3()));
+    /// assert!((col.transparency() - 0.125).abs() < 1e-6);
+    /// ```
+    pub fn trace(&self, ray: &Ray) -> AColor {
+        let diagnostics = Mutex::new(RenderDiagnostics::new());
+        let shadow_cache = ShadowCache::new();
+        let rays = RayCounts::new();
+        let light_selection = self.light_selection();
+        let sky = self.sky.as_ref().map(Sky::distribution);
+        let ctx = TraceContext {
+            pixel: (0, 0),
+            diagnostics: &diagnostics,
+            sampler: RefCell::new(SamplerKind::Independent.create(self.options.seed, 0, 1)),
+            shadow_cache: &shadow_cache,
+            rays: &rays,
+            light_selection: &light_selection,
+            sky: sky.as_ref(),
+            primary_hit: Cell::new(None),
+        };
+        set_bvh_overlay(self.options.bvh_overlay);
+        let col = self.trace_ray(&ctx, ray, white(), 0, Float::INFINITY);
+        set_bvh_overlay(None);
+        col
+    }
+
+    /// Finds the nearest intersection of the ray with the objects up to `t_max`.
+    /// Returns the index of the hit object and the intersection.
+    ///
+    /// Together with `is_occluded` and `transmission`, this allows implementing custom shading
+    /// on top of the objects of the scene.
+    pub fn closest_hit(&self, ray: &Ray, t_max: Float) -> Option<(usize, Intersection)> {
+        self.closest_hit_untextured(ray, t_max).map(|(i, mut intersection)| {
+            intersection.apply_textures();
+            (i, intersection)
+        })
+    }
+
+    /// Like `closest_hit`, but leaves the textures of the material to the caller.
+    fn closest_hit_untextured(&self, ray: &Ray, t_max: Float) -> Option<(usize, Intersection)> {
+        let mut nearest: Option<(usize, DelayedIntersection)> = None;
+        let mut nearest_t: Float = t_max;
+        for (i, obj) in self.objects.iter().enumerate() {
             if let Some(intersection) = obj.intersect(ray, nearest_t) {
                 nearest_t = intersection.t;
-                nearest = Some(intersection);
+                nearest = Some((i, intersection));
             }
         }
-        intensity *
-        match nearest {
-            Some(intersection) => self.shade(ray, &intersection.eval(), intensity, depth + 1),
-            None => AColor::transparent(),
+        nearest.map(|(i, intersection)| (i, intersection.eval()))
+    }
+
+    /// Computes the footprint of a pixel on the textures of the intersection,
+    /// from where the primary rays through the neighboring pixels hit the same object,
+    /// see `TextureFootprint::from_neighbors`.
+    /// Returns `None` if the material has no textures.
+    fn texture_footprint(&self,
+                         object: usize,
+                         inter: &Intersection,
+                         neighbors: &[Ray; 4])
+                         -> Option<TextureFootprint> {
+        let mat = &inter.material;
+        let textured = mat.color_texture.is_some() || mat.roughness_texture.is_some() ||
+                       mat.normal_texture.is_some();
+        if !textured || self.options.max_anisotropy == 0 {
+            return None;
+        }
+        let uv_of = |ray: &Ray| {
+            self.objects[object].intersect(ray, Float::INFINITY).map(|i| i.eval().uv)
+        };
+        TextureFootprint::from_neighbors(inter.uv,
+                                         (uv_of(&neighbors[0]), uv_of(&neighbors[1])),
+                                         (uv_of(&neighbors[2]), uv_of(&neighbors[3])),
+                                         self.options.max_anisotropy)
+    }
+
+    /// Traces the ray through the scene and returns its color.
+    ///
+    /// `weight` is the factor with which the result contributes to the pixel color.
+    /// It is only used to decide whether secondary rays are worth tracing.
+    /// If the color is not finite, the problem is recorded and black is returned instead.
+    fn trace_ray(&self,
+                 ctx: &TraceContext,
+                 ray: &Ray,
+                 weight: Color,
+                 depth: usize,
+                 t_max: Float)
+                 -> AColor {
+        self.trace_ray_filtered(ctx, ray, None, weight, depth, t_max)
+    }
+
+    /// Like `trace_ray`, but filters the textures at the hit point over the footprint
+    /// of the pixel if `neighbors` are given, the primary rays through the neighboring pixels
+    /// on the right, on the left, below, and above.
+    fn trace_ray_filtered(&self,
+                          ctx: &TraceContext,
+                          ray: &Ray,
+                          neighbors: Option<&[Ray; 4]>,
+                          weight: Color,
+                          depth: usize,
+                          t_max: Float)
+                          -> AColor {
+        ctx.count_ray(depth);
+        let (object, inter) = match self.closest_hit_untextured(ray, t_max) {
+            Some((object, mut inter)) => {
+                let footprint = neighbors.and_then(|n| self.texture_footprint(object, &inter, n));
+                inter.apply_filtered_textures(footprint.as_ref());
+                (object, inter)
+            }
+            None => {
+                let background = match ctx.sky {
+                    Some(sky) => sky.radiance(ray.dir()).with_alpha(),
+                    None => AColor::transparent(),
+                };
+                return self.through_media(ctx, ray, background, t_max);
+            }
+        };
+        if depth == 0 {
+            ctx.primary_hit.set(Some(PrimaryHit::new(object, ray, &inter)));
+        }
+        if inter.material.holdout {
+            // The object is cut out of the image like the background, but still casts shadows.
+            return self.through_media(ctx, ray, AColor::transparent(), inter.t);
+        }
+        let mut color = self.shade(ctx, ray, object, &inter, weight, depth + 1);
+        if !inter.front_face {
+            // The ray traveled inside the object, so light is absorbed on the way.
+            color = Color::transmittance(inter.material.absorption, inter.t) * color;
+        } else {
+            // Outside of the objects, the ray traveled through the medium and the fog.
+            color = self.through_media(ctx, ray, color, inter.t);
+        }
+        if color.is_finite() {
+            return color;
         }
+        ctx.diagnostics.lock().unwrap().record_non_finite(NonFiniteSample {
+            pixel: ctx.pixel,
+            object: object,
+            material: inter.material,
+            ray: ray.clone(),
+            depth: depth,
+        });
+        AColor::new(0., 0., 0.)
+    }
+
+    /// Returns the color of a ray whose end at `t` has the given color,
+    /// after passing through the medium and the fog of the scene.
+    fn through_media(&self, ctx: &TraceContext, ray: &Ray, color: AColor, t: Float) -> AColor {
+        let mut segments: Vec<(&Volume, Float, Float)> = self.all_volumes()
+            .filter_map(|volume| volume.segment(ray, t).map(|(start, end)| (volume, start, end)))
+            .collect();
+        // Composite the volumes from back to front.
+        segments.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(cmp::Ordering::Equal));
+        let mut color = color;
+        for (volume, start, end) in segments {
+            let scattered = self.in_scattering(ctx, ray, volume, start, end);
+            color = color.weighted(volume.transmittance(ray, t)) + scattered.with_alpha();
+        }
+        match self.fog {
+            Some(fog) => fog.apply(color, t),
+            None => color,
+        }
+    }
+
+    /// Returns the medium and the volumes of the scene.
+    fn all_volumes<'a>(&'a self) -> Box<Iterator<Item = &'a Volume> + 'a> {
+        let medium = self.medium.iter().map(|medium| medium as &Volume);
+        Box::new(medium.chain(self.volumes.iter().map(|volume| &**volume as &Volume)))
+    }
+
+    /// Computes the light of the light sources scattered by the volume towards the origin
+    /// of the ray, between the distances `start` and `end` on the ray.
+    ///
+    /// This is a Monte Carlo estimate of the integral: the interval is divided into
+    /// `volume.steps()` parts, and the scattering is computed at a random point of each.
+    fn in_scattering(&self,
+                     ctx: &TraceContext,
+                     ray: &Ray,
+                     volume: &Volume,
+                     start: Float,
+                     end: Float)
+                     -> Color {
+        let steps = volume.steps().max(1);
+        let step = (end - start) / steps as Float;
+        let mut optical_depth = black();
+        let mut previous_t = start;
+        let mut result = black();
+        for i in 0..steps {
+            let t = start + (i as Float + ctx.random()) * step;
+            let point = ray.origin() + t * ray.dir();
+            let (absorption, scattering) = volume.coefficients(point);
+            optical_depth = optical_depth + (t - previous_t) * (absorption + scattering);
+            previous_t = t;
+            if scattering.max_channel() <= 0. {
+                continue;
+            }
+            let mut scattered = black();
+            for (light, count) in self.choose_lights(ctx, point) {
+                let u = Vec2::new(ctx.random(), ctx.random());
+                let sample = match light.sample_direction(point, u) {
+                    Some(sample) => sample,
+                    None => continue,
+                };
+                let light_ray = Ray::new(point, sample.dir);
+                let visibility = self.light_visibility(ctx, light, &light_ray, sample.distance);
+                if let Some(visibility) = visibility {
+                    let light_col = (1. / (sample.pdf * count)) * sample.radiance;
+                    scattered = scattered + visibility * light_col;
+                }
+            }
+            // The scattered light is attenuated on the way back to the origin of the ray.
+            result = result + Color::transmittance(optical_depth, 1.) * (scattering * scattered);
+        }
+        (step * ISOTROPIC_PHASE) * result
+    }
+
+    /// Returns the fraction of the light of a light source at the distance `t_max` that reaches
+    /// the origin of the ray, or `None` if it is blocked completely.
+    /// It depends on the `ShadowMode` and is attenuated by the medium and the volumes.
+    fn light_visibility(&self,
+                        ctx: &TraceContext,
+                        light: &(Light + Sync),
+                        ray: &Ray,
+                        t_max: Float)
+                        -> Option<Color> {
+        ctx.count_shadow_ray();
+        let visibility = match self.options.shadows {
+            ShadowMode::Opaque => {
+                if self.is_occluded_cached(ctx, light, ray, t_max) {
+                    return None;
+                }
+                white()
+            }
+            ShadowMode::Transmissive => {
+                let transmission = self.transmission(ray, t_max);
+                if transmission.max_channel() <= 0. {
+                    return None;
+                }
+                transmission
+            }
+        };
+        Some(self.all_volumes().fold(visibility, |v, volume| volume.transmittance(ray, t_max) * v))
     }
 
-    fn is_hit_by(&self, ray: &Ray, t_max: f64) -> bool {
+    /// For light arriving at the intersection point on the object with the index `object` from
+    /// behind the surface, finds the distance that it travels inside the object and how much of
+    /// the light reaches the point where it enters the object, see `light_visibility`.
+    fn light_through(&self,
+                     ctx: &TraceContext,
+                     light: &(Light + Sync),
+                     object: usize,
+                     inter: &Intersection,
+                     light_dir: UnitVec3,
+                     t_max: Float)
+                     -> Option<(Float, Color)> {
+        let eps = self.options.ray_epsilon.at(inter);
+        let inside = Ray::new(inter.point - eps * inter.normal, light_dir).with_t_min(eps);
+        // Open surfaces have no other side, so the light enters right at the point.
+        // Other objects behind them block the light like for an ordinary shadow ray.
+        let thickness = match self.closest_hit(&inside, t_max) {
+            Some((i, exit)) if i == object => exit.t,
+            _ => 0.,
+        };
+        let behind = Ray::new(inside.origin() + (thickness + eps) * light_dir, light_dir)
+            .with_t_min(eps);
+        self.light_visibility(ctx, light, &behind, t_max - thickness - eps)
+            .map(|v| (thickness, v))
+    }
+
+    /// Returns `true` if the ray hits an object up to `t_max`, e.g. for shadow rays.
+    /// This is faster than `closest_hit`.
+    pub fn is_occluded(&self, ray: &Ray, t_max: Float) -> bool {
         for obj in self.objects.iter() {
             if obj.is_hit_by(ray, t_max) {
                 return true;
@@ -104,69 +1887,209 @@ impl Scene {
         false
     }
 
-    /// Determines the color of an intersection point.
-    fn shade(&self, ray: &Ray, inter: &Intersection, intensity: f64, depth: usize) -> AColor {
-        self.compute_illuminance(ray.dir, inter) +
-        self.compute_reflection_refraction(ray.dir, inter, intensity, depth)
+    /// Like `is_occluded`, but tests the part of the object that blocked the last shadow ray
+    /// towards the light first, see `ShadowCache`.
+    fn is_occluded_cached(&self,
+                          ctx: &TraceContext,
+                          light: &(Light + Sync),
+                          ray: &Ray,
+                          t_max: Float)
+                          -> bool {
+        let key = ShadowCache::key(light);
+        let cached = ctx.shadow_cache.occluders.borrow().get(&key).cloned();
+        if let Some((i, part)) = cached {
+            if self.objects[i].part_is_hit_by(part, ray, t_max) {
+                return true;
+            }
+        }
+        for (i, obj) in self.objects.iter().enumerate() {
+            if let Some(part) = obj.hit_part(ray, t_max) {
+                ctx.shadow_cache.occluders.borrow_mut().insert(key, (i, part));
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Computes the fraction of light that reaches the end of the ray at `t_max`
+    /// when passing through the objects in between, like shadow rays in `ShadowMode::Transmissive`.
+    pub fn transmission(&self, ray: &Ray, t_max: Float) -> Color {
+        let mut transmission = white();
+        let mut ray = ray.clone();
+        let mut t_max = t_max;
+        loop {
+            let inter = match self.closest_hit(&ray, t_max) {
+                Some((_, inter)) => inter,
+                None => return transmission,
+            };
+            let mat = inter.material;
+            transmission = mat.refractivity * transmission;
+            if !inter.front_face {
+                // The light traveled inside the object, so it is absorbed on the way.
+                transmission = Color::transmittance(mat.absorption, inter.t) * transmission;
+            }
+            if transmission.max_channel() <= self.options.intensity_threshold {
+                return black();
+            }
+            // Continue behind the intersection point:
+            let eps = self.options.ray_epsilon.at(&inter);
+            ray = Ray::new(inter.point + eps * ray.dir(), ray.dir()).with_t_min(eps);
+            t_max -= inter.t + eps;
+        }
+    }
+
+    /// Determines the color of an intersection point on the object with the index `object`.
+    fn shade(&self,
+             ctx: &TraceContext,
+             ray: &Ray,
+             object: usize,
+             inter: &Intersection,
+             weight: Color,
+             depth: usize)
+             -> AColor {
+        self.compute_illuminance(ctx, ray.dir(), object, inter) +
+        self.compute_reflection_refraction(ctx, ray.dir(), inter, weight, depth)
     }
 
     /// Computes the illuminance at the given intersection point.
     /// This means that ambient, diffuse, and specular reflection are taken into account,
     /// but not mirror-like reflection or refraction for transparent objects.
-    fn compute_illuminance(&self, dir: UnitVec3, inter: &Intersection) -> AColor {
+    fn compute_illuminance(&self,
+                           ctx: &TraceContext,
+                           dir: UnitVec3,
+                           object: usize,
+                           inter: &Intersection)
+                           -> AColor {
         let mat = inter.material;
-        // Start with the ambient color of the object.
-        let mut color = (mat.ambient * (self.ambient_color * mat.color)).with_alpha();
-        // Add the illuminance of every light up to get the final color:
-        for light in self.lights.iter() {
+        let bsdf: &Bsdf = match mat.bsdf {
+            Some(bsdf) => bsdf,
+            None => &mat,
+        };
+        // Start with the light emitted by the object and its ambient color.
+        let ambient = mat.ambient * (self.ambient_color * mat.color);
+        let mut color = (bsdf.emission(-dir, inter.normal) + ambient).with_alpha();
+        // Add the illuminance of every light sample up to get the final color:
+        for (light, count) in self.choose_lights(ctx, inter.point) {
+            let u = Vec2::new(ctx.random(), ctx.random());
+            let sample = match light.sample_direction(inter.point, u) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            let light_dir = sample.dir;
+            let light_col = (1. / (sample.pdf * count)) * sample.radiance;
+            let t_max = sample.distance;
+            let translucency = match mat.diffuse_model {
+                DiffuseModel::Subsurface { tint, mean_free_path, .. }
+                    if mat.bsdf.is_none() && light_dir * inter.normal < 0. &&
+                       dir * inter.normal < 0. => Some((tint, mean_free_path)),
+                _ => None,
+            };
+            if let Some((tint, mean_free_path)) = translucency {
+                // The light comes from behind and shines through the object.
+                let through = self.light_through(ctx, light, object, inter, light_dir, t_max);
+                let (thickness, visibility) = match through {
+                    Some(through) => through,
+                    None => continue, // the light is blocked behind the object
+                };
+                let light_col = (-thickness / mean_free_path).exp() * (visibility * light_col);
+                let shining_through = (mat.diffuse * -(light_dir * inter.normal)) * tint;
+                let reflected = bsdf.evaluate(-dir, light_dir, inter.normal) + shining_through;
+                color = color + (reflected * light_col).with_alpha();
+                continue;
+            }
             // Construct shadow ray:
-            let light_vec = light.pos - inter.point;
-            let t_max = light_vec.norm();
-            let light_dir = light_vec.normalize();
-            let shadow_ray = shadow_ray(inter, light_dir);
+            let shadow_ray = shadow_ray(inter, light_dir, self.options.ray_epsilon.at(inter));
             // Check if the point is in the shadow of the current light source.
-            if self.is_hit_by(&shadow_ray, t_max) {
-                continue; // the point is in the shadow of this light source
-            }
-            // Compute the diffuse reflection:
-            let lambert_coefficient = mat.diffuse * f64::max(0.0, light_dir * inter.normal);
-            let lambert = lambert_coefficient * (light.col * mat.color);
-            // Compute the specular reflection (Blinn-Phong):
-            let specular_coefficient =
-                mat.specular * compute_specular(-dir, light_dir, inter.normal, mat.shininess);
-            let specular = specular_coefficient * light.col;
-            // Add these two terms to overall color:
-            color = color + lambert.with_alpha() + specular.with_alpha();
+            let light_col = match self.light_visibility(ctx, light, &shadow_ray, t_max) {
+                Some(visibility) => visibility * light_col,
+                None => continue, // the point is in the shadow of this light source
+            };
+            // Add the diffuse and specular reflection to the overall color:
+            let reflected = bsdf.evaluate(-dir, light_dir, inter.normal) * light_col;
+            color = color + reflected.with_alpha();
         }
         return color;
     }
 
+    /// Computes the light arriving at the given intersection point from the ambient light
+    /// and one sample of every light source, weighted by the cosine factor of its direction.
+    fn compute_irradiance(&self, ctx: &TraceContext, inter: &Intersection) -> Color {
+        let mut irradiance = self.ambient_color;
+        for (light, count) in self.choose_lights(ctx, inter.point) {
+            let u = Vec2::new(ctx.random(), ctx.random());
+            let sample = match light.sample_direction(inter.point, u) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            let cos = sample.dir * inter.normal;
+            if cos <= 0. {
+                continue;
+            }
+            let shadow_ray = shadow_ray(inter, sample.dir, self.options.ray_epsilon.at(inter));
+            let visibility = self.light_visibility(ctx, light, &shadow_ray, sample.distance);
+            if let Some(visibility) = visibility {
+                let weight = cos / (sample.pdf * count);
+                irradiance = irradiance + weight * (visibility * sample.radiance);
+            }
+        }
+        irradiance
+    }
+
+    /// Prepares choosing the lights for a render, see `LightSampling`.
+    fn light_selection(&self) -> LightSelection {
+        LightSelection::new(&self.lights, self.options.light_sampling)
+    }
+
+    /// Chooses the lights to sample for shading the point, see `LightSampling`.
+    /// Every light is returned once per sample, together with the number that its
+    /// contributions are divided by, i.e. its number of samples, or the expected number
+    /// if the lights are chosen at random.
+    fn choose_lights<'a>(&'a self, ctx: &'a TraceContext<'a>, point: Vec3) -> ChosenLights<'a> {
+        ChosenLights {
+            lights: &self.lights,
+            ctx: ctx,
+            point: point,
+            next: 0,
+            sample: 0,
+        }
+    }
+
     /// Computes the refraction for transparent objects and reflection for reflective ones.
     fn compute_reflection_refraction(&self,
+                                     ctx: &TraceContext,
                                      dir: UnitVec3,
                                      inter: &Intersection,
-                                     intensity: f64,
+                                     weight: Color,
                                      depth: usize)
                                      -> AColor {
         let mut color = AColor::new(0., 0., 0.);
         let mat = inter.material;
 
         // Compute the REFLECTION:
-        if mat.reflectance > 0. && mat.reflectance * intensity > INTENSITY_THRESHOLD &&
-           depth < MAX_DEPTH {
-            let reflected_ray = reflect_ray(inter, dir);
-            let reflected_intensity = mat.reflectance * intensity;
-            color = color +
-                    self.trace_ray(&reflected_ray,
-                                   reflected_intensity,
-                                   depth + 1,
-                                   f64::INFINITY);
+        let reflectance = if self.options.fresnel_reflections {
+            let k = schlick(0., (dir * inter.normal).abs());
+            (1. - k) * mat.reflectance + k * white()
+        } else {
+            mat.reflectance
+        };
+        let reflected_weight = reflectance * weight;
+        if mat.reflectance.max_channel() > 0. &&
+           reflected_weight.max_channel() > self.options.intensity_threshold &&
+           depth < self.options.max_depth {
+            let reflected_ray = reflect_ray(inter, dir, self.options.ray_epsilon.at(inter));
+            let reflected = self.trace_ray(ctx,
+                                           &reflected_ray,
+                                           reflected_weight,
+                                           depth + 1,
+                                           Float::INFINITY);
+            color = color + reflected.weighted(reflectance);
         }
 
         // Compute the REFRACTION:
-        if mat.refractivity > 0. && mat.refractivity * intensity > INTENSITY_THRESHOLD &&
-           depth < MAX_DEPTH {
-            color = color + self.compute_recursive_refraction(dir, inter, intensity, depth);
+        if mat.refractivity.max_channel() > 0. &&
+           (mat.refractivity * weight).max_channel() > self.options.intensity_threshold &&
+           depth < self.options.max_depth {
+            color = color + self.compute_recursive_refraction(ctx, dir, inter, weight, depth);
         }
 
         return color;
@@ -174,50 +2097,80 @@ impl Scene {
 
     /// Traces the reflected and refracted (except in case of total reflection).
     fn compute_recursive_refraction(&self,
+                                    ctx: &TraceContext,
                                     dir: UnitVec3,
                                     inter: &Intersection,
-                                    intensity: f64,
+                                    weight: Color,
                                     depth: usize)
                                     -> AColor {
         let mat = inter.material;
+        let mut refraction_index = mat.refraction_index;
+        if mat.is_dispersive() {
+            // The weight tells which channels the ray carries.
+            // Split it into one ray per channel, each refracted according to its wavelength.
+            let channels = [Color::new(1., 0., 0.), Color::new(0., 1., 0.), Color::new(0., 0., 1.)];
+            let active: Vec<usize> =
+                (0..3).filter(|&i| (channels[i] * weight).max_channel() > 0.).collect();
+            if active.len() > 1 {
+                let mut color = AColor::new(0., 0., 0.);
+                for &i in active.iter() {
+                    let channel_color = self.compute_recursive_refraction(ctx,
+                                                                          dir,
+                                                                          inter,
+                                                                          channels[i] * weight,
+                                                                          depth);
+                    color = color + channel_color.weighted(channels[i]);
+                }
+                return color;
+            }
+            if let Some(&i) = active.first() {
+                refraction_index = mat.refraction_index_at(CHANNEL_WAVELENGTHS[i]);
+            }
+        }
         // TODO: We assume that the ray travels to or from vacuum (which is almost always the case).
         // But, for example, if the ray travels from glass (1.5) to water (1.33),
         // the ior used here (1.33) is incorrect, should be 1.33/1.5.
         let (ior, normal) = if dir * inter.normal < 0. {
             // Ray enters object:
-            (mat.refraction_index, inter.normal)
+            (refraction_index, inter.normal)
         } else {
             // Ray exits object:
-            (1. / mat.refraction_index, -inter.normal)
+            (1. / refraction_index, -inter.normal)
         };
         let ref inter = Intersection { normal: normal, ..*inter };
-        let reflected_ray = reflect_ray(inter, dir);
-        let refracted_ray = refract_ray(inter, dir, ior);
-        // TODO: Implement beer's law for light absorption inside material.
+        let eps = self.options.ray_epsilon.at(inter);
+        let reflected_ray = reflect_ray(inter, dir, eps);
+        let refracted_ray = refract_ray(inter, dir, ior, eps);
         match refracted_ray {
             None => {
                 // Total internal reflection:
-                let reflected_intensity = intensity * mat.refractivity;
-                let reflected = self.trace_ray(&reflected_ray,
-                                               reflected_intensity,
+                let reflected_weight = mat.refractivity;
+                let reflected = self.trace_ray(ctx,
+                                               &reflected_ray,
+                                               reflected_weight * weight,
                                                depth + 1,
-                                               f64::INFINITY);
-                return reflected_intensity * reflected;
+                                               Float::INFINITY);
+                return reflected.weighted(reflected_weight);
             }
             Some(refracted_ray) => {
                 // Both reflection and refraction:
-                let fresnel_factor = fresnel(dir, normal, ior);
-                let refracted_intensity = intensity * mat.refractivity * (1. - fresnel_factor);
-                let reflected_intensity = intensity * mat.refractivity * fresnel_factor;
-                let reflected = self.trace_ray(&reflected_ray,
-                                               reflected_intensity,
+                let fresnel_factor = match self.options.fresnel {
+                    FresnelModel::Exact => fresnel(dir, normal, ior),
+                    FresnelModel::Schlick => fresnel_schlick(dir, normal, ior),
+                };
+                let refracted_weight = (1. - fresnel_factor) * mat.refractivity;
+                let reflected_weight = fresnel_factor * mat.refractivity;
+                let reflected = self.trace_ray(ctx,
+                                               &reflected_ray,
+                                               reflected_weight * weight,
                                                depth + 1,
-                                               f64::INFINITY);
-                let refracted = self.trace_ray(&refracted_ray,
-                                               refracted_intensity,
+                                               Float::INFINITY);
+                let refracted = self.trace_ray(ctx,
+                                               &refracted_ray,
+                                               refracted_weight * weight,
                                                depth + 1,
-                                               f64::INFINITY);
-                return refracted_intensity * refracted + reflected_intensity * reflected;
+                                               Float::INFINITY);
+                return refracted.weighted(refracted_weight) + reflected.weighted(reflected_weight);
             }
         }
     }
@@ -0,0 +1,493 @@
+use lin_alg::{Float, Vec2};
+use random::Pcg32;
+
+/// Trait for generating the random numbers of the samples of a pixel.
+///
+/// Every sample of a pixel is a sequence of numbers in [0, 1), its dimensions.
+/// The renderer uses the first two for the position of the primary ray within the pixel
+/// and the following ones for stochastic effects like soft shadows, in a fixed order.
+/// Better samplers distribute the samples of a pixel more evenly than independent random
+/// numbers, which reduces the noise for the same number of samples.
+pub trait Sampler {
+    /// Starts the sample with the given index, from 0 to the number of samples per pixel.
+    /// The next number is its first dimension.
+    fn start_sample(&mut self, index: usize);
+
+    /// Returns the next dimension of the current sample.
+    fn next_1d(&mut self) -> Float;
+
+    /// Returns the next two dimensions of the current sample.
+    fn next_2d(&mut self) -> Vec2 {
+        let x = self.next_1d();
+        Vec2::new(x, self.next_1d())
+    }
+}
+
+/// The kinds of samplers that can be selected in the `RenderOptions`.
+///
+/// Except for `Independent`, the samples of a pixel cover every dimension evenly:
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// for &kind in &[SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol] {
+///     let mut sampler = kind.create(0, 42, 16);
+///     let mut hits = [[false; 16]; 3];
+///     for index in 0..16 {
+///         sampler.start_sample(index);
+///         let u = sampler.next_2d();
+///         let z = sampler.next_1d();
+///         for (dimension, &value) in [u.x(), u.y(), z].iter().enumerate() {
+///             assert!(value >= 0. && value < 1.);
+///             hits[dimension][(value * 16.) as usize] = true;
+///         }
+///     }
+///     // Halton's bases other than 2 only cover the intervals approximately.
+///     assert!(hits[0].iter().all(|&hit| hit), "{:?}", kind);
+///     if kind != SamplerKind::Halton {
+///         assert!(hits.iter().all(|h| h.iter().all(|&hit| hit)), "{:?}", kind);
+///     }
+/// }
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SamplerKind {
+    /// Independent random numbers.
+    Independent,
+    /// Every dimension is divided into one interval per sample (Latin hypercube sampling),
+    /// and every sample gets a random point of a different interval.
+    Stratified,
+    /// The Halton sequence, randomized by permuting its digits per pixel and dimension.
+    Halton,
+    /// The Sobol sequence, randomized by Owen scrambling per pixel and dimension.
+    Sobol,
+}
+
+impl SamplerKind {
+    /// Creates a sampler for `samples_per_pixel` samples of a pixel.
+    /// Pixels with different streams get different samples.
+    pub fn create(&self, seed: u64, stream: u64, samples_per_pixel: usize) -> Box<Sampler> {
+        let rng = Pcg32::new(seed, stream);
+        match *self {
+            SamplerKind::Independent => Box::new(IndependentSampler { rng: rng }),
+            SamplerKind::Stratified => {
+                Box::new(StratifiedSampler::new(rng, samples_per_pixel.max(1)))
+            }
+            SamplerKind::Halton => Box::new(HaltonSampler::new(rng)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(rng)),
+        }
+    }
+}
+
+/// Converts the bits of `x` to a number in [0, 1), like `Pcg32::next_float`.
+fn to_float(x: u32) -> Float {
+    (x >> 8) as Float / (1u32 << 24) as Float
+}
+
+/// Returns random values for the dimensions of a pixel, which are the same for all samples.
+/// They are generated on demand, in the order of the dimensions.
+struct DimensionValues {
+    rng: Pcg32,
+    values: Vec<u32>,
+}
+
+impl DimensionValues {
+    fn get(&mut self, dimension: usize) -> u32 {
+        while self.values.len() <= dimension {
+            self.values.push(self.rng.next_u32());
+        }
+        self.values[dimension]
+    }
+}
+
+/// A sampler returning independent random numbers.
+pub struct IndependentSampler {
+    rng: Pcg32,
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, _index: usize) {}
+
+    fn next_1d(&mut self) -> Float {
+        self.rng.next_float()
+    }
+}
+
+/// Permutes the numbers from 0 to `n - 1`, with a different permutation for every `key`.
+///
+/// This is the hash-based permutation by Kensler ("Correlated Multi-Jittered Sampling"),
+/// which does not need to store the permutation.
+fn permute(i: u32, n: u32, key: u32) -> u32 {
+    let mut w = n - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    let mut i = i;
+    // All steps are bijections on the numbers from 0 to w,
+    // so repeating them until the result is less than n terminates.
+    loop {
+        i ^= key;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= key >> 16;
+        i ^= (i & w) >> 4;
+        i ^= key >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= key >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | key >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < n {
+            return (i + key) % n;
+        }
+    }
+}
+
+/// A sampler that divides every dimension into one interval per sample.
+/// Samples beyond the number of samples per pixel are independent random numbers.
+pub struct StratifiedSampler {
+    count: usize,
+    keys: DimensionValues,
+    jitter: Pcg32,
+    index: usize,
+    dimension: usize,
+}
+
+impl StratifiedSampler {
+    fn new(mut rng: Pcg32, count: usize) -> StratifiedSampler {
+        let keys = Pcg32::new(rng.next_u32() as u64, rng.next_u32() as u64);
+        StratifiedSampler {
+            count: count,
+            keys: DimensionValues {
+                rng: keys,
+                values: Vec::new(),
+            },
+            jitter: rng,
+            index: 0,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, index: usize) {
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> Float {
+        let key = self.keys.get(self.dimension);
+        self.dimension += 1;
+        if self.index >= self.count {
+            return self.jitter.next_float();
+        }
+        // Every dimension visits the intervals in a different order.
+        let interval = permute(self.index as u32, self.count as u32, key);
+        ((interval as Float + self.jitter.next_float()) / self.count as Float).min(1. - 1e-7)
+    }
+}
+
+/// The bases of the dimensions of the Halton sequence.
+const PRIMES: [u32; 16] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53];
+
+/// Reverses the digits of `i` in the given base, mirrored at the decimal point (the radical
+/// inverse), and permutes them, with a different permutation for every digit position and
+/// `key`. This includes the zeros after the last digit of `i`, up to the precision of the result.
+fn scrambled_radical_inverse(base: u32, i: u32, key: u32) -> Float {
+    let inverse_base = 1. / base as Float;
+    let mut i = i;
+    let mut result = 0.;
+    let mut factor = inverse_base;
+    let mut position: u32 = 0;
+    while factor > 1e-9 {
+        let key = key.wrapping_add(position.wrapping_mul(0x9e3779b9));
+        result += permute(i % base, base, key) as Float * factor;
+        i /= base;
+        factor *= inverse_base;
+        position += 1;
+    }
+    result.min(1. - 1e-7)
+}
+
+/// A sampler using the Halton sequence, i.e. the radical inverses in the prime bases.
+///
+/// Every pixel permutes the digits of the sequence randomly per dimension and digit
+/// position (random digit scrambling), so neighboring pixels do not use the same samples.
+/// This keeps the distribution of the samples, but unlike shifting them by a random offset,
+/// it also breaks up the correlation of the higher dimensions of the sequence.
+/// Dimensions beyond the first 16 are independent random numbers.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let mut first = SamplerKind::Halton.create(0, 0, 4);
+/// let mut second = SamplerKind::Halton.create(0, 1, 4);
+/// first.start_sample(0);
+/// second.start_sample(0);
+/// assert!(first.next_1d() != second.next_1d());
+/// ```
+pub struct HaltonSampler {
+    keys: DimensionValues,
+    rng: Pcg32,
+    index: usize,
+    dimension: usize,
+}
+
+impl HaltonSampler {
+    fn new(mut rng: Pcg32) -> HaltonSampler {
+        let keys = Pcg32::new(rng.next_u32() as u64, rng.next_u32() as u64);
+        HaltonSampler {
+            keys: DimensionValues {
+                rng: keys,
+                values: Vec::new(),
+            },
+            rng: rng,
+            index: 0,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, index: usize) {
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> Float {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        if dimension >= PRIMES.len() {
+            return self.rng.next_float();
+        }
+        let key = self.keys.get(dimension);
+        scrambled_radical_inverse(PRIMES[dimension], self.index as u32, key)
+    }
+}
+
+/// The 32 direction numbers of each of the first 8 dimensions of the Sobol sequence.
+/// The first dimension is the van der Corput sequence. The others are computed from the
+/// primitive polynomials and initial direction numbers of Joe and Kuo, as the degree `s`,
+/// the coefficients `a`, and the numbers `m`: (1, 0, [1]), (2, 1, [1, 3]), (3, 1, [1, 3, 1]),
+/// (3, 2, [1, 1, 1]), (4, 1, [1, 1, 3, 3]), (4, 4, [1, 3, 5, 13]), (5, 2, [1, 1, 5, 5, 17]).
+const SOBOL_DIRECTIONS: [[u32; 32]; 8] =
+    [[0x80000000, 0x40000000, 0x20000000, 0x10000000, 0x08000000, 0x04000000, 0x02000000,
+      0x01000000, 0x00800000, 0x00400000, 0x00200000, 0x00100000, 0x00080000, 0x00040000,
+      0x00020000, 0x00010000, 0x00008000, 0x00004000, 0x00002000, 0x00001000, 0x00000800,
+      0x00000400, 0x00000200, 0x00000100, 0x00000080, 0x00000040, 0x00000020, 0x00000010,
+      0x00000008, 0x00000004, 0x00000002, 0x00000001],
+     [0x80000000, 0xc0000000, 0xa0000000, 0xf0000000, 0x88000000, 0xcc000000, 0xaa000000,
+      0xff000000, 0x80800000, 0xc0c00000, 0xa0a00000, 0xf0f00000, 0x88880000, 0xcccc0000,
+      0xaaaa0000, 0xffff0000, 0x80008000, 0xc000c000, 0xa000a000, 0xf000f000, 0x88008800,
+      0xcc00cc00, 0xaa00aa00, 0xff00ff00, 0x80808080, 0xc0c0c0c0, 0xa0a0a0a0, 0xf0f0f0f0,
+      0x88888888, 0xcccccccc, 0xaaaaaaaa, 0xffffffff],
+     [0x80000000, 0xc0000000, 0x60000000, 0x90000000, 0xe8000000, 0x5c000000, 0x8e000000,
+      0xc5000000, 0x68800000, 0x9cc00000, 0xee600000, 0x55900000, 0x80680000, 0xc09c0000,
+      0x60ee0000, 0x90550000, 0xe8808000, 0x5cc0c000, 0x8e606000, 0xc5909000, 0x6868e800,
+      0x9c9c5c00, 0xeeee8e00, 0x5555c500, 0x8000e880, 0xc0005cc0, 0x60008e60, 0x9000c590,
+      0xe8006868, 0x5c009c9c, 0x8e00eeee, 0xc5005555],
+     [0x80000000, 0xc0000000, 0x20000000, 0x50000000, 0xf8000000, 0x74000000, 0xa2000000,
+      0x93000000, 0xd8800000, 0x25400000, 0x59e00000, 0xe6d00000, 0x78080000, 0xb40c0000,
+      0x82020000, 0xc3050000, 0x208f8000, 0x51474000, 0xfbea2000, 0x75d93000, 0xa0858800,
+      0x914e5400, 0xdbe79e00, 0x25db6d00, 0x58800080, 0xe54000c0, 0x79e00020, 0xb6d00050,
+      0x800800f8, 0xc00c0074, 0x200200a2, 0x50050093],
+     [0x80000000, 0x40000000, 0x20000000, 0xb0000000, 0xf8000000, 0xdc000000, 0x7a000000,
+      0x9d000000, 0x5a800000, 0x2fc00000, 0xa1600000, 0xf0b00000, 0xda880000, 0x6fc40000,
+      0x81620000, 0x40bb0000, 0x22878000, 0xb3c9c000, 0xfb65a000, 0xddb2d000, 0x78022800,
+      0x9c0b3c00, 0x5a0fb600, 0x2d0ddb00, 0xa2878080, 0xf3c9c040, 0xdb65a020, 0x6db2d0b0,
+      0x800228f8, 0x400b3cdc, 0x200fb67a, 0xb00ddb9d],
+     [0x80000000, 0x40000000, 0x60000000, 0x30000000, 0xc8000000, 0x24000000, 0x56000000,
+      0xfb000000, 0xe0800000, 0x70400000, 0xa8600000, 0x14300000, 0x9ec80000, 0xdf240000,
+      0xb6d60000, 0x8bbb0000, 0x48008000, 0x64004000, 0x36006000, 0xcb003000, 0x2880c800,
+      0x54402400, 0xfe605600, 0xef30fb00, 0x7e48e080, 0xaf647040, 0x1eb6a860, 0x9f8b1430,
+      0xd6c81ec8, 0xbb249f24, 0x80d6d6d6, 0x40bbbbbb],
+     [0x80000000, 0xc0000000, 0xa0000000, 0xd0000000, 0x58000000, 0x94000000, 0x3e000000,
+      0xe3000000, 0xbe800000, 0x23c00000, 0x1e200000, 0xf3100000, 0x46780000, 0x67840000,
+      0x78460000, 0x84670000, 0xc6788000, 0xa784c000, 0xd846a000, 0x5467d000, 0x9e78d800,
+      0x33845400, 0xe6469e00, 0xb7673300, 0x20f86680, 0x104477c0, 0xf8668020, 0x4477c010,
+      0x668020f8, 0x77c01044, 0x8020f866, 0xc0104477],
+     [0x80000000, 0x40000000, 0xa0000000, 0x50000000, 0x88000000, 0x24000000, 0x12000000,
+      0x2d000000, 0x76800000, 0x9e400000, 0x08200000, 0x64100000, 0xb2280000, 0x7d140000,
+      0xfea20000, 0xba490000, 0x1a248000, 0x491b4000, 0xc4b5a000, 0xe3739000, 0xf6800800,
+      0xde400400, 0xa8200a00, 0x34100500, 0x3a280880, 0x59140240, 0xeca20120, 0x974902d0,
+      0x6ca48768, 0xd75b49e4, 0xcc95a082, 0x87639641]];
+
+/// Randomly permutes the numbers in [0, 1) given by their 32 bits, such that every interval
+/// `[k / 2^m, (k + 1) / 2^m)` is mapped to another one of them, with a different
+/// permutation for every `seed`: Owen's nested uniform scrambling.
+///
+/// This is the hash-based variant of Laine and Karras with the constants by Burley
+/// ("Practical Hash-based Owen Scrambling"), which works on the reversed bits:
+/// every bit only depends on the less significant ones there.
+fn owen_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x ^= x.wrapping_mul(0x3d20adea);
+    x = x.wrapping_add(seed);
+    x = x.wrapping_mul((seed >> 16) | 1);
+    x ^= x.wrapping_mul(0x05526c56);
+    x ^= x.wrapping_mul(0x53a22864);
+    x.reverse_bits()
+}
+
+/// A sampler using the Sobol sequence.
+///
+/// Every pixel scrambles the sequence with Owen scrambling, with a random seed per dimension,
+/// which keeps its good distribution, so neighboring pixels do not use the same samples.
+/// Unlike scrambling the bits with a fixed mask, this also makes the samples of a pixel
+/// uncorrelated enough that the error of the average falls faster.
+/// Dimensions beyond the first 8 are independent random numbers.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// // The mean error of estimating the integral of x * y over the unit square (1/4)
+/// // in 64 pixels with 256 samples each.
+/// let error = |kind: SamplerKind| {
+///     let errors = (0..64).map(|pixel| {
+///         let mut sampler = kind.create(0, pixel, 256);
+///         let mut sum = 0.0;
+///         for index in 0..256 {
+///             sampler.start_sample(index);
+///             let u = sampler.next_2d();
+///             sum += u.x() * u.y();
+///         }
+///         (sum / 256.0 - 0.25).abs()
+///     });
+///     errors.sum::<Float>() / 64.0
+/// };
+/// assert!(error(SamplerKind::Sobol) < 0.1 * error(SamplerKind::Independent));
+/// ```
+pub struct SobolSampler {
+    seeds: DimensionValues,
+    rng: Pcg32,
+    index: usize,
+    dimension: usize,
+}
+
+impl SobolSampler {
+    fn new(mut rng: Pcg32) -> SobolSampler {
+        let seeds = Pcg32::new(rng.next_u32() as u64, rng.next_u32() as u64);
+        SobolSampler {
+            seeds: DimensionValues {
+                rng: seeds,
+                values: Vec::new(),
+            },
+            rng: rng,
+            index: 0,
+            dimension: 0,
+        }
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, index: usize) {
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn next_1d(&mut self) -> Float {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        if dimension >= SOBOL_DIRECTIONS.len() {
+            return self.rng.next_float();
+        }
+        let mut x = 0;
+        let mut i = self.index as u32;
+        let mut k = 0;
+        while i > 0 {
+            if i & 1 == 1 {
+                x ^= SOBOL_DIRECTIONS[dimension][k];
+            }
+            i >>= 1;
+            k += 1;
+        }
+        to_float(owen_scramble(x, self.seeds.get(dimension)))
+    }
+}
+
+/// Options for adding samples to noisy pixels only, see `RenderOptions::adaptive_sampling`.
+///
+/// Every pixel first gets `samples_per_pixel` samples (at least 2). Then samples are added
+/// one by one until the 95% confidence interval of the mean luminance of the pixel is at most
+/// `threshold` wide on each side, or the pixel has `max_samples` samples.
+/// The Halton and Sobol samplers suit this best, since their first samples are always evenly
+/// distributed, however many follow.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AdaptiveSampling {
+    /// The maximum number of samples per pixel.
+    pub max_samples: usize,
+    /// The acceptable error of the luminance of a pixel, e.g. 0.01 for about 2.5 steps of
+    /// an 8-bit channel (before the exposure).
+    pub threshold: Float,
+}
+
+/// Keeps track of the mean and variance of a sequence of values (by Welford's algorithm),
+/// e.g. the luminances of the samples of a pixel.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let mut stats = SampleStatistics::new();
+/// assert_eq!(stats.error(), Float::INFINITY);
+/// for &x in &[1.0, 2.0, 3.0, 4.0] {
+///     stats.add(x);
+/// }
+/// assert_eq!(stats.mean(), 2.5);
+/// assert!((stats.variance() - 5.0 / 3.0).abs() < 1e-9);
+/// assert!((stats.error() - 1.96 * (5.0 / 12.0 as Float).sqrt()).abs() < 1e-9);
+/// ```
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SampleStatistics {
+    count: usize,
+    mean: Float,
+    /// The sum of the squared differences from the mean.
+    squares: Float,
+}
+
+impl SampleStatistics {
+    /// Creates the statistics of no values.
+    pub fn new() -> SampleStatistics {
+        SampleStatistics {
+            count: 0,
+            mean: 0.,
+            squares: 0.,
+        }
+    }
+
+    /// Adds a value.
+    pub fn add(&mut self, x: Float) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as Float;
+        self.squares += delta * (x - self.mean);
+    }
+
+    /// Returns the number of values.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the mean of the values, or 0 if there are none.
+    pub fn mean(&self) -> Float {
+        self.mean
+    }
+
+    /// Returns the (unbiased) sample variance of the values,
+    /// or infinity if there are less than two.
+    pub fn variance(&self) -> Float {
+        if self.count < 2 {
+            return Float::INFINITY;
+        }
+        self.squares / (self.count - 1) as Float
+    }
+
+    /// Returns the half width of the 95% confidence interval of the mean,
+    /// or infinity if there are less than two values.
+    pub fn error(&self) -> Float {
+        1.96 * (self.variance() / self.count as Float).sqrt()
+    }
+}
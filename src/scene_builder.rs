@@ -0,0 +1,206 @@
+use error::{Error, Result};
+use raytrace::*;
+
+/// Assembles a `Scene` step by step, see `Scene::builder`.
+///
+/// Unless set otherwise, the camera renders a 640x360 image with a horizontal field of view
+/// of 90 degrees, looking from (0, 0, 10) at the origin with y pointing upwards.
+/// The ambient light is white and the options are `RenderOptions::default()`.
+pub struct SceneBuilder {
+    camera: Camera,
+    objects: Vec<Box<Surface + Sync>>,
+    lights: Vec<Box<Light + Sync>>,
+    ambient_color: Color,
+    fog: Option<Fog>,
+    medium: Option<Medium>,
+    volumes: Vec<Box<Volume + Sync>>,
+    sky: Option<Sky>,
+    options: RenderOptions,
+    error: Option<Error>,
+}
+
+impl Scene {
+    /// Returns a builder for a scene without objects and lights.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let scene = Scene::builder()
+    ///     .resolution(64, 36)
+    ///     .look_at(Vec3::new(0.0, 2.0, 10.0), Vec3::zero())
+    ///     .add(Sphere {
+    ///         center: Vec3::zero(),
+    ///         radius: 2.0,
+    ///         material: color_material(white()),
+    ///     })
+    ///     .light(PointLight {
+    ///         pos: Vec3::new(0.0, 10.0, 10.0),
+    ///         col: white(),
+    ///     })
+    ///     .build()
+    ///     .unwrap();
+    /// assert_eq!(scene.camera.aspect_ratio, 64.0 / 36.0);
+    /// assert_eq!((scene.objects.len(), scene.lights.len()), (1, 1));
+    /// assert!(scene.validate().is_ok());
+    ///
+    /// // Errors while loading meshes are reported by `build`:
+    /// let result = Scene::builder().add_mesh("no/such/file.obj", color_material(white())).build();
+    /// assert!(result.is_err());
+    /// ```
+    pub fn builder() -> SceneBuilder {
+        let (width, height) = (640, 360);
+        SceneBuilder {
+            camera: Camera {
+                pos: Vec3::new(0.0, 0.0, 10.0),
+                look_at: Vec3::zero(),
+                up: Vec3::e2().to(),
+                horizontal_fov: (90.0 as Float).to_radians(),
+                aspect_ratio: width as Float / height as Float,
+                width: width,
+                height: height,
+            },
+            objects: Vec::new(),
+            lights: Vec::new(),
+            ambient_color: white(),
+            fog: None,
+            medium: None,
+            volumes: Vec::new(),
+            sky: None,
+            options: RenderOptions::default(),
+            error: None,
+        }
+    }
+}
+
+impl SceneBuilder {
+    /// Replaces the camera.
+    pub fn camera(mut self, camera: Camera) -> SceneBuilder {
+        self.camera = camera;
+        self
+    }
+
+    /// Sets the size of the image in pixels and the aspect ratio of the camera to match it.
+    pub fn resolution(mut self, width: usize, height: usize) -> SceneBuilder {
+        self.camera.width = width;
+        self.camera.height = height;
+        self.camera.aspect_ratio = width as Float / height as Float;
+        self
+    }
+
+    /// Moves the camera to `pos`, looking at `target`.
+    pub fn look_at(mut self, pos: Vec3, target: Vec3) -> SceneBuilder {
+        self.camera.pos = pos;
+        self.camera.look_at = target;
+        self
+    }
+
+    /// Sets the horizontal field of view of the camera in degrees.
+    pub fn fov_degrees(mut self, horizontal_fov: Float) -> SceneBuilder {
+        self.camera.horizontal_fov = horizontal_fov.to_radians();
+        self
+    }
+
+    /// Adds an object.
+    pub fn add<S: Surface + Sync + 'static>(mut self, object: S) -> SceneBuilder {
+        self.objects.push(Box::new(object));
+        self
+    }
+
+    /// Adds an object that is already boxed, e.g. one of a collection of different types.
+    pub fn add_boxed(mut self, object: Box<Surface + Sync>) -> SceneBuilder {
+        self.objects.push(object);
+        self
+    }
+
+    /// Adds the mesh from the OBJ file `path`, see `Mesh::from_obj_file`.
+    ///
+    /// If the file cannot be read, `build` returns the error.
+    #[cfg(feature = "fs")]
+    pub fn add_mesh(mut self, path: &str, material: Material) -> SceneBuilder {
+        match Mesh::from_obj_file(path, material) {
+            Ok(mesh) => self.objects.push(Box::new(mesh)),
+            Err(e) => {
+                if self.error.is_none() {
+                    self.error = Some(e.into());
+                }
+            }
+        }
+        self
+    }
+
+    /// Adds a light.
+    pub fn light<L: Light + Sync + 'static>(mut self, light: L) -> SceneBuilder {
+        self.lights.push(Box::new(light));
+        self
+    }
+
+    /// Sets the color of the ambient light.
+    pub fn ambient(mut self, color: Color) -> SceneBuilder {
+        self.ambient_color = color;
+        self
+    }
+
+    /// Fills the scene with fog.
+    pub fn fog(mut self, fog: Fog) -> SceneBuilder {
+        self.fog = Some(fog);
+        self
+    }
+
+    /// Fills the scene or a part of it with a medium that scatters light.
+    pub fn medium(mut self, medium: Medium) -> SceneBuilder {
+        self.medium = Some(medium);
+        self
+    }
+
+    /// Adds a region filled with a medium of varying density, e.g. a `VoxelVolume`.
+    pub fn volume<V: Volume + Sync + 'static>(mut self, volume: V) -> SceneBuilder {
+        self.volumes.push(Box::new(volume));
+        self
+    }
+
+    /// Shows the sky in the background and adds the sun as a directional light,
+    /// see `Sky::sun_light`.
+    pub fn sky(mut self, sky: Sky) -> SceneBuilder {
+        self.lights.push(Box::new(sky.sun_light(1.)));
+        self.sky = Some(sky);
+        self
+    }
+
+    /// Sets up outdoor lighting with the sky and the sun at the given position,
+    /// see `Sky::daylight`. `turbidity` is the haziness of the air, from about 2 to 10.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let scene = Scene::builder()
+    ///     .sun_and_sky(SunPosition::Angles { azimuth: 3.0, elevation: 0.5 }, 3.0)
+    ///     .build()
+    ///     .unwrap();
+    /// assert!(scene.sky.is_some() && scene.lights.len() == 1);
+    /// ```
+    pub fn sun_and_sky(self, sun: SunPosition, turbidity: Float) -> SceneBuilder {
+        self.sky(Sky::daylight(sun, turbidity))
+    }
+
+    /// Replaces the render options.
+    pub fn options(mut self, options: RenderOptions) -> SceneBuilder {
+        self.options = options;
+        self
+    }
+
+    /// Returns the scene, or the first error that occurred while adding objects.
+    pub fn build(self) -> Result<Scene> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        Ok(Scene {
+            camera: self.camera,
+            objects: self.objects,
+            lights: self.lights,
+            ambient_color: self.ambient_color,
+            fog: self.fog,
+            medium: self.medium,
+            volumes: self.volumes,
+            sky: self.sky,
+            options: self.options,
+        })
+    }
+}
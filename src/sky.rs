@@ -0,0 +1,337 @@
+use basic::*;
+use light::DirectionalLight;
+
+/// The analytic daylight sky model by Preetham, Shirley and Smits,
+/// used as the background of a scene, see `Scene::sky`.
+///
+/// The color of the sky depends on the position of the sun and the turbidity of the air,
+/// e.g. it is blue at noon and turns orange around the sun when it sets.
+/// The y-axis points up, and directions below the horizon get the color of the horizon.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let sky = Sky {
+///     sun_dir: Vec3::new(0.0, 1.0, 1.0).normalize(),
+///     turbidity: 3.0,
+///     brightness: 1.0,
+/// };
+/// let zenith = sky.radiance(Vec3::e2());
+/// assert!((zenith.luminance() - 1.0).abs() < 0.01);
+/// // The clear sky is blue away from the sun and brighter towards it.
+/// let away = sky.radiance(Vec3::new(0.0, 1.0, -1.0).normalize());
+/// assert!(away.blue() > away.red());
+/// assert!(sky.radiance(Vec3::new(0.0, 1.0, 1.5).normalize()).luminance() > away.luminance());
+///
+/// // The sunlight is reddened when it passes through more air.
+/// let sunset = Sky { sun_dir: Vec3::new(0.0, 0.05, 1.0).normalize(), ..sky };
+/// let (noon, evening) = (sky.sun_color(), sunset.sun_color());
+/// assert!(evening.red() / evening.blue() > noon.red() / noon.blue());
+/// let night = Sky { sun_dir: Vec3::new(0.0, -1.0, 1.0).normalize(), ..sky };
+/// assert_eq!(night.sun_color().max_channel(), 0.0);
+/// ```
+#[derive(Debug, Copy, Clone)]
+pub struct Sky {
+    /// The direction towards the sun.
+    pub sun_dir: UnitVec3,
+    /// The haziness of the air, from about 2 (clear) to 10 (hazy).
+    pub turbidity: Float,
+    /// The luminance of the sky at the zenith. The rest of the sky is scaled accordingly.
+    /// Near the sun and the horizon, the sky is several times brighter than at the zenith,
+    /// so values around 0.2 keep it from being overexposed.
+    pub brightness: Float,
+}
+
+/// The position of the sun in the sky, see `Sky::daylight`.
+///
+/// The y-axis points up, north is in the direction of -z and east in the direction of +x.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let east = SunPosition::Angles { azimuth: PI / 2.0, elevation: 0.0 }.direction();
+/// assert!(appr(east * Vec3::e1(), 1.0));
+///
+/// // At the equinox, the sun passes through the zenith at noon on the equator,
+/// let equator = SunPosition::Time { latitude: 0.0, day_of_year: 80.0, solar_time: 12.0 };
+/// assert!(equator.direction()[1] > 0.99);
+/// // and it rises in the east at 6 o'clock.
+/// let morning = SunPosition::Time { latitude: 0.0, day_of_year: 80.0, solar_time: 6.0 };
+/// assert!(morning.direction()[1].abs() < 0.01 && morning.direction()[0] > 0.99);
+/// // In summer in the north, the noon sun is in the south.
+/// let summer = SunPosition::Time { latitude: 0.9, day_of_year: 172.0, solar_time: 12.0 };
+/// let (azimuth, elevation) = summer.angles();
+/// assert!(appr(azimuth, PI) && appr(elevation, PI / 2.0 - 0.9 + (23.44 as Float).to_radians()));
+/// ```
+#[derive(Debug, Copy, Clone)]
+pub enum SunPosition {
+    /// The position given by angles in radians.
+    Angles {
+        /// The angle from north, clockwise as seen from above, i.e. east is pi/2.
+        azimuth: Float,
+        /// The angle above the horizon, negative if the sun has set.
+        elevation: Float,
+    },
+    /// The position at a place and time, approximated from the tilt of the earth.
+    Time {
+        /// The latitude of the place in radians, positive in the northern hemisphere.
+        latitude: Float,
+        /// The day of the year, from 1 (January 1) to 365.
+        day_of_year: Float,
+        /// The local solar time in hours, such that the sun is highest at 12.
+        solar_time: Float,
+    },
+}
+
+impl SunPosition {
+    /// Returns the azimuth and the elevation of the sun, see `SunPosition::Angles`.
+    pub fn angles(&self) -> (Float, Float) {
+        match *self {
+            SunPosition::Angles { azimuth, elevation } => (azimuth, elevation),
+            SunPosition::Time { latitude, day_of_year, solar_time } => {
+                let declination = -(23.44 as Float).to_radians() *
+                                  (2. * PI / 365. * (day_of_year + 10.)).cos();
+                let hour_angle = (15. * (solar_time - 12.)).to_radians();
+                let sin_elevation = latitude.sin() * declination.sin() +
+                                    latitude.cos() * declination.cos() * hour_angle.cos();
+                let elevation = sin_elevation.max(-1.).min(1.).asin();
+                let cos_azimuth = (declination.sin() - sin_elevation * latitude.sin()) /
+                                  (elevation.cos() * latitude.cos());
+                let azimuth = cos_azimuth.max(-1.).min(1.).acos();
+                // In the afternoon, the sun is in the west.
+                if hour_angle > 0. {
+                    (2. * PI - azimuth, elevation)
+                } else {
+                    (azimuth, elevation)
+                }
+            }
+        }
+    }
+
+    /// Returns the direction towards the sun.
+    pub fn direction(&self) -> UnitVec3 {
+        let (azimuth, elevation) = self.angles();
+        Vec3::new(azimuth.sin() * elevation.cos(),
+                  elevation.sin(),
+                  -azimuth.cos() * elevation.cos())
+            .normalize()
+    }
+}
+
+/// The coefficients A to E of the Perez distribution for the luminance Y and the
+/// chromaticities x and y, each linear in the turbidity T: `[a * T + b; 5]`.
+const PEREZ_Y: [(Float, Float); 5] = [(0.1787, -1.4630),
+                                      (-0.3554, 0.4275),
+                                      (-0.0227, 5.3251),
+                                      (0.1206, -2.5771),
+                                      (-0.0670, 0.3703)];
+const PEREZ_X: [(Float, Float); 5] = [(-0.0193, -0.2592),
+                                      (-0.0665, 0.0008),
+                                      (-0.0004, 0.2125),
+                                      (-0.0641, -0.8989),
+                                      (-0.0033, 0.0452)];
+const PEREZ_Y_CHROMA: [(Float, Float); 5] = [(-0.0167, -0.2608),
+                                             (-0.0950, 0.0092),
+                                             (-0.0079, 0.2102),
+                                             (-0.0441, -1.6537),
+                                             (-0.0109, 0.0529)];
+
+/// The wavelengths in micrometers used for the red, green and blue channel of the sunlight.
+const WAVELENGTHS: [Float; 3] = [0.680, 0.550, 0.440];
+
+impl Sky {
+    /// Creates the sky for the sun at the given position,
+    /// which is brighter when the sun is higher and darker when the air is hazy.
+    ///
+    /// The brightness follows the luminance of the sky at the zenith in the model,
+    /// scaled such that a clear sky is around 0.2 when the sun is 60 degrees high,
+    /// and fades out during twilight when the sun is up to 6 degrees below the horizon.
+    /// Together with `sun_light(1.0)`, this gives the sky and the sun a consistent color
+    /// and intensity, see `SceneBuilder::sun_and_sky`.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let sky = |elevation: Float| {
+    ///     Sky::daylight(SunPosition::Angles { azimuth: 0.0, elevation: elevation }, 3.0)
+    /// };
+    /// assert!(sky(1.0).brightness > sky(0.5).brightness);
+    /// assert!(sky(0.5).brightness > sky(0.0).brightness);
+    /// assert!(sky(0.0).brightness > sky(-0.05).brightness);
+    /// assert_eq!(sky(-0.2).brightness, 0.0);
+    /// ```
+    pub fn daylight(sun: SunPosition, turbidity: Float) -> Sky {
+        let sun_dir = sun.direction();
+        let theta_sun = sun_dir[1].max(0.).min(1.).acos();
+        // The luminance at the zenith in kcd/m^2.
+        let chi = (4. / 9. - turbidity / 120.) * (PI - 2. * theta_sun);
+        let zenith = (4.0453 * turbidity - 4.9710) * chi.tan() - 0.2155 * turbidity + 2.4192;
+        let twilight = (1. + sun.angles().1 / (6. as Float).to_radians()).max(0.).min(1.);
+        Sky {
+            sun_dir: sun_dir,
+            turbidity: turbidity,
+            brightness: 0.02 * zenith.max(0.) * twilight,
+        }
+    }
+
+    /// Returns the color of the sky in the direction `dir`.
+    /// To compute it for many directions, prepare the sky with `distribution` once.
+    pub fn radiance(&self, dir: UnitVec3) -> Color {
+        self.distribution().radiance(dir)
+    }
+
+    /// Prepares the Perez distributions of the sky, which only depend on the sun and the
+    /// turbidity, to compute the radiance in many directions.
+    #[doc(hidden)]
+    pub fn distribution(&self) -> SkyDistribution {
+        let t = self.turbidity;
+        let theta_sun = self.sun_zenith_angle();
+        let (zenith_x, zenith_y) = zenith_chromaticity(t, theta_sun);
+        let mut coefficients = [[0.; 5]; 3];
+        let mut zenith = [self.brightness, zenith_x, zenith_y];
+        for (i, perez_coefficients) in [PEREZ_Y, PEREZ_X, PEREZ_Y_CHROMA].iter().enumerate() {
+            for (c, &(a, b)) in coefficients[i].iter_mut().zip(perez_coefficients.iter()) {
+                *c = a * t + b;
+            }
+            // The distribution is relative to the zenith.
+            zenith[i] /= perez(&coefficients[i], 1., theta_sun);
+        }
+        SkyDistribution {
+            sun_dir: self.sun_dir,
+            coefficients: coefficients,
+            zenith: zenith,
+        }
+    }
+
+    /// Returns the fraction of the sunlight that reaches the ground after passing through
+    /// the atmosphere, which is scattered by air molecules (Rayleigh) and by haze (aerosols).
+    /// It is black if the sun is below the horizon.
+    pub fn sun_color(&self) -> Color {
+        if self.sun_dir[1] <= 0. {
+            return black();
+        }
+        let theta_degrees = self.sun_zenith_angle().to_degrees();
+        // The relative optical air mass (Kasten and Young).
+        let air_mass = 1. / (self.sun_dir[1] + 0.15 * (93.885 - theta_degrees).powf(-1.253));
+        let beta = 0.04608 * self.turbidity - 0.04586;
+        let channels: Vec<Float> = WAVELENGTHS.iter()
+            .map(|&lambda| {
+                let rayleigh = 0.008735 * lambda.powf(-4.08);
+                let aerosols = beta.max(0.) * lambda.powf(-1.3);
+                (-air_mass * (rayleigh + aerosols)).exp()
+            })
+            .collect();
+        Color::new(channels[0], channels[1], channels[2])
+    }
+
+    /// Returns a directional light for the sun, whose color is `sun_color` scaled by
+    /// the intensity of the light above the atmosphere.
+    pub fn sun_light(&self, intensity: Float) -> DirectionalLight {
+        DirectionalLight {
+            dir: -self.sun_dir,
+            col: intensity * self.sun_color(),
+        }
+    }
+
+    /// Returns the light arriving at a surface facing the sun from the sun (`sun_light(1.0)`)
+    /// and the sky, i.e. the color of a white object lit by the sun,
+    /// which is the illuminant that the white balance adapts to.
+    ///
+    /// The sky is integrated numerically over a fixed set of directions.
+    pub fn illuminant(&self) -> Color {
+        let (rings, sectors) = (4, 8);
+        let normal = self.sun_dir;
+        let tangent = normal.cross(if normal[0].abs() < 0.9 { Vec3::e1() } else { Vec3::e2() })
+            .normalize();
+        let bitangent = normal.cross(tangent.to());
+        let distribution = self.distribution();
+        let mut sky = black();
+        for i in 0..rings {
+            for j in 0..sectors {
+                // Cosine-weighted directions, so the average radiance times pi is the irradiance.
+                let r = ((i as Float + 0.5) / rings as Float).sqrt();
+                let phi = 2. * PI * (j as Float + 0.5) / sectors as Float;
+                let dir = (r * phi.cos()) * tangent.to() + (r * phi.sin()) * bitangent +
+                          (1. - r * r).sqrt() * normal.to();
+                sky = sky + distribution.radiance(dir.normalize());
+            }
+        }
+        self.sun_color() + (PI / (rings * sectors) as Float) * sky
+    }
+
+    /// Returns the correlated color temperature of the illuminant in Kelvin,
+    /// e.g. to match the white balance of the camera to the sky, see `WhiteBalance::Sky`.
+    /// Returns `None` at night, when there is no light.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let sky = |elevation: Float| {
+    ///     Sky::daylight(SunPosition::Angles { azimuth: 0.0, elevation: elevation }, 3.0)
+    /// };
+    /// let noon = sky(1.2).correlated_color_temperature().unwrap();
+    /// let sunset = sky(0.05).correlated_color_temperature().unwrap();
+    /// assert!(noon > 6000.0 && noon < 9000.0);
+    /// assert!(sunset < noon - 2000.0);
+    /// assert!(sky(-0.2).correlated_color_temperature().is_none());
+    /// ```
+    pub fn correlated_color_temperature(&self) -> Option<Float> {
+        self.illuminant().correlated_color_temperature()
+    }
+
+    /// Returns the angle between the sun and the zenith, at most pi/2.
+    fn sun_zenith_angle(&self) -> Float {
+        self.sun_dir[1].max(0.).min(1.).acos()
+    }
+}
+
+/// The distributions of the luminance and the chromaticities of a `Sky` over the directions,
+/// see `Sky::distribution`.
+#[doc(hidden)]
+pub struct SkyDistribution {
+    sun_dir: UnitVec3,
+    /// The coefficients of the Perez distribution for the luminance Y and the
+    /// chromaticities x and y at the turbidity of the sky.
+    coefficients: [[Float; 5]; 3],
+    /// The values of Y, x and y at the zenith, divided by their distribution there.
+    zenith: [Float; 3],
+}
+
+impl SkyDistribution {
+    /// Returns the color of the sky in the direction `dir`, see `Sky::radiance`.
+    pub fn radiance(&self, dir: UnitVec3) -> Color {
+        // Treat directions below the horizon like the horizon itself.
+        let cos_theta = dir[1].max(0.);
+        let gamma = (dir * self.sun_dir).max(-1.).min(1.).acos();
+        let value = |i: usize| self.zenith[i] * perez(&self.coefficients[i], cos_theta, gamma);
+        xyy_to_rgb(value(1), value(2), value(0))
+    }
+}
+
+/// The Perez distribution `(1 + A e^(B / cos theta)) (1 + C e^(D gamma) + E cos^2 gamma)`
+/// for the angle theta to the zenith and the angle gamma to the sun.
+fn perez(c: &[Float; 5], cos_theta: Float, gamma: Float) -> Float {
+    let cos_gamma = gamma.cos();
+    (1. + c[0] * (c[1] / cos_theta.max(EPS)).exp()) *
+    (1. + c[2] * (c[3] * gamma).exp() + c[4] * cos_gamma * cos_gamma)
+}
+
+/// Returns the chromaticities x and y of the sky at the zenith
+/// for the turbidity and the angle of the sun to the zenith.
+fn zenith_chromaticity(t: Float, theta_sun: Float) -> (Float, Float) {
+    let (s, s2, s3) = (theta_sun, theta_sun * theta_sun, theta_sun * theta_sun * theta_sun);
+    let x = t * t * (0.00166 * s3 - 0.00375 * s2 + 0.00209 * s) +
+            t * (-0.02903 * s3 + 0.06377 * s2 - 0.03202 * s + 0.00394) +
+            (0.11693 * s3 - 0.21196 * s2 + 0.06052 * s + 0.25886);
+    let y = t * t * (0.00275 * s3 - 0.00610 * s2 + 0.00317 * s) +
+            t * (-0.04214 * s3 + 0.08970 * s2 - 0.04153 * s + 0.00516) +
+            (0.15346 * s3 - 0.26756 * s2 + 0.06670 * s + 0.26688);
+    (x, y)
+}
+
+/// Converts the chromaticities x and y and the luminance to linear sRGB.
+/// Colors outside of the sRGB gamut are clipped.
+fn xyy_to_rgb(x: Float, y: Float, luminance: Float) -> Color {
+    let big_x = x / y * luminance;
+    let big_z = (1. - x - y) / y * luminance;
+    let r = 3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z;
+    let g = -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z;
+    let b = 0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z;
+    Color::new_unchecked(r.max(0.), g.max(0.), b.max(0.))
+}
@@ -0,0 +1,165 @@
+use basic::*;
+use img_output::*;
+use std::cell::Cell;
+use std::fmt;
+use std::time::Duration;
+
+/// What rendering a scene took, see `Scene::render_with_stats`.
+///
+/// The counts are summed over all threads.
+#[derive(Debug, Clone, Default)]
+pub struct RenderStats {
+    /// The number of rays from the camera.
+    pub primary_rays: u64,
+    /// The number of reflected and refracted rays.
+    pub secondary_rays: u64,
+    /// The number of rays towards light sources.
+    pub shadow_rays: u64,
+    /// The number of nodes of bounding volume hierarchies that rays were tested against.
+    /// Only counted with the feature `stats`.
+    pub bvh_node_visits: u64,
+    /// The number of ray-triangle intersection tests. Only counted with the feature `stats`.
+    pub triangle_tests: u64,
+    /// The time spent in each phase of rendering, in order.
+    pub phases: Vec<(&'static str, Duration)>,
+    /// The BVH node visits and triangle tests of every pixel, line by line, for the image
+    /// including the overscan, see `cost_heatmap`.
+    pub pixel_costs: Vec<u64>,
+    /// The width of the image including the overscan.
+    pub width: usize,
+}
+
+impl RenderStats {
+    /// Creates empty statistics.
+    pub fn new() -> RenderStats {
+        RenderStats::default()
+    }
+
+    /// Returns the number of rays of all kinds.
+    pub fn rays(&self) -> u64 {
+        self.primary_rays + self.secondary_rays + self.shadow_rays
+    }
+
+    /// Returns the time of all phases together.
+    pub fn total_time(&self) -> Duration {
+        self.phases.iter().fold(Duration::new(0, 0), |acc, &(_, time)| acc + time)
+    }
+
+    /// Returns the BVH node visits and triangle tests of the pixel at (x, y).
+    pub fn pixel_cost(&self, x: usize, y: usize) -> u64 {
+        self.pixel_costs[y * self.width + x]
+    }
+
+    /// Returns the highest cost of a pixel.
+    pub fn max_pixel_cost(&self) -> u64 {
+        self.pixel_costs.iter().cloned().max().unwrap_or(0)
+    }
+
+    /// Creates a false-color image of the costs of the pixels to spot expensive geometry:
+    /// from blue for no cost over cyan, green, and yellow to red for `max_cost` and above.
+    /// Scale it to the image with `max_pixel_cost`, or use the same value for several images
+    /// to compare them.
+    ///
+    /// Only objects with hierarchies (e.g. meshes) and triangles add to the costs,
+    /// and only with the feature `stats`. Without it, the image is blue.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let triangle = "v -1 -1 0\nv 1 -1 0\nv 0 1 0\nf 1 2 3\n";
+    /// let scene = Scene::builder()
+    ///     .resolution(16, 16)
+    ///     .add(Mesh::from_obj_reader(triangle.as_bytes(), color_material(white())).unwrap())
+    ///     .light(PointLight { pos: Vec3::new(0.0, 0.0, 10.0), col: white() })
+    ///     .build()
+    ///     .unwrap();
+    /// let (_, stats) = scene.render_with_stats();
+    /// let total: u64 = stats.pixel_costs.iter().sum();
+    /// assert_eq!(total, stats.bvh_node_visits + stats.triangle_tests);
+    /// let heatmap = stats.cost_heatmap(stats.max_pixel_cost());
+    /// assert!(heatmap.get(0, 0).opaque().blue() > 0.0);
+    /// if cfg!(feature = "stats") {
+    ///     // Pixels showing the triangle also trace a shadow ray, so they are the most expensive.
+    ///     let hot = heatmap.get(8, 8).opaque();
+    ///     assert_eq!((hot.red(), hot.green(), hot.blue()), (1.0, 0.0, 0.0));
+    ///     assert!(stats.pixel_cost(0, 0) < stats.pixel_cost(8, 8));
+    /// }
+    /// ```
+    pub fn cost_heatmap(&self, max_cost: u64) -> Image {
+        let height = if self.width > 0 { self.pixel_costs.len() / self.width } else { 0 };
+        let mut img = Image::new(self.width, height);
+        for (x, y, col) in img.iter_mut() {
+            let cost = self.pixel_cost(x, y) as Float / max_cost.max(1) as Float;
+            *col = false_color(cost).with_alpha();
+        }
+        img
+    }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f,
+                      "{} rays ({} primary, {} secondary, {} shadow)",
+                      self.rays(),
+                      self.primary_rays,
+                      self.secondary_rays,
+                      self.shadow_rays));
+        try!(writeln!(f,
+                      "{} BVH node visits, {} triangle tests",
+                      self.bvh_node_visits,
+                      self.triangle_tests));
+        for &(phase, time) in self.phases.iter() {
+            try!(writeln!(f,
+                          "{}: {}.{:03} seconds",
+                          phase,
+                          time.as_secs(),
+                          time.subsec_nanos() / 1_000_000));
+        }
+        let total = self.total_time();
+        write!(f, "total: {}.{:03} seconds", total.as_secs(), total.subsec_nanos() / 1_000_000)
+    }
+}
+
+thread_local! {
+    /// The BVH node visits and triangle tests on this thread, if they are counted.
+    static TRAVERSAL_COUNTS: Cell<Option<(u64, u64)>> = Cell::new(None);
+}
+
+/// Starts counting BVH node visits and triangle tests on the current thread.
+#[doc(hidden)]
+pub fn start_traversal_counts() {
+    TRAVERSAL_COUNTS.with(|counts| counts.set(Some((0, 0))));
+}
+
+/// Stops counting on the current thread and returns the BVH node visits and triangle tests
+/// since `start_traversal_counts`.
+#[doc(hidden)]
+pub fn finish_traversal_counts() -> (u64, u64) {
+    TRAVERSAL_COUNTS.with(|counts| counts.replace(None)).unwrap_or((0, 0))
+}
+
+/// Returns the BVH node visits and triangle tests on the current thread so far.
+#[doc(hidden)]
+pub fn traversal_counts() -> (u64, u64) {
+    TRAVERSAL_COUNTS.with(|counts| counts.get()).unwrap_or((0, 0))
+}
+
+/// Counts a visited BVH node if counting was started on the current thread.
+/// Custom surfaces with their own hierarchies may call this, too.
+/// Does nothing without the feature `stats`.
+#[inline(always)]
+pub fn count_node_visit() {
+    #[cfg(feature = "stats")]
+    TRAVERSAL_COUNTS.with(|counts| if let Some((nodes, triangles)) = counts.get() {
+        counts.set(Some((nodes + 1, triangles)));
+    });
+}
+
+/// Counts a ray-triangle test if counting was started on the current thread.
+/// Does nothing without the feature `stats`.
+#[inline(always)]
+pub fn count_triangle_test() {
+    #[cfg(feature = "stats")]
+    TRAVERSAL_COUNTS.with(|counts| if let Some((nodes, triangles)) = counts.get() {
+        counts.set(Some((nodes, triangles + 1)));
+    });
+}
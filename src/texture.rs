@@ -0,0 +1,319 @@
+use color::*;
+#[cfg(all(feature = "fs", feature = "image"))]
+use error::Result;
+use img_output::*;
+use lin_alg::*;
+use std::fmt;
+#[cfg(all(feature = "fs", feature = "image"))]
+use std::path::Path;
+
+/// Trait for textures, i.e. colors that vary over the surface of an object.
+///
+/// Textures are evaluated at the texture coordinates `uv` of a point on a surface.
+/// The point itself is passed as well, so procedural textures can also be solid textures,
+/// e.g. wood or marble that look as if carved out of a block.
+pub trait Texture {
+    /// Returns the color of the texture at the given texture coordinates and point.
+    fn sample(&self, uv: Vec2, point: Vec3) -> Color;
+
+    /// Returns the average color of the texture over the area covered by a pixel,
+    /// which avoids aliasing, e.g. on a checkered plane stretching to the horizon.
+    ///
+    /// The default implementation ignores the footprint and samples the center.
+    fn sample_filtered(&self, uv: Vec2, point: Vec3, _footprint: &TextureFootprint) -> Color {
+        self.sample(uv, point)
+    }
+}
+
+/// The area of a texture covered by a pixel, see `Texture::sample_filtered`.
+///
+/// It is the parallelogram around the texture coordinates of the pixel center
+/// spanned by `dx` and `dy`, the changes of the texture coordinates to the neighboring pixels.
+/// For surfaces viewed at grazing angles, it is long and thin.
+#[derive(Debug, Copy, Clone)]
+pub struct TextureFootprint {
+    /// The change of the texture coordinates to the pixel on the right.
+    pub dx: Vec2,
+    /// The change of the texture coordinates to the pixel below.
+    pub dy: Vec2,
+    /// The maximum ratio of the length of the footprint to its width,
+    /// see `RenderOptions::max_anisotropy`.
+    pub max_anisotropy: usize,
+}
+
+impl TextureFootprint {
+    /// Creates a footprint from the texture coordinates at the pixel center and
+    /// at the centers of its neighbors, `None` for neighbors showing another object.
+    ///
+    /// On each axis, the smaller change to the two neighbors is used,
+    /// so texture seams, e.g. from 1 back to 0 on a sphere, do not blur the pixels next to them.
+    /// Returns `None` if both neighbors on an axis are missing.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let (uv, seam) = (Vec2::new(0.99, 0.5), Vec2::new(0.01, 0.5));
+    /// let footprint = TextureFootprint::from_neighbors(uv,
+    ///                                                  (Some(seam), Some(Vec2::new(0.98, 0.5))),
+    ///                                                  (Some(Vec2::new(0.99, 0.4)), None),
+    ///                                                  16)
+    ///     .unwrap();
+    /// assert!(appr(footprint.dx.x(), 0.01) && appr(footprint.dy.y(), -0.1));
+    /// ```
+    pub fn from_neighbors(uv: Vec2,
+                          horizontal: (Option<Vec2>, Option<Vec2>),
+                          vertical: (Option<Vec2>, Option<Vec2>),
+                          max_anisotropy: usize)
+                          -> Option<TextureFootprint> {
+        let change = |(after, before): (Option<Vec2>, Option<Vec2>)| {
+            match (after.map(|a| a - uv), before.map(|b| uv - b)) {
+                (Some(a), Some(b)) => Some(if a.norm() <= b.norm() { a } else { b }),
+                (a, b) => a.or(b),
+            }
+        };
+        match (change(horizontal), change(vertical)) {
+            (Some(dx), Some(dy)) => {
+                Some(TextureFootprint {
+                    dx: dx,
+                    dy: dy,
+                    max_anisotropy: max_anisotropy,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<'a> fmt::Debug for Texture + Sync + 'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Texture")
+    }
+}
+
+/// A texture that has the same color everywhere.
+#[derive(Debug, Copy, Clone)]
+pub struct ConstantColor(pub Color);
+
+impl Texture for ConstantColor {
+    fn sample(&self, _uv: Vec2, _point: Vec3) -> Color {
+        self.0
+    }
+}
+
+/// A texture given by an image, which is repeated in both directions.
+///
+/// The image covers the texture coordinates from 0 to 1, with `(0, 0)` at its bottom left.
+/// Transparent parts of the image appear black.
+///
+/// For filtering, the texture keeps mipmaps, i.e. copies of the image with half the width
+/// and height of the previous one, down to a single pixel.
+/// Pixels covering many texels use the smaller copies, and long, thin footprints
+/// are covered by several samples along their major axis (anisotropic filtering),
+/// weighted like an elliptical Gaussian filter (EWA).
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let mut image = Image::new(64, 64);
+/// for y in 0..64 {
+///     for x in 0..64 {
+///         let c = if (x + y) % 2 == 0 { white() } else { black() };
+///         image.set(x, y, c.with_alpha());
+///     }
+/// }
+/// let texture = ImageTexture::new(image);
+/// assert_eq!(texture.mip_levels(), 7);
+/// let uv = Vec2::new(32.5 / 64.0, 31.5 / 64.0);
+/// // Without filtering, a distant checkerboard aliases to a single texel.
+/// let c = texture.sample(uv, Vec3::zero()).red();
+/// assert!(c < 0.01 || c > 0.99);
+/// // A footprint covering 8x8 texels averages them,
+/// let square = TextureFootprint {
+///     dx: Vec2::new(0.125, 0.0),
+///     dy: Vec2::new(0.0, 0.125),
+///     max_anisotropy: 16,
+/// };
+/// assert!(appr(texture.sample_filtered(uv, Vec3::zero(), &square).red(), 0.5));
+/// // and so does a thin one covering 16x1 texels.
+/// let thin = TextureFootprint {
+///     dx: Vec2::new(0.25, 0.0),
+///     dy: Vec2::new(0.0, 1.0 / 64.0),
+///     ..square
+/// };
+/// assert!((texture.sample_filtered(uv, Vec3::zero(), &thin).red() - 0.5).abs() < 0.05);
+/// ```
+#[derive(Clone)]
+pub struct ImageTexture {
+    image: Image,
+    /// The mipmaps, starting with the image itself.
+    levels: Vec<MipLevel>,
+}
+
+/// A level of the mipmaps of an `ImageTexture`.
+#[derive(Clone)]
+struct MipLevel {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl MipLevel {
+    /// Returns the color of the pixel, repeating the level outside of its bounds.
+    fn pixel(&self, x: isize, y: isize) -> Color {
+        let (width, height) = (self.width as isize, self.height as isize);
+        let x = ((x % width) + width) % width;
+        let y = ((y % height) + height) % height;
+        self.pixels[y as usize * self.width + x as usize]
+    }
+
+    /// Returns the next smaller level, averaging 2x2 pixels.
+    fn downsample(&self) -> MipLevel {
+        let (width, height) = ((self.width + 1) / 2, (self.height + 1) / 2);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height as isize {
+            for x in 0..width as isize {
+                let sum = self.pixel(2 * x, 2 * y) + self.pixel(2 * x + 1, 2 * y) +
+                          self.pixel(2 * x, 2 * y + 1) +
+                          self.pixel(2 * x + 1, 2 * y + 1);
+                pixels.push(0.25 * sum);
+            }
+        }
+        MipLevel {
+            width: width,
+            height: height,
+            pixels: pixels,
+        }
+    }
+
+    /// Interpolates the colors of the pixels bilinearly.
+    fn sample(&self, uv: Vec2) -> Color {
+        // The centers of the pixels are at half-integer coordinates.
+        let x = uv.x() * self.width as Float - 0.5;
+        let y = (1. - uv.y()) * self.height as Float - 0.5;
+        if !(x.is_finite() && y.is_finite()) {
+            return black();
+        }
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as isize, y0 as isize);
+        (1. - fy) * ((1. - fx) * self.pixel(x0, y0) + fx * self.pixel(x0 + 1, y0)) +
+        fy * ((1. - fx) * self.pixel(x0, y0 + 1) + fx * self.pixel(x0 + 1, y0 + 1))
+    }
+}
+
+impl ImageTexture {
+    /// Creates a texture from an image with linear colors and computes its mipmaps.
+    /// Panics if the image is empty.
+    pub fn new(image: Image) -> ImageTexture {
+        assert!(image.width > 0 && image.height > 0, "the image of a texture must not be empty");
+        let mut pixels = Vec::with_capacity(image.width * image.height);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                pixels.push(image.get(x, y).opaque());
+            }
+        }
+        let mut levels = vec![MipLevel {
+                                  width: image.width,
+                                  height: image.height,
+                                  pixels: pixels,
+                              }];
+        while levels[levels.len() - 1].width > 1 || levels[levels.len() - 1].height > 1 {
+            let next = levels[levels.len() - 1].downsample();
+            levels.push(next);
+        }
+        ImageTexture {
+            image: image,
+            levels: levels,
+        }
+    }
+
+    /// Reads the image of a texture from the given file, e.g. a PNG file with sRGB colors.
+    #[cfg(all(feature = "fs", feature = "image"))]
+    pub fn from_file(filepath: &Path, transfer: TransferFunction) -> Result<ImageTexture> {
+        read_pixels_from_file(filepath, transfer).map(ImageTexture::new)
+    }
+
+    /// Returns the image of the texture.
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    /// Returns the number of mipmap levels, including the image itself.
+    pub fn mip_levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Interpolates between the two nearest mipmap levels, where level `l` has pixels
+    /// of `2^l` texels (trilinear filtering).
+    fn sample_level(&self, uv: Vec2, level: Float) -> Color {
+        let last = self.levels.len() - 1;
+        let level = level.max(0.).min(last as Float);
+        let lower = level.floor() as usize;
+        let color = self.levels[lower].sample(uv);
+        if lower == last || level == lower as Float {
+            return color;
+        }
+        color.lerp(self.levels[lower + 1].sample(uv), level - lower as Float)
+    }
+}
+
+impl Texture for ImageTexture {
+    /// Interpolates the colors of the pixels bilinearly.
+    fn sample(&self, uv: Vec2, _point: Vec3) -> Color {
+        self.levels[0].sample(uv)
+    }
+
+    fn sample_filtered(&self, uv: Vec2, point: Vec3, footprint: &TextureFootprint) -> Color {
+        // Measure the footprint in texels of the image.
+        let (width, height) = (self.image.width as Float, self.image.height as Float);
+        let texels = |d: Vec2| Vec2::new(d.x() * width, d.y() * height).norm();
+        let (major, minor) = if texels(footprint.dx) >= texels(footprint.dy) {
+            (footprint.dx, footprint.dy)
+        } else {
+            (footprint.dy, footprint.dx)
+        };
+        let major_length = texels(major);
+        // When zooming in, bilinear interpolation is all that is needed.
+        if footprint.max_anisotropy == 0 || !(major_length > 1.) || !major_length.is_finite() {
+            return self.sample(uv, point);
+        }
+        // Footprints that are too thin are made wider, blurring them a bit.
+        let max_anisotropy = footprint.max_anisotropy as Float;
+        let minor_length = texels(minor).max(major_length / max_anisotropy).max(1.);
+        let level = minor_length.log2();
+        // Like EWA, the Gaussian reaches out to the neighboring pixels along the major axis.
+        let count = (2. * major_length / minor_length).ceil().max(1.) as usize;
+        let mut sum = black();
+        let mut total_weight = 0.;
+        for i in 0..count {
+            let s = 2. * (i as Float + 0.5) / count as Float - 1.;
+            let weight = (-2. * s * s).exp();
+            sum = sum + weight * self.sample_level(uv + s * major, level);
+            total_weight += weight;
+        }
+        (1. / total_weight) * sum
+    }
+}
+
+/// A texture computed by a function of the texture coordinates and the point.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// fn checkers(uv: Vec2, _point: Vec3) -> Color {
+///     if ((4. * uv.x()).floor() + (4. * uv.y()).floor()) % 2. == 0. {
+///         white()
+///     } else {
+///         black()
+///     }
+/// }
+/// static CHECKERS: Procedural = Procedural(checkers);
+/// assert_eq!(CHECKERS.sample(Vec2::new(0.1, 0.1), Vec3::zero()).red(), 1.);
+/// assert_eq!(CHECKERS.sample(Vec2::new(0.3, 0.1), Vec3::zero()).red(), 0.);
+/// let material = Material { color_texture: Some(&CHECKERS), ..color_material(white()) };
+/// ```
+#[derive(Copy, Clone)]
+pub struct Procedural(pub fn(Vec2, Vec3) -> Color);
+
+impl Texture for Procedural {
+    fn sample(&self, uv: Vec2, point: Vec3) -> Color {
+        (self.0)(uv, point)
+    }
+}
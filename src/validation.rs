@@ -0,0 +1,252 @@
+use basic::*;
+use std::fmt;
+
+/// How serious a problem in a scene is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The scene can be rendered, but probably does not look as intended.
+    Warning,
+    /// Rendering the scene produces non-finite colors, garbage, or panics.
+    Error,
+}
+
+/// The part of a scene that a problem was found in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SceneElement {
+    /// The camera.
+    Camera,
+    /// The object with the given index in `Scene::objects`.
+    Object(usize),
+    /// The light with the given index in `Scene::lights`.
+    Light(usize),
+}
+
+/// A mistake in the description of a scene.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// A coordinate or size of an object is NaN or infinite.
+    NonFiniteGeometry,
+    /// The object has no extent, e.g. a sphere with radius 0 or a triangle with collinear vertices.
+    DegenerateGeometry,
+    /// The given number of faces of a mesh have no area.
+    DegenerateFaces {
+        /// The number of degenerate faces.
+        count: usize,
+    },
+    /// A value of the material is NaN, infinite, or outside of its valid range.
+    InvalidMaterial {
+        /// The name of the field of `Material`.
+        field: &'static str,
+    },
+    /// A value of the camera is NaN, infinite, or outside of its valid range.
+    InvalidCamera {
+        /// The name of the field of `Camera`.
+        field: &'static str,
+    },
+    /// The up vector of the camera is parallel to the viewing direction,
+    /// so the orientation of the image is undefined.
+    CameraUpParallelToView,
+    /// A value of the light is NaN, infinite, or negative.
+    InvalidLight {
+        /// The name of the field of the light.
+        field: &'static str,
+    },
+    /// The light is inside a closed object, so the object casts a shadow on everything else.
+    LightInsideObject {
+        /// The index of the object in `Scene::objects`.
+        object: usize,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Problem::NonFiniteGeometry => write!(f, "non-finite coordinates or sizes"),
+            Problem::DegenerateGeometry => write!(f, "degenerate geometry without extent"),
+            Problem::DegenerateFaces { count } => write!(f, "{} faces without area", count),
+            Problem::InvalidMaterial { field } => write!(f, "invalid material value `{}`", field),
+            Problem::InvalidCamera { field } => write!(f, "invalid camera value `{}`", field),
+            Problem::CameraUpParallelToView => {
+                write!(f, "the up vector is parallel to the viewing direction")
+            }
+            Problem::InvalidLight { field } => write!(f, "invalid light value `{}`", field),
+            Problem::LightInsideObject { object } => write!(f, "inside object #{}", object),
+        }
+    }
+}
+
+/// A problem found in a scene, see `Scene::validate`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationIssue {
+    /// How serious the problem is.
+    pub severity: Severity,
+    /// Where the problem was found.
+    pub element: SceneElement,
+    /// What the problem is.
+    pub problem: Problem,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match self.element {
+            SceneElement::Camera => write!(f, "{}: camera: {}", severity, self.problem),
+            SceneElement::Object(i) => write!(f, "{}: object #{}: {}", severity, i, self.problem),
+            SceneElement::Light(i) => write!(f, "{}: light #{}: {}", severity, i, self.problem),
+        }
+    }
+}
+
+/// The problems found in a scene, see `Scene::validate`.
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+    /// All problems, in the order camera, objects, lights.
+    pub issues: Vec<ValidationIssue>,
+}
+
+impl ValidationReport {
+    /// Returns `true` if no problems were found, not even warnings.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Returns `true` if a problem of severity `Error` was found.
+    pub fn has_errors(&self) -> bool {
+        self.issues.iter().any(|issue| issue.severity == Severity::Error)
+    }
+
+    /// Adds the problems of a scene element.
+    pub fn add(&mut self, element: SceneElement, problems: Vec<(Severity, Problem)>) {
+        for (severity, problem) in problems {
+            self.issues.push(ValidationIssue {
+                severity: severity,
+                element: element,
+                problem: problem,
+            });
+        }
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_ok() {
+            return write!(f, "no problems found");
+        }
+        for (i, issue) in self.issues.iter().enumerate() {
+            if i > 0 {
+                try!(writeln!(f));
+            }
+            try!(write!(f, "{}", issue));
+        }
+        Ok(())
+    }
+}
+
+/// Returns `true` if all coordinates are finite (neither NaN nor infinite).
+pub fn is_finite_vec(v: Vec3) -> bool {
+    v.x().is_finite() && v.y().is_finite() && v.z().is_finite()
+}
+
+fn is_finite_color(c: Color) -> bool {
+    c.red().is_finite() && c.green().is_finite() && c.blue().is_finite()
+}
+
+fn is_nonnegative_color(c: Color) -> bool {
+    c.red() >= 0. && c.green() >= 0. && c.blue() >= 0.
+}
+
+/// Checks the values of a material.
+///
+/// Non-finite values and refraction indices that are not positive are errors.
+/// Negative values and materials that reflect and refract more light than they receive
+/// are warnings.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// assert!(validate_material(&glass()).is_empty());
+/// let mut material = glass();
+/// material.refraction_index = Float::NAN;
+/// assert_eq!(validate_material(&material),
+///            vec![(Severity::Error, Problem::InvalidMaterial { field: "refraction_index" })]);
+/// ```
+pub fn validate_material(material: &Material) -> Vec<(Severity, Problem)> {
+    let mut problems = Vec::new();
+    {
+        let mut report = |severity, field| {
+            problems.push((severity, Problem::InvalidMaterial { field: field }));
+        };
+        let scalars = [("ambient", material.ambient),
+                       ("diffuse", material.diffuse),
+                       ("specular", material.specular),
+                       ("shininess", material.shininess)];
+        for &(field, value) in scalars.iter() {
+            if !value.is_finite() {
+                report(Severity::Error, field);
+            } else if value < 0. {
+                report(Severity::Warning, field);
+            }
+        }
+        let colors = [("color", material.color),
+                      ("reflectance", material.reflectance),
+                      ("refractivity", material.refractivity),
+                      ("absorption", material.absorption),
+                      ("emission", material.emission)];
+        for &(field, value) in colors.iter() {
+            if !is_finite_color(value) {
+                report(Severity::Error, field);
+            } else if !is_nonnegative_color(value) {
+                report(Severity::Warning, field);
+            }
+        }
+        if !(material.refraction_index > 0. && material.refraction_index.is_finite()) {
+            report(Severity::Error, "refraction_index");
+        }
+        let total = material.reflectance + material.refractivity;
+        if is_finite_color(total) && total.max_channel() > 1. {
+            // More light leaves the surface than arrives.
+            report(Severity::Warning, "reflectance");
+        }
+        match material.diffuse_model {
+            DiffuseModel::Lambert => (),
+            DiffuseModel::OrenNayar { roughness } => {
+                if !roughness.is_finite() {
+                    report(Severity::Error, "diffuse_model");
+                } else if roughness < 0. {
+                    report(Severity::Warning, "diffuse_model");
+                }
+            }
+            DiffuseModel::Subsurface { wrap, tint, mean_free_path } => {
+                if !wrap.is_finite() || !is_finite_color(tint) || mean_free_path.is_nan() {
+                    report(Severity::Error, "diffuse_model");
+                } else if wrap < 0. || wrap > 1. || !is_nonnegative_color(tint) ||
+                          !(mean_free_path > 0.) {
+                    report(Severity::Warning, "diffuse_model");
+                }
+            }
+        }
+        match material.specular_model {
+            SpecularModel::BlinnPhong => (),
+            SpecularModel::Ggx { roughness, f0 } => {
+                if !roughness.is_finite() || !f0.is_finite() {
+                    report(Severity::Error, "specular_model");
+                } else if roughness < 0. || roughness > 1. || f0 < 0. || f0 > 1. {
+                    report(Severity::Warning, "specular_model");
+                }
+            }
+        }
+        let wavelengths = CHANNEL_WAVELENGTHS.iter().chain(Some(&REFERENCE_WAVELENGTH));
+        let indices_are_valid = wavelengths.map(|&l| material.refraction_index_at(l))
+            .all(|n| n > 0. && n.is_finite());
+        if material.is_dispersive() && !indices_are_valid {
+            report(Severity::Error, "dispersion");
+        }
+        if material.sides == Sides::Front && material.refractivity.max_channel() > 0. {
+            // Rays inside the object never leave it.
+            report(Severity::Warning, "sides");
+        }
+    }
+    problems
+}
@@ -0,0 +1,209 @@
+use basic::*;
+use error::{Error, Result};
+use medium::Volume;
+use objects::aabb::Aabb;
+#[cfg(feature = "fs")]
+use std::fs::File;
+use std::io::{self, BufRead};
+
+/// A three-dimensional grid of densities, e.g. from a smoke simulation.
+///
+/// The grid covers the unit cube, with the voxel `(x, y, z)` centered at
+/// `((x + 0.5) / nx, (y + 0.5) / ny, (z + 0.5) / nz)` for the size `(nx, ny, nz)`.
+#[derive(Debug, Clone)]
+pub struct VoxelGrid {
+    size: (usize, usize, usize),
+    densities: Vec<Float>,
+}
+
+impl VoxelGrid {
+    /// Creates a grid of the given size from its densities,
+    /// ordered by z, then y, then x, i.e. x changes fastest.
+    /// Panics if the number of densities does not match the size.
+    pub fn new(size: (usize, usize, usize), densities: Vec<Float>) -> VoxelGrid {
+        assert_eq!(size.0 * size.1 * size.2, densities.len(), "wrong number of densities");
+        VoxelGrid {
+            size: size,
+            densities: densities,
+        }
+    }
+
+    /// Reads a grid from the file `path`, see `from_reader`.
+    #[cfg(feature = "fs")]
+    pub fn from_file(path: &str) -> Result<VoxelGrid> {
+        let file = try!(File::open(path));
+        VoxelGrid::from_reader(io::BufReader::new(file))
+    }
+
+    /// Reads a grid in a simple text format: the size `nx ny nz`, followed by the densities
+    /// in the order of `new`, separated by whitespace. `#` starts a comment.
+    ///
+    /// ```
+    /// use raydiancy::raytrace::*;
+    /// let data = "# a 2x1x2 grid\n2 1 2\n0 1\n2 3\n";
+    /// let grid = VoxelGrid::from_reader(data.as_bytes()).unwrap();
+    /// assert_eq!(grid.size(), (2, 1, 2));
+    /// assert_eq!((grid.get(1, 0, 0), grid.get(0, 0, 1)), (1.0, 2.0));
+    /// assert_eq!(grid.max_density(), 3.0);
+    /// // Between the voxel centers, the densities are interpolated.
+    /// assert_eq!(grid.density(Vec3::new(0.5, 0.5, 0.25)), 0.5);
+    /// assert_eq!(grid.density(Vec3::new(0.5, 0.5, 0.5)), 1.5);
+    /// assert_eq!(grid.density(Vec3::new(0.0, 0.0, 0.0)), 0.0);
+    /// assert_eq!(grid.density(Vec3::new(1.5, 0.5, 0.5)), 0.0);
+    ///
+    /// assert!(VoxelGrid::from_reader("2 2 2\n1 2 3".as_bytes()).is_err());
+    /// assert!(VoxelGrid::from_reader("1 1 1\nx".as_bytes()).is_err());
+    /// ```
+    pub fn from_reader<R: BufRead>(reader: R) -> Result<VoxelGrid> {
+        let mut size: Vec<usize> = Vec::new();
+        let mut densities = Vec::new();
+        let mut last_line = 0;
+        for (i, line) in reader.lines().enumerate() {
+            let number = i + 1;
+            last_line = number;
+            let line = try!(line);
+            let content = line.split('#').next().unwrap_or("");
+            for token in content.split_whitespace() {
+                if size.len() < 3 {
+                    match token.parse() {
+                        Ok(n) => size.push(n),
+                        Err(_) => return Err(parse_error(number, "invalid size")),
+                    }
+                } else {
+                    match token.parse::<Float>() {
+                        Ok(d) if d >= 0. && d.is_finite() => densities.push(d),
+                        _ => return Err(parse_error(number, "invalid density")),
+                    }
+                }
+            }
+        }
+        if size.len() < 3 {
+            return Err(parse_error(last_line, "missing size"));
+        }
+        if size[0] * size[1] * size[2] != densities.len() {
+            let message = format!("expected {} densities, found {}",
+                                  size[0] * size[1] * size[2],
+                                  densities.len());
+            return Err(parse_error(last_line, &message));
+        }
+        Ok(VoxelGrid::new((size[0], size[1], size[2]), densities))
+    }
+
+    /// Returns the number of voxels along the x-, y-, and z-axis.
+    pub fn size(&self) -> (usize, usize, usize) {
+        self.size
+    }
+
+    /// Returns the density of the voxel `(x, y, z)`.
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Float {
+        self.densities[(z * self.size.1 + y) * self.size.0 + x]
+    }
+
+    /// Returns the largest density.
+    pub fn max_density(&self) -> Float {
+        self.densities.iter().fold(0., |acc, &d| acc.max(d))
+    }
+
+    /// Returns the density at the point of the unit cube, interpolated trilinearly
+    /// between the voxel centers. Beyond the outer voxel centers, the voxels outside of the grid
+    /// count as density 0, and outside of the unit cube, the density is 0.
+    pub fn density(&self, point: Vec3) -> Float {
+        let size = [self.size.0, self.size.1, self.size.2];
+        // The lower of the two neighboring voxels on each axis and the weight of the upper one.
+        let mut lower = [0isize; 3];
+        let mut weight = [0.; 3];
+        for i in 0..3 {
+            let x = point[i] * size[i] as Float - 0.5;
+            if !(x > -1. && x < size[i] as Float) {
+                return 0.;
+            }
+            lower[i] = x.floor() as isize;
+            weight[i] = x - x.floor();
+        }
+        let mut result = 0.;
+        for corner in 0..8 {
+            let mut w = 1.;
+            let mut index = [0usize; 3];
+            let mut inside = true;
+            for i in 0..3 {
+                let upper = (corner >> i) & 1 == 1;
+                w *= if upper { weight[i] } else { 1. - weight[i] };
+                let j = lower[i] + upper as isize;
+                inside &= j >= 0 && j < size[i] as isize;
+                index[i] = j as usize;
+            }
+            if inside && w > 0. {
+                result += w * self.get(index[0], index[1], index[2]);
+            }
+        }
+        result
+    }
+}
+
+fn parse_error(line: usize, message: &str) -> Error {
+    Error::Parse {
+        line: line,
+        message: message.to_string(),
+    }
+}
+
+/// A box filled with a medium whose density is given by a voxel grid, like smoke or a cloud.
+///
+/// The absorption and scattering coefficients are proportional to the density.
+///
+/// ```
+/// use raydiancy::raytrace::*;
+/// let volume = VoxelVolume {
+///     grid: VoxelGrid::new((2, 2, 2), vec![1.0; 8]),
+///     bounds: Aabb::new(Vec3::zero(), 2.0 * Vec3::ones()),
+///     absorption: Color::new_gray(0.1),
+///     scattering: Color::new_gray(0.3),
+///     steps: 32,
+/// };
+/// // The density is 1 around the center of the box.
+/// let (absorption, scattering) = volume.coefficients(Vec3::ones());
+/// assert_eq!((absorption.red(), scattering.red()), (0.1, 0.3));
+/// let ray = Ray::newn(Vec3::new(-1.0, 1.0, 1.0), Vec3::e1().to());
+/// assert_eq!(volume.segment(&ray, Float::INFINITY), Some((1.0, 3.0)));
+/// // The density fades out towards the sides, so less light is absorbed than for density 1.
+/// let transmittance = volume.transmittance(&ray, Float::INFINITY).red();
+/// assert!(transmittance > (-0.8 as Float).exp() && transmittance < 1.0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct VoxelVolume {
+    /// The densities, which are stretched to fill `bounds`.
+    pub grid: VoxelGrid,
+    /// The box filled with the grid.
+    pub bounds: Aabb,
+    /// The fraction of the light absorbed per unit of distance at density 1.
+    pub absorption: Color,
+    /// The fraction of the light scattered per unit of distance at density 1.
+    pub scattering: Color,
+    /// The number of points per ray where the density is evaluated.
+    pub steps: usize,
+}
+
+impl VoxelVolume {
+    /// Returns the density at the point, 0 outside of the bounds.
+    pub fn density(&self, point: Vec3) -> Float {
+        let (min, diagonal) = (self.bounds.min(), self.bounds.diagonal());
+        let p = point - min;
+        let local = Vec3::new(p.x() / diagonal.x(), p.y() / diagonal.y(), p.z() / diagonal.z());
+        self.grid.density(local)
+    }
+}
+
+impl Volume for VoxelVolume {
+    fn segment(&self, ray: &Ray, t_max: Float) -> Option<(Float, Float)> {
+        self.bounds.clip_ray(ray, ray.t_min(), t_max)
+    }
+
+    fn coefficients(&self, point: Vec3) -> (Color, Color) {
+        let density = self.density(point);
+        (density * self.absorption, density * self.scattering)
+    }
+
+    fn steps(&self) -> usize {
+        self.steps
+    }
+}